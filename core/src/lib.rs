@@ -50,6 +50,7 @@ pub use edn::{
 
 pub use edn::parse::{
     parse_query,
+    parse_rules,
     ParseError as EdnParseError,
 };
 
@@ -87,6 +88,8 @@ pub use sql_types::{
     SQLTypeAffinity,
     SQLValueType,
     SQLValueTypeSet,
+    tuple_from_bytes,
+    tuple_to_bytes,
 };
 
 /// Bit flags used in `flags0` column in temporary tables created during search,
@@ -98,6 +101,8 @@ pub enum AttributeBitFlags {
     IndexVAET     = 1 << 1,
     IndexFulltext = 1 << 2,
     UniqueValue   = 1 << 3,
+    /// Only used to prune the transaction log; not expanded into a column of `datoms`.
+    NoHistory     = 1 << 4,
 }
 
 pub mod attribute {
@@ -169,6 +174,13 @@ pub struct Attribute {
 
     /// `true` if this attribute doesn't require history to be kept, i.e., it is `:db/noHistory true`.
     pub no_history: bool,
+
+    /// `Some(attributes)` if this attribute is a composite tuple, i.e., it has `:db/tupleAttrs`.
+    ///
+    /// Composite attributes always have value type `Tuple` and are single-valued.  The transactor
+    /// maintains them: whenever an entity has a value for each of `attributes`, it also has the
+    /// tuple of those values, in order, for the composite.
+    pub tuple_attrs: Option<Vec<Entid>>,
 }
 
 impl Attribute {
@@ -188,6 +200,9 @@ impl Attribute {
         if self.unique.is_some() {
             flags |= AttributeBitFlags::UniqueValue as u8;
         }
+        if self.no_history {
+            flags |= AttributeBitFlags::NoHistory as u8;
+        }
         flags
     }
 
@@ -223,6 +238,10 @@ impl Attribute {
             attribute_map.insert(values::DB_NO_HISTORY.clone(), edn::Value::Boolean(true));
         }
 
+        if let Some(ref tuple_attrs) = self.tuple_attrs {
+            attribute_map.insert(values::DB_TUPLE_ATTRS.clone(), edn::Value::Vector(tuple_attrs.iter().map(|&a| edn::Value::Integer(a)).collect()));
+        }
+
         edn::Value::Map(attribute_map)
     }
 }
@@ -238,6 +257,7 @@ impl Default for Attribute {
             unique: None,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         }
     }
 }
@@ -279,6 +299,10 @@ pub struct Schema {
     /// Maintain a vec of unique attribute IDs for which the corresponding attribute in `attribute_map`
     /// has `.component == true`.
     pub component_attributes: Vec<Entid>,
+
+    /// Map from each attribute to the composite tuple attributes that have it in their
+    /// `:db/tupleAttrs`, so that the transactor can find the composites to maintain.
+    pub composite_tuples: BTreeMap<Entid, Vec<Entid>>,
 }
 
 pub trait HasSchema {
@@ -302,8 +326,9 @@ pub trait HasSchema {
 
 impl Schema {
     pub fn new(ident_map: IdentMap, entid_map: EntidMap, attribute_map: AttributeMap) -> Schema {
-        let mut s = Schema { ident_map, entid_map, attribute_map, component_attributes: Vec::new() };
+        let mut s = Schema { ident_map, entid_map, attribute_map, component_attributes: Vec::new(), composite_tuples: BTreeMap::new() };
         s.update_component_attributes();
+        s.update_composite_tuples();
         s
     }
 
@@ -328,6 +353,23 @@ impl Schema {
         components.sort_unstable();
         self.component_attributes = components;
     }
+
+    pub fn update_composite_tuples(&mut self) {
+        let mut composites: BTreeMap<Entid, Vec<Entid>> = BTreeMap::new();
+        for (&c, attribute) in self.attribute_map.iter() {
+            if let Some(ref tuple_attrs) = attribute.tuple_attrs {
+                for &a in tuple_attrs {
+                    composites.entry(a).or_insert_with(Vec::new).push(c);
+                }
+            }
+        }
+        self.composite_tuples = composites;
+    }
+
+    /// The composite tuple attributes that have `a` as a component.
+    pub fn composites_for_component(&self, a: Entid) -> &[Entid] {
+        self.composite_tuples.get(&a).map_or(&[][..], |composites| composites.as_slice())
+    }
 }
 
 impl HasSchema for Schema {
@@ -437,6 +479,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         };
 
         assert!(attr1.flags() & AttributeBitFlags::IndexAVET as u8 != 0);
@@ -452,6 +495,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         };
 
         assert!(attr2.flags() & AttributeBitFlags::IndexAVET as u8 == 0);
@@ -467,12 +511,22 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         };
 
         assert!(attr3.flags() & AttributeBitFlags::IndexAVET as u8 == 0);
         assert!(attr3.flags() & AttributeBitFlags::IndexVAET as u8 == 0);
         assert!(attr3.flags() & AttributeBitFlags::IndexFulltext as u8 != 0);
         assert!(attr3.flags() & AttributeBitFlags::UniqueValue as u8 != 0);
+        assert!(attr3.flags() & AttributeBitFlags::NoHistory as u8 == 0);
+
+        let attr4 = Attribute {
+            no_history: true,
+            tuple_attrs: None,
+            .. attr3
+        };
+
+        assert!(attr4.flags() & AttributeBitFlags::NoHistory as u8 != 0);
     }
 
     #[test]
@@ -488,6 +542,38 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_tuple_bytes_roundtrip() {
+        let uuid = Uuid::parse_str("55555555-5555-5555-5555-555555555555").expect("parsed");
+        let tuple = vec![
+            TypedValue::Ref(65536),
+            TypedValue::Long(-1),
+            TypedValue::Boolean(true),
+            TypedValue::Double(1.5.into()),
+            TypedValue::instant(1_500_000_000_000_000),
+            TypedValue::typed_string("a string"),
+            TypedValue::typed_ns_keyword("foo", "bar"),
+            TypedValue::Keyword(ValueRc::new(Keyword::plain("baz"))),
+            TypedValue::Uuid(uuid),
+            vec![TypedValue::Long(1), TypedValue::typed_string("")].into(),
+        ];
+
+        let bytes = tuple_to_bytes(&tuple[..]);
+        assert_eq!(tuple_from_bytes(&bytes[..]), Some(tuple));
+
+        // Encoding is deterministic, so equal tuples collide in unique indices.
+        assert_eq!(bytes, tuple_to_bytes(&tuple_from_bytes(&bytes[..]).expect("decoded")[..]));
+
+        // Truncated input doesn't decode.
+        assert_eq!(tuple_from_bytes(&bytes[..bytes.len() - 1]), None);
+
+        // Tags are stored, so they are pinned rather than following the order of `ValueType`.
+        assert_eq!(tuple_to_bytes(&[TypedValue::Long(1), TypedValue::Boolean(true)]),
+                   vec![3, 0, 0, 0, 0, 0, 0, 0, 1,
+                        1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(tuple_from_bytes(&[9]), None);
+    }
+
     #[test]
     fn test_as_edn_value() {
         let mut schema = Schema::default();
@@ -500,6 +586,7 @@ mod test {
             multival: false,
             component: false,
             no_history: true,
+            tuple_attrs: None,
         };
         associate_ident(&mut schema, Keyword::namespaced("foo", "bar"), 97);
         add_attribute(&mut schema, 97, attr1);
@@ -512,6 +599,7 @@ mod test {
             multival: true,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         };
         associate_ident(&mut schema, Keyword::namespaced("foo", "bas"), 98);
         add_attribute(&mut schema, 98, attr2);
@@ -524,6 +612,7 @@ mod test {
             multival: false,
             component: true,
             no_history: false,
+            tuple_attrs: None,
         };
 
         associate_ident(&mut schema, Keyword::namespaced("foo", "bat"), 99);
@@ -12,7 +12,28 @@ use std::collections::{
     BTreeSet,
 };
 
+use edn::{
+    FromMicros,
+    Keyword,
+    ToMicros,
+    Utc,
+    ValueRc,
+};
+
+use chrono::{
+    DateTime,
+};
+
+use ordered_float::{
+    OrderedFloat,
+};
+
+use uuid::{
+    Uuid,
+};
+
 use types::{
+    TypedValue,
     ValueType,
     ValueTypeTag,
 };
@@ -58,6 +79,7 @@ impl SQLValueType for ValueType {
             ValueType::String  => (10, None),
             ValueType::Uuid    => (11, None),
             ValueType::Keyword => (13, None),
+            ValueType::Tuple   => (15, None),
         }
     }
 
@@ -88,6 +110,7 @@ impl SQLValueType for ValueType {
             ValueType::String       => false,
             Keyword                 => false,
             Uuid                    => false,
+            Tuple                   => false,
         }
     }
 }
@@ -137,3 +160,168 @@ impl SQLValueTypeSet for ValueTypeSet {
         !acc.is_empty()
     }
 }
+
+/// A tuple is stored as a BLOB: the concatenation of its values, each a byte naming its
+/// `ValueType` followed by the value.  Integral values take eight big-endian bytes; strings and
+/// keywords a four byte length followed by their UTF-8 text; and nested tuples a four byte length
+/// followed by their encoding.
+///
+/// The encoding is deterministic, so that equal tuples are stored identically and can be found by
+/// equality in the `datoms` table.
+pub fn tuple_to_bytes(values: &[TypedValue]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for value in values {
+        push_tuple_value(&mut bytes, value);
+    }
+    bytes
+}
+
+/// The values of the tuple encoded by `tuple_to_bytes`, or `None` if `bytes` isn't such an
+/// encoding.
+pub fn tuple_from_bytes(bytes: &[u8]) -> Option<Vec<TypedValue>> {
+    let mut values = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (value, remaining) = read_tuple_value(rest)?;
+        values.push(value);
+        rest = remaining;
+    }
+    Some(values)
+}
+
+// The byte naming the `ValueType` of each value in a tuple's encoding.  These are stored, so they
+// must never change, even if `ValueType` is reordered or extended.
+const TUPLE_TAG_REF: u8 = 0;
+const TUPLE_TAG_BOOLEAN: u8 = 1;
+const TUPLE_TAG_INSTANT: u8 = 2;
+const TUPLE_TAG_LONG: u8 = 3;
+const TUPLE_TAG_DOUBLE: u8 = 4;
+const TUPLE_TAG_STRING: u8 = 5;
+const TUPLE_TAG_KEYWORD: u8 = 6;
+const TUPLE_TAG_UUID: u8 = 7;
+const TUPLE_TAG_TUPLE: u8 = 8;
+
+fn tuple_tag(value_type: ValueType) -> u8 {
+    match value_type {
+        ValueType::Ref => TUPLE_TAG_REF,
+        ValueType::Boolean => TUPLE_TAG_BOOLEAN,
+        ValueType::Instant => TUPLE_TAG_INSTANT,
+        ValueType::Long => TUPLE_TAG_LONG,
+        ValueType::Double => TUPLE_TAG_DOUBLE,
+        ValueType::String => TUPLE_TAG_STRING,
+        ValueType::Keyword => TUPLE_TAG_KEYWORD,
+        ValueType::Uuid => TUPLE_TAG_UUID,
+        ValueType::Tuple => TUPLE_TAG_TUPLE,
+    }
+}
+
+fn tuple_tag_value_type(tag: u8) -> Option<ValueType> {
+    match tag {
+        TUPLE_TAG_REF => Some(ValueType::Ref),
+        TUPLE_TAG_BOOLEAN => Some(ValueType::Boolean),
+        TUPLE_TAG_INSTANT => Some(ValueType::Instant),
+        TUPLE_TAG_LONG => Some(ValueType::Long),
+        TUPLE_TAG_DOUBLE => Some(ValueType::Double),
+        TUPLE_TAG_STRING => Some(ValueType::String),
+        TUPLE_TAG_KEYWORD => Some(ValueType::Keyword),
+        TUPLE_TAG_UUID => Some(ValueType::Uuid),
+        TUPLE_TAG_TUPLE => Some(ValueType::Tuple),
+        _ => None,
+    }
+}
+
+fn push_u64(bytes: &mut Vec<u8>, x: u64) {
+    for i in (0..8).rev() {
+        bytes.push((x >> (i * 8)) as u8);
+    }
+}
+
+fn push_str(bytes: &mut Vec<u8>, s: &str) {
+    push_len(bytes, s.len());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn push_len(bytes: &mut Vec<u8>, len: usize) {
+    let len = len as u32;
+    for i in (0..4).rev() {
+        bytes.push((len >> (i * 8)) as u8);
+    }
+}
+
+fn push_tuple_value(bytes: &mut Vec<u8>, value: &TypedValue) {
+    bytes.push(tuple_tag(value.value_type()));
+    match value {
+        &TypedValue::Ref(x) => push_u64(bytes, x as u64),
+        &TypedValue::Boolean(x) => push_u64(bytes, if x { 1 } else { 0 }),
+        &TypedValue::Instant(x) => push_u64(bytes, x.to_micros() as u64),
+        &TypedValue::Long(x) => push_u64(bytes, x as u64),
+        &TypedValue::Double(x) => push_u64(bytes, x.into_inner().to_bits()),
+        &TypedValue::String(ref x) => push_str(bytes, x.as_str()),
+        &TypedValue::Keyword(ref x) => {
+            // A keyword's namespace is never empty, so an empty namespace means it has none.
+            push_str(bytes, x.namespace().unwrap_or(""));
+            push_str(bytes, x.name());
+        },
+        &TypedValue::Uuid(ref x) => bytes.extend_from_slice(x.as_bytes()),
+        &TypedValue::Tuple(ref x) => {
+            let nested = tuple_to_bytes(x.as_slice());
+            push_len(bytes, nested.len());
+            bytes.extend_from_slice(&nested);
+        },
+    }
+}
+
+fn read_bytes(bytes: &[u8], len: usize) -> Option<(&[u8], &[u8])> {
+    if bytes.len() < len {
+        None
+    } else {
+        Some(bytes.split_at(len))
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let (head, rest) = read_bytes(bytes, 8)?;
+    Some((head.iter().fold(0u64, |acc, &b| (acc << 8) | (b as u64)), rest))
+}
+
+fn read_len_prefixed(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (head, rest) = read_bytes(bytes, 4)?;
+    let len = head.iter().fold(0u32, |acc, &b| (acc << 8) | (b as u32));
+    read_bytes(rest, len as usize)
+}
+
+fn read_str(bytes: &[u8]) -> Option<(&str, &[u8])> {
+    let (head, rest) = read_len_prefixed(bytes)?;
+    ::std::str::from_utf8(head).ok().map(|s| (s, rest))
+}
+
+fn read_tuple_value(bytes: &[u8]) -> Option<(TypedValue, &[u8])> {
+    let (&tag, rest) = bytes.split_first()?;
+    let value_type = tuple_tag_value_type(tag)?;
+    match value_type {
+        ValueType::Ref => read_u64(rest).map(|(x, rest)| (TypedValue::Ref(x as i64), rest)),
+        ValueType::Boolean => read_u64(rest).map(|(x, rest)| (TypedValue::Boolean(x != 0), rest)),
+        ValueType::Instant => read_u64(rest).map(|(x, rest)| (TypedValue::Instant(DateTime::<Utc>::from_micros(x as i64)), rest)),
+        ValueType::Long => read_u64(rest).map(|(x, rest)| (TypedValue::Long(x as i64), rest)),
+        ValueType::Double => read_u64(rest).map(|(x, rest)| (TypedValue::Double(OrderedFloat(f64::from_bits(x))), rest)),
+        ValueType::String => read_str(rest).map(|(x, rest)| (x.into(), rest)),
+        ValueType::Keyword => {
+            let (namespace, rest) = read_str(rest)?;
+            let (name, rest) = read_str(rest)?;
+            let keyword = if namespace.is_empty() {
+                Keyword::plain(name)
+            } else {
+                Keyword::namespaced(namespace, name)
+            };
+            Some((keyword.into(), rest))
+        },
+        ValueType::Uuid => {
+            let (head, rest) = read_bytes(rest, 16)?;
+            Uuid::from_bytes(head).ok().map(|u| (TypedValue::Uuid(u), rest))
+        },
+        ValueType::Tuple => {
+            let (head, rest) = read_len_prefixed(rest)?;
+            tuple_from_bytes(head).map(|x| (TypedValue::Tuple(ValueRc::new(x)), rest))
+        },
+    }
+}
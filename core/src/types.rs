@@ -121,6 +121,12 @@ pub enum ValueType {
     String,
     Keyword,
     Uuid,
+    /// A fixed-length vector of values.  Only two kinds of attribute have tuple values: composite
+    /// tuples, which have `:db/tupleAttrs` and are maintained by the transactor from their
+    /// component attributes, and `:db/tupleAttrs` itself.  There are no free-standing tuple
+    /// attributes.  Tuple values are given as vectors of values, entids, or idents; their elements
+    /// can't be tempids or lookup refs.
+    Tuple,
 }
 
 pub type ValueTypeTag = i32;
@@ -137,6 +143,7 @@ impl ValueType {
         s.insert(ValueType::String);
         s.insert(ValueType::Keyword);
         s.insert(ValueType::Uuid);
+        s.insert(ValueType::Tuple);
         s
     }
 }
@@ -163,6 +170,7 @@ impl ValueType {
             ValueType::String => "string",
             ValueType::Keyword => "keyword",
             ValueType::Uuid => "uuid",
+            ValueType::Tuple => "tuple",
         })
     }
 
@@ -180,6 +188,7 @@ impl ValueType {
             "string" => Some(ValueType::String),
             "keyword" => Some(ValueType::Keyword),
             "uuid" => Some(ValueType::Uuid),
+            "tuple" => Some(ValueType::Tuple),
             _ => None,
         }
     }
@@ -194,6 +203,7 @@ impl ValueType {
             ValueType::String => "string",
             ValueType::Keyword => "keyword",
             ValueType::Uuid => "uuid",
+            ValueType::Tuple => "tuple",
         })
     }
 
@@ -207,6 +217,7 @@ impl ValueType {
             ValueType::String => values::DB_TYPE_STRING.clone(),
             ValueType::Keyword => values::DB_TYPE_KEYWORD.clone(),
             ValueType::Uuid => values::DB_TYPE_UUID.clone(),
+            ValueType::Tuple => values::DB_TYPE_TUPLE.clone(),
         }
     }
 
@@ -229,6 +240,7 @@ impl fmt::Display for ValueType {
             ValueType::String =>  ":db.type/string",
             ValueType::Keyword => ":db.type/keyword",
             ValueType::Uuid =>    ":db.type/uuid",
+            ValueType::Tuple =>   ":db.type/tuple",
         })
     }
 }
@@ -248,6 +260,7 @@ pub enum TypedValue {
     String(ValueRc<String>),
     Keyword(ValueRc<Keyword>),
     Uuid(Uuid),                        // It's only 128 bits, so this should be acceptable to clone.
+    Tuple(ValueRc<Vec<TypedValue>>),   // The values of a composite's component attributes, in order.
 }
 
 /// `TypedValue` is the value type for programmatic use in transaction builders.
@@ -425,6 +438,7 @@ impl TypedValue {
             &TypedValue::String(_) => ValueType::String,
             &TypedValue::Keyword(_) => ValueType::Keyword,
             &TypedValue::Uuid(_) => ValueType::Uuid,
+            &TypedValue::Tuple(_) => ValueType::Tuple,
         }
     }
 
@@ -563,6 +577,12 @@ impl From<f64> for TypedValue {
     }
 }
 
+impl From<Vec<TypedValue>> for TypedValue {
+    fn from(value: Vec<TypedValue>) -> TypedValue {
+        TypedValue::Tuple(ValueRc::new(value))
+    }
+}
+
 impl TypedValue {
     pub fn into_known_entid(self) -> Option<KnownEntid> {
         match self {
@@ -688,6 +708,13 @@ impl TypedValue {
             _ => None,
         }
     }
+
+    pub fn into_tuple(self) -> Option<ValueRc<Vec<TypedValue>>> {
+        match self {
+            TypedValue::Tuple(v) => Some(v),
+            _ => None,
+        }
+    }
 }
 
 impl Binding {
@@ -37,8 +37,12 @@ pub const TX0: i64 = 0x10000000;
 /// This is the start of the :db.part/user partition.
 pub const USER0: i64 = 0x10000;
 
+/// The number of entids in each partition installed with `:db.install/partition`.  New partitions
+/// are carved from the end of :db.part/user.
+pub const PARTITION_SIZE: i64 = 0x100000;
+
 // Corresponds to the version of the :db.schema/core vocabulary.
-pub const CORE_SCHEMA_VERSION: u32 = 1;
+pub const CORE_SCHEMA_VERSION: u32 = 4;
 
 lazy_static! {
     static ref V1_IDENTS: [(symbols::Keyword, i64); 40] = {
@@ -85,8 +89,19 @@ lazy_static! {
         ]
     };
 
+    static ref V3_IDENTS: [(symbols::Keyword, i64); 1] = {
+            [(ns_keyword!("db.partition", "allowExcision"), entids::DB_PARTITION_ALLOW_EXCISION),
+        ]
+    };
+
+    static ref V4_IDENTS: [(symbols::Keyword, i64); 2] = {
+            [(ns_keyword!("db.type", "tuple"),        entids::DB_TYPE_TUPLE),
+             (ns_keyword!("db", "tupleAttrs"),        entids::DB_TUPLE_ATTRS),
+        ]
+    };
+
     pub static ref V1_PARTS: [(symbols::Keyword, i64, i64, i64, bool); 3] = {
-            [(ns_keyword!("db.part", "db"), 0, USER0 - 1, (1 + V1_IDENTS.len()) as i64, false),
+            [(ns_keyword!("db.part", "db"), 0, USER0 - 1, (1 + V1_IDENTS.len() + V3_IDENTS.len() + V4_IDENTS.len()) as i64, false),
              (ns_keyword!("db.part", "user"), USER0, TX0 - 1, USER0, true),
              (ns_keyword!("db.part", "tx"), TX0, i64::max_value(), TX0, false),
         ]
@@ -112,6 +127,23 @@ lazy_static! {
         ]
     };
 
+    static ref V2_CORE_SCHEMA: [(symbols::Keyword); 3] = {
+            [(ns_keyword!("db", "excise")),
+             (ns_keyword!("db.excise", "attrs")),
+             (ns_keyword!("db.excise", "before")),
+        ]
+    };
+
+    static ref V3_CORE_SCHEMA: [(symbols::Keyword); 1] = {
+            [(ns_keyword!("db.partition", "allowExcision")),
+        ]
+    };
+
+    static ref V4_CORE_SCHEMA: [(symbols::Keyword); 1] = {
+            [(ns_keyword!("db", "tupleAttrs")),
+        ]
+    };
+
     static ref V1_SYMBOLIC_SCHEMA: Value = {
         let s = r#"
 {:db/ident             {:db/valueType   :db.type/keyword
@@ -162,6 +194,78 @@ lazy_static! {
             .map_err(|_| DbErrorKind::BadBootstrapDefinition("Unable to parse V1_SYMBOLIC_SCHEMA".into()))
             .unwrap()
     };
+
+    // What an excision purged: the target entity, and the attributes and the transaction before
+    // which it purged, if it was limited to those.
+    static ref V2_SYMBOLIC_SCHEMA: Value = {
+        let s = r#"
+{:db/excise            {:db/valueType   :db.type/ref
+                        :db/cardinality :db.cardinality/one}
+ :db.excise/attrs      {:db/valueType   :db.type/ref
+                        :db/cardinality :db.cardinality/many}
+ :db.excise/before     {:db/valueType   :db.type/ref
+                        :db/cardinality :db.cardinality/one}}"#;
+        edn::parse::value(s)
+            .map(|v| v.without_spans())
+            .map_err(|_| DbErrorKind::BadBootstrapDefinition("Unable to parse V2_SYMBOLIC_SCHEMA".into()))
+            .unwrap()
+    };
+
+    // Whether entities in a partition installed with :db.install/partition can be excised.
+    static ref V3_SYMBOLIC_SCHEMA: Value = {
+        let s = r#"
+{:db.partition/allowExcision {:db/valueType   :db.type/boolean
+                              :db/cardinality :db.cardinality/one}}"#;
+        edn::parse::value(s)
+            .map(|v| v.without_spans())
+            .map_err(|_| DbErrorKind::BadBootstrapDefinition("Unable to parse V3_SYMBOLIC_SCHEMA".into()))
+            .unwrap()
+    };
+
+    // The attributes a composite tuple attribute is maintained from, in order.
+    static ref V4_SYMBOLIC_SCHEMA: Value = {
+        let s = r#"
+{:db/tupleAttrs        {:db/valueType   :db.type/tuple
+                        :db/cardinality :db.cardinality/one}}"#;
+        edn::parse::value(s)
+            .map(|v| v.without_spans())
+            .map_err(|_| DbErrorKind::BadBootstrapDefinition("Unable to parse V4_SYMBOLIC_SCHEMA".into()))
+            .unwrap()
+    };
+}
+
+/// The idents, core schema attributes, and symbolic schema that each version of the
+/// :db.schema/core vocabulary added to the bootstrap, oldest first.  Version 2 gave existing
+/// idents a schema, so added no idents of its own.
+fn bootstrap_versions() -> [(u32, &'static [(symbols::Keyword, i64)], &'static [symbols::Keyword], &'static Value); 4] {
+    [(1, &V1_IDENTS[..], &V1_CORE_SCHEMA[..], &*V1_SYMBOLIC_SCHEMA),
+     (2, &[],            &V2_CORE_SCHEMA[..], &*V2_SYMBOLIC_SCHEMA),
+     (3, &V3_IDENTS[..], &V3_CORE_SCHEMA[..], &*V3_SYMBOLIC_SCHEMA),
+     (4, &V4_IDENTS[..], &V4_CORE_SCHEMA[..], &*V4_SYMBOLIC_SCHEMA),
+    ]
+}
+
+/// The bootstrap idents added after the given version of the :db.schema/core vocabulary, with their
+/// entids.  Since version 0, that's all of them.
+pub(crate) fn bootstrap_idents_since(version: u32) -> Vec<(symbols::Keyword, i64)> {
+    bootstrap_versions().iter()
+                        .filter(|&&(v, _, _, _)| v > version)
+                        .flat_map(|&(_, idents, _, _)| idents.iter().cloned())
+                        .collect()
+}
+
+/// The core schema attributes added after the given version of the :db.schema/core vocabulary.
+pub(crate) fn core_schema_since(version: u32) -> Vec<symbols::Keyword> {
+    bootstrap_versions().iter()
+                        .filter(|&&(v, _, _, _)| v > version)
+                        .flat_map(|&(_, _, core_schema, _)| core_schema.iter().cloned())
+                        .collect()
+}
+
+/// Return `true` if `entid` is one of the entities the store is bootstrapped with, like
+/// `:db/ident`.
+pub(crate) fn is_bootstrap_entid(entid: i64) -> bool {
+    bootstrap_idents_since(0).iter().any(|&(_, e)| e == entid)
 }
 
 /// Convert (ident, entid) pairs into [:db/add IDENT :db/ident IDENT] `Value` instances.
@@ -176,10 +280,9 @@ fn idents_to_assertions(idents: &[(symbols::Keyword, i64)]) -> Vec<Value> {
 }
 
 /// Convert an ident list into [:db/add :db.schema/core :db.schema/attribute IDENT] `Value` instances.
-fn schema_attrs_to_assertions(version: u32, idents: &[symbols::Keyword]) -> Vec<Value> {
+fn schema_attrs_to_assertions(idents: &[symbols::Keyword]) -> Vec<Value> {
     let schema_core = Value::Keyword(ns_keyword!("db.schema", "core"));
     let schema_attr = Value::Keyword(ns_keyword!("db.schema", "attribute"));
-    let schema_version = Value::Keyword(ns_keyword!("db.schema", "version"));
     idents
         .into_iter()
         .map(|ident| {
@@ -189,13 +292,17 @@ fn schema_attrs_to_assertions(version: u32, idents: &[symbols::Keyword]) -> Vec<
                                schema_attr.clone(),
                                value])
         })
-        .chain(::std::iter::once(Value::Vector(vec![values::DB_ADD.clone(),
-                                             schema_core.clone(),
-                                             schema_version,
-                                             Value::Integer(version as i64)])))
         .collect()
 }
 
+/// Make the [:db/add :db.schema/core :db.schema/version VERSION] `Value` instance.
+fn schema_version_to_assertion(version: u32) -> Value {
+    Value::Vector(vec![values::DB_ADD.clone(),
+                       Value::Keyword(ns_keyword!("db.schema", "core")),
+                       Value::Keyword(ns_keyword!("db.schema", "version")),
+                       Value::Integer(version as i64)])
+}
+
 /// Convert {:ident {:key :value ...} ...} to
 /// vec![(symbols::Keyword(:ident), symbols::Keyword(:key), TypedValue(:value)), ...].
 ///
@@ -282,23 +389,35 @@ pub(crate) fn bootstrap_partition_map() -> PartitionMap {
 }
 
 pub(crate) fn bootstrap_ident_map() -> IdentMap {
-    V1_IDENTS.iter()
-             .map(|&(ref ident, entid)| (ident.clone(), entid))
-             .collect()
+    bootstrap_idents_since(0).into_iter().collect()
 }
 
 pub(crate) fn bootstrap_schema() -> Schema {
     let ident_map = bootstrap_ident_map();
-    let bootstrap_triples = symbolic_schema_to_triples(&ident_map, &V1_SYMBOLIC_SCHEMA).expect("symbolic schema");
+    let mut bootstrap_triples = vec![];
+    for &(_, _, _, symbolic_schema) in bootstrap_versions().iter() {
+        bootstrap_triples.extend(symbolic_schema_to_triples(&ident_map, symbolic_schema).expect("symbolic schema"));
+    }
     Schema::from_ident_map_and_triples(ident_map, bootstrap_triples).unwrap()
 }
 
 pub(crate) fn bootstrap_entities() -> Vec<Entity<edn::ValueAndSpan>> {
-    let bootstrap_assertions: Value = Value::Vector([
-        symbolic_schema_to_assertions(&V1_SYMBOLIC_SCHEMA).expect("symbolic schema"),
-        idents_to_assertions(&V1_IDENTS[..]),
-        schema_attrs_to_assertions(CORE_SCHEMA_VERSION, V1_CORE_SCHEMA.as_ref()),
-    ].concat());
+    bootstrap_entities_since(0)
+}
+
+/// The bootstrap assertions added after the given version of the :db.schema/core vocabulary, and
+/// the assertion of the current version.  Transacting these upgrades a store of that version.
+pub(crate) fn bootstrap_entities_since(version: u32) -> Vec<Entity<edn::ValueAndSpan>> {
+    let mut assertions = vec![];
+    for &(v, idents, core_schema, symbolic_schema) in bootstrap_versions().iter() {
+        if v > version {
+            assertions.extend(symbolic_schema_to_assertions(symbolic_schema).expect("symbolic schema"));
+            assertions.extend(idents_to_assertions(idents));
+            assertions.extend(schema_attrs_to_assertions(core_schema));
+        }
+    }
+    assertions.push(schema_version_to_assertion(CORE_SCHEMA_VERSION));
+    let bootstrap_assertions: Value = Value::Vector(assertions);
 
     // Failure here is a coding error (since the inputs are fixed), not a runtime error.
     // TODO: represent these bootstrap data errors rather than just panicing.
@@ -46,6 +46,7 @@ use mentat_core::{
     AttributeBitFlags,
     Entid,
     FromMicros,
+    HasSchema,
     IdentMap,
     Schema,
     AttributeMap,
@@ -53,6 +54,8 @@ use mentat_core::{
     ToMicros,
     ValueType,
     ValueRc,
+    tuple_from_bytes,
+    tuple_to_bytes,
 };
 
 use errors::{
@@ -147,7 +150,13 @@ where S: AsRef<str> {
 /// Version history:
 ///
 /// 1: initial Rust Mentat schema.
-pub const CURRENT_VERSION: i32 = 1;
+/// 2: core schema attributes `:db/excise`, `:db.excise/attrs`, and `:db.excise/before`.
+/// 3: `:db.partition/allowExcision`, for partitions installed with `:db.install/partition`.
+/// 4: `:db.type/tuple` and `:db/tupleAttrs`, for composite tuples.
+///
+/// Each version is also the version of the :db.schema/core vocabulary.  Stores of older versions
+/// are upgraded when they're opened: see `upgrade_from`.
+pub const CURRENT_VERSION: i32 = 4;
 
 /// MIN_SQLITE_VERSION should be changed when there's a new minimum version of sqlite required
 /// for the project to work.
@@ -319,6 +328,47 @@ pub fn create_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     Ok(db)
 }
 
+/// Upgrade a store of the given older version to the current version by transacting the bootstrap
+/// idents and core schema attributes added since, like `:db/tupleAttrs`.
+///
+/// The SQL schema hasn't changed, but the new idents have fixed entids in `:db.part/db`.  If the
+/// store has already allocated those entids, or uses the new idents for its own entities, the
+/// upgrade is refused rather than clobbering them.
+fn upgrade_from(conn: &mut rusqlite::Connection, version: i32) -> Result<DB> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+
+    let db = read_db(&tx)?;
+    let bootstrap_schema = bootstrap::bootstrap_schema();
+
+    for (ident, entid) in bootstrap::bootstrap_idents_since(version as u32) {
+        if db.schema.ident_map.contains_key(&ident) {
+            bail!(DbErrorKind::StoreUpgradeFailed(version, format!("ident {} is already in use", ident)));
+        }
+        if db.partition_map.contains_entid(entid) {
+            bail!(DbErrorKind::StoreUpgradeFailed(version, format!("entid {} is already in use", entid)));
+        }
+    }
+
+    // As for the initial bootstrap transaction, the bootstrap schema resolves the core idents the
+    // additions name.
+    let (_report, mut partition_map, _next_schema, _watcher) = transact(&tx, db.partition_map, &db.schema, &bootstrap_schema, NullWatcher(), bootstrap::bootstrap_entities_since(version as u32))?;
+
+    // The new idents have fixed entids, so nothing was allocated for them.
+    let next_entid = bootstrap::bootstrap_partition_map()[":db.part/db"].next_entid();
+    if let Some(partition) = partition_map.get_mut(":db.part/db") {
+        if partition.next_entid() < next_entid {
+            partition.set_next_entid(next_entid);
+        }
+    }
+    update_partition_map(&tx, &partition_map)?;
+
+    set_user_version(&tx, CURRENT_VERSION)?;
+
+    let db = read_db(&tx)?;
+    tx.commit()?;
+    Ok(db)
+}
+
 pub fn ensure_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     if rusqlite::version_number() < MIN_SQLITE_VERSION {
         panic!("Mentat requires at least sqlite {}", MIN_SQLITE_VERSION);
@@ -327,6 +377,7 @@ pub fn ensure_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     let user_version = get_user_version(&conn)?;
     match user_version {
         0               => create_current_version(conn),
+        v if v > 0 && v < CURRENT_VERSION => upgrade_from(conn, v),
         CURRENT_VERSION => read_db(conn),
 
         // TODO: support updating an existing store.
@@ -368,6 +419,12 @@ impl TypedSQLValue for TypedValue {
             (13, rusqlite::types::Value::Text(x)) => {
                 to_namespaced_keyword(&x).map(|k| k.into())
             },
+            (15, rusqlite::types::Value::Blob(x)) => {
+                match tuple_from_bytes(x.as_slice()) {
+                    Some(elements) => Ok(elements.into()),
+                    None => bail!(DbErrorKind::BadSQLValuePair(rusqlite::types::Value::Blob(x), value_type_tag)),
+                }
+            },
             (_, value) => bail!(DbErrorKind::BadSQLValuePair(value, value_type_tag)),
         }
     }
@@ -388,6 +445,10 @@ impl TypedSQLValue for TypedValue {
             &Value::Float(ref x) => Some(TypedValue::Double(x.clone())),
             &Value::Text(ref x) => Some(x.clone().into()),
             &Value::Keyword(ref x) => Some(x.clone().into()),
+            &Value::Vector(ref xs) => {
+                let elements: Option<Vec<TypedValue>> = xs.iter().map(TypedValue::from_edn_value).collect();
+                elements.map(|elements| elements.into())
+            },
             _ => None
         }
     }
@@ -404,6 +465,7 @@ impl TypedSQLValue for TypedValue {
             &TypedValue::String(ref x) => (rusqlite::types::ValueRef::Text(x.as_str()).into(), 10),
             &TypedValue::Uuid(ref u) => (rusqlite::types::Value::Blob(u.as_bytes().to_vec()).into(), 11),
             &TypedValue::Keyword(ref x) => (rusqlite::types::ValueRef::Text(&x.to_string()).into(), 13),
+            &TypedValue::Tuple(ref x) => (rusqlite::types::Value::Blob(tuple_to_bytes(x.as_slice())).into(), 15),
         }
     }
 
@@ -418,6 +480,7 @@ impl TypedSQLValue for TypedValue {
             &TypedValue::String(ref x) => (Value::Text(x.as_ref().clone()), ValueType::String),
             &TypedValue::Uuid(ref u) => (Value::Uuid(u.clone()), ValueType::Uuid),
             &TypedValue::Keyword(ref x) => (Value::Keyword(x.as_ref().clone()), ValueType::Keyword),
+            &TypedValue::Tuple(ref x) => (Value::Vector(x.iter().map(|v| v.to_edn_value_pair().0).collect()), ValueType::Tuple),
         }
     }
 }
@@ -505,6 +568,33 @@ pub trait MentatStoring {
     /// are exactly those (a, v) pairs that have an assertion [e a v] in the store.
     fn resolve_avs<'a>(&self, avs: &'a [&'a AVPair]) -> Result<AVMap<'a>>;
 
+    /// Look up the value `v` of the datom `[e a v]` in the store, if there is one.
+    ///
+    /// It is assumed that the attribute `a` is `:db.cardinality/one`, so that at most one such
+    /// datom exists.
+    fn resolve_ea(&self, e: Entid, a: Entid) -> Result<Option<TypedValue>>;
+
+    /// Look up the values `v` of the datoms `[e a v]` in the store, for each of the entities `es`
+    /// and each of the `attributes`.
+    ///
+    /// Like `resolve_ea`, it is assumed that the attributes are `:db.cardinality/one`.  Returns a
+    /// map (e, a) -> v whose keys are exactly those (e, a) pairs that have a datom in the store.
+    fn resolve_eas(&self, es: &[Entid], attributes: &[Entid]) -> Result<HashMap<(Entid, Entid), TypedValue>>;
+
+    /// Look up all `[a v]` pairs of the datoms `[e a v]` in the store.
+    fn resolve_e(&self, e: Entid) -> Result<Vec<(Entid, TypedValue)>>;
+
+    /// Look up all values `v` of the datoms `[e a v]` in the store.
+    fn resolve_ea_values(&self, e: Entid, a: Entid) -> Result<Vec<TypedValue>>;
+
+    /// Look up all `[e a]` pairs of the datoms `[e a v]` in the store that refer to `v`, i.e., for
+    /// which `a` is `:db.type/ref`.
+    fn resolve_v_refs(&self, v: Entid) -> Result<Vec<(Entid, Entid)>>;
+
+    /// Look up the `:db/txInstant` of the latest transaction in the store, not counting the
+    /// bootstrap transaction.
+    fn resolve_latest_tx_instant(&self) -> Result<Option<DateTime<Utc>>>;
+
     /// Begin (or prepare) the underlying storage layer for a new Mentat transaction.
     ///
     /// Use this to create temporary tables, prepare indices, set pragmas, etc, before the initial
@@ -521,6 +611,14 @@ pub trait MentatStoring {
     /// final `insert_non_fts_searches` invocation.
     fn commit_transaction(&self, tx_id: Entid) -> Result<()>;
 
+    /// Write the given assertions directly to the store as the transaction `tx_id`, without
+    /// searching for existing datoms.
+    ///
+    /// This is only correct for assertions about entities that have no datoms in the store, like
+    /// those allocated by the transaction itself, and for attributes that aren't `:db/fulltext`.
+    /// It needs no temporary tables, so `begin_tx_application` need not be called first.
+    fn insert_fresh_datoms<'a>(&self, entities: &'a [ReducedEntity], tx_id: Entid) -> Result<()>;
+
     /// Extract metadata-related [e a typed_value added] datoms committed in the given transaction.
     fn committed_metadata_assertions(&self, tx_id: Entid) -> Result<Vec<(Entid, Entid, TypedValue, bool)>>;
 }
@@ -589,6 +687,42 @@ fn insert_transaction(conn: &rusqlite::Connection, tx: Entid) -> Result<()> {
     Ok(())
 }
 
+/// Prune the history of `:db/noHistory` attributes from the `transactions` table.
+///
+/// For such attributes, the transaction log only records assertions of datoms in the store's
+/// current state.  That means dropping the new transaction's retractions, and the earlier
+/// assertions of the datoms the new transaction retracts or replaces.  This must happen after
+/// `insert_transaction` but before `update_datoms`, while `datoms` still knows the transactions
+/// that asserted the replaced datoms.
+fn prune_no_history(conn: &rusqlite::Connection, tx: Entid) -> Result<()> {
+    let s = format!(r#"
+      DELETE FROM transactions
+      WHERE rowid IN (SELECT t.rowid
+                      FROM temp.search_results AS s, datoms AS d, transactions AS t
+                      WHERE s.flags0 & {} IS NOT 0 AND
+                            s.rid IS NOT NULL AND
+                            ((s.added0 IS 0) OR
+                             (s.added0 IS 1 AND s.search_type IS ':db.cardinality/one' AND s.v0 IS NOT s.v)) AND
+                            d.rowid = s.rid AND
+                            t.tx = d.tx AND t.added = 1 AND
+                            t.e = d.e AND t.a = d.a AND t.value_type_tag = d.value_type_tag AND t.v = d.v)"#,
+      AttributeBitFlags::NoHistory as u8);
+
+    let mut stmt = conn.prepare_cached(&s)?;
+    stmt.execute(&[]).context(DbErrorKind::TxPruneNoHistoryFailed)?;
+
+    let s = format!(r#"
+      DELETE FROM transactions
+      WHERE tx = ? AND added = 0 AND
+            a IN (SELECT a0 FROM temp.search_results WHERE flags0 & {} IS NOT 0)"#,
+      AttributeBitFlags::NoHistory as u8);
+
+    let mut stmt = conn.prepare_cached(&s)?;
+    stmt.execute(&[&tx]).context(DbErrorKind::TxPruneNoHistoryFailed)?;
+
+    Ok(())
+}
+
 /// Update the contents of the `datoms` materialized view with the new transaction.
 ///
 /// This applies the contents of `search_results` to the `datoms` table (in place).
@@ -948,10 +1082,76 @@ impl MentatStoring for rusqlite::Connection {
     fn commit_transaction(&self, tx_id: Entid) -> Result<()> {
         search(&self)?;
         insert_transaction(&self, tx_id)?;
+        prune_no_history(&self, tx_id)?;
         update_datoms(&self, tx_id)?;
         Ok(())
     }
 
+    fn insert_fresh_datoms<'a>(&self, entities: &'a [ReducedEntity<'a>], tx_id: Entid) -> Result<()> {
+        // `datoms` takes the most bindings per row, so it determines the chunk size.
+        let bindings_per_statement = 8;
+
+        let max_vars = self.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+        let chunks: itertools::IntoChunks<_> = entities.into_iter().chunks(max_vars / bindings_per_statement);
+
+        // We'd like to flat_map here, but it's not obvious how to flat_map across Result.
+        let results: Result<Vec<()>> = chunks.into_iter().map(|chunk| -> Result<()> {
+            // We must keep these computed values somewhere to reference them later, so we can't
+            // combine this map and the subsequent flat_maps.
+            let block: Vec<(i64 /* e */,
+                            i64 /* a */,
+                            ToSqlOutput<'a> /* value */,
+                            i32 /* value_type_tag */,
+                            bool /* index_avet */,
+                            bool /* index_vaet */,
+                            bool /* unique_value */)> = chunk.map(|&(e, a, ref attribute, ref typed_value, _added)| {
+                let (value, value_type_tag): (ToSqlOutput, i32) = typed_value.to_sql_value_pair();
+                let flags = attribute.flags();
+                (e, a, value, value_type_tag,
+                 flags & AttributeBitFlags::IndexAVET as u8 != 0,
+                 flags & AttributeBitFlags::IndexVAET as u8 != 0,
+                 flags & AttributeBitFlags::UniqueValue as u8 != 0)
+            }).collect();
+            let count = block.len();
+
+            // `params` reference computed values in `block`.
+            let params: Vec<&ToSql> = block.iter().flat_map(|&(ref e, ref a, ref value, ref value_type_tag, ref index_avet, ref index_vaet, ref unique_value)| {
+                once(e as &ToSql)
+                    .chain(once(a as &ToSql))
+                    .chain(once(value as &ToSql))
+                    .chain(once(&tx_id as &ToSql))
+                    .chain(once(value_type_tag as &ToSql))
+                    .chain(once(index_avet as &ToSql))
+                    .chain(once(index_vaet as &ToSql))
+                    .chain(once(unique_value as &ToSql))
+            }).collect();
+
+            if bindings_per_statement * count > max_vars {
+                bail!(DbErrorKind::TooManySQLVariables(bindings_per_statement * count, max_vars));
+            }
+            let s: String = format!("INSERT INTO datoms (e, a, v, tx, value_type_tag, index_avet, index_vaet, unique_value) VALUES {}",
+                                    repeat_values(bindings_per_statement, count));
+            let mut stmt = self.prepare_cached(s.as_str())?;
+            stmt.execute(&params).context(DbErrorKind::DatomsUpdateFailedToAdd)?;
+
+            let params: Vec<&ToSql> = block.iter().flat_map(|&(ref e, ref a, ref value, ref value_type_tag, _, _, _)| {
+                once(e as &ToSql)
+                    .chain(once(a as &ToSql))
+                    .chain(once(value as &ToSql))
+                    .chain(once(&tx_id as &ToSql))
+                    .chain(once(value_type_tag as &ToSql))
+            }).collect();
+
+            let s: String = format!("INSERT INTO transactions (e, a, v, tx, value_type_tag) VALUES {}",
+                                    repeat_values(5, count));
+            let mut stmt = self.prepare_cached(s.as_str())?;
+            stmt.execute(&params).context(DbErrorKind::TxInsertFailedToAddMissingDatoms)?;
+            Ok(())
+        }).collect::<Result<Vec<()>>>();
+
+        results.map(|_| ())
+    }
+
     fn committed_metadata_assertions(&self, tx_id: Entid) -> Result<Vec<(Entid, Entid, TypedValue, bool)>> {
         // TODO: use concat! to avoid creating String instances.
         let mut stmt = self.prepare_cached(format!("SELECT e, a, v, value_type_tag, added FROM transactions WHERE tx = ? AND a IN {} ORDER BY e, a, v, value_type_tag, added", entids::METADATA_SQL_LIST.as_str()).as_str())?;
@@ -964,6 +1164,90 @@ impl MentatStoring for rusqlite::Connection {
         })?.collect();
         m
     }
+
+    fn resolve_ea(&self, e: Entid, a: Entid) -> Result<Option<TypedValue>> {
+        let mut stmt = self.prepare_cached("SELECT v, value_type_tag FROM all_datoms WHERE e = ? AND a = ? LIMIT 1")?;
+        let params = [&e as &ToSql, &a as &ToSql];
+        let mut rows = stmt.query_and_then(&params[..], |row| -> Result<TypedValue> {
+            TypedValue::from_sql_value_pair(row.get_checked(0)?, row.get_checked(1)?)
+        })?;
+        let value = match rows.next() {
+            Some(value) => Some(value?),
+            None => None,
+        };
+        Ok(value)
+    }
+
+    fn resolve_eas(&self, es: &[Entid], attributes: &[Entid]) -> Result<HashMap<(Entid, Entid), TypedValue>> {
+        let mut values = HashMap::new();
+        if es.is_empty() || attributes.is_empty() {
+            return Ok(values);
+        }
+
+        let max_vars = self.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+        assert!(attributes.len() < max_vars, "Too many attributes: {} >= {}", attributes.len(), max_vars);
+        for chunk in es.chunks(max_vars - attributes.len()) {
+            let s = format!("SELECT e, a, v, value_type_tag FROM all_datoms WHERE a IN ({}) AND e IN ({})",
+                            repeat("?").take(attributes.len()).join(", "),
+                            repeat("?").take(chunk.len()).join(", "));
+            let mut stmt = self.prepare(s.as_str())?;
+            let params: Vec<&ToSql> = attributes.iter().chain(chunk.iter()).map(|x| x as &ToSql).collect();
+            let rows = stmt.query_and_then(&params, |row| -> Result<((Entid, Entid), TypedValue)> {
+                let v = TypedValue::from_sql_value_pair(row.get_checked(2)?, row.get_checked(3)?)?;
+                Ok(((row.get_checked(0)?, row.get_checked(1)?), v))
+            })?;
+            for row in rows {
+                let (ea, v) = row?;
+                values.insert(ea, v);
+            }
+        }
+        Ok(values)
+    }
+
+    fn resolve_e(&self, e: Entid) -> Result<Vec<(Entid, TypedValue)>> {
+        let mut stmt = self.prepare_cached("SELECT a, v, value_type_tag FROM all_datoms WHERE e = ?")?;
+        let avs: Result<Vec<(Entid, TypedValue)>> = stmt.query_and_then(&[&e], |row| -> Result<(Entid, TypedValue)> {
+            let a: Entid = row.get_checked(0)?;
+            let v = TypedValue::from_sql_value_pair(row.get_checked(1)?, row.get_checked(2)?)?;
+            Ok((a, v))
+        })?.collect();
+        avs
+    }
+
+    fn resolve_ea_values(&self, e: Entid, a: Entid) -> Result<Vec<TypedValue>> {
+        let mut stmt = self.prepare_cached("SELECT v, value_type_tag FROM all_datoms WHERE e = ? AND a = ?")?;
+        let params = [&e as &ToSql, &a as &ToSql];
+        let vs: Result<Vec<TypedValue>> = stmt.query_and_then(&params[..], |row| -> Result<TypedValue> {
+            TypedValue::from_sql_value_pair(row.get_checked(0)?, row.get_checked(1)?)
+        })?.collect();
+        vs
+    }
+
+    fn resolve_v_refs(&self, v: Entid) -> Result<Vec<(Entid, Entid)>> {
+        let mut stmt = self.prepare_cached("SELECT e, a FROM datoms WHERE v = ? AND value_type_tag = ?")?;
+        let tag = ValueType::Ref.value_type_tag();
+        let params = [&v as &ToSql, &tag as &ToSql];
+        let eas: Result<Vec<(Entid, Entid)>> = stmt.query_and_then(&params[..], |row| -> Result<(Entid, Entid)> {
+            Ok((row.get_checked(0)?, row.get_checked(1)?))
+        })?.collect();
+        eas
+    }
+
+    fn resolve_latest_tx_instant(&self) -> Result<Option<DateTime<Utc>>> {
+        let mut stmt = self.prepare_cached("SELECT v, value_type_tag FROM datoms WHERE a = ? AND e > ? ORDER BY e DESC LIMIT 1")?;
+        let params = [&entids::DB_TX_INSTANT as &ToSql, &bootstrap::TX0 as &ToSql];
+        let mut rows = stmt.query_and_then(&params[..], |row| -> Result<TypedValue> {
+            TypedValue::from_sql_value_pair(row.get_checked(0)?, row.get_checked(1)?)
+        })?;
+        let instant = match rows.next() {
+            Some(value) => match value? {
+                TypedValue::Instant(instant) => Some(instant),
+                _ => None,
+            },
+            None => None,
+        };
+        Ok(instant)
+    }
 }
 
 /// Update the current partition map materialized view.
@@ -973,7 +1257,7 @@ pub fn update_partition_map(conn: &rusqlite::Connection, partition_map: &Partiti
     let max_vars = conn.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
     let max_partitions = max_vars / values_per_statement;
     if partition_map.len() > max_partitions {
-        bail!(DbErrorKind::NotYetImplemented(format!("No more than {} partitions are supported", max_partitions)));
+        bail!(DbErrorKind::TooManyPartitions(max_partitions));
     }
 
     // Like "UPDATE parts SET idx = CASE WHEN part = ? THEN ? WHEN part = ? THEN ? ELSE idx END".
@@ -995,6 +1279,71 @@ pub fn update_partition_map(conn: &rusqlite::Connection, partition_map: &Partiti
     Ok(())
 }
 
+/// Install the partitions asserted by `[:db.part/db :db.install/partition e]` in the given
+/// transaction, naming each by the `:db/ident` of `e`.
+///
+/// Each new partition takes the last `PARTITION_SIZE` entids of `:db.part/user`, so the entids
+/// already allocated there are unaffected.  A new partition allows excision only if `e` has
+/// `:db.partition/allowExcision true`.
+pub(crate) fn install_partitions(conn: &rusqlite::Connection, partition_map: &mut PartitionMap, schema: &Schema, tx_id: Entid) -> Result<()> {
+    let installations: Vec<(Entid, TypedValue, bool)> = {
+        let mut stmt = conn.prepare_cached("SELECT e, v, value_type_tag, added FROM transactions WHERE tx = ? AND a = ? ORDER BY v")?;
+        let params = [&tx_id as &ToSql, &entids::DB_INSTALL_PARTITION as &ToSql];
+        let m: Result<Vec<_>> = stmt.query_and_then(&params[..], |row| -> Result<(Entid, TypedValue, bool)> {
+            Ok((row.get_checked(0)?,
+                TypedValue::from_sql_value_pair(row.get_checked(1)?, row.get_checked(2)?)?,
+                row.get_checked(3)?))
+        })?.collect();
+        m?
+    };
+
+    for (e, v, added) in installations {
+        if !added {
+            bail!(DbErrorKind::BadSchemaAssertion(format!("Retracting [{} :db.install/partition {:?}] is not supported", e, v)));
+        }
+        if e != entids::DB_PART_DB {
+            bail!(DbErrorKind::BadSchemaAssertion(format!("Expected [:db.part/db :db.install/partition ...] but got [{} :db.install/partition {:?}]", e, v)));
+        }
+        let partition = match v {
+            TypedValue::Ref(partition) => partition,
+            v => bail!(DbErrorKind::BadSchemaAssertion(format!("Expected [:db.part/db :db.install/partition PARTITION] but got [:db.part/db :db.install/partition {:?}]", v))),
+        };
+        let name = match schema.get_ident(partition) {
+            Some(ident) => ident.to_string(),
+            None => bail!(DbErrorKind::BadSchemaAssertion(format!("Partition {} installed without a :db/ident", partition))),
+        };
+        if partition_map.contains_key(&name) {
+            bail!(DbErrorKind::BadSchemaAssertion(format!("Partition {} is already installed", name)));
+        }
+        let allow_excision = match conn.resolve_ea(partition, entids::DB_PARTITION_ALLOW_EXCISION)? {
+            Some(TypedValue::Boolean(allow_excision)) => allow_excision,
+            _ => false,
+        };
+
+        let (start, end) = {
+            let user = match partition_map.get_mut(":db.part/user") {
+                Some(user) => user,
+                None => bail!(DbErrorKind::UnknownPartition(":db.part/user".into())),
+            };
+            let start = user.end - bootstrap::PARTITION_SIZE + 1;
+            if start <= user.next_entid() {
+                bail!(DbErrorKind::BadSchemaAssertion(format!("No room in :db.part/user to install partition {}", name)));
+            }
+            let end = user.end;
+            user.end = start - 1;
+            (start, end)
+        };
+
+        conn.execute("UPDATE parts SET end = ? WHERE part = ':db.part/user'", &[&(start - 1) as &ToSql])
+            .context(DbErrorKind::FailedToUpdatePartitionMap)?;
+        conn.execute("INSERT INTO parts (part, start, end, idx, allow_excision) VALUES (?, ?, ?, ?, ?)",
+                     &[&name as &ToSql, &start as &ToSql, &end as &ToSql, &start as &ToSql, &allow_excision as &ToSql])
+            .context(DbErrorKind::FailedToUpdatePartitionMap)?;
+        partition_map.insert(name, Partition::new(start, end, start, allow_excision));
+    }
+    Ok(())
+}
+
 /// Update the metadata materialized views based on the given metadata report.
 ///
 /// This updates the "entids", "idents", and "schema" materialized views, copying directly from the
@@ -1025,14 +1374,38 @@ pub fn update_metadata(conn: &rusqlite::Connection, _old_schema: &Schema, new_sc
     let mut insert_stmt = conn.prepare(format!("INSERT INTO schema SELECT e, a, v, value_type_tag FROM datoms WHERE e = ? AND a IN {}", entids::SCHEMA_SQL_LIST.as_str()).as_str())?;
     let mut index_stmt = conn.prepare("UPDATE datoms SET index_avet = ? WHERE a = ?")?;
     let mut unique_value_stmt = conn.prepare("UPDATE datoms SET unique_value = ? WHERE a = ?")?;
+    // Grouping, rather than joining `datoms` to itself, keeps these checks linear in the number of
+    // the attribute's datoms.
     let mut cardinality_stmt = conn.prepare(r#"
-SELECT EXISTS
-    (SELECT 1
-        FROM datoms AS left, datoms AS right
-        WHERE left.a = ? AND
-        left.a = right.a AND
-        left.e = right.e AND
-        left.v <> right.v)"#)?;
+SELECT e
+    FROM datoms
+    WHERE a = ?
+    GROUP BY e
+    HAVING COUNT(*) > 1
+    ORDER BY e"#)?;
+    let mut unique_stmt = conn.prepare(r#"
+SELECT DISTINCT e
+    FROM datoms
+    WHERE a = ? AND
+    v IN (SELECT v
+          FROM datoms
+          WHERE a = ?
+          GROUP BY v
+          HAVING COUNT(DISTINCT e) > 1)
+    ORDER BY e"#)?;
+    let mut has_values_stmt = conn.prepare("SELECT DISTINCT e FROM datoms WHERE a = ? ORDER BY e")?;
+    // Once an attribute is :db/noHistory, the log only keeps assertions of its current datoms.
+    let mut no_history_stmt = conn.prepare(r#"
+DELETE FROM transactions
+    WHERE a = ? AND
+    (added = 0 OR
+     NOT EXISTS (SELECT 1
+                 FROM datoms AS d
+                 WHERE d.e = transactions.e AND
+                 d.a = transactions.a AND
+                 d.value_type_tag = transactions.value_type_tag AND
+                 d.v = transactions.v AND
+                 d.tx = transactions.tx))"#)?;
 
     for (&entid, alterations) in &metadata_report.attributes_altered {
         delete_stmt.execute(&[&entid as &ToSql])?;
@@ -1047,31 +1420,58 @@ SELECT EXISTS
                     index_stmt.execute(&[&attribute.index, &entid as &ToSql])?;
                 },
                 &Unique => {
-                    // TODO: This can fail if there are conflicting values; give a more helpful
-                    // error message in this case.
-                    if unique_value_stmt.execute(&[to_bool_ref(attribute.unique.is_some()), &entid as &ToSql]).is_err() {
-                        match attribute.unique {
-                            Some(attribute::Unique::Value) => bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be :db.unique/value", entid))),
-                            Some(attribute::Unique::Identity) => bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be :db.unique/identity", entid))),
-                            None => unreachable!(), // This shouldn't happen, even after we support removing :db/unique.
+                    // We can always drop :db/unique.  Adding it fails if distinct entities share a
+                    // value, so we find them before SQLite's unique index does.
+                    if let Some(ref unique) = attribute.unique {
+                        let entities: rusqlite::Result<Vec<Entid>> = unique_stmt.query_map(&[&entid as &ToSql, &entid as &ToSql], |row| row.get(0))?.collect();
+                        let entities = entities?;
+                        if !entities.is_empty() {
+                            bail!(DbErrorKind::SchemaAlterationFailed {
+                                attribute: entid,
+                                alteration: match *unique {
+                                    attribute::Unique::Value => ":db.unique/value",
+                                    attribute::Unique::Identity => ":db.unique/identity",
+                                }.to_string(),
+                                entities,
+                            });
                         }
                     }
+                    unique_value_stmt.execute(&[to_bool_ref(attribute.unique.is_some()), &entid as &ToSql])?;
                 },
                 &Cardinality => {
                     // We can always go from :db.cardinality/one to :db.cardinality many.  It's
                     // :db.cardinality/many to :db.cardinality/one that can fail.
-                    //
-                    // TODO: improve the failure message.  Perhaps try to mimic what Datomic says in
-                    // this case?
                     if !attribute.multival {
-                        let mut rows = cardinality_stmt.query(&[&entid as &ToSql])?;
-                        if rows.next().is_some() {
-                            bail!(DbErrorKind::SchemaAlterationFailed(format!("Cannot alter schema attribute {} to be :db.cardinality/one", entid)));
+                        let entities: rusqlite::Result<Vec<Entid>> = cardinality_stmt.query_map(&[&entid as &ToSql], |row| row.get(0))?.collect();
+                        let entities = entities?;
+                        if !entities.is_empty() {
+                            bail!(DbErrorKind::SchemaAlterationFailed {
+                                attribute: entid,
+                                alteration: ":db.cardinality/one".to_string(),
+                                entities,
+                            });
                         }
                     }
                 },
-                &NoHistory | &IsComponent => {
-                    // There's no on disk change required for either of these.
+                &NoHistory => {
+                    if attribute.no_history {
+                        no_history_stmt.execute(&[&entid as &ToSql])?;
+                    }
+                },
+                &IsComponent => {
+                    // There's no on disk change required for this.
+                },
+                &metadata::AttributeAlteration::ValueType => {
+                    // Values of the old type must have been retracted before the type changes.
+                    let entities: rusqlite::Result<Vec<Entid>> = has_values_stmt.query_map(&[&entid as &ToSql], |row| row.get(0))?.collect();
+                    let entities = entities?;
+                    if !entities.is_empty() {
+                        bail!(DbErrorKind::SchemaAlterationFailed {
+                            attribute: entid,
+                            alteration: attribute.value_type.to_string(),
+                            entities,
+                        });
+                    }
                 },
             }
         }
@@ -1114,7 +1514,12 @@ mod tests {
         InternSet,
     };
     use edn::entities::{
+        AttributePlace,
+        Entity,
+        EntityPlace,
         OpType,
+        TxFunction,
+        ValuePlace,
     };
     use mentat_core::{
         HasSchema,
@@ -1125,11 +1530,21 @@ mod tests {
     use mentat_core::util::Either::*;
     use std::collections::{
         BTreeMap,
+        BTreeSet,
     };
     use errors;
     use internal_types::{
         Term,
     };
+    use excision::{
+        Excision,
+    };
+    use explain::{
+        TempIdResolution,
+    };
+    use tx_functions::{
+        TxFunctionOutput,
+    };
 
     fn run_test_add(mut conn: TestConn) {
         // Test inserting :db.cardinality/one elements.
@@ -1251,7 +1666,7 @@ mod tests {
         // Test that we type-check properly.  In the value position, (transaction-tx) yields a ref;
         // :db/ident expects a keyword.
         assert_transact!(conn, "[[:db/add 444 :db/ident (transaction-tx)]]",
-                         Err("not yet implemented: Transaction function transaction-tx produced value of type :db.type/ref but expected type :db.type/keyword"));
+                         Err("transaction function :transaction-tx produced value of type :db.type/ref but expected type :db.type/keyword"));
 
         // Test that we can assert metadata about the current transaction.
         assert_transact!(conn, "[[:db/add (transaction-tx) :test/ref (transaction-tx)]]");
@@ -1260,6 +1675,40 @@ mod tests {
                           [?tx :test/ref ?tx ?tx true]]");
     }
 
+    #[test]
+    fn test_tx_instant_monotonic() {
+        let mut conn = TestConn::default();
+
+        // Historical data can be imported with backdated instants, in order.
+        assert_transact!(conn, "[[:db/add (transaction-tx) :db/txInstant #inst \"2017-06-16T00:56:41.257Z\"]
+                                 [:db/add 100 :db.schema/version 1]]");
+        assert_transact!(conn, "[[:db/add (transaction-tx) :db/txInstant #inst \"2017-06-16T00:56:41.257Z\"]
+                                 [:db/add 100 :db.schema/version 2]]");
+        assert_transact!(conn, "[[:db/add (transaction-tx) :db/txInstant #inst \"2017-06-17T00:00:00.000Z\"]
+                                 [:db/add 100 :db.schema/version 3]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :db.schema/version 2 ?tx false]
+                          [100 :db.schema/version 3 ?tx true]
+                          [?tx :db/txInstant #inst \"2017-06-17T00:00:00.000Z\" ?tx true]]");
+
+        // But not out of order, nor in the future.
+        assert_transact!(conn, "[[:db/add (transaction-tx) :db/txInstant #inst \"2017-06-16T00:56:41.257Z\"]
+                                 [:db/add 100 :db.schema/version 4]]",
+                         Err("transaction instant 2017-06-16 00:56:41.257 UTC is before the latest transaction instant 2017-06-17 00:00:00 UTC"));
+        assert_transact!(conn, "[[:db/add (transaction-tx) :db/txInstant #inst \"2999-01-01T00:00:00.000Z\"]
+                                 [:db/add 100 :db.schema/version 4]]",
+                         Err("transaction instant 2999-01-01 00:00:00 UTC is in the future"));
+
+        // Once the store has caught up with the present, the past is closed.
+        assert_transact!(conn, "[[:db/add 100 :db.schema/version 4]]");
+        let report = conn.transact("[[:db/add (transaction-tx) :db/txInstant #inst \"2017-06-18T00:00:00.000Z\"]
+                                     [:db/add 100 :db.schema/version 5]]");
+        match report.err().map(|e| e.kind()) {
+            Some(DbErrorKind::NonMonotonicTxInstant { .. }) => {},
+            x => panic!("expected NonMonotonicTxInstant error, got {:?}", x),
+        }
+    }
+
     #[test]
     fn test_retract() {
         let mut conn = TestConn::default();
@@ -1424,9 +1873,13 @@ mod tests {
                                  [:db/add \"t1\" :db/ident :name/Petr]]",
                          Err("schema constraint violation: conflicting upserts:\n  tempid External(\"t1\") upserts to {KnownEntid(100), KnownEntid(101)}\n  tempid External(\"t2\") upserts to {KnownEntid(100), KnownEntid(101)}\n"));
 
-        // tempids in :db/retract that don't upsert fail.
-        assert_transact!(conn, "[[:db/retract \"t1\" :db/ident :name/Anonymous]]",
-                         Err("not yet implemented: [:db/retract ...] entity referenced tempid that did not upsert: t1"));
+        // tempids in :db/retract that don't upsert name new entities, so there's nothing to retract.
+        let report = assert_transact!(conn, "[[:db/retract \"t1\" :db/ident :name/Anonymous]
+                                              [:db/retract 100 :db.schema/attribute \"t2\"]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :db/txInstant ?ms ?tx true]]");
+        assert_matches!(tempids(&report),
+                        "{}");
 
         // tempids in :db/retract that do upsert are retracted.  The ref given doesn't exist, so the
         // assertion will be ignored.
@@ -1697,8 +2150,21 @@ mod tests {
 
         // We can't always go from :db.cardinality/many to :db.cardinality/one.
         assert_transact!(conn, "[[:db/add 100 :db/cardinality :db.cardinality/one]]",
-                         // TODO: give more helpful error details.
-                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.cardinality/one"));
+                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.cardinality/one: violated by entities [200]"));
+
+        // The error says which entities are in the way.
+        assert_transact!(conn, "[[:db/add 201 :test/ident 1]]");
+        let report = conn.transact("[[:db/add 100 :db/cardinality :db.cardinality/one]]");
+        assert_eq!(report.err().map(|e| e.kind()),
+                   Some(DbErrorKind::SchemaAlterationFailed {
+                       attribute: 100,
+                       alteration: ":db.cardinality/one".to_string(),
+                       entities: vec![200],
+                   }));
+
+        // Once the offending values are gone, the alteration succeeds.
+        assert_transact!(conn, "[[:db/retract 200 :test/ident 1]]");
+        assert_transact!(conn, "[[:db/add 100 :db/cardinality :db.cardinality/one]]");
     }
 
     #[test]
@@ -1715,13 +2181,22 @@ mod tests {
 
         // We can't always migrate to be :db.unique/value.
         assert_transact!(conn, "[[:db/add :test/ident :db/unique :db.unique/value]]",
-                         // TODO: give more helpful error details.
-                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.unique/value"));
+                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.unique/value: violated by entities [200, 201]"));
 
         // Not even indirectly!
         assert_transact!(conn, "[[:db/add :test/ident :db/unique :db.unique/identity]]",
-                         // TODO: give more helpful error details.
-                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.unique/identity"));
+                         Err("schema alteration failed: Cannot alter schema attribute 100 to be :db.unique/identity: violated by entities [200, 201]"));
+
+        // Entities that don't share a value aren't in the way.
+        assert_transact!(conn, "[[:db/add 202 :test/ident 3]]");
+        let report = conn.transact("[[:db/add :test/ident :db/unique :db.unique/identity]]");
+        assert_eq!(report.err().map(|e| e.kind()),
+                   Some(DbErrorKind::SchemaAlterationFailed {
+                       attribute: 100,
+                       alteration: ":db.unique/identity".to_string(),
+                       entities: vec![200, 201],
+                   }));
+        assert_transact!(conn, "[[:db/retract 202 :test/ident 3]]");
 
         // But we can if we make sure there's no repeated [a v] pair.
         assert_transact!(conn, "[[:db/add 201 :test/ident 2]]");
@@ -2162,6 +2637,28 @@ mod tests {
         // Verify that we can explode map notation with nested maps, even if the inner map would be
         // dangling, if we give a :db/id explicitly.
         assert_transact!(conn, "[{:test/dangling {:db/id \"t\" :test/many 12}}]");
+
+        // Check that we can retract nested maps, which are identified by a :db/unique
+        // :db.unique/identity attribute if they have no :db/id.
+        assert_transact!(conn, "[{:db/id 600 :test/dangling {:test/unique 20 :test/many 21}}]");
+        assert_transact!(conn, "[[:db/retract 600 :test/dangling {:test/unique 20 :test/many 21}]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[600 :test/dangling ?f ?tx false]
+                          [?f :test/many 21 ?tx false]
+                          [?f :test/unique 20 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Check that we refuse to guess which entity a retracted nested map names when it has more
+        // than one :db.unique/identity attribute, since they might name different entities.
+        assert_transact!(conn, "[[:db/add 555 :db/ident :test/other_unique]
+                                 [:db/add 555 :db/unique :db.unique/identity]
+                                 [:db/add 555 :db/index true]
+                                 [:db/add 555 :db/valueType :db.type/long]]");
+        assert_transact!(conn, "[{:db/id 601 :test/dangling {:test/unique 30}}
+                                 {:db/id 602 :test/dangling {:test/other_unique 31}}]");
+        assert_transact!(conn,
+                         "[[:db/retract 601 :test/dangling {:test/unique 30 :test/other_unique 31}]]",
+                         Err("cannot retract nested map value of attribute 444: without :db/id, it must have only one :db.unique/identity attribute"));
     }
 
     #[test]
@@ -2267,6 +2764,22 @@ mod tests {
         assert_matches!(tempids(&report),
                         "{}");
 
+        // Check that we can explode nested vectors and maps in reversed notation.  A nested map
+        // refers to the enclosing entity, so it isn't dangling.
+        let report = assert_transact!(conn, "[{:db/id 700 :test/_dangling [701 {:test/many 13}]}]");
+        assert_matches!(conn.last_transaction(),
+                        "[[701 :test/dangling 700 ?tx true]
+                          [?e :test/many 13 ?tx true]
+                          [?e :test/dangling 700 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+        assert_matches!(tempids(&report),
+                        "{}");
+
+        // Check that retractions in reversed notation retract.
+        assert_transact!(conn, "[[:db/retract 700 :test/_dangling 701]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[701 :test/dangling 700 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
     }
 
     #[test]
@@ -2291,15 +2804,11 @@ mod tests {
         // nested value vectors, so we only test things that "get through" to the map notation
         // dynamic processor here.
 
-        // Verify that we can't explode reverse notation in map notation with nested value maps.
+        // Verify that we can't retract nested value maps in reverse notation that don't identify an
+        // existing entity.
         assert_transact!(conn,
-                         "[{:test/_dangling {:test/many 14}}]",
-                         Err("not yet implemented: Cannot explode map notation value in :attr/_reversed notation for attribute 444"));
-
-        // Verify that we can't explode reverse notation in map notation with nested value vectors.
-        assert_transact!(conn,
-                         "[{:test/_dangling [:test/many]}]",
-                         Err("not yet implemented: Cannot explode vector value in :attr/_reversed notation for attribute 444"));
+                         "[[:db/retract 500 :test/_dangling {:test/many 14}]]",
+                         Err("cannot retract nested map value of attribute 444: it has neither :db/id nor a :db.unique/identity attribute"));
 
         // Verify that we can't use reverse notation with non-:db.type/ref attributes.
         assert_transact!(conn,
@@ -2570,6 +3079,832 @@ mod tests {
         Err("schema constraint violation: cardinality conflicts:\n  AddRetractConflict { e: 100, a: 200, vs: {Long(7)} }\n  AddRetractConflict { e: 100, a: 201, vs: {Long(8)} }\n"));
     }
 
+    #[test]
+    fn test_cas() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, r#"[
+            {:db/id 200 :db/ident :test/one :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            {:db/id 201 :db/ident :test/many :db/valueType :db.type/long :db/cardinality :db.cardinality/many}
+            {:db/id 202 :db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+        ]"#);
+
+        // No value: `nil` matches, anything else doesn't.
+        assert_transact!(conn, "[[:db/cas 100 :test/one 1 2]]",
+                         Err("compare-and-swap failed for [100 200]: expected Some(Long(1)) but found None"));
+        assert_transact!(conn, "[[:db/cas 100 :test/one nil 1]
+                                 [:db/add 100 :test/name \"Ivan\"]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :test/one 1 ?tx true]
+                          [100 :test/name \"Ivan\" ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // A matching value swaps.
+        assert_transact!(conn, "[[:db/cas 100 :test/one 1 2]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :test/one 1 ?tx false]
+                          [100 :test/one 2 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // A stale value fails the whole transaction.
+        assert_transact!(conn, "[[:db/add 101 :test/one 10]
+                                 [:db/cas 100 :test/one 1 3]]",
+                         Err("compare-and-swap failed for [100 200]: expected Some(Long(1)) but found Some(Long(2))"));
+        assert_transact!(conn, "[[:db/cas 100 :test/one nil 3]]",
+                         Err("compare-and-swap failed for [100 200]: expected None but found Some(Long(2))"));
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :test/one 1 ?tx false]
+                          [100 :test/one 2 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Lookup refs name the entity.
+        assert_transact!(conn, "[[:db/cas (lookup-ref :test/name \"Ivan\") :test/one 2 3]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :test/one 2 ?tx false]
+                          [100 :test/one 3 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Only existing entities and cardinality one attributes can be swapped.
+        assert_transact!(conn, "[[:db/cas \"t\" :test/one nil 1]]",
+                         Err("cannot :db/cas attribute 200 of a tempid"));
+        assert_transact!(conn, "[[:db/cas 100 :test/many nil 1]]",
+                         Err("cannot :db/cas attribute 201 that is :db.cardinality/many"));
+        assert_transact!(conn, "[[:db/cas 100 :test/one [1] 2]]",
+                         Err("cannot :db/cas attribute 200 from a value that is not an atom, entid, or lookup ref"));
+    }
+
+    #[test]
+    fn test_composite_tuples() {
+        let mut conn = TestConn::default();
+
+        // Components are named by ident, so they must be installed before the composite.
+        assert_transact!(conn, r#"[
+            {:db/id 200 :db/ident :visit/page :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+            {:db/id 201 :db/ident :visit/date :db/valueType :db.type/instant :db/cardinality :db.cardinality/one}
+            {:db/id 202 :db/ident :visit/tag :db/valueType :db.type/keyword :db/cardinality :db.cardinality/many}
+            {:db/id 205 :db/ident :page/url :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+        ]"#);
+        assert_transact!(conn, r#"[
+            {:db/id 203 :db/ident :visit/page+date :db/valueType :db.type/tuple :db/tupleAttrs [:visit/page :visit/date] :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+        ]"#);
+        assert_matches!(conn.last_transaction(),
+                        "[[203 :db/ident :visit/page+date ?tx true]
+                          [203 :db/valueType :db.type/tuple ?tx true]
+                          [203 :db/cardinality :db.cardinality/one ?tx true]
+                          [203 :db/unique :db.unique/identity ?tx true]
+                          [203 :db/index true ?tx true]
+                          [203 :db/tupleAttrs [200 201] ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // The composite is maintained once every component is present.
+        assert_transact!(conn, r#"[[:db/add 100 :visit/page 300]]"#);
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :visit/page 300 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+        assert_transact!(conn, r#"[[:db/add 100 :visit/date #inst "2018-01-01T00:00:00.000Z"]]"#);
+        assert_matches!(conn.last_transaction(),
+                        r#"[[100 :visit/date #inst "2018-01-01T00:00:00.000Z" ?tx true]
+                            [100 :visit/page+date [300 #inst "2018-01-01T00:00:00.000Z"] ?tx true]
+                            [?tx :db/txInstant ?ms ?tx true]]"#);
+
+        // Changing a component changes the composite.
+        assert_transact!(conn, r#"[[:db/add 100 :visit/date #inst "2018-01-02T00:00:00.000Z"]]"#);
+        assert_matches!(conn.last_transaction(),
+                        r#"[[100 :visit/date #inst "2018-01-01T00:00:00.000Z" ?tx false]
+                            [100 :visit/date #inst "2018-01-02T00:00:00.000Z" ?tx true]
+                            [100 :visit/page+date [300 #inst "2018-01-01T00:00:00.000Z"] ?tx false]
+                            [100 :visit/page+date [300 #inst "2018-01-02T00:00:00.000Z"] ?tx true]
+                            [?tx :db/txInstant ?ms ?tx true]]"#);
+
+        // A tempid asserting the same components upserts to the existing entity.
+        let report = assert_transact!(conn, r#"[{:db/id "v" :visit/page 300 :visit/date #inst "2018-01-02T00:00:00.000Z" :visit/tag :visit/new}]"#);
+        assert_matches!(tempids(&report),
+                        r#"{"v" 100}"#);
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :visit/tag :visit/new ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // The composite names its entity in lookup refs.
+        assert_transact!(conn, r#"[[:db/add (lookup-ref :visit/page+date [300 #inst "2018-01-02T00:00:00.000Z"]) :visit/tag :visit/old]]"#);
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :visit/tag :visit/old ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Components can be given by tempids, which must resolve before the composite can upsert,
+        // and by lookup refs.
+        assert_transact!(conn, r#"[[:db/add 300 :page/url "https://example.com/"]]"#);
+        let report = assert_transact!(conn, r#"[
+            {:db/id "p" :page/url "https://example.com/"}
+            {:db/id "v" :visit/page "p" :visit/date #inst "2018-01-02T00:00:00.000Z" :visit/tag :visit/by-tempid}
+        ]"#);
+        assert_matches!(tempids(&report),
+                        r#"{"p" 300 "v" 100}"#);
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :visit/tag :visit/by-tempid ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+        let report = assert_transact!(conn, r#"[
+            {:db/id "v" :visit/page (lookup-ref :page/url "https://example.com/") :visit/date #inst "2018-01-02T00:00:00.000Z" :visit/tag :visit/by-lookup-ref}
+        ]"#);
+        assert_matches!(tempids(&report),
+                        r#"{"v" 100}"#);
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :visit/tag :visit/by-lookup-ref ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Composites can't be transacted directly.
+        assert_transact!(conn, r#"[[:db/add 101 :visit/page+date [300 #inst "2018-01-03T00:00:00.000Z"]]]"#,
+                         Err("cannot transact composite tuple attribute 203: it is maintained from its :db/tupleAttrs"));
+
+        // Retracting a component retracts the composite.
+        assert_transact!(conn, r#"[[:db/retract 100 :visit/date #inst "2018-01-02T00:00:00.000Z"]]"#);
+        assert_matches!(conn.last_transaction(),
+                        r#"[[100 :visit/date #inst "2018-01-02T00:00:00.000Z" ?tx false]
+                            [100 :visit/page+date [300 #inst "2018-01-02T00:00:00.000Z"] ?tx false]
+                            [?tx :db/txInstant ?ms ?tx true]]"#);
+
+        // Composites must name their components, which must be single-valued.
+        assert_transact!(conn, r#"[{:db/id 204 :db/ident :visit/bad :db/valueType :db.type/tuple :db/cardinality :db.cardinality/one}]"#,
+                         Err("bad schema assertion: :db/valueType :db.type/tuple without :db/tupleAttrs for entid: 204"));
+        assert_transact!(conn, r#"[{:db/id 204 :db/ident :visit/bad :db/valueType :db.type/tuple :db/tupleAttrs [:visit/page :visit/tag] :db/cardinality :db.cardinality/one}]"#,
+                         Err("bad schema assertion: :db/tupleAttrs names 202, which is :db.cardinality/many, for entid: 204"));
+        assert_transact!(conn, r#"[{:db/id 204 :db/ident :visit/bad :db/valueType :db.type/tuple :db/tupleAttrs [:visit/page "date"] :db/cardinality :db.cardinality/one}]"#,
+                         Err("element 1 of the :db/tupleAttrs of attribute 204 must be an entid or ident"));
+        assert_transact!(conn, r#"[{:db/id 204 :db/ident :visit/bad :db/valueType :db.type/tuple :db/tupleAttrs [:visit/page (lookup-ref :db/ident :visit/date)] :db/cardinality :db.cardinality/one}]"#,
+                         Err("element 1 of the :db/tupleAttrs of attribute 204 must be an entid or ident"));
+
+        // Components must stay fit to be components.
+        assert_transact!(conn, "[[:db/add 201 :db/cardinality :db.cardinality/many]]",
+                         Err("bad schema assertion: :db/tupleAttrs names 201, which is :db.cardinality/many, for entid: 203"));
+        assert_eq!(conn.migrate_value_type(201, ValueType::Long, |_| None).map_err(|e| e.to_string()),
+                   Err("cannot change the value type of attribute 201: it is a component of composite tuple 203".to_string()));
+        assert_eq!(conn.migrate_value_type(203, ValueType::String, |_| None).map_err(|e| e.to_string()),
+                   Err("cannot change the value type of attribute 203: it is a composite tuple".to_string()));
+    }
+
+    #[test]
+    fn test_retract_entity_and_attribute() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, r#"[
+            {:db/id 200 :db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity :db/index true}
+            {:db/id 201 :db/ident :test/child :db/valueType :db.type/ref :db/cardinality :db.cardinality/many :db/isComponent true}
+            {:db/id 202 :db/ident :test/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+            {:db/id 203 :db/ident :test/tag :db/valueType :db.type/long :db/cardinality :db.cardinality/many}
+        ]"#);
+
+        assert_transact!(conn, r#"[
+            {:db/id 100 :test/name "Ivan" :test/child 101}
+            {:db/id 101 :test/name "Petr" :test/child 102}
+            {:db/id 102 :test/name "Olga"}
+            {:db/id 103 :test/name "Anna" :test/friend 101 :test/tag [1 2]}
+        ]"#);
+
+        // Retracting an entity retracts its components, recursively, and all references to them.
+        assert_transact!(conn, r#"[[:db/retractEntity (lookup-ref :test/name "Ivan")]]"#);
+        assert_matches!(conn.last_transaction(),
+                        r#"[[100 :test/name "Ivan" ?tx false]
+                            [100 :test/child 101 ?tx false]
+                            [101 :test/name "Petr" ?tx false]
+                            [101 :test/child 102 ?tx false]
+                            [102 :test/name "Olga" ?tx false]
+                            [103 :test/friend 101 ?tx false]
+                            [?tx :db/txInstant ?ms ?tx true]]"#);
+
+        // Retracting an attribute retracts all of its values.
+        assert_transact!(conn, "[[:db/retractAttribute 103 :test/tag]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[103 :test/tag 1 ?tx false]
+                          [103 :test/tag 2 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Retracting what isn't there is a no-op.
+        assert_transact!(conn, "[[:db/retractEntity 100]
+                                 [:db/retractAttribute 103 :test/tag]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :db/txInstant ?ms ?tx true]]");
+
+        // Tempids don't name anything to retract.
+        assert_transact!(conn, "[[:db/retractEntity \"t\"]]",
+                         Err("cannot retract tempid t: it doesn't name an existing entity"));
+        assert_transact!(conn, "[[:db/retractAttribute \"t\" :test/tag]]",
+                         Err("cannot retract tempid t: it doesn't name an existing entity"));
+    }
+
+    #[test]
+    fn test_tx_functions() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, r#"[
+            {:db/id 200 :db/ident :test/count :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#);
+        assert_transact!(conn, "[[:db/add 100 :test/count 1]]");
+
+        // In value position, a function produces a value from what's already in the store.
+        conn.tx_functions.register(Keyword::namespaced("counter", "increment"), |context| {
+            let count = match context.lookup_value_for_attribute(100, &Keyword::namespaced("test", "count"))? {
+                Some(TypedValue::Long(count)) => count,
+                _ => 0,
+            };
+            Ok(TxFunctionOutput::value(TypedValue::Long(count + 1)))
+        });
+
+        // In entity position, a function produces an entity, and can return more entities to
+        // transact.
+        conn.tx_functions.register(Keyword::namespaced("counter", "entity"), |_context| {
+            Ok(TxFunctionOutput {
+                value: TypedValue::Ref(100),
+                entities: vec![Entity::AddOrRetract {
+                    op: OpType::Add,
+                    e: EntityPlace::TxFunction(TxFunction { op: edn::PlainSymbol::plain("transaction-tx") }),
+                    a: AttributePlace::from(Keyword::namespaced("db", "doc")),
+                    v: ValuePlace::Atom(TypedValue::typed_string("incremented")),
+                }],
+            })
+        });
+
+        assert_transact!(conn, "[[:db/add (counter/entity) :test/count (counter/increment)]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :test/count 1 ?tx false]
+                          [100 :test/count 2 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]
+                          [?tx :db/doc \"incremented\" ?tx true]]");
+
+        assert_transact!(conn, "[{:db/id 100 :test/count (counter/increment)}]");
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :test/count 2 ?tx false]
+                          [100 :test/count 3 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Functions are type-checked like values.
+        assert_transact!(conn, "[[:db/add (counter/increment) :test/count 1]]",
+                         Err("transaction function :counter/increment produced value of type :db.type/long but expected type :db.type/ref"));
+
+        assert!(conn.tx_functions.deregister(&Keyword::namespaced("counter", "increment")));
+        assert_transact!(conn, "[[:db/add 100 :test/count (counter/increment)]]",
+                         Err("unknown transaction function :counter/increment"));
+
+        // A function whose entities call it again can't loop forever.
+        conn.tx_functions.register(Keyword::namespaced("counter", "forever"), |_context| {
+            Ok(TxFunctionOutput {
+                value: TypedValue::Ref(100),
+                entities: vec![Entity::AddOrRetract {
+                    op: OpType::Add,
+                    e: EntityPlace::TxFunction(TxFunction { op: edn::PlainSymbol::plain("counter/forever") }),
+                    a: AttributePlace::from(Keyword::namespaced("test", "count")),
+                    v: ValuePlace::Atom(TypedValue::Long(1)),
+                }],
+            })
+        });
+        assert_transact!(conn, "[[:db/add (counter/forever) :test/count 1]]",
+                         Err("transaction functions nested more than 64 deep"));
+    }
+
+    #[test]
+    fn test_no_history() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/visited]
+                                 [:db/add 111 :db/valueType :db.type/long]
+                                 [:db/add 111 :db/cardinality :db.cardinality/one]
+                                 [:db/add 111 :db/noHistory true]
+                                 [:db/add 222 :db/ident :test/tag]
+                                 [:db/add 222 :db/valueType :db.type/keyword]
+                                 [:db/add 222 :db/cardinality :db.cardinality/many]
+                                 [:db/add 222 :db/noHistory true]
+                                 [:db/add 333 :db/ident :test/name]
+                                 [:db/add 333 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/cardinality :db.cardinality/one]]");
+
+        assert_transact!(conn, "[[:db/add 100 :test/visited 1]
+                                 [:db/add 100 :test/tag :test/a]
+                                 [:db/add 100 :test/tag :test/b]
+                                 [:db/add 100 :test/name \"Ivan\"]]");
+        let first = conn.last_tx_id();
+
+        // Retractions of :db/noHistory attributes don't make it to the log, and neither do the
+        // assertions they undo.  Other attributes keep their history.
+        assert_transact!(conn, "[[:db/add 100 :test/visited 2]
+                                 [:db/retract 100 :test/tag :test/a]
+                                 [:db/add 100 :test/name \"Petr\"]]");
+        assert_matches!(::debug::transactions_after(&conn.sqlite, &conn.schema, first - 1).expect("transactions"),
+                        "[[[100 :test/tag :test/b ?tx1 true]
+                           [100 :test/name \"Ivan\" ?tx1 true]
+                           [?tx1 :db/txInstant ?ms1 ?tx1 true]]
+                          [[100 :test/visited 2 ?tx2 true]
+                           [100 :test/name \"Ivan\" ?tx2 false]
+                           [100 :test/name \"Petr\" ?tx2 true]
+                           [?tx2 :db/txInstant ?ms2 ?tx2 true]]]");
+        assert_matches!(conn.datoms(),
+                        "[[100 :test/visited 2]
+                          [100 :test/tag :test/b]
+                          [100 :test/name \"Petr\"]
+                          [111 :db/ident :test/visited]
+                          [111 :db/valueType :db.type/long]
+                          [111 :db/cardinality :db.cardinality/one]
+                          [111 :db/noHistory true]
+                          [222 :db/ident :test/tag]
+                          [222 :db/valueType :db.type/keyword]
+                          [222 :db/cardinality :db.cardinality/many]
+                          [222 :db/noHistory true]
+                          [333 :db/ident :test/name]
+                          [333 :db/valueType :db.type/string]
+                          [333 :db/cardinality :db.cardinality/one]]");
+
+        // Altering an attribute to be :db/noHistory prunes its existing history.
+        assert_transact!(conn, "[[:db/add 333 :db/noHistory true]]");
+        assert_matches!(::debug::transactions_after(&conn.sqlite, &conn.schema, first - 1).expect("transactions"),
+                        "[[[100 :test/tag :test/b ?tx1 true]
+                           [?tx1 :db/txInstant ?ms1 ?tx1 true]]
+                          [[100 :test/visited 2 ?tx2 true]
+                           [100 :test/name \"Petr\" ?tx2 true]
+                           [?tx2 :db/txInstant ?ms2 ?tx2 true]]
+                          [[333 :db/noHistory true ?tx3 true]
+                           [?tx3 :db/txInstant ?ms3 ?tx3 true]]]");
+    }
+
+    #[test]
+    fn test_excise() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/name]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 111 :db/index true]
+                                 [:db/add 111 :db/fulltext true]
+                                 [:db/add 222 :db/ident :test/friend]
+                                 [:db/add 222 :db/valueType :db.type/ref]
+                                 [:db/add 333 :db/ident :test/age]
+                                 [:db/add 333 :db/valueType :db.type/long]]");
+        assert_transact!(conn, "[[:db/add 100 :test/name \"Ivan's secret\"]
+                                 [:db/add 100 :test/age 30]
+                                 [:db/add 101 :test/name \"Petr\"]
+                                 [:db/add 101 :test/friend 100]]");
+        assert_transact!(conn, "[[:db/add 100 :test/age 31]]");
+        assert_matches!(conn.fulltext_values(),
+                        "[[1 \"Ivan's secret\"]
+                          [2 \"Petr\"]]");
+
+        // Excising an entity purges everything about it and every reference to it, including its
+        // history.  The excision transaction records what was purged, but not the purged values.
+        let report = conn.excise(&[Excision::entity(100)]).expect("excised");
+        assert_eq!(report.tx_id, conn.last_tx_id());
+        assert_matches!(conn.last_transaction(),
+                        "[[65536 :db/excise 100 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+        assert_matches!(conn.datoms(),
+                        "[[101 :test/name 2]
+                          [111 :db/ident :test/name]
+                          [111 :db/valueType :db.type/string]
+                          [111 :db/index true]
+                          [111 :db/fulltext true]
+                          [222 :db/ident :test/friend]
+                          [222 :db/valueType :db.type/ref]
+                          [333 :db/ident :test/age]
+                          [333 :db/valueType :db.type/long]
+                          [65536 :db/excise 100]]");
+        assert_matches!(conn.fulltext_values(),
+                        "[[2 \"Petr\"]]");
+        let count: i64 = conn.sqlite.query_row("SELECT COUNT(*) FROM transactions WHERE e = 100 OR (v = 100 AND value_type_tag = 0 AND a <> 18)", &[], |row| row.get(0)).expect("count");
+        assert_eq!(count, 0);
+
+        // Excisions can be limited to some attributes, and to transactions before a given one.
+        let before_tx = conn.last_tx_id();
+        let mut attrs = BTreeSet::default();
+        attrs.insert(111);
+        conn.excise(&[Excision { target: 101, attrs: Some(attrs), before_tx: Some(before_tx) }]).expect("excised");
+        assert_matches!(conn.last_transaction(),
+                        format!("[[65537 :db/excise 101 ?tx true]
+                                  [65537 :db.excise/attrs :test/name ?tx true]
+                                  [65537 :db.excise/before {} ?tx true]
+                                  [?tx :db/txInstant ?ms ?tx true]]", before_tx));
+        assert_matches!(conn.fulltext_values(),
+                        "[]");
+        let count: i64 = conn.sqlite.query_row("SELECT COUNT(*) FROM transactions WHERE e = 101", &[], |row| row.get(0)).expect("count");
+        assert_eq!(count, 0);
+
+        // The schema, and partitions that don't allow excision, are off limits.
+        assert_eq!(conn.excise(&[Excision::entity(111)]).map_err(|e| e.to_string()),
+                   Err("cannot excise 111: it is part of the schema".to_string()));
+        let tx_id = conn.last_tx_id();
+        assert_eq!(conn.excise(&[Excision::entity(tx_id)]).map_err(|e| e.to_string()),
+                   Err(format!("cannot excise {}: its partition does not allow excision", tx_id)));
+
+        // So are entities that those partitions refer to, since purging the references would
+        // reach into them.
+        assert_transact!(conn, "[[:db/add 102 :test/age 40]
+                                 [:db/add (transaction-tx) :test/friend 102]]");
+        let tx_id = conn.last_tx_id();
+        assert_eq!(conn.excise(&[Excision::entity(102)]).map_err(|e| e.to_string()),
+                   Err(format!("cannot excise 102: entity {} refers to it, and its partition does not allow excision", tx_id)));
+        assert_matches!(conn.last_transaction(),
+                        "[[102 :test/age 40 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]
+                          [?tx :test/friend 102 ?tx true]]");
+    }
+
+    #[test]
+    fn test_migrate_value_type() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/count]
+                                 [:db/add 111 :db/valueType :db.type/long]
+                                 [:db/add 222 :db/ident :test/tag]
+                                 [:db/add 222 :db/valueType :db.type/string]
+                                 [:db/add 222 :db/cardinality :db.cardinality/many]
+                                 [:db/add 333 :db/ident :test/code]
+                                 [:db/add 333 :db/valueType :db.type/long]
+                                 [:db/add 333 :db/unique :db.unique/identity]
+                                 [:db/add 333 :db/index true]]");
+        assert_transact!(conn, "[[:db/add 100 :test/count 1]
+                                 [:db/add 100 :test/tag \"red\"]
+                                 [:db/add 100 :test/tag \"dark blue\"]
+                                 [:db/add 100 :test/code 1]
+                                 [:db/add 101 :test/code 2]]");
+        assert_transact!(conn, "[[:db/add 100 :test/count 2]]");
+        let datoms = conn.datoms();
+
+        // Values that can't be converted leave everything untouched.
+        let to_keyword = |v: &TypedValue| -> Option<TypedValue> {
+            match v {
+                &TypedValue::String(ref s) if !s.contains(' ') => Some(Keyword::plain(s.as_str()).into()),
+                _ => None,
+            }
+        };
+        assert_eq!(conn.migrate_value_type(222, ValueType::Keyword, to_keyword).map_err(|e| e.to_string()),
+                   Err("cannot change the value type of attribute 222: String(\"dark blue\") cannot be converted to a :db.type/keyword".to_string()));
+
+        // So do conversions that would collide.
+        let to_boolean = |v: &TypedValue| -> Option<TypedValue> {
+            match v {
+                &TypedValue::Long(x) => Some((x != 0).into()),
+                _ => None,
+            }
+        };
+        assert_eq!(conn.migrate_value_type(333, ValueType::Boolean, to_boolean).map_err(|e| e.to_string()),
+                   Err("cannot change the value type of attribute 333: more than one entity would have the unique value Boolean(true)".to_string()));
+
+        // Neither the bootstrap schema nor the constraints on the attribute can be escaped.
+        assert_eq!(conn.migrate_value_type(entids::DB_IDENT, ValueType::String, |_| None).map_err(|e| e.to_string()),
+                   Err("cannot change the value type of attribute 1: it is part of the bootstrap schema".to_string()));
+        assert_eq!(conn.migrate_value_type(111, ValueType::Long, |v| Some(v.clone())).map_err(|e| e.to_string()),
+                   Err("cannot change the value type of attribute 111: it is already a :db.type/long".to_string()));
+        assert_eq!(conn.datoms().0, datoms.0);
+
+        // A successful migration retracts the old values and changes the value type in one
+        // transaction, and asserts the converted values in another.
+        let to_double = |v: &TypedValue| -> Option<TypedValue> {
+            match v {
+                &TypedValue::Long(x) => Some((x as f64).into()),
+                _ => None,
+            }
+        };
+        let before_tx = conn.last_tx_id();
+        let report = conn.migrate_value_type(111, ValueType::Double, to_double).expect("migrated");
+        assert_eq!(report.tx_id, conn.last_tx_id());
+        assert_matches!(::debug::transactions_after(&conn.sqlite, &conn.schema, before_tx).expect("transactions"),
+                        "[[[100 :test/count 2 ?tx1 false]
+                           [111 :db/valueType :db.type/long ?tx1 false]
+                           [111 :db/valueType :db.type/double ?tx1 true]
+                           [?tx1 :db/txInstant ?ms1 ?tx1 true]]
+                          [[100 :test/count 2.0 ?tx2 true]
+                           [?tx2 :db/txInstant ?ms2 ?tx2 true]]]");
+        assert_eq!(conn.schema.require_attribute_for_entid(111).expect("attribute").value_type, ValueType::Double);
+        assert_matches!(conn.datoms(),
+                        "[[100 :test/count 2.0]
+                          [100 :test/tag \"dark blue\"]
+                          [100 :test/tag \"red\"]
+                          [100 :test/code 1]
+                          [101 :test/code 2]
+                          [111 :db/ident :test/count]
+                          [111 :db/valueType :db.type/double]
+                          [222 :db/ident :test/tag]
+                          [222 :db/valueType :db.type/string]
+                          [222 :db/cardinality :db.cardinality/many]
+                          [333 :db/ident :test/code]
+                          [333 :db/valueType :db.type/long]
+                          [333 :db/unique :db.unique/identity]
+                          [333 :db/index true]]");
+
+        // The migrated attribute only accepts values of its new type.
+        assert_transact!(conn, "[[:db/add 100 :test/count 2.5]]");
+        assert_transact!(conn, "[[:db/add 100 :test/count 3]]",
+                         Err("value \'3\' is not the expected Mentat value type Double"));
+    }
+
+    #[test]
+    fn test_revert_tx() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/name]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 222 :db/ident :test/tag]
+                                 [:db/add 222 :db/valueType :db.type/string]
+                                 [:db/add 222 :db/cardinality :db.cardinality/many]]");
+        let tx1 = assert_transact!(conn, "[[:db/add 100 :test/name \"Ivan\"]
+                                           [:db/add 100 :test/tag \"a\"]]").tx_id;
+        let tx2 = assert_transact!(conn, "[[:db/add 100 :test/name \"Ivan the Terrible\"]
+                                           [:db/add 100 :test/tag \"b\"]
+                                           [:db/retract 100 :test/tag \"a\"]]").tx_id;
+
+        // Reverting retracts what was asserted, including implicitly, and reasserts what was
+        // retracted.
+        let report = conn.revert_tx(tx2).expect("reverted");
+        assert_eq!(report.tx_id, conn.last_tx_id());
+        assert_matches!(conn.last_transaction(),
+                        "[[100 :test/name \"Ivan\" ?tx true]
+                          [100 :test/name \"Ivan the Terrible\" ?tx false]
+                          [100 :test/tag \"a\" ?tx true]
+                          [100 :test/tag \"b\" ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+        assert_matches!(conn.datoms(),
+                        "[[100 :test/name \"Ivan\"]
+                          [100 :test/tag \"a\"]
+                          [111 :db/ident :test/name]
+                          [111 :db/valueType :db.type/string]
+                          [222 :db/ident :test/tag]
+                          [222 :db/valueType :db.type/string]
+                          [222 :db/cardinality :db.cardinality/many]]");
+
+        // Later transactions that changed the same datoms conflict.
+        assert_eq!(conn.revert_tx(tx1).map_err(|e| e.to_string()),
+                   Err(format!("cannot revert transaction {}: transaction {} later changed [100 111 String(\"Ivan\")]", tx1, tx2)));
+
+        // Values of cardinality many attributes only conflict with changes to the same value.
+        let tx3 = assert_transact!(conn, "[[:db/add 100 :test/tag \"c\"]]").tx_id;
+        assert_transact!(conn, "[[:db/add 100 :test/tag \"d\"]]");
+        conn.revert_tx(tx3).expect("reverted");
+        assert_matches!(conn.datoms(),
+                        "[[100 :test/name \"Ivan\"]
+                          [100 :test/tag \"a\"]
+                          [100 :test/tag \"d\"]
+                          [111 :db/ident :test/name]
+                          [111 :db/valueType :db.type/string]
+                          [222 :db/ident :test/tag]
+                          [222 :db/valueType :db.type/string]
+                          [222 :db/cardinality :db.cardinality/many]]");
+
+        assert_eq!(conn.revert_tx(100).map_err(|e| e.to_string()),
+                   Err("cannot revert transaction 100: it is not a transaction".to_string()));
+
+        // Changes to :db/noHistory attributes can't be reverted, so neither can the rest of their
+        // transaction.
+        assert_transact!(conn, "[[:db/add 333 :db/ident :test/visited]
+                                 [:db/add 333 :db/valueType :db.type/long]
+                                 [:db/add 333 :db/cardinality :db.cardinality/one]
+                                 [:db/add 333 :db/noHistory true]]");
+        let tx4 = assert_transact!(conn, "[[:db/add 100 :test/visited 1]
+                                           [:db/add 100 :test/tag \"e\"]]").tx_id;
+        assert_eq!(conn.revert_tx(tx4).map_err(|e| e.to_string()),
+                   Err(format!("cannot revert transaction {}: attribute 333 is :db/noHistory", tx4)));
+        assert_matches!(conn.datoms(),
+                        "[[100 :test/name \"Ivan\"]
+                          [100 :test/tag \"a\"]
+                          [100 :test/tag \"d\"]
+                          [100 :test/tag \"e\"]
+                          [100 :test/visited 1]
+                          [111 :db/ident :test/name]
+                          [111 :db/valueType :db.type/string]
+                          [222 :db/ident :test/tag]
+                          [222 :db/valueType :db.type/string]
+                          [222 :db/cardinality :db.cardinality/many]
+                          [333 :db/ident :test/visited]
+                          [333 :db/valueType :db.type/long]
+                          [333 :db/cardinality :db.cardinality/one]
+                          [333 :db/noHistory true]]");
+    }
+
+    #[test]
+    fn test_explain_transact() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/name]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 111 :db/unique :db.unique/identity]
+                                 [:db/add 111 :db/index true]
+                                 [:db/add 222 :db/ident :test/age]
+                                 [:db/add 222 :db/valueType :db.type/long]
+                                 [:db/add 222 :db/cardinality :db.cardinality/one]
+                                 [:db/add 333 :db/ident :test/tag]
+                                 [:db/add 333 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/cardinality :db.cardinality/many]]");
+        assert_transact!(conn, r#"[[:db/add 100 :test/name "Alice"]
+                                   [:db/add 100 :test/age 30]
+                                   [:db/add 100 :test/tag "a"]]"#);
+        let last_tx_id = conn.last_tx_id();
+
+        let explanation = conn.explain_transact(r#"[
+            {:db/id "alice" :test/name "Alice" :test/age 31 :test/tag "a"}
+            {:db/id "bob" :test/name "Bob"}
+            [:db/retract (lookup-ref :test/name "Alice") :test/tag "b"]
+            {:db/ident :test/nickname :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("explained");
+
+        assert_eq!(explanation.tempids["alice"], TempIdResolution::Upserted(100));
+        let bob = match explanation.tempids["bob"] {
+            TempIdResolution::Allocated(e) => e,
+            r => panic!("expected bob to be allocated, got {:?}", r),
+        };
+        assert_eq!(explanation.lookup_refs.get(&(111, TypedValue::typed_string("Alice"))), Some(&100));
+
+        assert!(explanation.asserted.contains(&(100, 222, TypedValue::Long(31))));
+        assert!(explanation.asserted.contains(&(bob, 111, TypedValue::typed_string("Bob"))));
+        assert_eq!(explanation.retracted, vec![(100, 222, TypedValue::Long(30))]);
+        assert_eq!(explanation.unchanged, vec![(100, 111, TypedValue::typed_string("Alice"), true),
+                                               (100, 333, TypedValue::typed_string("a"), true),
+                                               (100, 333, TypedValue::typed_string("b"), false)]);
+        assert_eq!(explanation.schema_alterations.map(|report| report.attributes_installed.len()), Some(1));
+
+        // Nothing changed.
+        assert_eq!(conn.last_tx_id(), last_tx_id);
+        assert!(conn.schema.get_entid(&Keyword::namespaced("test", "nickname")).is_none());
+        assert_matches!(conn.datoms(),
+                        r#"[[100 :test/name "Alice"]
+                            [100 :test/age 30]
+                            [100 :test/tag "a"]
+                            [111 :db/ident :test/name]
+                            [111 :db/valueType :db.type/string]
+                            [111 :db/unique :db.unique/identity]
+                            [111 :db/index true]
+                            [222 :db/ident :test/age]
+                            [222 :db/valueType :db.type/long]
+                            [222 :db/cardinality :db.cardinality/one]
+                            [333 :db/ident :test/tag]
+                            [333 :db/valueType :db.type/string]
+                            [333 :db/cardinality :db.cardinality/many]]"#);
+
+        // A transaction that would fail explains nothing.
+        assert!(conn.explain_transact(r#"[[:db/add "x" :test/age "not a long"]]"#).is_err());
+        assert_eq!(conn.last_tx_id(), last_tx_id);
+    }
+
+    #[test]
+    fn test_transact_with_known_temp_ids() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, "[
+            {:db/ident :test/id
+             :db/valueType :db.type/string
+             :db/unique :db.unique/identity
+             :db/index true
+             :db/cardinality :db.cardinality/one}
+            {:db/ident :test/ref
+             :db/valueType :db.type/ref
+             :db/cardinality :db.cardinality/one}
+        ]");
+        assert_transact!(conn, r#"[[:db/add 200 :test/id "1"]]"#);
+
+        let known_temp_ids = assert_transact!(conn, r#"[{:db/id "a" :test/id "0"}]"#).tempids;
+        let a = known_temp_ids["a"];
+
+        // "a" refers to the entity allocated earlier, while "b" still upserts and "c" is allocated.
+        let report = conn.transact_with_known_temp_ids(r#"[
+            {:db/id "a" :test/ref "c"}
+            {:db/id "b" :test/id "1" :test/ref "a"}
+            {:db/id "c" :test/ref "a"}
+        ]"#, &known_temp_ids).expect("transacted");
+        assert_eq!(report.tempids["a"], a);
+        assert_eq!(report.tempids["b"], 200);
+        let c = report.tempids["c"];
+        assert_matches!(conn.last_transaction(),
+                        format!("[[200 :test/ref {} ?tx true]
+                                  [{} :test/ref {} ?tx true]
+                                  [{} :test/ref {} ?tx true]
+                                  [?tx :db/txInstant ?ms ?tx true]]", a, a, c, c, a));
+
+        // Assertions about only new entities take the fast path, which doesn't search the store.
+        let report = conn.transact_with_known_temp_ids(r#"[{:db/id "d" :test/id "2" :test/ref "a"}]"#, &known_temp_ids).expect("transacted");
+        let d = report.tempids["d"];
+        assert_matches!(conn.last_transaction(),
+                        format!("[[{} :test/id \"2\" ?tx true]
+                                  [{} :test/ref {} ?tx true]
+                                  [?tx :db/txInstant ?ms ?tx true]]", d, d, a));
+    }
+
+    #[test]
+    fn test_install_partition() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/name]
+                                 [:db/add 111 :db/valueType :db.type/string]
+                                 [:db/add 222 :db/ident :test/friend]
+                                 [:db/add 222 :db/valueType :db.type/ref]]");
+
+        // Tempids can only name partitions that exist.
+        assert_transact!(conn, "[[:db/add (tempid :my.part \"x\") :test/name \"x\"]]",
+                         Err("unknown partition: :my.part"));
+
+        // A partition is named by the ident of the installed entity, and taken from the end of
+        // :db.part/user.  It doesn't allow excision unless it says so.
+        let report = assert_transact!(conn, "[{:db/id \"p\" :db/ident :my.part}
+                                              [:db/add :db.part/db :db.install/partition \"p\"]]");
+        assert_matches!(tempids(&report),
+                        "{\"p\" 65536}");
+        let start = bootstrap::TX0 - bootstrap::PARTITION_SIZE;
+        assert_eq!(conn.partition_map[":my.part"], Partition::new(start, bootstrap::TX0 - 1, start, false));
+        assert_eq!(conn.partition_map[":db.part/user"].end, start - 1);
+        assert_eq!(read_partition_map(&conn.sqlite).expect("partition map"), conn.partition_map);
+
+        // Tempids are allocated in the partition they name, and in :db.part/user otherwise.
+        let report = assert_transact!(conn, "[[:db/add (tempid :my.part \"x\") :test/name \"x\"]
+                                              [:db/add (tempid :my.part \"x\") :test/friend \"y\"]
+                                              [:db/add \"y\" :test/name \"y\"]
+                                              [:db/add (tempid :my.part) :test/name \"z\"]]");
+        assert_eq!(report.tempids.get("x"), Some(&start));
+        assert_eq!(report.tempids.get("y"), Some(&65537));
+        assert_eq!(report.tempids.len(), 2);
+        assert_eq!(conn.partition_map[":my.part"].next_entid(), start + 2);
+        assert_eq!(read_partition_map(&conn.sqlite).expect("partition map"), conn.partition_map);
+
+        // A tempid belongs to exactly one partition.
+        assert_transact!(conn, "[[:db/add (tempid :my.part \"x\") :test/name \"x\"]
+                                 [:db/add (tempid :db.part/user \"x\") :test/name \"y\"]]",
+                         Err("cannot allocate tempid x in both partition :my.part and partition :db.part/user"));
+
+        // Partitions can't be installed twice, or without an ident.
+        assert_transact!(conn, "[[:db/add :db.part/db :db.install/partition :my.part]]",
+                         Err("bad schema assertion: Partition :my.part is already installed"));
+        assert_transact!(conn, "[[:db/add :db.part/db :db.install/partition \"q\"]
+                                 [:db/add \"q\" :test/name \"q\"]]",
+                         Err("bad schema assertion: Partition 65538 installed without a :db/ident"));
+
+        let report = assert_transact!(conn, "[{:db/id \"p\" :db/ident :my.excisable :db.partition/allowExcision true}
+                                              [:db/add :db.part/db :db.install/partition \"p\"]]");
+        let start = start - bootstrap::PARTITION_SIZE;
+        assert_eq!(conn.partition_map[":my.excisable"], Partition::new(start, start + bootstrap::PARTITION_SIZE - 1, start, true));
+        assert_eq!(report.tempids.get("p"), Some(&65538));
+        assert_eq!(read_partition_map(&conn.sqlite).expect("partition map"), conn.partition_map);
+    }
+
+    /// Undo what the bootstrap has added since the given version, as if the store had been made by
+    /// that version of Mentat.
+    fn downgrade_to(conn: &rusqlite::Connection, version: i32) {
+        let ident_map = bootstrap::bootstrap_ident_map();
+        let added_idents: Vec<Entid> = bootstrap::bootstrap_idents_since(version as u32).into_iter().map(|(_, entid)| entid).collect();
+        let added_attributes: Vec<Entid> = bootstrap::core_schema_since(version as u32).iter().map(|ident| ident_map[ident]).collect();
+        let list = |entids: &[Entid]| format!("({})", entids.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", "));
+        let (added_idents, added_attributes) = (list(&added_idents), list(&added_attributes));
+        for table in &["datoms", "transactions"] {
+            conn.execute(&format!("DELETE FROM {} WHERE e IN {}", table, added_idents), &[]).expect("deleted idents");
+            conn.execute(&format!("DELETE FROM {} WHERE e IN {} AND a <> {}", table, added_attributes, entids::DB_IDENT), &[]).expect("deleted attributes");
+            conn.execute(&format!("DELETE FROM {} WHERE e = {} AND a = {} AND v IN {}", table, entids::DB_SCHEMA_CORE, entids::DB_SCHEMA_ATTRIBUTE, added_attributes), &[]).expect("deleted core schema attributes");
+            conn.execute(&format!("UPDATE {} SET v = {} WHERE e = {} AND a = {}", table, version, entids::DB_SCHEMA_CORE, entids::DB_SCHEMA_VERSION), &[]).expect("updated core schema version");
+        }
+        conn.execute(&format!("DELETE FROM idents WHERE e IN {}", added_idents), &[]).expect("deleted idents");
+        conn.execute(&format!("DELETE FROM schema WHERE e IN {}", added_attributes), &[]).expect("deleted attributes");
+        let idx = bootstrap::bootstrap_partition_map()[":db.part/db"].next_entid() - bootstrap::bootstrap_idents_since(version as u32).len() as i64;
+        conn.execute("UPDATE parts SET idx = ? WHERE part = ':db.part/db'", &[&idx]).expect("updated partition");
+        set_user_version(conn, version).expect("set version");
+    }
+
+    fn read_bootstrap_datoms(conn: &rusqlite::Connection) -> Vec<(i64, i64, rusqlite::types::Value, i64)> {
+        let mut stmt = conn.prepare("SELECT e, a, v, value_type_tag FROM datoms WHERE a <> ? ORDER BY e, a, value_type_tag, v").expect("prepared");
+        let datoms: rusqlite::Result<Vec<_>> = stmt.query_map(&[&entids::DB_TX_INSTANT], |row| (row.get(0), row.get(1), row.get(2), row.get(3))).expect("queried").collect();
+        datoms.expect("datoms")
+    }
+
+    #[test]
+    fn test_upgrade() {
+        for version in 1..CURRENT_VERSION {
+            let mut sqlite = new_connection("").expect("Couldn't open in-memory db");
+            ensure_current_version(&mut sqlite).expect("created");
+            let expected_datoms = read_bootstrap_datoms(&sqlite);
+
+            downgrade_to(&sqlite, version);
+            assert_eq!(get_user_version(&sqlite).expect("version"), version);
+            let old_schema = read_db(&sqlite).expect("read").schema;
+            for (ident, _) in bootstrap::bootstrap_idents_since(version as u32) {
+                assert_eq!(old_schema.get_entid(&ident), None);
+            }
+            for ident in bootstrap::core_schema_since(version as u32) {
+                assert!(old_schema.attribute_for_ident(&ident).is_none());
+            }
+
+            // Opening the old store installs what the bootstrap has added since.
+            let db = ensure_current_version(&mut sqlite).expect("upgraded");
+            assert_eq!(get_user_version(&sqlite).expect("version"), CURRENT_VERSION);
+            assert_eq!(db.schema, bootstrap::bootstrap_schema());
+            assert_eq!(db.partition_map[":db.part/db"], bootstrap::bootstrap_partition_map()[":db.part/db"]);
+            assert_eq!(read_db(&sqlite).expect("read"), db);
+            assert_eq!(read_bootstrap_datoms(&sqlite), expected_datoms);
+
+            // The upgraded store opens as the current version.
+            assert_eq!(ensure_current_version(&mut sqlite).expect("opened"), db);
+        }
+    }
+
+    #[test]
+    fn test_upgrade_refuses_allocated_entids() {
+        let mut sqlite = new_connection("").expect("Couldn't open in-memory db");
+        ensure_current_version(&mut sqlite).expect("created");
+        downgrade_to(&sqlite, 1);
+
+        // As if the store had allocated an entid for its own attribute in :db.part/db.
+        sqlite.execute("UPDATE parts SET idx = idx + 1 WHERE part = ':db.part/db'", &[]).expect("updated partition");
+
+        let err = ensure_current_version(&mut sqlite).expect_err("refused");
+        let first_added = bootstrap::bootstrap_idents_since(1)[0].1;
+        assert_eq!(err.to_string(), format!("cannot upgrade store from version 1: entid {} is already in use", first_added));
+        assert_eq!(get_user_version(&sqlite).expect("version"), 1);
+    }
+
     #[test]
     #[cfg(feature = "sqlcipher")]
     fn test_sqlcipher_openable() {
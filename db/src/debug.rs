@@ -87,10 +87,26 @@ use schema::{
     SchemaBuilding,
 };
 use types::*;
+use excision::{
+    Excision,
+    excise,
+};
+use migration::{
+    migrate_value_type,
+};
+use revert::{
+    revert_tx,
+};
+use explain::{
+    TxExplanation,
+};
 use tx::{
-    transact,
+    explain_transact,
     transact_terms,
+    transact_with_options,
+    TxOptions,
 };
+use tx_functions::TxFunctions;
 use watcher::NullWatcher;
 
 /// Represents a *datom* (assertion) in the store.
@@ -318,6 +334,7 @@ pub struct TestConn {
     pub sqlite: rusqlite::Connection,
     pub partition_map: PartitionMap,
     pub schema: Schema,
+    pub tx_functions: TxFunctions,
 }
 
 impl TestConn {
@@ -338,7 +355,32 @@ impl TestConn {
             // We're about to write, so go straight ahead and get an IMMEDIATE transaction.
             let tx = self.sqlite.transaction_with_behavior(TransactionBehavior::Immediate)?;
             // Applying the transaction can fail, so we don't unwrap.
-            let details = transact(&tx, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), entities)?;
+            let details = transact_with_options(&tx, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), TxOptions::new().tx_functions(&self.tx_functions), entities)?;
+            tx.commit()?;
+            details
+        };
+
+        let (report, next_partition_map, next_schema, _watcher) = details;
+        self.partition_map = next_partition_map;
+        if let Some(next_schema) = next_schema {
+            self.schema = next_schema;
+        }
+
+        // Verify that we've updated the materialized views during transacting.
+        self.assert_materialized_views();
+
+        Ok(report)
+    }
+
+    pub fn transact_with_known_temp_ids<I>(&mut self, transaction: I, known_temp_ids: &BTreeMap<String, Entid>) -> Result<TxReport> where I: Borrow<str> {
+        // Failure to parse the transaction is a coding error, so we unwrap.
+        let entities = edn::parse::entities(transaction.borrow()).expect(format!("to be able to parse {} into entities", transaction.borrow()).as_str());
+
+        let details = {
+            // The block scopes the borrow of self.sqlite.
+            let tx = self.sqlite.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let options = TxOptions::new().tx_functions(&self.tx_functions).known_temp_ids(known_temp_ids);
+            let details = transact_with_options(&tx, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), options, entities)?;
             tx.commit()?;
             details
         };
@@ -355,13 +397,19 @@ impl TestConn {
         Ok(report)
     }
 
+    pub fn explain_transact<I>(&self, transaction: I) -> Result<TxExplanation> where I: Borrow<str> {
+        // Failure to parse the transaction is a coding error, so we unwrap.
+        let entities = edn::parse::entities(transaction.borrow()).expect(format!("to be able to parse {} into entities", transaction.borrow()).as_str());
+        explain_transact(&self.sqlite, self.partition_map.clone(), &self.schema, &self.schema, TxOptions::new().tx_functions(&self.tx_functions), entities)
+    }
+
     pub fn transact_simple_terms<I>(&mut self, terms: I, tempid_set: InternSet<TempId>) -> Result<TxReport> where I: IntoIterator<Item=TermWithTempIds> {
         let details = {
             // The block scopes the borrow of self.sqlite.
             // We're about to write, so go straight ahead and get an IMMEDIATE transaction.
             let tx = self.sqlite.transaction_with_behavior(TransactionBehavior::Immediate)?;
             // Applying the transaction can fail, so we don't unwrap.
-            let details = transact_terms(&tx, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), terms, tempid_set)?;
+            let details = transact_terms(&tx, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), &[], terms, tempid_set)?;
             tx.commit()?;
             details
         };
@@ -378,6 +426,65 @@ impl TestConn {
         Ok(report)
     }
 
+    pub fn excise(&mut self, excisions: &[Excision]) -> Result<TxReport> {
+        let details = {
+            // The block scopes the borrow of self.sqlite.
+            let tx = self.sqlite.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let details = excise(&tx, self.partition_map.clone(), &self.schema, NullWatcher(), &[], excisions)?;
+            tx.commit()?;
+            details
+        };
+
+        let (report, next_partition_map, _watcher) = details;
+        self.partition_map = next_partition_map;
+
+        // Verify that we've updated the materialized views during excising.
+        self.assert_materialized_views();
+
+        Ok(report)
+    }
+
+    pub fn migrate_value_type<F>(&mut self, attribute: Entid, value_type: ValueType, convert: F) -> Result<TxReport>
+    where F: Fn(&TypedValue) -> Option<TypedValue> {
+        let details = {
+            // The block scopes the borrow of self.sqlite.
+            let tx = self.sqlite.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let details = migrate_value_type(&tx, self.partition_map.clone(), &self.schema, NullWatcher(), &[], attribute, value_type, convert)?;
+            tx.commit()?;
+            details
+        };
+
+        let (report, next_partition_map, next_schema, _watcher) = details;
+        self.partition_map = next_partition_map;
+        self.schema = next_schema;
+
+        // Verify that we've updated the materialized views during migrating.
+        self.assert_materialized_views();
+
+        Ok(report)
+    }
+
+    pub fn revert_tx(&mut self, tx: Entid) -> Result<TxReport> {
+        let details = {
+            // The block scopes the borrow of self.sqlite.
+            let transaction = self.sqlite.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let details = revert_tx(&transaction, self.partition_map.clone(), &self.schema, &self.schema, NullWatcher(), &[], tx)?;
+            transaction.commit()?;
+            details
+        };
+
+        let (report, next_partition_map, next_schema, _watcher) = details;
+        self.partition_map = next_partition_map;
+        if let Some(next_schema) = next_schema {
+            self.schema = next_schema;
+        }
+
+        // Verify that we've updated the materialized views during reverting.
+        self.assert_materialized_views();
+
+        Ok(report)
+    }
+
     pub fn last_tx_id(&self) -> Entid {
         self.partition_map.get(&":db.part/tx".to_string()).unwrap().next_entid() - 1
     }
@@ -403,12 +510,12 @@ impl TestConn {
 
         // Does not include :db/txInstant.
         let datoms = datoms_after(&conn, &db.schema, 0).unwrap();
-        assert_eq!(datoms.0.len(), 94);
+        assert_eq!(datoms.0.len(), 112);
 
         // Includes :db/txInstant.
         let transactions = transactions_after(&conn, &db.schema, 0).unwrap();
         assert_eq!(transactions.0.len(), 1);
-        assert_eq!(transactions.0[0].0.len(), 95);
+        assert_eq!(transactions.0[0].0.len(), 113);
 
         let mut parts = db.partition_map;
 
@@ -423,6 +530,7 @@ impl TestConn {
             sqlite: conn,
             partition_map: parts,
             schema: db.schema,
+            tx_functions: TxFunctions::default(),
         };
 
         // Verify that we've created the materialized views during bootstrapping.
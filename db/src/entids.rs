@@ -57,11 +57,14 @@ pub const DB_DOC: Entid = 37;
 pub const DB_SCHEMA_VERSION: Entid = 38;
 pub const DB_SCHEMA_ATTRIBUTE: Entid = 39;
 pub const DB_SCHEMA_CORE: Entid = 40;
+pub const DB_PARTITION_ALLOW_EXCISION: Entid = 41;
+pub const DB_TYPE_TUPLE: Entid = 42;
+pub const DB_TUPLE_ATTRS: Entid = 43;
 
 /// Return `false` if the given attribute will not change the metadata: recognized idents, schema,
 /// partitions in the partition map.
 pub fn might_update_metadata(attribute: Entid) -> bool {
-    if attribute >= DB_DOC {
+    if attribute >= DB_DOC && attribute != DB_TUPLE_ATTRS {
         return false
     }
     match attribute {
@@ -72,6 +75,7 @@ pub fn might_update_metadata(attribute: Entid) -> bool {
         DB_FULLTEXT |
         DB_INDEX |
         DB_IS_COMPONENT |
+        DB_TUPLE_ATTRS |
         DB_UNIQUE |
         DB_VALUE_TYPE =>
             true,
@@ -88,23 +92,25 @@ lazy_static! {
 
     /// Attributes that are "schema related".  These might change the "schema" materialized view.
     pub static ref SCHEMA_SQL_LIST: String = {
-        format!("({}, {}, {}, {}, {}, {})",
+        format!("({}, {}, {}, {}, {}, {}, {})",
                 DB_CARDINALITY,
                 DB_FULLTEXT,
                 DB_INDEX,
                 DB_IS_COMPONENT,
+                DB_TUPLE_ATTRS,
                 DB_UNIQUE,
                 DB_VALUE_TYPE)
     };
 
     /// Attributes that are "metadata" related.  These might change one of the materialized views.
     pub static ref METADATA_SQL_LIST: String = {
-        format!("({}, {}, {}, {}, {}, {}, {})",
+        format!("({}, {}, {}, {}, {}, {}, {}, {})",
                 DB_CARDINALITY,
                 DB_FULLTEXT,
                 DB_IDENT,
                 DB_INDEX,
                 DB_IS_COMPONENT,
+                DB_TUPLE_ATTRS,
                 DB_UNIQUE,
                 DB_VALUE_TYPE)
     };
@@ -27,7 +27,10 @@ use edn::entities::{
     TempId,
 };
 use mentat_core::{
+    DateTime,
+    Keyword,
     KnownEntid,
+    Utc,
 };
 use types::{
     Entid,
@@ -208,6 +211,11 @@ pub enum DbErrorKind {
     // #[fail(display = "bad SQL store user_version: {}", _0)]
     // BadSQLiteStoreVersion(i32),
 
+    /// A store of an older version couldn't be upgraded to the current version, like when the store
+    /// already uses an entid that the current bootstrap installs.
+    #[fail(display = "cannot upgrade store from version {}: {}", _0, _1)]
+    StoreUpgradeFailed(i32, String),
+
     /// A bootstrap definition couldn't be parsed or installed.  This is a programmer error, not
     /// a runtime error.
     #[fail(display = "bad bootstrap definition: {}", _0)]
@@ -233,8 +241,14 @@ pub enum DbErrorKind {
     #[fail(display = "cannot reverse-cache non-unique attribute: {}", _0)]
     CannotCacheNonUniqueAttributeInReverse(Entid),
 
-    #[fail(display = "schema alteration failed: {}", _0)]
-    SchemaAlterationFailed(String),
+    /// Existing datoms don't satisfy an alteration to an attribute, like `entities` having more
+    /// than one value when altering to `:db.cardinality/one`.
+    #[fail(display = "schema alteration failed: Cannot alter schema attribute {} to be {}: violated by entities {:?}", attribute, alteration, entities)]
+    SchemaAlterationFailed {
+        attribute: Entid,
+        alteration: String,
+        entities: Vec<Entid>,
+    },
 
     /// A transaction tried to violate a constraint of the schema of the Mentat store.
     #[fail(display = "schema constraint violation: {}", _0)]
@@ -248,6 +262,120 @@ pub enum DbErrorKind {
     #[fail(display = "Cannot transact a fulltext assertion with a typed value that is not :db/valueType :db.type/string")]
     WrongTypeValueForFtsAssertion,
 
+    /// A `[:db/cas e a old new]` found a value other than `old` for `[e a]`.  `None` means no value.
+    #[fail(display = "compare-and-swap failed for [{} {}]: expected {:?} but found {:?}", e, a, expected, actual)]
+    CasFailed {
+        e: Entid,
+        a: Entid,
+        expected: Option<TypedValue>,
+        actual: Option<TypedValue>,
+    },
+
+    /// A `[:db/cas e a old new]` named an attribute that is `:db.cardinality/many`, which has no
+    /// single value to compare.
+    #[fail(display = "cannot :db/cas attribute {} that is :db.cardinality/many", _0)]
+    CasOfCardinalityMany(Entid),
+
+    /// A `[:db/cas e a old new]` named a tempid, which doesn't have a value to compare yet.
+    #[fail(display = "cannot :db/cas attribute {} of a tempid", _0)]
+    CasOfTempId(Entid),
+
+    /// A `[:db/cas e a old new]` expected an `old` value that isn't an atom, an entid, or a lookup ref.
+    #[fail(display = "cannot :db/cas attribute {} from a value that is not an atom, entid, or lookup ref", _0)]
+    BadCasValue(Entid),
+
+    /// A `[:db/retractEntity e]` or `[:db/retractAttribute e a]` named a tempid, which doesn't name
+    /// an existing entity to retract.
+    #[fail(display = "cannot retract tempid {}: it doesn't name an existing entity", _0)]
+    RetractionOfTempId(String),
+
+    /// A nested map retracted without a `:db/id`, like `[:db/retract e :attr {:other/attr v}]`, has
+    /// no `:db.unique/identity` attribute to say which existing entity it is.
+    #[fail(display = "cannot retract nested map value of attribute {}: it has neither :db/id nor a :db.unique/identity attribute", _0)]
+    UnidentifiedNestedRetraction(Entid),
+
+    /// A nested map retracted without a `:db/id` has more than one `:db.unique/identity`
+    /// attribute, which might identify different entities.
+    #[fail(display = "cannot retract nested map value of attribute {}: without :db/id, it must have only one :db.unique/identity attribute", _0)]
+    AmbiguousNestedRetraction(Entid),
+
+    /// A transaction called a function that isn't registered, like `(no.such/function)`.
+    #[fail(display = "unknown transaction function {}", _0)]
+    UnknownTxFunction(Keyword),
+
+    /// A transaction function produced a value of the wrong type for where it was called.
+    #[fail(display = "transaction function {} produced value of type {} but expected type {}", function, actual, expected)]
+    TxFunctionValueTypeMismatch {
+        function: Keyword,
+        expected: ValueType,
+        actual: ValueType,
+    },
+
+    /// Transaction functions returned entities that called transaction functions, and so on,
+    /// more than the given number of times: probably a function that calls itself.
+    #[fail(display = "transaction functions nested more than {} deep", _0)]
+    TxFunctionDepthExceeded(usize),
+
+    /// The store has more partitions than a single SQLite statement can update.
+    #[fail(display = "no more than {} partitions are supported", _0)]
+    TooManyPartitions(usize),
+
+    /// A statement needed more SQL variables than SQLite allows.
+    #[fail(display = "a statement needs {} SQL variables, but no more than {} are supported", _0, _1)]
+    TooManySQLVariables(usize, usize),
+
+    /// The same tempid named two different partitions, like `(tempid :a.part "x")` and
+    /// `(tempid :b.part "x")`.
+    #[fail(display = "cannot allocate tempid {} in both partition {} and partition {}", _0, _1, _2)]
+    ConflictingTempIdPartitions(String, String, String),
+
+    /// Tempids that upsert to the same entity named two different partitions.
+    #[fail(display = "cannot allocate tempids that upsert to the same entity in both partition {} and partition {}", _0, _1)]
+    ConflictingUpsertPartitions(String, String),
+
+    /// A tempid named a partition that isn't installed, like `(tempid :no.such/part)`.
+    #[fail(display = "unknown partition: {}", _0)]
+    UnknownPartition(String),
+
+    /// An excision targeted an entity that can't be excised, like part of the schema.
+    #[fail(display = "cannot excise {}: {}", _0, _1)]
+    ExcisionNotAllowed(Entid, String),
+
+    /// A transaction asserted a `:db/txInstant` earlier than that of the latest transaction.
+    #[fail(display = "transaction instant {} is before the latest transaction instant {}", instant, latest)]
+    NonMonotonicTxInstant {
+        instant: DateTime<Utc>,
+        latest: DateTime<Utc>,
+    },
+
+    /// A transaction asserted a `:db/txInstant` later than the time of transacting.
+    #[fail(display = "transaction instant {} is in the future", _0)]
+    FutureTxInstant(DateTime<Utc>),
+
+    /// The value type of an attribute couldn't be changed, like when one of its values can't be
+    /// converted to the new type.
+    #[fail(display = "cannot change the value type of attribute {}: {}", _0, _1)]
+    ValueTypeMigrationFailed(Entid, String),
+
+    /// A transaction couldn't be reverted, like when a later transaction changed the same datoms.
+    #[fail(display = "cannot revert transaction {}: {}", _0, _1)]
+    RevertTxFailed(Entid, String),
+
+    /// A transaction asserted or retracted a composite tuple attribute, which the transactor
+    /// maintains from the attribute's `:db/tupleAttrs`.
+    #[fail(display = "cannot transact composite tuple attribute {}: it is maintained from its :db/tupleAttrs", _0)]
+    CompositeTupleTransacted(Entid),
+
+    /// An element of a tuple value wasn't a value, entid, or ident.  Tuple elements can't be
+    /// tempids, lookup refs, or transaction functions.
+    #[fail(display = "element {} of the value of tuple attribute {} must be a value, entid, or ident", _1, _0)]
+    UnsupportedTupleElement(Entid, usize),
+
+    /// An element of the `:db/tupleAttrs` of a composite wasn't an entid or ident.  The composite is
+    /// named as it was given, since it might be a tempid.
+    #[fail(display = "element {} of the :db/tupleAttrs of attribute {} must be an entid or ident", _1, _0)]
+    UnsupportedTupleAttrsElement(String, usize),
+
     // SQL errors.
     #[fail(display = "could not update a cache")]
     CacheUpdateFailed,
@@ -267,6 +395,9 @@ pub enum DbErrorKind {
     #[fail(display = "Could not insert transaction: failed to retract datoms already present")]
     TxInsertFailedToRetractDatoms,
 
+    #[fail(display = "Could not insert transaction: failed to prune the history of :db/noHistory attributes")]
+    TxPruneNoHistoryFailed,
+
     #[fail(display = "Could not update datoms: failed to retract datoms already present")]
     DatomsUpdateFailedToRetract,
 
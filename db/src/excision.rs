@@ -0,0 +1,238 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Excision: permanently purging datoms from the store.
+//!
+//! Retracting a datom leaves it in the transaction log.  Excising it removes every trace of it --
+//! from `datoms`, from `transactions`, and, for fulltext values that nothing else refers to, from
+//! `fulltext_values` -- as if it had never been asserted.  Only entities in partitions that allow
+//! excision can be excised.
+//!
+//! The excision itself is recorded as a transaction, with a `:db/txInstant` and any other metadata
+//! the caller supplies.  Each excision is recorded in that transaction as a new entity, asserting
+//! `:db/excise` of the target, and `:db.excise/attrs` and `:db.excise/before` if the excision was
+//! limited to those, so that there's an audit trail of what was purged, if not of the purged
+//! values themselves.
+
+use std::collections::{
+    BTreeSet,
+};
+
+use itertools::Itertools;
+
+use mentat_core::util::Either;
+
+use rusqlite;
+use rusqlite::types::{
+    ToSql,
+};
+
+use edn::{
+    InternSet,
+};
+
+use edn::entities::{
+    OpType,
+    TempId,
+};
+
+use mentat_core::{
+    Entid,
+    HasSchema,
+    Schema,
+    SQLValueType,
+    TxReport,
+    TypedValue,
+    ValueType,
+};
+
+use db::{
+    TypedSQLValue,
+};
+
+use entids;
+
+use errors::{
+    DbErrorKind,
+    Result,
+};
+
+use internal_types::{
+    Term,
+    TermWithTempIds,
+};
+
+use schema::{
+    SchemaBuilding,
+};
+
+use tx::{
+    transact_terms,
+};
+
+use types::{
+    PartitionMap,
+};
+
+use watcher::{
+    TransactWatcher,
+};
+
+/// The datoms to purge from the store.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Excision {
+    /// The entity to purge.
+    pub target: Entid,
+
+    /// If `Some`, purge only the datoms `[target a v]` for the given attributes `a`.  If `None`,
+    /// purge every datom about `target`, and every datom that refers to `target`; the entities that
+    /// refer to `target` must then be in partitions that allow excision, too.
+    pub attrs: Option<BTreeSet<Entid>>,
+
+    /// If `Some`, purge only datoms from transactions before the given transaction.
+    pub before_tx: Option<Entid>,
+}
+
+impl Excision {
+    /// Purge everything about `target`.
+    pub fn entity(target: Entid) -> Excision {
+        Excision {
+            target,
+            attrs: None,
+            before_tx: None,
+        }
+    }
+
+    /// An SQL condition matching the rows of `datoms`, `all_datoms`, and `transactions` to purge.
+    /// Every value is an entid, so we can safely interpolate them.  Earlier excisions' records of
+    /// `target` are kept.
+    fn sql_condition(&self) -> String {
+        let matches_e = match self.attrs {
+            Some(ref attrs) => format!("(e = {} AND a IN ({}))", self.target, attrs.iter().join(", ")),
+            None => format!("(e = {} OR (v = {} AND value_type_tag = {} AND a <> {}))", self.target, self.target, ValueType::Ref.value_type_tag(), entids::DB_EXCISE),
+        };
+        match self.before_tx {
+            Some(before_tx) => format!("{} AND tx < {}", matches_e, before_tx),
+            None => matches_e,
+        }
+    }
+}
+
+/// Purge the datoms matching the given `excisions` from the given SQLite `conn`, and record a
+/// transaction asserting the given `tx_metadata` about itself, and what it purged.
+///
+/// Every purged datom that was in the store's current state is reported to the `watcher` as a
+/// retraction, so that caches and observers forget it.  If you want this work to occur inside a
+/// SQLite transaction, establish one on the connection prior to calling this function.
+pub fn excise<'conn, 'a, W>(conn: &'conn rusqlite::Connection,
+                            partition_map: PartitionMap,
+                            schema: &'a Schema,
+                            mut watcher: W,
+                            tx_metadata: &'a [(Entid, TypedValue)],
+                            excisions: &[Excision]) -> Result<(TxReport, PartitionMap, W)>
+    where W: TransactWatcher {
+
+    for excision in excisions {
+        match partition_map.values().find(|partition| partition.contains_entid(excision.target)) {
+            Some(partition) if partition.allow_excision => {},
+            Some(_) => bail!(DbErrorKind::ExcisionNotAllowed(excision.target, "its partition does not allow excision".into())),
+            None => bail!(DbErrorKind::UnrecognizedEntid(excision.target)),
+        }
+        // Attributes and idents can live in partitions that allow excision, but purging them
+        // would leave the materialized schema disagreeing with the store.
+        if schema.get_ident(excision.target).is_some() || schema.attribute_for_entid(excision.target).is_some() {
+            bail!(DbErrorKind::ExcisionNotAllowed(excision.target, "it is part of the schema".into()));
+        }
+        match excision.attrs {
+            Some(ref attrs) => {
+                for &a in attrs {
+                    schema.require_attribute_for_entid(a)?;
+                }
+            },
+            None => {
+                // Purging the references to `target` purges datoms about the referring entities,
+                // so they too must be in partitions that allow excision.
+                let mut stmt = conn.prepare(format!("SELECT DISTINCT e FROM transactions WHERE {} AND e <> {}", excision.sql_condition(), excision.target).as_str())?;
+                let referrers: rusqlite::Result<Vec<Entid>> = stmt.query_map(&[], |row| row.get(0))?.collect();
+                for e in referrers? {
+                    if !partition_map.values().any(|partition| partition.allow_excision && partition.contains_entid(e)) {
+                        bail!(DbErrorKind::ExcisionNotAllowed(excision.target, format!("entity {} refers to it, and its partition does not allow excision", e)));
+                    }
+                }
+            },
+        }
+    }
+
+    let fulltext_attrs = schema.attribute_map.iter()
+                               .filter(|&(_, attribute)| attribute.fulltext)
+                               .map(|(&a, _)| a)
+                               .join(", ");
+
+    for excision in excisions {
+        let condition = excision.sql_condition();
+
+        // Purged datoms in the current state are retractions as far as anybody watching is concerned.
+        {
+            let mut stmt = conn.prepare(format!("SELECT e, a, v, value_type_tag FROM all_datoms WHERE {}", condition).as_str())?;
+            let datoms: Result<Vec<(Entid, Entid, TypedValue)>> = stmt.query_and_then(&[], |row| -> Result<(Entid, Entid, TypedValue)> {
+                Ok((row.get_checked(0)?,
+                    row.get_checked(1)?,
+                    TypedValue::from_sql_value_pair(row.get_checked(2)?, row.get_checked(3)?)?))
+            })?.collect();
+            for (e, a, v) in datoms? {
+                watcher.datom(OpType::Retract, e, a, &v);
+            }
+        }
+
+        // Fulltext values are shared, so we can only purge those that nothing else refers to once
+        // we've purged the datoms that refer to them.
+        let fulltext_rowids: Vec<i64> = if fulltext_attrs.is_empty() {
+            vec![]
+        } else {
+            let mut stmt = conn.prepare(format!("SELECT DISTINCT v FROM transactions WHERE {} AND a IN ({})", condition, fulltext_attrs).as_str())?;
+            let rowids: rusqlite::Result<Vec<i64>> = stmt.query_map(&[], |row| row.get(0))?.collect();
+            rowids?
+        };
+
+        conn.execute(format!("DELETE FROM datoms WHERE {}", condition).as_str(), &[])?;
+        conn.execute(format!("DELETE FROM transactions WHERE {}", condition).as_str(), &[])?;
+
+        if !fulltext_rowids.is_empty() {
+            let mut stmt = conn.prepare(format!(r#"
+                DELETE FROM fulltext_values
+                WHERE rowid = ?
+                  AND NOT EXISTS (SELECT 1 FROM datoms WHERE index_fulltext IS NOT 0 AND v = ?)
+                  AND NOT EXISTS (SELECT 1 FROM transactions WHERE a IN ({}) AND v = ?)"#, fulltext_attrs).as_str())?;
+            for rowid in fulltext_rowids {
+                let params = [&rowid as &ToSql, &rowid as &ToSql, &rowid as &ToSql];
+                stmt.execute(&params[..])?;
+            }
+        }
+    }
+
+    // Record what was purged, each excision as its own entity.
+    let mut tempids: InternSet<TempId> = InternSet::new();
+    let mut terms: Vec<TermWithTempIds> = vec![];
+    for (i, excision) in excisions.iter().enumerate() {
+        let e = tempids.intern(TempId::Internal(i as i64));
+        terms.push(Term::AddOrRetract(OpType::Add, Either::Right(e.clone()), entids::DB_EXCISE, Either::Left(TypedValue::Ref(excision.target))));
+        if let Some(ref attrs) = excision.attrs {
+            for &a in attrs {
+                terms.push(Term::AddOrRetract(OpType::Add, Either::Right(e.clone()), entids::DB_EXCISE_ATTRS, Either::Left(TypedValue::Ref(a))));
+            }
+        }
+        if let Some(before_tx) = excision.before_tx {
+            terms.push(Term::AddOrRetract(OpType::Add, Either::Right(e.clone()), entids::DB_EXCISE_BEFORE, Either::Left(TypedValue::Ref(before_tx))));
+        }
+    }
+
+    let (report, next_partition_map, _next_schema, watcher) = transact_terms(conn, partition_map, schema, schema, watcher, tx_metadata, terms, tempids)?;
+    Ok((report, next_partition_map, watcher))
+}
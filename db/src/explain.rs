@@ -0,0 +1,130 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Explaining a transaction: what transacting some entities would do, without doing it.
+//!
+//! The transactor records what it resolves and writes as it goes, and `explain_transact` rolls the
+//! store back afterwards.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use rusqlite;
+use rusqlite::types::{
+    ToSql,
+};
+
+use mentat_core::{
+    Entid,
+    Schema,
+    TypedValue,
+};
+
+use db::{
+    ReducedEntity,
+    TypedSQLValue,
+};
+
+use errors::{
+    Result,
+};
+
+use metadata::{
+    MetadataReport,
+};
+
+use schema::{
+    SchemaBuilding,
+};
+
+/// How a tempid came to name an entity.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub enum TempIdResolution {
+    /// The tempid upserted to an existing entity.
+    Upserted(Entid),
+
+    /// The tempid didn't upsert, so a new entid was allocated for it.
+    Allocated(Entid),
+}
+
+impl TempIdResolution {
+    pub fn entid(&self) -> Entid {
+        match *self {
+            TempIdResolution::Upserted(e) => e,
+            TempIdResolution::Allocated(e) => e,
+        }
+    }
+}
+
+/// What transacting some entities would do.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TxExplanation {
+    /// The transaction that would have been recorded.
+    pub tx_id: Entid,
+
+    /// How each string literal tempid would resolve.
+    pub tempids: BTreeMap<String, TempIdResolution>,
+
+    /// The entity each `(lookup-ref a v)` resolves to, by `[a v]`.
+    pub lookup_refs: BTreeMap<(Entid, TypedValue), Entid>,
+
+    /// The `[e a v]` datoms that would be asserted.
+    pub asserted: Vec<(Entid, Entid, TypedValue)>,
+
+    /// The `[e a v]` datoms that would be retracted, either explicitly or by asserting a new value
+    /// for a cardinality one attribute.
+    pub retracted: Vec<(Entid, Entid, TypedValue)>,
+
+    /// The `[e a v added]` datoms that would change nothing: assertions of datoms already in the
+    /// store, and retractions of datoms that aren't.
+    pub unchanged: Vec<(Entid, Entid, TypedValue, bool)>,
+
+    /// The changes to the schema, if any.
+    pub schema_alterations: Option<MetadataReport>,
+}
+
+/// Compare the datoms the transactor sent to the store with those the transaction `tx_id` actually
+/// logged, recording the difference in `explanation`.
+pub(crate) fn explain_datoms<'a, I>(conn: &rusqlite::Connection, schema: &Schema, tx_id: Entid, requested: I, explanation: &mut TxExplanation) -> Result<()>
+where I: IntoIterator<Item=&'a ReducedEntity<'a>> {
+    let mut stmt = conn.prepare("SELECT e, a, v, value_type_tag, added FROM transactions WHERE tx = ? ORDER BY e, a, value_type_tag, v")?;
+    let rows: Result<Vec<(Entid, Entid, rusqlite::types::Value, i32, bool)>> = stmt.query_and_then(&[&tx_id as &ToSql], |row| -> Result<(Entid, Entid, rusqlite::types::Value, i32, bool)> {
+        Ok((row.get_checked(0)?, row.get_checked(1)?, row.get_checked(2)?, row.get_checked(3)?, row.get_checked(4)?))
+    })?.collect();
+
+    let mut logged: BTreeSet<(Entid, Entid, TypedValue, bool)> = BTreeSet::new();
+    for (e, a, v, value_type_tag, added) in rows? {
+        // A fulltext value is stored as the rowid of its text in `fulltext_values`.
+        let typed_value = if schema.require_attribute_for_entid(a)?.fulltext {
+            let text: String = conn.query_row("SELECT text FROM fulltext_values WHERE rowid = ?", &[&v as &ToSql], |row| row.get(0))?;
+            TypedValue::typed_string(text)
+        } else {
+            TypedValue::from_sql_value_pair(v, value_type_tag)?
+        };
+        if added {
+            explanation.asserted.push((e, a, typed_value.clone()));
+        } else {
+            explanation.retracted.push((e, a, typed_value.clone()));
+        }
+        logged.insert((e, a, typed_value, added));
+    }
+
+    for &(e, a, _, ref v, added) in requested {
+        let datom = (e, a, v.clone(), added);
+        if !logged.contains(&datom) {
+            explanation.unchanged.push(datom);
+        }
+    }
+    explanation.unchanged.sort();
+
+    Ok(())
+}
@@ -86,6 +86,7 @@ impl TransactableValue for ValueAndSpan {
                             EntityPlace::Entid(a) => Ok(EntityPlace::LookupRef(entities::LookupRef { a: entities::AttributePlace::Entid(a), v: v.clone() })),
                             EntityPlace::TempId(_) |
                             EntityPlace::TxFunction(_) |
+                            EntityPlace::PartitionedTempId(_) |
                             EntityPlace::LookupRef(_) => bail!(DbErrorKind::InputError(errors::InputError::BadEntityPlace)),
                         }
                     },
@@ -128,7 +129,8 @@ impl TransactableValue for TypedValue {
             TypedValue::Long(_) |
             TypedValue::Double(_) |
             TypedValue::Instant(_) |
-            TypedValue::Uuid(_) => bail!(DbErrorKind::InputError(errors::InputError::BadEntityPlace)),
+            TypedValue::Uuid(_) |
+            TypedValue::Tuple(_) => bail!(DbErrorKind::InputError(errors::InputError::BadEntityPlace)),
         }
     }
 
@@ -164,6 +166,29 @@ pub enum LookupRefOrTempId {
     TempId(TempIdHandle)
 }
 
+/// The precondition of a `[:db/cas e a old new]`: before the transaction, `e` has the value `old`
+/// for the cardinality one attribute `a`, or no value if `old` is `None`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cas<E, V> {
+    pub e: E,
+    pub a: Entid,
+    pub old: Option<V>,
+}
+
+pub type CasWithLookupRefs = Cas<KnownEntidOr<LookupRefOrTempId>, TypedValueOr<LookupRefOrTempId>>;
+pub type CasWithoutLookupRefs = Cas<KnownEntid, TypedValue>;
+
+/// A `[:db/retractEntity e]` or `[:db/retractAttribute e a]`, to be expanded into `[:db/retract e a
+/// v]` terms for the datoms in the store.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Retraction<E> {
+    Entity(E),
+    Attribute(E, Entid),
+}
+
+pub type RetractionWithLookupRefs = Retraction<KnownEntidOr<LookupRefOrTempId>>;
+pub type RetractionWithoutLookupRefs = Retraction<KnownEntid>;
+
 pub type TermWithTempIdsAndLookupRefs = Term<KnownEntidOr<LookupRefOrTempId>, TypedValueOr<LookupRefOrTempId>>;
 pub type TermWithTempIds = Term<KnownEntidOr<TempIdHandle>, TypedValueOr<TempIdHandle>>;
 pub type TermWithoutTempIds = Term<KnownEntid, TypedValue>;
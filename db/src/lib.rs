@@ -46,13 +46,19 @@ pub mod cache;
 pub mod db;
 mod bootstrap;
 pub mod entids;
+mod excision;
+mod explain;
 pub mod internal_types;    // pub because we need them for building entities programmatically.
 mod metadata;
+mod migration;
+mod registry;
+mod revert;
 mod schema;
 pub mod tx_observer;
 mod watcher;
 mod tx;
 mod tx_checking;
+pub mod tx_functions;
 pub mod types;
 mod upsert_resolution;
 
@@ -93,9 +99,47 @@ pub use watcher::{
     TransactWatcher,
 };
 
+pub use excision::{
+    Excision,
+    excise,
+};
+
+pub use explain::{
+    TempIdResolution,
+    TxExplanation,
+};
+
+pub use metadata::{
+    AttributeAlteration,
+    IdentAlteration,
+    MetadataReport,
+};
+
+pub use migration::{
+    migrate_value_type,
+};
+
+pub use registry::{
+    Registry,
+};
+
+pub use revert::{
+    revert_tx,
+};
+
 pub use tx::{
+    explain_transact,
     transact,
     transact_terms,
+    transact_with_options,
+    TxOptions,
+};
+
+pub use tx_functions::{
+    MAX_TX_FUNCTION_DEPTH,
+    TxFunctionContext,
+    TxFunctionOutput,
+    TxFunctions,
 };
 
 pub use tx_observer::{
@@ -18,9 +18,8 @@
 //! - they can add (and, eventually, retract and alter) schema attributes using various `:db/*`
 //!   attributes;
 //!
-//! - eventually, they will be able to add (and possibly retract) entid partitions using a Mentat
-//!   equivalent (perhaps :db/partition or :db.partition/start) to Datomic's `:db.install/partition`
-//!   attribute.
+//! - they can add (but not retract) entid partitions using the `:db.install/partition` attribute,
+//!   as in Datomic.  Partitions are installed by `db::install_partitions` rather than this module.
 //!
 //! This module recognizes, validates, applies, and reports on these mutations.
 
@@ -68,6 +67,8 @@ pub enum AttributeAlteration {
     NoHistory,
     /// - change whether an attribute is treated as a component
     IsComponent,
+    /// - change the value type of an attribute, when migrating its values to the new type
+    ValueType,
 }
 
 /// An alteration to an ident.
@@ -104,6 +105,12 @@ impl MetadataReport {
 ///
 /// Returns a report summarizing the mutations that were applied.
 pub fn update_attribute_map_from_entid_triples<A, R>(attribute_map: &mut AttributeMap, assertions: A, retractions: R) -> Result<MetadataReport>
+    where A: IntoIterator<Item=(Entid, Entid, TypedValue)>,
+          R: IntoIterator<Item=(Entid, Entid, TypedValue)> {
+    update_attribute_map(attribute_map, assertions, retractions, false)
+}
+
+fn update_attribute_map<A, R>(attribute_map: &mut AttributeMap, assertions: A, retractions: R, migrating: bool) -> Result<MetadataReport>
     where A: IntoIterator<Item=(Entid, Entid, TypedValue)>,
           R: IntoIterator<Item=(Entid, Entid, TypedValue)> {
 
@@ -173,6 +180,7 @@ pub fn update_attribute_map_from_entid_triples<A, R>(attribute_map: &mut Attribu
                     TypedValue::Ref(entids::DB_TYPE_REF)     => { builder.value_type(ValueType::Ref); },
                     TypedValue::Ref(entids::DB_TYPE_STRING)  => { builder.value_type(ValueType::String); },
                     TypedValue::Ref(entids::DB_TYPE_UUID)    => { builder.value_type(ValueType::Uuid); },
+                    TypedValue::Ref(entids::DB_TYPE_TUPLE)   => { builder.value_type(ValueType::Tuple); },
                     _ => bail!(DbErrorKind::BadSchemaAssertion(format!("Expected [... :db/valueType :db.type/*] but got [... :db/valueType {:?}] for entid {} and attribute {}", value, entid, attr)))
                 }
             },
@@ -221,6 +229,17 @@ pub fn update_attribute_map_from_entid_triples<A, R>(attribute_map: &mut Attribu
                 }
             },
 
+            entids::DB_TUPLE_ATTRS => {
+                let tuple_attrs: Option<Vec<Entid>> = match *value {
+                    TypedValue::Tuple(ref values) if !values.is_empty() => values.iter().map(|v| v.clone().into_entid()).collect(),
+                    _ => None,
+                };
+                match tuple_attrs {
+                    Some(tuple_attrs) => { builder.tuple_attrs(tuple_attrs); },
+                    None => bail!(DbErrorKind::BadSchemaAssertion(format!("Expected [... :db/tupleAttrs [attribute ...]] but got [... :db/tupleAttrs {:?}]", value)))
+                }
+            },
+
             _ => {
                 bail!(DbErrorKind::BadSchemaAssertion(format!("Do not recognize attribute {} for entid {}", attr, entid)))
             }
@@ -244,13 +263,57 @@ pub fn update_attribute_map_from_entid_triples<A, R>(attribute_map: &mut Attribu
             },
 
             Entry::Occupied(mut entry) => {
-                builder.validate_alter_attribute().context(DbErrorKind::BadSchemaAssertion(format!("Schema alteration for existing attribute with entid {} is not valid", entid)))?;
+                let valid = if migrating {
+                    builder.validate_migrate_attribute()
+                } else {
+                    builder.validate_alter_attribute()
+                };
+                valid.context(DbErrorKind::BadSchemaAssertion(format!("Schema alteration for existing attribute with entid {} is not valid", entid)))?;
                 let mutations = builder.mutate(entry.get_mut());
+
+                // A fulltext attribute must stay a string, and a component attribute a ref.
+                if mutations.contains(&AttributeAlteration::ValueType) {
+                    entry.get().validate(|| entid.to_string())?;
+                }
                 attributes_altered.insert(entid, mutations);
             },
         }
     }
 
+    // A composite tuple can only be checked against its components once they're installed, which
+    // might be in the same transaction.  Altering an attribute might also break the composites
+    // that name it as a component.
+    let mut composites: BTreeSet<Entid> = attributes_installed.iter().chain(attributes_altered.keys()).cloned().collect();
+    if !attributes_altered.is_empty() {
+        for (&c, attribute) in attribute_map.iter() {
+            if let Some(ref tuple_attrs) = attribute.tuple_attrs {
+                if tuple_attrs.iter().any(|a| attributes_altered.contains_key(a)) {
+                    composites.insert(c);
+                }
+            }
+        }
+    }
+    for entid in composites.iter() {
+        let attribute = &attribute_map[entid];
+        match attribute.tuple_attrs {
+            None if attribute.value_type == ValueType::Tuple && *entid != entids::DB_TUPLE_ATTRS => {
+                bail!(DbErrorKind::BadSchemaAssertion(format!(":db/valueType :db.type/tuple without :db/tupleAttrs for entid: {}", entid)))
+            },
+            None => {},
+            Some(ref tuple_attrs) => {
+                for component in tuple_attrs {
+                    match attribute_map.get(component) {
+                        None => bail!(DbErrorKind::BadSchemaAssertion(format!(":db/tupleAttrs names {}, which is not an attribute, for entid: {}", component, entid))),
+                        Some(c) if c.multival => bail!(DbErrorKind::BadSchemaAssertion(format!(":db/tupleAttrs names {}, which is :db.cardinality/many, for entid: {}", component, entid))),
+                        Some(c) if c.fulltext => bail!(DbErrorKind::BadSchemaAssertion(format!(":db/tupleAttrs names {}, which is :db/fulltext, for entid: {}", component, entid))),
+                        Some(c) if c.value_type == ValueType::Tuple => bail!(DbErrorKind::BadSchemaAssertion(format!(":db/tupleAttrs names {}, which is :db.type/tuple, for entid: {}", component, entid))),
+                        Some(_) => {},
+                    }
+                }
+            },
+        }
+    }
+
     Ok(MetadataReport {
         attributes_installed: attributes_installed,
         attributes_altered: attributes_altered,
@@ -268,6 +331,19 @@ pub fn update_attribute_map_from_entid_triples<A, R>(attribute_map: &mut Attribu
 /// Returns a report summarizing the mutations that were applied.
 pub fn update_schema_from_entid_quadruples<U>(schema: &mut Schema, assertions: U) -> Result<MetadataReport>
     where U: IntoIterator<Item=(Entid, Entid, TypedValue, bool)> {
+    update_schema(schema, assertions, false)
+}
+
+/// Just like `update_schema_from_entid_quadruples`, but the quadruples can change the
+/// `:db/valueType` of existing attributes.  That's only safe when the attributes' values are being
+/// migrated to the new type: see `migration::migrate_value_type`.
+pub(crate) fn migrate_schema_from_entid_quadruples<U>(schema: &mut Schema, assertions: U) -> Result<MetadataReport>
+    where U: IntoIterator<Item=(Entid, Entid, TypedValue, bool)> {
+    update_schema(schema, assertions, true)
+}
+
+fn update_schema<U>(schema: &mut Schema, assertions: U, migrating: bool) -> Result<MetadataReport>
+    where U: IntoIterator<Item=(Entid, Entid, TypedValue, bool)> {
 
     // Group attribute assertions into asserted, retracted, and updated.  We assume all our
     // attribute assertions are :db/cardinality :db.cardinality/one (so they'll only be added or
@@ -296,9 +372,10 @@ pub fn update_schema_from_entid_quadruples<U>(schema: &mut Schema, assertions: U
     let asserted_triples = attribute_set.asserted.into_iter().map(|((e, a), typed_value)| (e, a, typed_value));
     let altered_triples = attribute_set.altered.into_iter().map(|((e, a), (_old_value, new_value))| (e, a, new_value));
 
-    let report = update_attribute_map_from_entid_triples(&mut schema.attribute_map,
-                                                         asserted_triples.chain(altered_triples),
-                                                         retracted_triples)?;
+    let report = update_attribute_map(&mut schema.attribute_map,
+                                      asserted_triples.chain(altered_triples),
+                                      retracted_triples,
+                                      migrating)?;
 
     let mut idents_altered: BTreeMap<Entid, IdentAlteration> = BTreeMap::new();
 
@@ -324,6 +401,7 @@ pub fn update_schema_from_entid_quadruples<U>(schema: &mut Schema, assertions: U
 
     if report.attributes_did_change() {
         schema.update_component_attributes();
+        schema.update_composite_tuples();
     }
 
     Ok(MetadataReport {
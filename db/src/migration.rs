@@ -0,0 +1,192 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Value type migration: changing the `:db/valueType` of an existing attribute.
+//!
+//! Transacting a new `:db/valueType` for an existing attribute is an error, because the store's
+//! datoms would no longer agree with the schema.  A migration instead converts every value of the
+//! attribute with a caller-supplied conversion.  If any value can't be converted, nothing is
+//! changed.
+//!
+//! The migration is recorded as two transactions: the first retracts every value of the attribute
+//! and changes its `:db/valueType`, and the second asserts the converted values.  The transaction
+//! log keeps the values the attribute had before the migration, with their old type.
+
+use std::collections::{
+    BTreeSet,
+};
+
+use rusqlite;
+use rusqlite::types::{
+    ToSql,
+};
+
+use edn::{
+    InternSet,
+};
+
+use edn::entities::{
+    OpType,
+};
+
+use mentat_core::{
+    Attribute,
+    Entid,
+    HasSchema,
+    KnownEntid,
+    Schema,
+    TxReport,
+    TypedValue,
+    ValueType,
+};
+
+use mentat_core::util::Either;
+
+use bootstrap;
+
+use db::{
+    TypedSQLValue,
+};
+
+use entids;
+
+use errors::{
+    DbErrorKind,
+    Result,
+};
+
+use internal_types::{
+    Term,
+    TermWithTempIds,
+};
+
+use schema::{
+    AttributeValidation,
+    SchemaBuilding,
+};
+
+use tx::{
+    transact_terms,
+    transact_value_type_migration_terms,
+};
+
+use types::{
+    PartitionMap,
+};
+
+use watcher::{
+    TransactWatcher,
+};
+
+fn convert_value<F>(attribute: Entid, value_type: ValueType, convert: &F, value: &TypedValue) -> Result<TypedValue>
+    where F: Fn(&TypedValue) -> Option<TypedValue> {
+    match convert(value) {
+        Some(ref converted) if converted.value_type() == value_type => Ok(converted.clone()),
+        Some(converted) => bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, format!("{:?} was converted to {:?}, which is not a {}", value, converted, value_type))),
+        None => bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, format!("{:?} cannot be converted to a {}", value, value_type))),
+    }
+}
+
+/// Change the value type of `attribute` to `value_type`, converting each of its values `v` in the
+/// given SQLite `conn` to `convert(v)`, and record transactions asserting the given `tx_metadata`
+/// about themselves.  Returns the report of the transaction that asserts the converted values.
+///
+/// Fails, having changed nothing, if `convert` returns `None` or a value of the wrong type for any
+/// value, or if the converted values would violate the attribute's cardinality or uniqueness.  If
+/// you want the work to be undone on failure, establish a SQLite transaction on the connection
+/// prior to calling this function.
+pub fn migrate_value_type<'conn, 'a, W, F>(conn: &'conn rusqlite::Connection,
+                                          partition_map: PartitionMap,
+                                          schema: &'a Schema,
+                                          watcher: W,
+                                          tx_metadata: &'a [(Entid, TypedValue)],
+                                          attribute: Entid,
+                                          value_type: ValueType,
+                                          convert: F) -> Result<(TxReport, PartitionMap, Schema, W)>
+    where W: TransactWatcher,
+          F: Fn(&TypedValue) -> Option<TypedValue> {
+
+    let old_attribute = schema.require_attribute_for_entid(attribute)?.clone();
+    if bootstrap::is_bootstrap_entid(attribute) {
+        bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, "it is part of the bootstrap schema".into()));
+    }
+    if old_attribute.value_type == value_type {
+        bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, format!("it is already a {}", value_type)));
+    }
+
+    // Composite tuples are maintained from their components' values, so neither can change type.
+    if old_attribute.tuple_attrs.is_some() {
+        bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, "it is a composite tuple".into()));
+    }
+    if let Some(&c) = schema.composites_for_component(attribute).first() {
+        bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, format!("it is a component of composite tuple {}", c)));
+    }
+    if value_type == ValueType::Tuple {
+        bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, "only composites can be tuples".into()));
+    }
+
+    // A fulltext attribute must stay a string, and a component attribute a ref.
+    let new_attribute = Attribute {
+        value_type: value_type,
+        .. old_attribute.clone()
+    };
+    new_attribute.validate(|| attribute.to_string())
+                 .map_err(|e| DbErrorKind::ValueTypeMigrationFailed(attribute, e.to_string()))?;
+
+    let new_value_type = schema.require_entid(&value_type.into_keyword())?.0;
+
+    // Convert everything before writing anything.
+    let datoms: Vec<(Entid, TypedValue, TypedValue)> = {
+        let mut stmt = conn.prepare("SELECT e, v, value_type_tag FROM datoms WHERE a = ?")?;
+        let rows: Result<Vec<(Entid, TypedValue)>> = stmt.query_and_then(&[&attribute as &ToSql], |row| -> Result<(Entid, TypedValue)> {
+            Ok((row.get_checked(0)?,
+                TypedValue::from_sql_value_pair(row.get_checked(1)?, row.get_checked(2)?)?))
+        })?.collect();
+        let mut datoms = vec![];
+        for (e, v) in rows? {
+            let converted = convert_value(attribute, value_type, &convert, &v)?;
+            datoms.push((e, v, converted));
+        }
+        datoms
+    };
+
+    // Distinct values can convert to the same value, like 1.2 and 1.4 to 1.
+    {
+        let mut seen_datoms: BTreeSet<(Entid, &TypedValue)> = BTreeSet::new();
+        let mut seen_values: BTreeSet<&TypedValue> = BTreeSet::new();
+        for &(e, _, ref converted) in &datoms {
+            if !seen_datoms.insert((e, converted)) {
+                bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, format!("entity {} would have {:?} more than once", e, converted)));
+            }
+            if old_attribute.unique.is_some() && !seen_values.insert(converted) {
+                bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, format!("more than one entity would have the unique value {:?}", converted)));
+            }
+        }
+    }
+
+    // Retract the old values and change the value type...
+    let retractions: Vec<TermWithTempIds> = datoms.iter()
+        .map(|&(e, ref v, _)| Term::AddOrRetract(OpType::Retract, Either::Left(KnownEntid(e)), attribute, Either::Left(v.clone())))
+        .chain(::std::iter::once(Term::AddOrRetract(OpType::Add, Either::Left(KnownEntid(attribute)), entids::DB_VALUE_TYPE, Either::Left(TypedValue::Ref(new_value_type)))))
+        .collect();
+    let (_report, partition_map, next_schema, watcher) = transact_value_type_migration_terms(conn, partition_map, schema, schema, watcher, tx_metadata, retractions)?;
+    let next_schema = match next_schema {
+        Some(next_schema) => next_schema,
+        None => bail!(DbErrorKind::ValueTypeMigrationFailed(attribute, "the value type did not change".into())),
+    };
+
+    // ... then assert the new values against the new schema.
+    let assertions: Vec<TermWithTempIds> = datoms.into_iter()
+        .map(|(e, _, converted)| Term::AddOrRetract(OpType::Add, Either::Left(KnownEntid(e)), attribute, Either::Left(converted)))
+        .collect();
+    let (report, partition_map, _next_schema, watcher) = transact_terms(conn, partition_map, &next_schema, &next_schema, watcher, tx_metadata, assertions, InternSet::new())?;
+
+    Ok((report, partition_map, next_schema, watcher))
+}
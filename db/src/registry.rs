@@ -0,0 +1,79 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! User-registered functions, named by keywords.
+//!
+//! Transaction functions and constraints are both looked up this way.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::fmt;
+use std::sync::Arc;
+
+use mentat_core::{
+    Keyword,
+};
+
+/// Functions of the unsized type `F`, like `TxFunctionFn`, by name.  Cloning a registry shares
+/// the functions rather than copying them.
+pub struct Registry<F: ?Sized> {
+    functions: BTreeMap<Keyword, Arc<Box<F>>>,
+}
+
+impl<F: ?Sized> Registry<F> {
+    /// Register `function` under `name`, replacing any function previously registered under it.
+    pub fn register(&mut self, name: Keyword, function: Box<F>) {
+        self.functions.insert(name, Arc::new(function));
+    }
+
+    /// Returns `true` if a function was registered under `name`.
+    pub fn deregister(&mut self, name: &Keyword) -> bool {
+        self.functions.remove(name).is_some()
+    }
+
+    pub fn is_registered(&self, name: &Keyword) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    pub fn get(&self, name: &Keyword) -> Option<Arc<Box<F>>> {
+        self.functions.get(name).cloned()
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<Keyword, Arc<Box<F>>> {
+        self.functions.iter()
+    }
+}
+
+// Derived `Clone` and `Default` would needlessly require `F: Clone` and `F: Default`.
+impl<F: ?Sized> Clone for Registry<F> {
+    fn clone(&self) -> Self {
+        Registry {
+            functions: self.functions.clone(),
+        }
+    }
+}
+
+impl<F: ?Sized> Default for Registry<F> {
+    fn default() -> Self {
+        Registry {
+            functions: BTreeMap::new(),
+        }
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Registry<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.functions.keys()).finish()
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Reverting a transaction: transacting its inverse.
+//!
+//! Every datom a transaction asserted is retracted, and every datom it retracted -- explicitly, or
+//! implicitly by asserting a new value for a cardinality one attribute -- is reasserted.  Datoms
+//! about the transaction entity itself, like its `:db/txInstant`, are left alone.
+//!
+//! A later transaction that changed the same cardinality one attribute of an entity, or the same
+//! value of a cardinality many attribute, conflicts with the revert: undoing the earlier change
+//! would silently undo part of the later one too.  Such reverts fail, having changed nothing.
+//!
+//! The history of `:db/noHistory` attributes isn't kept, so changes to them can't be reverted: a
+//! revert of a transaction that asserted a value of such an attribute fails rather than reverting
+//! only the rest of the transaction.  Retractions of such attributes aren't logged at all, so a
+//! transaction that only retracted their values looks to a revert as if it never touched them.
+
+use std::collections::{
+    BTreeSet,
+};
+
+use rusqlite;
+use rusqlite::types::{
+    ToSql,
+};
+
+use edn::{
+    InternSet,
+};
+
+use edn::entities::{
+    OpType,
+};
+
+use mentat_core::{
+    Entid,
+    KnownEntid,
+    Schema,
+    TxReport,
+    TypedValue,
+};
+
+use mentat_core::util::Either;
+
+use db::{
+    TypedSQLValue,
+};
+
+use errors::{
+    DbErrorKind,
+    Result,
+};
+
+use internal_types::{
+    Term,
+    TermWithTempIds,
+};
+
+use schema::{
+    SchemaBuilding,
+};
+
+use tx::{
+    transact_terms,
+};
+
+use types::{
+    PartitionMap,
+};
+
+use watcher::{
+    TransactWatcher,
+};
+
+/// A datom from the `transactions` table, with its value as stored, for matching against other
+/// rows, and as typed, for transacting.
+struct LoggedDatom {
+    e: Entid,
+    a: Entid,
+    v: rusqlite::types::Value,
+    value_type_tag: i32,
+    typed_value: TypedValue,
+    added: bool,
+}
+
+fn logged_datoms(conn: &rusqlite::Connection, schema: &Schema, tx: Entid) -> Result<Vec<LoggedDatom>> {
+    let mut stmt = conn.prepare("SELECT e, a, v, value_type_tag, added FROM transactions WHERE tx = ? AND e IS NOT ? ORDER BY e, a, value_type_tag, v")?;
+    let rows: Result<Vec<(Entid, Entid, rusqlite::types::Value, i32, bool)>> = stmt.query_and_then(&[&tx as &ToSql, &tx as &ToSql], |row| -> Result<(Entid, Entid, rusqlite::types::Value, i32, bool)> {
+        Ok((row.get_checked(0)?, row.get_checked(1)?, row.get_checked(2)?, row.get_checked(3)?, row.get_checked(4)?))
+    })?.collect();
+
+    let mut datoms = vec![];
+    for (e, a, v, value_type_tag, added) in rows? {
+        // A fulltext value is stored as the rowid of its text in `fulltext_values`.
+        let typed_value = if schema.require_attribute_for_entid(a)?.fulltext {
+            let text: String = conn.query_row("SELECT text FROM fulltext_values WHERE rowid = ?", &[&v as &ToSql], |row| row.get(0))?;
+            TypedValue::typed_string(text)
+        } else {
+            TypedValue::from_sql_value_pair(v.clone(), value_type_tag)?
+        };
+        datoms.push(LoggedDatom { e, a, v, value_type_tag, typed_value, added });
+    }
+    Ok(datoms)
+}
+
+/// The first transaction after `tx` that changed the same cardinality one attribute of `datom`'s
+/// entity, or the same value of a cardinality many attribute, if any.
+fn later_conflict(conn: &rusqlite::Connection, schema: &Schema, tx: Entid, datom: &LoggedDatom) -> Result<Option<Entid>> {
+    let multival = schema.require_attribute_for_entid(datom.a)?.multival;
+    let conflict: Option<Entid> = if multival {
+        let mut stmt = conn.prepare_cached("SELECT tx FROM transactions WHERE tx > ? AND e = ? AND a = ? AND v = ? AND value_type_tag = ? ORDER BY tx LIMIT 1")?;
+        let mut rows = stmt.query_and_then(&[&tx as &ToSql, &datom.e as &ToSql, &datom.a as &ToSql, &datom.v as &ToSql, &datom.value_type_tag as &ToSql], |row| -> Result<Entid> {
+            Ok(row.get_checked(0)?)
+        })?;
+        match rows.next() {
+            Some(tx) => Some(tx?),
+            None => None,
+        }
+    } else {
+        let mut stmt = conn.prepare_cached("SELECT tx FROM transactions WHERE tx > ? AND e = ? AND a = ? ORDER BY tx LIMIT 1")?;
+        let mut rows = stmt.query_and_then(&[&tx as &ToSql, &datom.e as &ToSql, &datom.a as &ToSql], |row| -> Result<Entid> {
+            Ok(row.get_checked(0)?)
+        })?;
+        match rows.next() {
+            Some(tx) => Some(tx?),
+            None => None,
+        }
+    };
+    Ok(conflict)
+}
+
+/// Transact the inverse of the transaction `tx` against the given SQLite `conn`, recording a
+/// transaction asserting the given `tx_metadata` about itself.
+///
+/// Fails, having changed nothing, if `tx` isn't a transaction in the store, if it changed a
+/// `:db/noHistory` attribute, or if a later transaction changed any of the same datoms.
+///
+/// If you want this work to occur inside a SQLite transaction, establish one on the connection
+/// prior to calling this function.
+pub fn revert_tx<'conn, 'a, W>(conn: &'conn rusqlite::Connection,
+                               partition_map: PartitionMap,
+                               schema_for_mutation: &'a Schema,
+                               schema: &'a Schema,
+                               watcher: W,
+                               tx_metadata: &'a [(Entid, TypedValue)],
+                               tx: Entid) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
+    where W: TransactWatcher {
+
+    if !partition_map.get(":db.part/tx").map_or(false, |partition| partition.contains_entid(tx)) {
+        bail!(DbErrorKind::RevertTxFailed(tx, "it is not a transaction".into()));
+    }
+    let logged: i64 = conn.query_row("SELECT COUNT(*) FROM transactions WHERE tx = ?", &[&tx as &ToSql], |row| row.get(0))?;
+    if logged == 0 {
+        bail!(DbErrorKind::RevertTxFailed(tx, "it is not a transaction".into()));
+    }
+
+    let datoms = logged_datoms(conn, schema, tx)?;
+    for datom in &datoms {
+        if schema.require_attribute_for_entid(datom.a)?.no_history {
+            bail!(DbErrorKind::RevertTxFailed(tx, format!("attribute {} is :db/noHistory", datom.a)));
+        }
+    }
+    for datom in &datoms {
+        if let Some(later) = later_conflict(conn, schema, tx, datom)? {
+            bail!(DbErrorKind::RevertTxFailed(tx, format!("transaction {} later changed [{} {} {:?}]", later, datom.e, datom.a, datom.typed_value)));
+        }
+    }
+
+    // Reasserting the old value of a cardinality one attribute implicitly retracts the new one, so
+    // we don't retract it explicitly.
+    let mut reasserted: BTreeSet<(Entid, Entid)> = BTreeSet::new();
+    for datom in &datoms {
+        if !datom.added && !schema.require_attribute_for_entid(datom.a)?.multival {
+            reasserted.insert((datom.e, datom.a));
+        }
+    }
+
+    let terms: Vec<TermWithTempIds> = datoms.into_iter()
+        .filter(|datom| !(datom.added && reasserted.contains(&(datom.e, datom.a))))
+        .map(|datom| {
+            let op = if datom.added { OpType::Retract } else { OpType::Add };
+            Term::AddOrRetract(op, Either::Left(KnownEntid(datom.e)), datom.a, Either::Left(datom.typed_value))
+        })
+        .collect();
+
+    transact_terms(conn, partition_map, schema_for_mutation, schema, watcher, tx_metadata, terms, InternSet::new())
+}
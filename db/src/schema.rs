@@ -17,6 +17,7 @@ use errors::{
     Result,
 };
 use edn::symbols;
+use entids;
 use mentat_core::{
     attribute,
     Attribute,
@@ -56,6 +57,12 @@ impl AttributeValidation for Attribute {
         if self.component && self.value_type != ValueType::Ref {
             bail!(DbErrorKind::BadSchemaAssertion(format!(":db/isComponent true without :db/valueType :db.type/ref for entid: {}", ident())))
         }
+        if self.tuple_attrs.is_some() && self.value_type != ValueType::Tuple {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(":db/tupleAttrs without :db/valueType :db.type/tuple for entid: {}", ident())))
+        }
+        if self.tuple_attrs.is_some() && self.multival {
+            bail!(DbErrorKind::BadSchemaAssertion(format!(":db/tupleAttrs with :db/cardinality :db.cardinality/many for entid: {}", ident())))
+        }
         // TODO: consider warning if we have :db/index true for :db/valueType :db.type/string,
         // since this may be inefficient.  More generally, we should try to drive complex
         // :db/valueType (string, uri, json in the future) users to opt-in to some hash-indexing
@@ -83,6 +90,7 @@ pub struct AttributeBuilder {
     pub fulltext: Option<bool>,
     pub component: Option<bool>,
     pub no_history: Option<bool>,
+    pub tuple_attrs: Option<Vec<Entid>>,
 }
 
 impl AttributeBuilder {
@@ -151,6 +159,11 @@ impl AttributeBuilder {
         self
     }
 
+    pub fn tuple_attrs<'a>(&'a mut self, tuple_attrs: Vec<Entid>) -> &'a mut Self {
+        self.tuple_attrs = Some(tuple_attrs);
+        self
+    }
+
     pub fn validate_install_attribute(&self) -> Result<()> {
         if self.value_type.is_none() {
             bail!(DbErrorKind::BadSchemaAssertion("Schema attribute for new attribute does not set :db/valueType".into()));
@@ -162,9 +175,18 @@ impl AttributeBuilder {
         if self.value_type.is_some() {
             bail!(DbErrorKind::BadSchemaAssertion("Schema alteration must not set :db/valueType".into()));
         }
+        self.validate_migrate_attribute()
+    }
+
+    /// Like `validate_alter_attribute`, but allows changing `:db/valueType`, which is only safe
+    /// when migrating an attribute's values to the new type.
+    pub fn validate_migrate_attribute(&self) -> Result<()> {
         if self.fulltext.is_some() {
             bail!(DbErrorKind::BadSchemaAssertion("Schema alteration must not set :db/fulltext".into()));
         }
+        if self.tuple_attrs.is_some() {
+            bail!(DbErrorKind::BadSchemaAssertion("Schema alteration must not set :db/tupleAttrs".into()));
+        }
         Ok(())
     }
 
@@ -191,12 +213,21 @@ impl AttributeBuilder {
         if let Some(no_history) = self.no_history {
             attribute.no_history = no_history;
         }
+        if let Some(ref tuple_attrs) = self.tuple_attrs {
+            attribute.tuple_attrs = Some(tuple_attrs.clone());
+        }
 
         attribute
     }
 
     pub fn mutate(&self, attribute: &mut Attribute) -> Vec<AttributeAlteration> {
         let mut mutations = Vec::new();
+        if let Some(value_type) = self.value_type {
+            if value_type != attribute.value_type {
+                attribute.value_type = value_type;
+                mutations.push(AttributeAlteration::ValueType);
+            }
+        }
         if let Some(multival) = self.multival {
             if multival != attribute.multival {
                 attribute.multival = multival;
@@ -285,9 +316,10 @@ impl SchemaBuilding for Schema {
                                                                                 // No retractions.
                                                                                 ::std::iter::empty())?;
 
-        // Rebuild the component attributes list if necessary.
+        // Rebuild the component attributes list and composite tuples index if necessary.
         if metadata_report.attributes_did_change() {
             schema.update_component_attributes();
+            schema.update_composite_tuples();
         }
         Ok(schema)
     }
@@ -299,6 +331,14 @@ pub trait SchemaTypeChecking {
     /// Either assert that the given value is in the value type's value set, or (in limited cases)
     /// coerce the given value into the value type's value set.
     fn to_typed_value(&self, value: &edn::ValueAndSpan, value_type: ValueType) -> Result<TypedValue>;
+
+    /// The value type of the element at `index` of values of the tuple attribute `a`, if the
+    /// schema constrains it.
+    fn tuple_element_type(&self, a: Entid, index: usize) -> Option<ValueType>;
+
+    /// Typecheck and coerce the given elements of a value of the tuple attribute `a`, like
+    /// `to_typed_value` does for other values.
+    fn to_typed_tuple(&self, a: Entid, elements: Vec<TypedValue>) -> Result<TypedValue>;
 }
 
 impl SchemaTypeChecking for Schema {
@@ -318,6 +358,7 @@ impl SchemaTypeChecking for Schema {
                 (ValueType::Uuid, tv @ TypedValue::Uuid(_)) => Ok(tv),
                 (ValueType::Instant, tv @ TypedValue::Instant(_)) => Ok(tv),
                 (ValueType::Keyword, tv @ TypedValue::Keyword(_)) => Ok(tv),
+                (ValueType::Tuple, tv @ TypedValue::Tuple(_)) => Ok(tv),
                 // Ref coerces a little: we interpret some things depending on the schema as a Ref.
                 (ValueType::Ref, TypedValue::Long(x)) => Ok(TypedValue::Ref(x)),
                 (ValueType::Ref, TypedValue::Keyword(ref x)) => self.require_entid(&x).map(|entid| entid.into()),
@@ -333,11 +374,50 @@ impl SchemaTypeChecking for Schema {
                 (vt @ ValueType::Uuid, _) |
                 (vt @ ValueType::Instant, _) |
                 (vt @ ValueType::Keyword, _) |
+                (vt @ ValueType::Tuple, _) |
                 (vt @ ValueType::Ref, _)
                 => bail!(DbErrorKind::BadValuePair(format!("{}", value), vt)),
             }
         }
     }
+
+    fn tuple_element_type(&self, a: Entid, index: usize) -> Option<ValueType> {
+        // The elements of `:db/tupleAttrs` are attributes.
+        if a == entids::DB_TUPLE_ATTRS {
+            return Some(ValueType::Ref);
+        }
+        self.attribute_for_entid(a)
+            .and_then(|attribute| attribute.tuple_attrs.as_ref())
+            .and_then(|tuple_attrs| tuple_attrs.get(index))
+            .and_then(|&component| self.attribute_for_entid(component))
+            .map(|component| component.value_type)
+    }
+
+    fn to_typed_tuple(&self, a: Entid, elements: Vec<TypedValue>) -> Result<TypedValue> {
+        if let Some(tuple_attrs) = self.attribute_for_entid(a).and_then(|attribute| attribute.tuple_attrs.as_ref()) {
+            if elements.len() != tuple_attrs.len() {
+                bail!(DbErrorKind::BadValuePair(format!("{:?}", elements), ValueType::Tuple));
+            }
+        }
+
+        let mut typed_elements = Vec::with_capacity(elements.len());
+        for (index, element) in elements.into_iter().enumerate() {
+            let typed_element = match (self.tuple_element_type(a, index), element) {
+                (None, element) => element,
+                // Ref coerces a little, as in `to_typed_value`.
+                (Some(ValueType::Ref), TypedValue::Long(x)) => TypedValue::Ref(x),
+                (Some(ValueType::Ref), TypedValue::Keyword(ref x)) => self.require_entid(&x)?.into(),
+                (Some(value_type), element) => {
+                    if element.value_type() != value_type {
+                        bail!(DbErrorKind::BadValuePair(format!("{:?}", element), value_type));
+                    }
+                    element
+                },
+            };
+            typed_elements.push(typed_element);
+        }
+        Ok(typed_elements.into())
+    }
 }
 
 
@@ -374,6 +454,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         });
         // attribute is unique by value and an index
         add_attribute(&mut schema, Keyword::namespaced("foo", "baz"), 98, Attribute {
@@ -384,6 +465,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         });
         // attribue is unique by identity and an index
         add_attribute(&mut schema, Keyword::namespaced("foo", "bat"), 99, Attribute {
@@ -394,6 +476,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         });
         // attribute is a components and a `Ref`
         add_attribute(&mut schema, Keyword::namespaced("foo", "bak"), 100, Attribute {
@@ -404,6 +487,7 @@ mod test {
             multival: false,
             component: true,
             no_history: false,
+            tuple_attrs: None,
         });
         // fulltext attribute is a string and an index
         add_attribute(&mut schema, Keyword::namespaced("foo", "bap"), 101, Attribute {
@@ -414,6 +498,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         });
 
         assert!(validate_attribute_map(&schema.entid_map, &schema.attribute_map).is_ok());
@@ -432,6 +517,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
@@ -450,6 +536,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
@@ -468,6 +555,7 @@ mod test {
             multival: false,
             component: true,
             no_history: false,
+            tuple_attrs: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
@@ -486,6 +574,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
@@ -503,6 +592,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            tuple_attrs: None,
         });
 
         let err = validate_attribute_map(&schema.entid_map, &schema.attribute_map).err().map(|e| e.kind());
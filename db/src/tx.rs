@@ -61,6 +61,11 @@ use db;
 use db::{
     MentatStoring,
 };
+use explain::{
+    TempIdResolution,
+    TxExplanation,
+    explain_datoms,
+};
 use edn::{
     InternSet,
     Keyword,
@@ -74,9 +79,15 @@ use errors::{
 use internal_types::{
     AddAndRetract,
     AEVTrie,
+    Cas,
+    CasWithLookupRefs,
+    CasWithoutLookupRefs,
     KnownEntidOr,
     LookupRef,
     LookupRefOrTempId,
+    Retraction,
+    RetractionWithLookupRefs,
+    RetractionWithoutLookupRefs,
     TempIdHandle,
     TempIdMap,
     Term,
@@ -110,8 +121,14 @@ use metadata;
 use rusqlite;
 use schema::{
     SchemaBuilding,
+    SchemaTypeChecking,
 };
 use tx_checking;
+use tx_functions::{
+    MAX_TX_FUNCTION_DEPTH,
+    TxFunctionContext,
+    TxFunctions,
+};
 use types::{
     AVMap,
     AVPair,
@@ -127,9 +144,53 @@ use upsert_resolution::{
     Generation,
 };
 use watcher::{
+    NullWatcher,
     TransactWatcher,
 };
 
+/// What a transaction needs besides its entities, all optional: the user-registered transaction
+/// functions that the entities can call, further `[a v]` pairs to assert about the transaction
+/// entity itself, and the entities that earlier transactions allocated or upserted for named
+/// tempids.
+///
+/// ```ignore
+/// let options = TxOptions::new().tx_functions(&tx_functions).tx_metadata(&tx_metadata);
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TxOptions<'a> {
+    tx_functions: Option<&'a TxFunctions>,
+    tx_metadata: &'a [(Entid, TypedValue)],
+    known_temp_ids: Option<&'a BTreeMap<String, Entid>>,
+}
+
+impl<'a> TxOptions<'a> {
+    pub fn new() -> TxOptions<'a> {
+        TxOptions::default()
+    }
+
+    /// Let the entities call the functions registered in `tx_functions`.
+    pub fn tx_functions(mut self, tx_functions: &'a TxFunctions) -> TxOptions<'a> {
+        self.tx_functions = Some(tx_functions);
+        self
+    }
+
+    /// Assert each `[a v]` pair of `tx_metadata` about the transaction entity.  This is how to
+    /// record who made a change and why, or to supply a `:db/txInstant` when importing historical
+    /// data.
+    pub fn tx_metadata(mut self, tx_metadata: &'a [(Entid, TypedValue)]) -> TxOptions<'a> {
+        self.tx_metadata = tx_metadata;
+        self
+    }
+
+    /// Make each tempid named in `known_temp_ids` refer to the given entity rather than upserting
+    /// or being allocated.  This lets a sequence of transactions, like the batches of a bulk
+    /// import, share tempids: pass the accumulated `tempids` of the earlier transactions' reports.
+    pub fn known_temp_ids(mut self, known_temp_ids: &'a BTreeMap<String, Entid>) -> TxOptions<'a> {
+        self.known_temp_ids = Some(known_temp_ids);
+        self
+    }
+}
+
 /// A transaction on its way to being applied.
 #[derive(Debug)]
 pub struct Tx<'conn, 'a, W> where W: TransactWatcher {
@@ -155,6 +216,20 @@ pub struct Tx<'conn, 'a, W> where W: TransactWatcher {
 
     watcher: W,
 
+    /// Transaction functions, transaction metadata, and known tempids.
+    options: TxOptions<'a>,
+
+    /// The partition to allocate each tempid in, for tempids like `(tempid :my.part)`.  Other
+    /// tempids are allocated in `:db.part/user`.
+    temp_id_partitions: BTreeMap<TempId, String>,
+
+    /// What the transaction resolves and writes, if it is being explained.
+    explanation: Option<TxExplanation>,
+
+    /// Whether the transaction can change the `:db/valueType` of attributes whose values it
+    /// retracts, as when migrating them to a new type.
+    migrating_value_types: bool,
+
     /// The transaction ID of the transaction.
     tx_id: Entid,
 }
@@ -171,6 +246,7 @@ pub fn remove_db_id<V: TransactableValue>(map: &mut entmod::MapNotation<V>) -> R
             entmod::ValuePlace::LookupRef(e) => Some(entmod::EntityPlace::LookupRef(e)),
             entmod::ValuePlace::TempId(e) => Some(entmod::EntityPlace::TempId(e)),
             entmod::ValuePlace::TxFunction(e) => Some(entmod::EntityPlace::TxFunction(e)),
+            entmod::ValuePlace::PartitionedTempId(e) => Some(entmod::EntityPlace::PartitionedTempId(e)),
             entmod::ValuePlace::Atom(v) => Some(v.into_entity_place()?),
             entmod::ValuePlace::Vector(_) |
             entmod::ValuePlace::MapNotation(_) => {
@@ -184,6 +260,227 @@ pub fn remove_db_id<V: TransactableValue>(map: &mut entmod::MapNotation<V>) -> R
     Ok(db_id)
 }
 
+/// The state of pipeline stage 1, shared by all the entities of a transaction, including those
+/// returned by transaction functions.
+struct InProcess<'a> {
+    store: &'a rusqlite::Connection,
+    partition_map: &'a PartitionMap,
+    schema: &'a Schema,
+    tx_functions: Option<&'a TxFunctions>,
+    mentat_id_count: i64,
+    tx_id: KnownEntid,
+    temp_ids: InternSet<TempId>,
+    temp_id_partitions: BTreeMap<TempId, String>,
+    lookup_refs: InternSet<AVPair>,
+    /// Entities returned by transaction functions, still to be converted.
+    spliced: Vec<Entity<TypedValue>>,
+}
+
+impl<'a> InProcess<'a> {
+    fn with_schema_and_partition_map(store: &'a rusqlite::Connection, schema: &'a Schema, partition_map: &'a PartitionMap, tx_functions: Option<&'a TxFunctions>, tx_id: KnownEntid) -> InProcess<'a> {
+        InProcess {
+            store,
+            partition_map,
+            schema,
+            tx_functions,
+            mentat_id_count: 0,
+            tx_id,
+            temp_ids: InternSet::new(),
+            temp_id_partitions: BTreeMap::default(),
+            lookup_refs: InternSet::new(),
+            spliced: vec![],
+        }
+    }
+
+    /// Call `tx_function`, returning the value that stands in its place.  Entities returned by a
+    /// user-registered function are saved in `spliced`, to be converted in turn.
+    fn call_tx_function(&mut self, tx_function: &entmod::TxFunction) -> Result<TypedValue> {
+        if tx_function.op.0 == "transaction-tx" {
+            return Ok(TypedValue::Ref(self.tx_id.0));
+        }
+
+        let function = match self.tx_functions.and_then(|tx_functions| tx_functions.get(&tx_function.keyword())) {
+            Some(function) => function,
+            None => bail!(DbErrorKind::UnknownTxFunction(tx_function.keyword())),
+        };
+        let output = (*function)(&TxFunctionContext::new(self.store, self.schema, self.tx_id.0))?;
+        self.spliced.extend(output.entities);
+        Ok(output.value)
+    }
+
+    /// Call `tx_function` in entity position, where it must produce a ref.
+    fn call_tx_function_for_entity(&mut self, tx_function: &entmod::TxFunction) -> Result<KnownEntid> {
+        match self.call_tx_function(tx_function)? {
+            TypedValue::Ref(e) => Ok(KnownEntid(e)),
+            v => bail!(DbErrorKind::TxFunctionValueTypeMismatch {
+                function: tx_function.keyword(),
+                expected: ValueType::Ref,
+                actual: v.value_type(),
+            }),
+        }
+    }
+
+    fn ensure_entid_exists(&self, e: Entid) -> Result<KnownEntid> {
+        if self.partition_map.contains_entid(e) {
+            Ok(KnownEntid(e))
+        } else {
+            bail!(DbErrorKind::UnrecognizedEntid(e))
+        }
+    }
+
+    fn ensure_ident_exists(&self, e: &Keyword) -> Result<KnownEntid> {
+        self.schema.require_entid(e)
+    }
+
+    fn intern_lookup_ref<W: TransactableValue>(&mut self, lookup_ref: &entmod::LookupRef<W>) -> Result<LookupRef> {
+        let lr_a: i64 = match lookup_ref.a {
+            AttributePlace::Entid(entmod::EntidOrIdent::Entid(ref a)) => *a,
+            AttributePlace::Entid(entmod::EntidOrIdent::Ident(ref a)) => self.schema.require_entid(&a)?.into(),
+        };
+        let lr_attribute: &Attribute = self.schema.require_attribute_for_entid(lr_a)?;
+
+        let lr_typed_value: TypedValue = match lookup_ref.v.clone().into_typed_value(&self.schema, lr_attribute.value_type)? {
+            // Like `[:visit/page+date [123 #inst "2018-01-01"]]`, which looks up a composite tuple.
+            TypedValue::Tuple(elements) => self.schema.to_typed_tuple(lr_a, (*elements).clone())?,
+            v => v,
+        };
+        if lr_attribute.unique.is_none() {
+            bail!(DbErrorKind::NotYetImplemented(format!("Cannot resolve (lookup-ref {} {:?}) with attribute that is not :db/unique", lr_a, lr_typed_value)))
+        }
+
+        Ok(self.lookup_refs.intern((lr_a, lr_typed_value)))
+    }
+
+    /// Allocate private internal tempids reserved for Mentat.  Internal tempids just need to be
+    /// unique within one transaction; they should never escape a transaction.
+    fn allocate_mentat_id<W: TransactableValue>(&mut self) -> entmod::EntityPlace<W> {
+        self.mentat_id_count += 1;
+        entmod::EntityPlace::TempId(TempId::Internal(self.mentat_id_count).into())
+    }
+
+    /// Intern the tempid `(tempid :my.part)` or `(tempid :my.part "name")`, remembering which
+    /// partition to allocate it in.
+    fn intern_partitioned_temp_id(&mut self, temp_id: &entmod::PartitionedTempId) -> Result<TempIdHandle> {
+        let partition = temp_id.partition.to_string();
+        if !self.partition_map.contains_key(&partition) {
+            bail!(DbErrorKind::UnknownPartition(partition));
+        }
+
+        let tempid = match temp_id.name {
+            Some(ref name) => TempId::External(name.clone()),
+            None => {
+                self.mentat_id_count += 1;
+                TempId::Internal(self.mentat_id_count)
+            },
+        };
+
+        if let Some(existing) = self.temp_id_partitions.get(&tempid) {
+            if *existing != partition {
+                bail!(DbErrorKind::ConflictingTempIdPartitions(tempid.to_string(), existing.clone(), partition));
+            }
+        }
+        self.temp_id_partitions.insert(tempid.clone(), partition);
+
+        Ok(self.temp_ids.intern(tempid))
+    }
+
+    fn entity_e_into_term_e<W: TransactableValue>(&mut self, x: entmod::EntityPlace<W>) -> Result<KnownEntidOr<LookupRefOrTempId>> {
+        match x {
+            entmod::EntityPlace::Entid(e) => {
+                let e = match e {
+                    entmod::EntidOrIdent::Entid(ref e) => self.ensure_entid_exists(*e)?,
+                    entmod::EntidOrIdent::Ident(ref e) => self.ensure_ident_exists(&e)?,
+                };
+                Ok(Either::Left(e))
+            },
+
+            entmod::EntityPlace::TempId(e) => {
+                Ok(Either::Right(LookupRefOrTempId::TempId(self.temp_ids.intern(e))))
+            },
+
+            entmod::EntityPlace::LookupRef(ref lookup_ref) => {
+                Ok(Either::Right(LookupRefOrTempId::LookupRef(self.intern_lookup_ref(lookup_ref)?)))
+            },
+
+            entmod::EntityPlace::TxFunction(ref tx_function) => {
+                Ok(Either::Left(self.call_tx_function_for_entity(tx_function)?))
+            },
+
+            entmod::EntityPlace::PartitionedTempId(ref temp_id) => {
+                Ok(Either::Right(LookupRefOrTempId::TempId(self.intern_partitioned_temp_id(temp_id)?)))
+            },
+        }
+    }
+
+    fn entity_a_into_term_a(&mut self, x: entmod::EntidOrIdent) -> Result<Entid> {
+        let a = match x {
+            entmod::EntidOrIdent::Entid(ref a) => *a,
+            entmod::EntidOrIdent::Ident(ref a) => self.schema.require_entid(&a)?.into(),
+        };
+        Ok(a)
+    }
+
+    fn entity_e_into_term_v<W: TransactableValue>(&mut self, x: entmod::EntityPlace<W>) -> Result<TypedValueOr<LookupRefOrTempId>> {
+        self.entity_e_into_term_e(x).map(|r| r.map_left(|ke| TypedValue::Ref(ke.0)))
+    }
+
+    fn entity_v_into_term_e<W: TransactableValue>(&mut self, x: entmod::ValuePlace<W>, backward_a: &entmod::EntidOrIdent) -> Result<KnownEntidOr<LookupRefOrTempId>> {
+        match backward_a.unreversed() {
+            None => {
+                bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode map notation value in :attr/_reversed notation for forward attribute")));
+            },
+            Some(forward_a) => {
+                let forward_a = self.entity_a_into_term_a(forward_a)?;
+                let forward_attribute = self.schema.require_attribute_for_entid(forward_a)?;
+                if forward_attribute.value_type != ValueType::Ref {
+                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot use :attr/_reversed notation for attribute {} that is not :db/valueType :db.type/ref", forward_a)))
+                }
+
+                match x {
+                    entmod::ValuePlace::Atom(v) => {
+                        // Here is where we do schema-aware typechecking: we either assert
+                        // that the given value is in the attribute's value set, or (in
+                        // limited cases) coerce the value into the attribute's value set.
+                        match v.as_tempid() {
+                            Some(tempid) => Ok(Either::Right(LookupRefOrTempId::TempId(self.temp_ids.intern(tempid)))),
+                            None => {
+                                if let TypedValue::Ref(entid) = v.into_typed_value(&self.schema, ValueType::Ref)? {
+                                    Ok(Either::Left(KnownEntid(entid)))
+                                } else {
+                                    // The given value is expected to be :db.type/ref, so this shouldn't happen.
+                                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot use :attr/_reversed notation for attribute {} with value that is not :db.valueType :db.type/ref", forward_a)))
+                                }
+                            }
+                        }
+                    },
+
+                    entmod::ValuePlace::Entid(entid) =>
+                        Ok(Either::Left(KnownEntid(self.entity_a_into_term_a(entid)?))),
+
+                    entmod::ValuePlace::TempId(tempid) =>
+                        Ok(Either::Right(LookupRefOrTempId::TempId(self.temp_ids.intern(tempid)))),
+
+                    entmod::ValuePlace::LookupRef(ref lookup_ref) =>
+                        Ok(Either::Right(LookupRefOrTempId::LookupRef(self.intern_lookup_ref(lookup_ref)?))),
+
+                    entmod::ValuePlace::TxFunction(ref tx_function) => {
+                        Ok(Either::Left(self.call_tx_function_for_entity(tx_function)?))
+                    },
+
+                    entmod::ValuePlace::PartitionedTempId(ref temp_id) =>
+                        Ok(Either::Right(LookupRefOrTempId::TempId(self.intern_partitioned_temp_id(temp_id)?))),
+
+                    entmod::ValuePlace::Vector(_) =>
+                        bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode vector value in :attr/_reversed notation for attribute {}", forward_a))),
+
+                    entmod::ValuePlace::MapNotation(_) =>
+                        bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode map notation value in :attr/_reversed notation for attribute {}", forward_a))),
+                }
+            },
+        }
+    }
+}
+
 impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
     pub fn new(
         store: &'conn rusqlite::Connection,
@@ -191,6 +488,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         schema_for_mutation: &'a Schema,
         schema: &'a Schema,
         watcher: W,
+        options: TxOptions<'a>,
         tx_id: Entid) -> Tx<'conn, 'a, W> {
         Tx {
             store: store,
@@ -198,6 +496,10 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             schema_for_mutation: Cow::Borrowed(schema_for_mutation),
             schema: schema,
             watcher: watcher,
+            options: options,
+            temp_id_partitions: BTreeMap::default(),
+            explanation: None,
+            migrating_value_types: false,
             tx_id: tx_id,
         }
     }
@@ -249,169 +551,130 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
     /// rewriting.
     ///
     /// The `Term` instances produce share interned TempId and LookupRef handles, and we return the
-    /// interned handle sets so that consumers can ensure all handles are used appropriately.
-    fn entities_into_terms_with_temp_ids_and_lookup_refs<I, V: TransactableValue>(&self, entities: I) -> Result<(Vec<TermWithTempIdsAndLookupRefs>, InternSet<TempId>, InternSet<AVPair>)> where I: IntoIterator<Item=Entity<V>> {
-        struct InProcess<'a> {
-            partition_map: &'a PartitionMap,
-            schema: &'a Schema,
-            mentat_id_count: i64,
-            tx_id: KnownEntid,
-            temp_ids: InternSet<TempId>,
-            lookup_refs: InternSet<AVPair>,
-        }
-
-        impl<'a> InProcess<'a> {
-            fn with_schema_and_partition_map(schema: &'a Schema, partition_map: &'a PartitionMap, tx_id: KnownEntid) -> InProcess<'a> {
-                InProcess {
-                    partition_map,
-                    schema,
-                    mentat_id_count: 0,
-                    tx_id,
-                    temp_ids: InternSet::new(),
-                    lookup_refs: InternSet::new(),
-                }
-            }
-
-            fn ensure_entid_exists(&self, e: Entid) -> Result<KnownEntid> {
-                if self.partition_map.contains_entid(e) {
-                    Ok(KnownEntid(e))
-                } else {
-                    bail!(DbErrorKind::UnrecognizedEntid(e))
-                }
-            }
-
-            fn ensure_ident_exists(&self, e: &Keyword) -> Result<KnownEntid> {
-                self.schema.require_entid(e)
+    /// interned handle sets so that consumers can ensure all handles are used appropriately.  We
+    /// also return the preconditions of any `[:db/cas e a old new]` entities, which must be checked
+    /// before the transaction is applied, and any `[:db/retractEntity e]` and `[:db/retractAttribute
+    /// e a]` entities, which can only be expanded once their lookup refs are resolved.
+    fn entities_into_terms_with_temp_ids_and_lookup_refs<I, V: TransactableValue>(&self, entities: I) -> Result<(Vec<TermWithTempIdsAndLookupRefs>, InternSet<TempId>, BTreeMap<TempId, String>, InternSet<AVPair>, Vec<CasWithLookupRefs>, Vec<RetractionWithLookupRefs>)> where I: IntoIterator<Item=Entity<V>> {
+        let mut in_process = InProcess::with_schema_and_partition_map(self.store, &self.schema, &self.partition_map, self.options.tx_functions, KnownEntid(self.tx_id));
+
+        let mut terms: Vec<TermWithTempIdsAndLookupRefs> = vec![];
+        let mut cas: Vec<CasWithLookupRefs> = vec![];
+        let mut retractions: Vec<RetractionWithLookupRefs> = vec![];
+
+        self.explode_entities(&mut in_process, entities, &mut terms, &mut cas, &mut retractions)?;
+
+        // Transaction functions can return entities to transact, which can call transaction
+        // functions in turn.  A function that calls itself would do so forever, so we give up
+        // after a while.
+        let mut depth = 0;
+        while !in_process.spliced.is_empty() {
+            depth += 1;
+            if depth > MAX_TX_FUNCTION_DEPTH {
+                bail!(DbErrorKind::TxFunctionDepthExceeded(MAX_TX_FUNCTION_DEPTH));
             }
+            let spliced = ::std::mem::replace(&mut in_process.spliced, vec![]);
+            self.explode_entities(&mut in_process, spliced, &mut terms, &mut cas, &mut retractions)?;
+        }
 
-            fn intern_lookup_ref<W: TransactableValue>(&mut self, lookup_ref: &entmod::LookupRef<W>) -> Result<LookupRef> {
-                let lr_a: i64 = match lookup_ref.a {
-                    AttributePlace::Entid(entmod::EntidOrIdent::Entid(ref a)) => *a,
-                    AttributePlace::Entid(entmod::EntidOrIdent::Ident(ref a)) => self.schema.require_entid(&a)?.into(),
-                };
-                let lr_attribute: &Attribute = self.schema.require_attribute_for_entid(lr_a)?;
-
-                let lr_typed_value: TypedValue = lookup_ref.v.clone().into_typed_value(&self.schema, lr_attribute.value_type)?;
-                if lr_attribute.unique.is_none() {
-                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot resolve (lookup-ref {} {:?}) with attribute that is not :db/unique", lr_a, lr_typed_value)))
-                }
-
-                Ok(self.lookup_refs.intern((lr_a, lr_typed_value)))
-            }
-
-            /// Allocate private internal tempids reserved for Mentat.  Internal tempids just need to be
-            /// unique within one transaction; they should never escape a transaction.
-            fn allocate_mentat_id<W: TransactableValue>(&mut self) -> entmod::EntityPlace<W> {
-                self.mentat_id_count += 1;
-                entmod::EntityPlace::TempId(TempId::Internal(self.mentat_id_count).into())
-            }
-
-            fn entity_e_into_term_e<W: TransactableValue>(&mut self, x: entmod::EntityPlace<W>) -> Result<KnownEntidOr<LookupRefOrTempId>> {
-                match x {
-                    entmod::EntityPlace::Entid(e) => {
-                        let e = match e {
-                            entmod::EntidOrIdent::Entid(ref e) => self.ensure_entid_exists(*e)?,
-                            entmod::EntidOrIdent::Ident(ref e) => self.ensure_ident_exists(&e)?,
-                        };
-                        Ok(Either::Left(e))
-                    },
-
-                    entmod::EntityPlace::TempId(e) => {
-                        Ok(Either::Right(LookupRefOrTempId::TempId(self.temp_ids.intern(e))))
-                    },
-
-                    entmod::EntityPlace::LookupRef(ref lookup_ref) => {
-                        Ok(Either::Right(LookupRefOrTempId::LookupRef(self.intern_lookup_ref(lookup_ref)?)))
-                    },
+        Ok((terms, in_process.temp_ids, in_process.temp_id_partitions, in_process.lookup_refs, cas, retractions))
+    }
 
-                    entmod::EntityPlace::TxFunction(ref tx_function) => {
-                        match tx_function.op.0.as_str() {
-                            "transaction-tx" => Ok(Either::Left(self.tx_id)),
-                            unknown @ _ => bail!(DbErrorKind::NotYetImplemented(format!("Unknown transaction function {}", unknown))),
+    /// Explode the nested map `map_notation`, which is either the value of the ref attribute `a`
+    /// or, if `reversed`, an entity that refers to the enclosing entity with `a`.  The nested map's
+    /// `[a v]` pairs are pushed onto `deque` with the given `op`, and the nested entity is returned.
+    ///
+    /// An asserted nested map without a `:db/id` gets a fresh internal tempid, provided that the new
+    /// entity isn't "dangling": it must be a component, include a `:db/unique :db.unique/identity`
+    /// attribute, or refer to another entity.  A retracted nested map must already exist, so
+    /// without a `:db/id` it is identified by a lookup ref on its one unique identity attribute.
+    fn explode_nested_map<V: TransactableValue>(&self,
+                                                in_process: &mut InProcess,
+                                                op: OpType,
+                                                a: Entid,
+                                                reversed: bool,
+                                                mut map_notation: entmod::MapNotation<V>,
+                                                deque: &mut VecDeque<Entity<V>>) -> Result<entmod::EntityPlace<V>> {
+        let attribute = self.schema.require_attribute_for_entid(a)?;
+
+        let db_id: entmod::EntityPlace<V> = match remove_db_id(&mut map_notation)? {
+            Some(db_id) => db_id,
+            None => {
+                // Per http://docs.datomic.com/transactions.html: "Either the reference to the
+                // nested map must be a component attribute, or the nested map must include a
+                // unique attribute. This constraint prevents the accidental creation of
+                // easily-orphaned entities that have no identity or relation to other entities."
+                // An entity given with :attr/_reversed notation refers to the enclosing entity, and
+                // so isn't orphaned.
+                let mut dangling = !reversed && !attribute.component;
+                let mut identities = 0;
+                let mut lookup_ref: Option<entmod::LookupRef<V>> = None;
+                for (inner_a, inner_v) in map_notation.iter() {
+                    if inner_a.unreversed().is_some() {
+                        dangling = false;
+                        continue;
+                    }
+                    let inner_a = in_process.entity_a_into_term_a(inner_a.clone())?;
+                    if self.schema.require_attribute_for_entid(inner_a)?.unique == Some(attribute::Unique::Identity) {
+                        dangling = false;
+                        identities += 1;
+                        if let &entmod::ValuePlace::Atom(ref v) = inner_v {
+                            lookup_ref = Some(entmod::LookupRef {
+                                a: AttributePlace::Entid(entmod::EntidOrIdent::Entid(inner_a)),
+                                v: v.clone(),
+                            });
                         }
-                    },
+                    }
                 }
-            }
-
-            fn entity_a_into_term_a(&mut self, x: entmod::EntidOrIdent) -> Result<Entid> {
-                let a = match x {
-                    entmod::EntidOrIdent::Entid(ref a) => *a,
-                    entmod::EntidOrIdent::Ident(ref a) => self.schema.require_entid(&a)?.into(),
-                };
-                Ok(a)
-            }
-
-            fn entity_e_into_term_v<W: TransactableValue>(&mut self, x: entmod::EntityPlace<W>) -> Result<TypedValueOr<LookupRefOrTempId>> {
-                self.entity_e_into_term_e(x).map(|r| r.map_left(|ke| TypedValue::Ref(ke.0)))
-            }
 
-            fn entity_v_into_term_e<W: TransactableValue>(&mut self, x: entmod::ValuePlace<W>, backward_a: &entmod::EntidOrIdent) -> Result<KnownEntidOr<LookupRefOrTempId>> {
-                match backward_a.unreversed() {
-                    None => {
-                        bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode map notation value in :attr/_reversed notation for forward attribute")));
+                match op {
+                    OpType::Add => {
+                        if dangling {
+                            bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode nested map value that would lead to dangling entity for attribute {}", a)));
+                        }
+                        in_process.allocate_mentat_id()
                     },
-                    Some(forward_a) => {
-                        let forward_a = self.entity_a_into_term_a(forward_a)?;
-                        let forward_attribute = self.schema.require_attribute_for_entid(forward_a)?;
-                        if forward_attribute.value_type != ValueType::Ref {
-                            bail!(DbErrorKind::NotYetImplemented(format!("Cannot use :attr/_reversed notation for attribute {} that is not :db/valueType :db.type/ref", forward_a)))
+                    OpType::Retract => {
+                        // With more than one identity, the lookup refs might name different
+                        // entities, and we'd retract from whichever we happened to pick.
+                        if identities > 1 {
+                            bail!(DbErrorKind::AmbiguousNestedRetraction(a));
                         }
-
-                        match x {
-                            entmod::ValuePlace::Atom(v) => {
-                                // Here is where we do schema-aware typechecking: we either assert
-                                // that the given value is in the attribute's value set, or (in
-                                // limited cases) coerce the value into the attribute's value set.
-                                match v.as_tempid() {
-                                    Some(tempid) => Ok(Either::Right(LookupRefOrTempId::TempId(self.temp_ids.intern(tempid)))),
-                                    None => {
-                                        if let TypedValue::Ref(entid) = v.into_typed_value(&self.schema, ValueType::Ref)? {
-                                            Ok(Either::Left(KnownEntid(entid)))
-                                        } else {
-                                            // The given value is expected to be :db.type/ref, so this shouldn't happen.
-                                            bail!(DbErrorKind::NotYetImplemented(format!("Cannot use :attr/_reversed notation for attribute {} with value that is not :db.valueType :db.type/ref", forward_a)))
-                                        }
-                                    }
-                                }
-                            },
-
-                            entmod::ValuePlace::Entid(entid) =>
-                                Ok(Either::Left(KnownEntid(self.entity_a_into_term_a(entid)?))),
-
-                            entmod::ValuePlace::TempId(tempid) =>
-                                Ok(Either::Right(LookupRefOrTempId::TempId(self.temp_ids.intern(tempid)))),
-
-                            entmod::ValuePlace::LookupRef(ref lookup_ref) =>
-                                Ok(Either::Right(LookupRefOrTempId::LookupRef(self.intern_lookup_ref(lookup_ref)?))),
-
-                            entmod::ValuePlace::TxFunction(ref tx_function) => {
-                                match tx_function.op.0.as_str() {
-                                    "transaction-tx" => Ok(Either::Left(KnownEntid(self.tx_id.0))),
-                                    unknown @ _ => bail!(DbErrorKind::NotYetImplemented(format!("Unknown transaction function {}", unknown))),
-                                }
-                            },
-
-                            entmod::ValuePlace::Vector(_) =>
-                                bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode vector value in :attr/_reversed notation for attribute {}", forward_a))),
-
-                            entmod::ValuePlace::MapNotation(_) =>
-                                bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode map notation value in :attr/_reversed notation for attribute {}", forward_a))),
+                        match lookup_ref {
+                            Some(lookup_ref) => entmod::EntityPlace::LookupRef(lookup_ref),
+                            None => bail!(DbErrorKind::UnidentifiedNestedRetraction(a)),
                         }
                     },
                 }
-            }
+            },
+        };
+
+        for (inner_a, inner_v) in map_notation {
+            deque.push_front(Entity::AddOrRetract {
+                op: op,
+                e: db_id.clone(),
+                a: AttributePlace::Entid(inner_a),
+                v: inner_v,
+            });
         }
 
-        let mut in_process = InProcess::with_schema_and_partition_map(&self.schema, &self.partition_map, KnownEntid(self.tx_id));
+        Ok(db_id)
+    }
 
+    /// Convert `entities` into terms, `[:db/cas ...]` preconditions, and retractions, exploding
+    /// vectors and map notation along the way.
+    fn explode_entities<I, V: TransactableValue>(&self,
+                                                 in_process: &mut InProcess,
+                                                 entities: I,
+                                                 terms: &mut Vec<TermWithTempIdsAndLookupRefs>,
+                                                 cas: &mut Vec<CasWithLookupRefs>,
+                                                 retractions: &mut Vec<RetractionWithLookupRefs>) -> Result<()> where I: IntoIterator<Item=Entity<V>> {
         // We want to handle entities in the order they're given to us, while also "exploding" some
         // entities into many.  We therefore push the initial entities onto the back of the deque,
         // take from the front of the deque, and explode onto the front as well.
         let mut deque: VecDeque<Entity<V>> = VecDeque::default();
         deque.extend(entities);
 
-        let mut terms: Vec<TermWithTempIdsAndLookupRefs> = Vec::with_capacity(deque.len());
-
         while let Some(entity) = deque.pop_front() {
             match entity {
                 Entity::MapNotation(mut map_notation) => {
@@ -431,17 +694,118 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                     }
                 },
 
+                Entity::Cas { e, a, old, new } => {
+                    let AttributePlace::Entid(a) = a;
+                    let a = in_process.entity_a_into_term_a(a)?;
+                    let attribute = self.schema.require_attribute_for_entid(a)?;
+                    if attribute.multival {
+                        bail!(DbErrorKind::CasOfCardinalityMany(a));
+                    }
+
+                    // A tempid names an entity that doesn't exist yet, so there's nothing to compare.
+                    let cas_e = match in_process.entity_e_into_term_e(e.clone())? {
+                        Either::Right(LookupRefOrTempId::TempId(_)) => {
+                            bail!(DbErrorKind::CasOfTempId(a));
+                        },
+                        cas_e => cas_e,
+                    };
+
+                    let old = match old {
+                        None => None,
+                        Some(entmod::ValuePlace::Atom(v)) =>
+                            Some(Either::Left(v.into_typed_value(&self.schema, attribute.value_type)?)),
+                        Some(entmod::ValuePlace::Entid(entid)) =>
+                            Some(Either::Left(TypedValue::Ref(in_process.entity_a_into_term_a(entid)?))),
+                        Some(entmod::ValuePlace::LookupRef(ref lookup_ref)) => {
+                            if attribute.value_type != ValueType::Ref {
+                                bail!(DbErrorKind::NotYetImplemented(format!("Cannot resolve value lookup ref for attribute {} that is not :db/valueType :db.type/ref", a)))
+                            }
+                            Some(Either::Right(LookupRefOrTempId::LookupRef(in_process.intern_lookup_ref(lookup_ref)?)))
+                        },
+                        Some(entmod::ValuePlace::TempId(_)) |
+                        Some(entmod::ValuePlace::PartitionedTempId(_)) |
+                        Some(entmod::ValuePlace::TxFunction(_)) |
+                        Some(entmod::ValuePlace::Vector(_)) |
+                        Some(entmod::ValuePlace::MapNotation(_)) => {
+                            bail!(DbErrorKind::BadCasValue(a));
+                        },
+                    };
+
+                    cas.push(Cas { e: cas_e, a, old });
+
+                    // If the precondition holds, this is just an assertion.
+                    deque.push_front(Entity::AddOrRetract {
+                        op: OpType::Add,
+                        e: e,
+                        a: AttributePlace::Entid(entmod::EntidOrIdent::Entid(a)),
+                        v: new,
+                    });
+                },
+
+                Entity::RetractEntity { e } => {
+                    let e = match in_process.entity_e_into_term_e(e)? {
+                        Either::Right(LookupRefOrTempId::TempId(tempid)) => {
+                            bail!(DbErrorKind::RetractionOfTempId(tempid.to_string()));
+                        },
+                        e => e,
+                    };
+                    retractions.push(Retraction::Entity(e));
+                },
+
+                Entity::RetractAttribute { e, a } => {
+                    let AttributePlace::Entid(a) = a;
+                    let a = in_process.entity_a_into_term_a(a)?;
+                    self.schema.require_attribute_for_entid(a)?;
+
+                    let e = match in_process.entity_e_into_term_e(e)? {
+                        Either::Right(LookupRefOrTempId::TempId(tempid)) => {
+                            bail!(DbErrorKind::RetractionOfTempId(tempid.to_string()));
+                        },
+                        e => e,
+                    };
+                    retractions.push(Retraction::Attribute(e, a));
+                },
+
                 Entity::AddOrRetract { op, e, a, v } => {
                     let AttributePlace::Entid(a) = a;
 
                     if let Some(reversed_a) = a.unreversed() {
-                        let reversed_e = in_process.entity_v_into_term_e(v, &a)?;
+                        let reversed_e = match v {
+                            // Each value refers to the entity with the forward attribute, so
+                            // cardinality doesn't constrain vectors here.
+                            entmod::ValuePlace::Vector(vs) => {
+                                for vv in vs {
+                                    deque.push_front(Entity::AddOrRetract {
+                                        op: op,
+                                        e: e.clone(),
+                                        a: AttributePlace::Entid(a.clone()),
+                                        v: vv,
+                                    });
+                                }
+                                continue
+                            },
+
+                            entmod::ValuePlace::MapNotation(map_notation) => {
+                                let forward_a = in_process.entity_a_into_term_a(reversed_a.clone())?;
+                                if self.schema.require_attribute_for_entid(forward_a)?.value_type != ValueType::Ref {
+                                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot use :attr/_reversed notation for attribute {} that is not :db/valueType :db.type/ref", forward_a)))
+                                }
+
+                                let db_id = self.explode_nested_map(in_process, op, forward_a, true, map_notation, &mut deque)?;
+                                in_process.entity_e_into_term_e(db_id)?
+                            },
+
+                            v => in_process.entity_v_into_term_e(v, &a)?,
+                        };
                         let reversed_a = in_process.entity_a_into_term_a(reversed_a)?;
                         let reversed_v = in_process.entity_e_into_term_v(e)?;
-                        terms.push(Term::AddOrRetract(OpType::Add, reversed_e, reversed_a, reversed_v));
+                        terms.push(Term::AddOrRetract(op, reversed_e, reversed_a, reversed_v));
                     } else {
                         let a = in_process.entity_a_into_term_a(a)?;
                         let attribute = self.schema.require_attribute_for_entid(a)?;
+                        if attribute.tuple_attrs.is_some() {
+                            bail!(DbErrorKind::CompositeTupleTransacted(a));
+                        }
 
                         let v = match v {
                             entmod::ValuePlace::Atom(v) => {
@@ -453,6 +817,12 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                                         Some(tempid) => Either::Right(LookupRefOrTempId::TempId(in_process.temp_ids.intern(tempid))),
                                         None => v.into_typed_value(&self.schema, attribute.value_type).map(Either::Left)?,
                                     }
+                                } else if attribute.value_type == ValueType::Tuple {
+                                    let elements = match v.into_typed_value(&self.schema, attribute.value_type)? {
+                                        TypedValue::Tuple(elements) => elements,
+                                        value => bail!(DbErrorKind::BadValuePair(format!("{:?}", value), ValueType::Tuple)),
+                                    };
+                                    Either::Left(self.schema.to_typed_tuple(a, (*elements).clone())?)
                                 } else {
                                     v.into_typed_value(&self.schema, attribute.value_type).map(Either::Left)?
                                 }
@@ -464,6 +834,14 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                             entmod::ValuePlace::TempId(tempid) =>
                                 Either::Right(LookupRefOrTempId::TempId(in_process.temp_ids.intern(tempid))),
 
+                            entmod::ValuePlace::PartitionedTempId(ref temp_id) => {
+                                if attribute.value_type != ValueType::Ref {
+                                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot use tempid for attribute {} that is not :db/valueType :db.type/ref", a)))
+                                }
+
+                                Either::Right(LookupRefOrTempId::TempId(in_process.intern_partitioned_temp_id(temp_id)?))
+                            },
+
                             entmod::ValuePlace::LookupRef(ref lookup_ref) => {
                                 if attribute.value_type != ValueType::Ref {
                                     bail!(DbErrorKind::NotYetImplemented(format!("Cannot resolve value lookup ref for attribute {} that is not :db/valueType :db.type/ref", a)))
@@ -473,107 +851,56 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                             },
 
                             entmod::ValuePlace::TxFunction(ref tx_function) => {
-                                let typed_value = match tx_function.op.0.as_str() {
-                                    "transaction-tx" => TypedValue::Ref(self.tx_id),
-                                    unknown @ _ => bail!(DbErrorKind::NotYetImplemented(format!("Unknown transaction function {}", unknown))),
-                                };
+                                let typed_value = in_process.call_tx_function(tx_function)?;
 
                                 // Here we do schema-aware typechecking: we assert that the computed
-                                // value is in the attribute's value set.  If and when we have
-                                // transaction functions that produce numeric values, we'll have to
-                                // be more careful here, because a function that produces an integer
-                                // value can be used where a double is expected.  See also
+                                // value is in the attribute's value set.  We don't coerce: a
+                                // user-registered function that produces an integer value can't be
+                                // used where a double is expected.  See also
                                 // `SchemaTypeChecking.to_typed_value(...)`.
                                 if attribute.value_type != typed_value.value_type() {
-                                    bail!(DbErrorKind::NotYetImplemented(format!("Transaction function {} produced value of type {} but expected type {}",
-                                                                               tx_function.op.0.as_str(), typed_value.value_type(), attribute.value_type)));
+                                    bail!(DbErrorKind::TxFunctionValueTypeMismatch {
+                                        function: tx_function.keyword(),
+                                        expected: attribute.value_type,
+                                        actual: typed_value.value_type(),
+                                    });
                                 }
 
                                 Either::Left(typed_value)
                             },
 
+                            // A vector is the value of a tuple attribute, or many values of a
+                            // cardinality many attribute.
                             entmod::ValuePlace::Vector(vs) => {
-                                if !attribute.multival {
-                                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode vector value for attribute {} that is not :db.cardinality :db.cardinality/many", a)));
-                                }
+                                if attribute.value_type == ValueType::Tuple {
+                                    Either::Left(self.vector_into_tuple(in_process, &e, a, vs)?)
+                                } else {
+                                    if !attribute.multival {
+                                        bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode vector value for attribute {} that is not :db.cardinality :db.cardinality/many", a)));
+                                    }
 
-                                for vv in vs {
-                                    deque.push_front(Entity::AddOrRetract {
-                                        op: op.clone(),
-                                        e: e.clone(),
-                                        a: AttributePlace::Entid(entmod::EntidOrIdent::Entid(a)),
-                                        v: vv,
-                                    });
+                                    for vv in vs {
+                                        deque.push_front(Entity::AddOrRetract {
+                                            op: op.clone(),
+                                            e: e.clone(),
+                                            a: AttributePlace::Entid(entmod::EntidOrIdent::Entid(a)),
+                                            v: vv,
+                                        });
+                                    }
+                                    continue
                                 }
-                                continue
                             },
 
-                            entmod::ValuePlace::MapNotation(mut map_notation) => {
+                            entmod::ValuePlace::MapNotation(map_notation) => {
                                 // TODO: consider handling this at the tx-parser level.  That would be
                                 // more strict and expressive, but it would lead to splitting
                                 // AddOrRetract, which proliferates types and code, or only handling
                                 // nested maps rather than map values, like Datomic does.
-                                if op != OpType::Add {
-                                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode nested map value in :db/retract for attribute {}", a)));
-                                }
-
                                 if attribute.value_type != ValueType::Ref {
                                     bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode nested map value for attribute {} that is not :db/valueType :db.type/ref", a)))
                                 }
 
-                                // :db/id is optional; if it's not given, we generate a special internal tempid
-                                // to use for upserting.  This tempid will not be reported in the TxReport.
-                                let db_id: Option<entmod::EntityPlace<V>> = remove_db_id(&mut map_notation)?;
-                                let mut dangling = db_id.is_none();
-                                let db_id: entmod::EntityPlace<V> = db_id.unwrap_or_else(|| in_process.allocate_mentat_id());
-
-                                // We're nested, so we want to ensure we're not creating "dangling"
-                                // entities that can't be reached.  If we're :db/isComponent, then this
-                                // is not dangling.  Otherwise, the resulting map needs to have a
-                                // :db/unique :db.unique/identity [a v] pair, so that it's reachable.
-                                // Per http://docs.datomic.com/transactions.html: "Either the reference
-                                // to the nested map must be a component attribute, or the nested map
-                                // must include a unique attribute. This constraint prevents the
-                                // accidental creation of easily-orphaned entities that have no identity
-                                // or relation to other entities."
-                                if attribute.component {
-                                    dangling = false;
-                                }
-
-                                for (inner_a, inner_v) in map_notation {
-                                    if let Some(reversed_a) = inner_a.unreversed() {
-                                        // We definitely have a reference.  The reference might be
-                                        // dangling (a bare entid, for example), but we don't yet
-                                        // support nested maps and reverse notation simultaneously
-                                        // (i.e., we don't accept {:reverse/_attribute {:nested map}})
-                                        // so we don't need to check that the nested map reference isn't
-                                        // dangling.
-                                        dangling = false;
-
-                                        let reversed_e = in_process.entity_v_into_term_e(inner_v, &inner_a)?;
-                                        let reversed_a = in_process.entity_a_into_term_a(reversed_a)?;
-                                        let reversed_v = in_process.entity_e_into_term_v(db_id.clone())?;
-                                        terms.push(Term::AddOrRetract(OpType::Add, reversed_e, reversed_a, reversed_v));
-                                    } else {
-                                        let inner_a = in_process.entity_a_into_term_a(inner_a)?;
-                                        let inner_attribute = self.schema.require_attribute_for_entid(inner_a)?;
-                                        if inner_attribute.unique == Some(attribute::Unique::Identity) {
-                                            dangling = false;
-                                        }
-
-                                        deque.push_front(Entity::AddOrRetract {
-                                            op: OpType::Add,
-                                            e: db_id.clone(),
-                                            a: AttributePlace::Entid(entmod::EntidOrIdent::Entid(inner_a)),
-                                            v: inner_v,
-                                        });
-                                    }
-                                }
-
-                                if dangling {
-                                    bail!(DbErrorKind::NotYetImplemented(format!("Cannot explode nested map value that would lead to dangling entity for attribute {}", a)));
-                                }
-
+                                let db_id = self.explode_nested_map(in_process, op, a, false, map_notation, &mut deque)?;
                                 in_process.entity_e_into_term_v(db_id)?
                             },
                         };
@@ -584,7 +911,38 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                 },
             }
         };
-        Ok((terms, in_process.temp_ids, in_process.lookup_refs))
+        Ok(())
+    }
+
+    /// Convert the elements of a vector value of the tuple attribute `a` of `e` into a tuple.
+    ///
+    /// Elements must be values, entids, or idents: see `ValueType::Tuple`.
+    fn vector_into_tuple<V: TransactableValue>(&self, in_process: &mut InProcess, e: &entmod::EntityPlace<V>, a: Entid, vs: Vec<entmod::ValuePlace<V>>) -> Result<TypedValue> {
+        let mut elements = Vec::with_capacity(vs.len());
+        for (index, v) in vs.into_iter().enumerate() {
+            let value_type = match self.schema.tuple_element_type(a, index) {
+                Some(value_type) => value_type,
+                None => bail!(DbErrorKind::BadValuePair(format!("vector with more than {} elements", index), ValueType::Tuple)),
+            };
+            let element = match v {
+                entmod::ValuePlace::Atom(v) => {
+                    if value_type == ValueType::Ref && v.as_tempid().is_some() {
+                        bail!(unsupported_tuple_element(e, a, index));
+                    }
+                    v.into_typed_value(&self.schema, value_type)?
+                },
+                entmod::ValuePlace::Entid(entid) if value_type == ValueType::Ref => TypedValue::Ref(in_process.entity_a_into_term_a(entid)?),
+                entmod::ValuePlace::Entid(_) => bail!(DbErrorKind::BadValuePair(format!("entid in element {}", index), value_type)),
+                entmod::ValuePlace::TempId(_) |
+                entmod::ValuePlace::PartitionedTempId(_) |
+                entmod::ValuePlace::LookupRef(_) |
+                entmod::ValuePlace::TxFunction(_) |
+                entmod::ValuePlace::Vector(_) |
+                entmod::ValuePlace::MapNotation(_) => bail!(unsupported_tuple_element(e, a, index)),
+            };
+            elements.push(element);
+        }
+        self.schema.to_typed_tuple(a, elements)
     }
 
     /// Pipeline stage 2: rewrite `Term` instances with lookup refs into `Term` instances without
@@ -603,6 +961,98 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         }).collect::<Result<Vec<_>>>()
     }
 
+    /// Like `resolve_lookup_refs`, but for `[:db/cas e a old new]` preconditions, which never
+    /// refer to tempids.
+    fn resolve_cas_lookup_refs(&self, lookup_ref_map: &AVMap, cas: Vec<CasWithLookupRefs>) -> Result<Vec<CasWithoutLookupRefs>> {
+        cas.into_iter().map(|Cas { e, a, old }| -> Result<CasWithoutLookupRefs> {
+            let e = match replace_lookup_ref(&lookup_ref_map, e, |x| KnownEntid(x))? {
+                Either::Left(e) => e,
+                Either::Right(_) => unreachable!("tempids are rejected when :db/cas entities are converted"),
+            };
+            let old = match old {
+                Some(old) => match replace_lookup_ref(&lookup_ref_map, old, |x| TypedValue::Ref(x))? {
+                    Either::Left(old) => Some(old),
+                    Either::Right(_) => unreachable!("tempids are rejected when :db/cas entities are converted"),
+                },
+                None => None,
+            };
+            Ok(Cas { e, a, old })
+        }).collect::<Result<Vec<_>>>()
+    }
+
+    /// Fail unless every `[:db/cas e a old new]` precondition holds in the store as it is before
+    /// this transaction changes anything.
+    fn check_cas(&self, cas: Vec<CasWithoutLookupRefs>) -> Result<()> {
+        for Cas { e, a, old } in cas {
+            let actual = self.store.resolve_ea(e.0, a)?;
+            if actual != old {
+                bail!(DbErrorKind::CasFailed { e: e.0, a, expected: old, actual });
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `resolve_lookup_refs`, but for `[:db/retractEntity e]` and `[:db/retractAttribute e a]`
+    /// entities, which never refer to tempids.
+    fn resolve_retraction_lookup_refs(&self, lookup_ref_map: &AVMap, retractions: Vec<RetractionWithLookupRefs>) -> Result<Vec<RetractionWithoutLookupRefs>> {
+        retractions.into_iter().map(|retraction| -> Result<RetractionWithoutLookupRefs> {
+            let resolve = |e: KnownEntidOr<LookupRefOrTempId>| -> Result<KnownEntid> {
+                match replace_lookup_ref(&lookup_ref_map, e, |x| KnownEntid(x))? {
+                    Either::Left(e) => Ok(e),
+                    Either::Right(_) => unreachable!("tempids are rejected when retractions are converted"),
+                }
+            };
+            match retraction {
+                Retraction::Entity(e) => Ok(Retraction::Entity(resolve(e)?)),
+                Retraction::Attribute(e, a) => Ok(Retraction::Attribute(resolve(e)?, a)),
+            }
+        }).collect::<Result<Vec<_>>>()
+    }
+
+    /// Expand `[:db/retractEntity e]` and `[:db/retractAttribute e a]` into `[:db/retract e a v]`
+    /// terms for the datoms in the store.
+    ///
+    /// Retracting an entity retracts every datom about it and every datom referring to it, and
+    /// recursively retracts the entities it refers to through component attributes.
+    fn expand_retractions(&self, retractions: Vec<RetractionWithoutLookupRefs>) -> Result<Vec<TermWithTempIds>> {
+        let mut terms: Vec<TermWithTempIds> = vec![];
+        let mut entities: Vec<Entid> = vec![];
+
+        for retraction in retractions {
+            match retraction {
+                Retraction::Entity(e) => entities.push(e.0),
+                Retraction::Attribute(e, a) => {
+                    for v in self.store.resolve_ea_values(e.0, a)? {
+                        terms.push(Term::AddOrRetract(OpType::Retract, Either::Left(e), a, Either::Left(v)));
+                    }
+                },
+            }
+        }
+
+        // Component entities can be shared or cyclic, so we track what we've already retracted.
+        let mut seen: BTreeSet<Entid> = BTreeSet::default();
+        while let Some(e) = entities.pop() {
+            if !seen.insert(e) {
+                continue;
+            }
+
+            for (a, v) in self.store.resolve_e(e)? {
+                if self.schema.require_attribute_for_entid(a)?.component {
+                    if let TypedValue::Ref(component) = v {
+                        entities.push(component);
+                    }
+                }
+                terms.push(Term::AddOrRetract(OpType::Retract, Either::Left(KnownEntid(e)), a, Either::Left(v)));
+            }
+
+            for (referrer, a) in self.store.resolve_v_refs(e)? {
+                terms.push(Term::AddOrRetract(OpType::Retract, Either::Left(KnownEntid(referrer)), a, Either::Left(TypedValue::Ref(e))));
+            }
+        }
+
+        Ok(terms)
+    }
+
     /// Transact the given `entities` against the store.
     ///
     /// This approach is explained in https://github.com/mozilla/mentat/wiki/Transacting.
@@ -610,25 +1060,111 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
     pub fn transact_entities<I, V: TransactableValue>(&mut self, entities: I) -> Result<TxReport>
     where I: IntoIterator<Item=Entity<V>> {
         // Pipeline stage 1: entities -> terms with tempids and lookup refs.
-        let (terms_with_temp_ids_and_lookup_refs, tempid_set, lookup_ref_set) = self.entities_into_terms_with_temp_ids_and_lookup_refs(entities)?;
+        let (terms_with_temp_ids_and_lookup_refs, tempid_set, temp_id_partitions, lookup_ref_set, cas, retractions) = self.entities_into_terms_with_temp_ids_and_lookup_refs(entities)?;
+        self.temp_id_partitions = temp_id_partitions;
 
         // Pipeline stage 2: resolve lookup refs -> terms with tempids.
         let lookup_ref_avs: Vec<&(i64, TypedValue)> = lookup_ref_set.iter().map(|rc| &**rc).collect();
         let lookup_ref_map: AVMap = self.store.resolve_avs(&lookup_ref_avs[..])?;
 
-        let terms_with_temp_ids = self.resolve_lookup_refs(&lookup_ref_map, terms_with_temp_ids_and_lookup_refs)?;
+        if let Some(ref mut explanation) = self.explanation {
+            explanation.lookup_refs.extend(lookup_ref_map.iter().map(|(&&(a, ref v), &e)| ((a, v.clone()), e)));
+        }
+
+        let mut terms_with_temp_ids = self.resolve_lookup_refs(&lookup_ref_map, terms_with_temp_ids_and_lookup_refs)?;
+
+        // Entity and attribute retractions expand into retractions of what's in the store.
+        let retractions = self.resolve_retraction_lookup_refs(&lookup_ref_map, retractions)?;
+        terms_with_temp_ids.extend(self.expand_retractions(retractions)?);
+
+        // Compare-and-swap preconditions are checked against the store before we write anything.
+        let cas = self.resolve_cas_lookup_refs(&lookup_ref_map, cas)?;
+        self.check_cas(cas)?;
 
         self.transact_simple_terms(terms_with_temp_ids, tempid_set)
     }
 
+    /// Allocate entids for the given tempids, which are labelled so that tempids with the same
+    /// label get the same entid.  Each label is allocated in the partition that one of its tempids
+    /// names, like `(tempid :my.part)`, or in `:db.part/user` if none do.
+    fn allocate_temp_ids(&mut self, unresolved_temp_ids: BTreeMap<TempIdHandle, usize>) -> Result<TempIdMap> {
+        let mut label_partitions: BTreeMap<usize, Option<&str>> = BTreeMap::default();
+        for (tempid, &label) in &unresolved_temp_ids {
+            let partition = self.temp_id_partitions.get(&**tempid).map(|p| p.as_str());
+            let entry = label_partitions.entry(label).or_insert(None);
+            match (*entry, partition) {
+                (_, None) => {},
+                (None, Some(p)) => *entry = Some(p),
+                (Some(q), Some(p)) => {
+                    if p != q {
+                        bail!(DbErrorKind::ConflictingUpsertPartitions(q.to_string(), p.to_string()));
+                    }
+                },
+            }
+        }
+
+        // Like before partitions could be named, each partition allocates as many entids as it has
+        // tempids, and allocates labels in order.
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::default();
+        for (_, label) in &unresolved_temp_ids {
+            let partition = label_partitions[label].unwrap_or(":db.part/user");
+            *counts.entry(partition).or_insert(0) += 1;
+        }
+
+        let mut next_entids: BTreeMap<&str, Entid> = BTreeMap::default();
+        for (partition, count) in counts {
+            next_entids.insert(partition, self.partition_map.allocate_entids(partition, count).start);
+        }
+
+        let mut label_entids: BTreeMap<usize, KnownEntid> = BTreeMap::default();
+        for (&label, partition) in &label_partitions {
+            let next_entid = next_entids.get_mut(partition.unwrap_or(":db.part/user")).unwrap();
+            label_entids.insert(label, KnownEntid(*next_entid));
+            *next_entid += 1;
+        }
+
+        Ok(unresolved_temp_ids
+            .into_iter()
+            .map(|(tempid, label)| (tempid, label_entids[&label]))
+            .collect())
+    }
+
     pub fn transact_simple_terms<I>(&mut self, terms: I, tempid_set: InternSet<TempId>) -> Result<TxReport>
     where I: IntoIterator<Item=TermWithTempIds> {
         // TODO: push these into an internal transaction report?
         let mut tempids: BTreeMap<TempId, KnownEntid> = BTreeMap::default();
 
+        // Assertions about the transaction entity itself go along with everything else, so that
+        // they are type and cardinality checked like any other assertion.
+        let tx_id = self.tx_id;
+        let tx_metadata_terms: Vec<TermWithTempIds> = self.options.tx_metadata.iter()
+            .map(|&(a, ref v)| Term::AddOrRetract(OpType::Add, Either::Left(KnownEntid(tx_id)), a, Either::Left(v.clone())))
+            .collect();
+
         // Pipeline stage 3: upsert tempids -> terms without tempids or lookup refs.
         // Now we can collect upsert populations.
-        let (mut generation, inert_terms) = Generation::from(terms, &self.schema)?;
+        let (mut generation, inert_terms) = Generation::from(terms.into_iter().chain(tx_metadata_terms), &self.schema)?;
+
+        // Tempids that an earlier transaction resolved don't upsert or get allocated again.
+        if let Some(known_temp_ids) = self.options.known_temp_ids {
+            let temp_id_map: TempIdMap = tempid_set.iter()
+                .filter_map(|tempid| match **tempid {
+                    TempId::External(ref name) => known_temp_ids.get(name).map(|&e| (tempid.clone(), KnownEntid(e))),
+                    TempId::Internal(_) => None,
+                })
+                .collect();
+
+            debug!("known tempids {:?}", temp_id_map);
+
+            generation = generation.resolve_known_temp_ids(&temp_id_map);
+            for (tempid, entid) in temp_id_map {
+                tempids.insert((*tempid).clone(), entid);
+            }
+        }
+
+        // A tempid can upsert by a unique identity composite tuple once the values of its
+        // components are known.
+        generation.add_composite_upserts(&self.schema);
 
         // And evolve them forward.
         while generation.can_evolve() {
@@ -644,6 +1180,9 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
 
             generation = generation.evolve_one_step(&temp_id_map);
 
+            // Resolving tempids might have given values to more components.
+            generation.add_composite_upserts(&self.schema);
+
             // Errors.  BTree* since we want deterministic results.
             let mut conflicting_upserts: BTreeMap<TempId, BTreeSet<KnownEntid>> = BTreeMap::default();
 
@@ -674,18 +1213,15 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
 
         debug!("unresolved tempids {:?}", unresolved_temp_ids);
 
-        // TODO: track partitions for temporary IDs.
-        let entids = self.partition_map.allocate_entids(":db.part/user", unresolved_temp_ids.len());
-
-        let temp_id_allocations = unresolved_temp_ids
-            .into_iter()
-            .map(|(tempid, index)| (tempid, KnownEntid(entids.start + (index as i64))))
-            .collect();
+        let temp_id_allocations = self.allocate_temp_ids(unresolved_temp_ids)?;
 
         debug!("tempid allocations {:?}", temp_id_allocations);
 
         let final_populations = generation.into_final_populations(&temp_id_allocations)?;
 
+        // Entities that can't have any datoms in the store yet.
+        let fresh_entids: BTreeSet<Entid> = temp_id_allocations.values().map(|e| e.0).chain(once(tx_id)).collect();
+
         // Report each tempid that is allocated.
         for (tempid, &entid) in &temp_id_allocations {
             // Every tempid should be allocated at most once.
@@ -693,6 +1229,15 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             tempids.insert((**tempid).clone(), entid);
         }
 
+        if let Some(ref mut explanation) = self.explanation {
+            explanation.tempids = tempids.iter()
+                .filter_map(|(tempid, &KnownEntid(e))| match *tempid {
+                    TempId::External(ref name) => Some((name.clone(), if fresh_entids.contains(&e) { TempIdResolution::Allocated(e) } else { TempIdResolution::Upserted(e) })),
+                    TempId::Internal(_) => None,
+                })
+                .collect();
+        }
+
         // Verify that every tempid we interned either resolved or has been allocated.
         assert_eq!(tempids.len(), tempid_set.len());
         for tempid in tempid_set.iter() {
@@ -710,8 +1255,12 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         // store.
         let mut tx_might_update_metadata = false;
 
+        // Likewise for [:db.part/db :db.install/partition e] assertions.
+        let mut tx_might_install_partitions = false;
+
         // Mutable so that we can add the transaction :db/txInstant.
         let mut aev_trie = into_aev_trie(&self.schema, final_populations, inert_terms)?;
+        maintain_composite_tuples(self.store, &self.schema, &fresh_entids, &mut aev_trie)?;
 
         let tx_instant;
         { // TODO: Don't use this block to scope borrowing the schema; instead, extract a helper function.
@@ -728,6 +1277,11 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         // Assertions that are :db.cardinality/many and :db.fulltext.
         let mut fts_many: Vec<db::ReducedEntity> = vec![];
 
+        // If every datom is an assertion about a fresh entity, none of them can already be in the
+        // store or replace a value that is, so there's no need to search for existing datoms.
+        // That's common when importing large amounts of new data.
+        let mut fresh_datoms_only = true;
+
         // We need to ensure that callers can't blindly transact entities that haven't been
         // allocated by this store.
 
@@ -744,12 +1298,15 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         // Pipeline stage 4: final terms (after rewriting) -> DB insertions.
         // Collect into non_fts_*.
 
-        tx_instant = get_or_insert_tx_instant(&mut aev_trie, &self.schema, self.tx_id)?;
+        tx_instant = get_or_insert_tx_instant(&mut aev_trie, &self.schema, self.store, self.tx_id)?;
 
         for ((a, attribute), evs) in aev_trie {
             if entids::might_update_metadata(a) {
                 tx_might_update_metadata = true;
             }
+            if a == entids::DB_INSTALL_PARTITION {
+                tx_might_install_partitions = true;
+            }
 
             let mut queue = match (attribute.fulltext, attribute.multival) {
                 (false, true) => &mut non_fts_many,
@@ -758,7 +1315,14 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                 (true, true) => &mut fts_many,
             };
 
+            if attribute.fulltext || entids::might_update_metadata(a) || a == entids::DB_INSTALL_PARTITION {
+                fresh_datoms_only = false;
+            }
+
             for (e, ars) in evs {
+                if !ars.retract.is_empty() || !fresh_entids.contains(&e) {
+                    fresh_datoms_only = false;
+                }
                 for (added, v) in ars.add.into_iter().map(|v| (true, v)).chain(ars.retract.into_iter().map(|v| (false, v))) {
                     let op = match added {
                         true => OpType::Add,
@@ -770,23 +1334,36 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             }
         }
 
-        if !non_fts_one.is_empty() {
-            self.store.insert_non_fts_searches(&non_fts_one[..], db::SearchType::Inexact)?;
-        }
+        if fresh_datoms_only {
+            // Fulltext assertions always take the slow path, so `fts_*` are empty.
+            self.store.insert_fresh_datoms(&non_fts_one[..], self.tx_id)?;
+            self.store.insert_fresh_datoms(&non_fts_many[..], self.tx_id)?;
+        } else {
+            self.store.begin_tx_application()?;
 
-        if !non_fts_many.is_empty() {
-            self.store.insert_non_fts_searches(&non_fts_many[..], db::SearchType::Exact)?;
-        }
+            if !non_fts_one.is_empty() {
+                self.store.insert_non_fts_searches(&non_fts_one[..], db::SearchType::Inexact)?;
+            }
 
-        if !fts_one.is_empty() {
-            self.store.insert_fts_searches(&fts_one[..], db::SearchType::Inexact)?;
-        }
+            if !non_fts_many.is_empty() {
+                self.store.insert_non_fts_searches(&non_fts_many[..], db::SearchType::Exact)?;
+            }
+
+            if !fts_one.is_empty() {
+                self.store.insert_fts_searches(&fts_one[..], db::SearchType::Inexact)?;
+            }
 
-        if !fts_many.is_empty() {
-            self.store.insert_fts_searches(&fts_many[..], db::SearchType::Exact)?;
+            if !fts_many.is_empty() {
+                self.store.insert_fts_searches(&fts_many[..], db::SearchType::Exact)?;
+            }
+
+            self.store.commit_transaction(self.tx_id)?;
         }
 
-        self.store.commit_transaction(self.tx_id)?;
+        if let Some(ref mut explanation) = self.explanation {
+            let requested = non_fts_one.iter().chain(non_fts_many.iter()).chain(fts_one.iter()).chain(fts_many.iter());
+            explain_datoms(self.store, self.schema, self.tx_id, requested, explanation)?;
+        }
         }
 
         db::update_partition_map(self.store, &self.partition_map)?;
@@ -797,7 +1374,11 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             let metadata_assertions = self.store.committed_metadata_assertions(self.tx_id)?;
 
             let mut new_schema = (*self.schema_for_mutation).clone(); // Clone the underlying Schema for modification.
-            let metadata_report = metadata::update_schema_from_entid_quadruples(&mut new_schema, metadata_assertions)?;
+            let metadata_report = if self.migrating_value_types {
+                metadata::migrate_schema_from_entid_quadruples(&mut new_schema, metadata_assertions)?
+            } else {
+                metadata::update_schema_from_entid_quadruples(&mut new_schema, metadata_assertions)?
+            };
 
             // We might not have made any changes to the schema, even though it looked like we
             // would.  This should not happen, even during bootstrapping: we mutate an empty
@@ -808,9 +1389,19 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                 let old_schema = (*self.schema_for_mutation).clone(); // Clone the original Schema for comparison.
                 *self.schema_for_mutation.to_mut() = new_schema; // Store the new Schema.
                 db::update_metadata(self.store, &old_schema, &*self.schema_for_mutation, &metadata_report)?;
+
+                if let Some(ref mut explanation) = self.explanation {
+                    explanation.schema_alterations = Some(metadata_report);
+                }
             }
         }
 
+        // New partitions are named by their :db/ident, which might have been asserted in this very
+        // transaction, so we install them once the schema is up to date.
+        if tx_might_install_partitions {
+            db::install_partitions(self.store, &mut self.partition_map, &*self.schema_for_mutation, self.tx_id)?;
+        }
+
         Ok(TxReport {
             tx_id: self.tx_id,
             tx_instant,
@@ -824,12 +1415,12 @@ fn start_tx<'conn, 'a, W>(conn: &'conn rusqlite::Connection,
                        mut partition_map: PartitionMap,
                        schema_for_mutation: &'a Schema,
                        schema: &'a Schema,
-                       watcher: W) -> Result<Tx<'conn, 'a, W>>
+                       watcher: W,
+                       options: TxOptions<'a>) -> Result<Tx<'conn, 'a, W>>
     where W: TransactWatcher {
     let tx_id = partition_map.allocate_entid(":db.part/tx");
-    conn.begin_tx_application()?;
 
-    Ok(Tx::new(conn, partition_map, schema_for_mutation, schema, watcher, tx_id))
+    Ok(Tx::new(conn, partition_map, schema_for_mutation, schema, watcher, options, tx_id))
 }
 
 fn conclude_tx<W>(tx: Tx<W>, report: TxReport) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
@@ -858,27 +1449,96 @@ pub fn transact<'conn, 'a, I, V, W>(conn: &'conn rusqlite::Connection,
           V: TransactableValue,
           W: TransactWatcher {
 
-    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher)?;
+    transact_with_options(conn, partition_map, schema_for_mutation, schema, watcher, TxOptions::new(), entities)
+}
+
+/// Just like `transact`, but with the transaction functions, transaction metadata, and known
+/// tempids given by `options`.
+pub fn transact_with_options<'conn, 'a, I, V, W>(conn: &'conn rusqlite::Connection,
+                                              partition_map: PartitionMap,
+                                              schema_for_mutation: &'a Schema,
+                                              schema: &'a Schema,
+                                              watcher: W,
+                                              options: TxOptions<'a>,
+                                              entities: I) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
+    where I: IntoIterator<Item=Entity<V>>,
+          V: TransactableValue,
+          W: TransactWatcher {
+
+    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher, options)?;
     let report = tx.transact_entities(entities)?;
     conclude_tx(tx, report)
 }
 
+/// Explain what transacting the given `entities` against the given SQLite `conn` would do, without
+/// changing the store: which tempids would upsert or be allocated, which lookup refs resolve to
+/// which entities, which datoms would be asserted, retracted, or left unchanged, and how the schema
+/// would change.
+///
+/// The transaction is applied inside a SQLite savepoint that is always rolled back, and the updated
+/// partition map and schema are discarded.
+pub fn explain_transact<'conn, 'a, I, V>(conn: &'conn rusqlite::Connection,
+                                         partition_map: PartitionMap,
+                                         schema_for_mutation: &'a Schema,
+                                         schema: &'a Schema,
+                                         options: TxOptions<'a>,
+                                         entities: I) -> Result<TxExplanation>
+    where I: IntoIterator<Item=Entity<V>>,
+          V: TransactableValue {
+
+    conn.execute_batch("SAVEPOINT mentat_explain")?;
+
+    let explained = start_tx(conn, partition_map, schema_for_mutation, schema, NullWatcher(), options)
+        .and_then(|mut tx| {
+            tx.explanation = Some(TxExplanation::default());
+            tx.transact_entities(entities)?;
+            let mut explanation = tx.explanation.take().unwrap();
+            explanation.tx_id = tx.tx_id;
+            Ok(explanation)
+        });
+
+    // Whether or not the transaction succeeded, leave the store as we found it.
+    conn.execute_batch("ROLLBACK TO mentat_explain; RELEASE mentat_explain")?;
+
+    explained
+}
+
 /// Just like `transact`, but accepts lower-level inputs to allow bypassing the parser interface.
 pub fn transact_terms<'conn, 'a, I, W>(conn: &'conn rusqlite::Connection,
                                        partition_map: PartitionMap,
                                        schema_for_mutation: &'a Schema,
                                        schema: &'a Schema,
                                        watcher: W,
+                                       tx_metadata: &'a [(Entid, TypedValue)],
                                        terms: I,
                                        tempid_set: InternSet<TempId>) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
     where I: IntoIterator<Item=TermWithTempIds>,
           W: TransactWatcher {
 
-    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher)?;
+    // Terms are already past the point where transaction functions are called.
+    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher, TxOptions::new().tx_metadata(tx_metadata))?;
     let report = tx.transact_simple_terms(terms, tempid_set)?;
     conclude_tx(tx, report)
 }
 
+/// Just like `transact_terms`, but the terms can change the `:db/valueType` of attributes, provided
+/// they also retract every value of those attributes.  See `migration::migrate_value_type`.
+pub(crate) fn transact_value_type_migration_terms<'conn, 'a, I, W>(conn: &'conn rusqlite::Connection,
+                                                                   partition_map: PartitionMap,
+                                                                   schema_for_mutation: &'a Schema,
+                                                                   schema: &'a Schema,
+                                                                   watcher: W,
+                                                                   tx_metadata: &'a [(Entid, TypedValue)],
+                                                                   terms: I) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
+    where I: IntoIterator<Item=TermWithTempIds>,
+          W: TransactWatcher {
+
+    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher, TxOptions::new().tx_metadata(tx_metadata))?;
+    tx.migrating_value_types = true;
+    let report = tx.transact_simple_terms(terms, InternSet::new())?;
+    conclude_tx(tx, report)
+}
+
 fn extend_aev_trie<'schema, I>(schema: &'schema Schema, terms: I, trie: &mut AEVTrie<'schema>) -> Result<()>
 where I: IntoIterator<Item=TermWithoutTempIds>
 {
@@ -908,9 +1568,81 @@ pub(crate) fn into_aev_trie<'schema>(schema: &'schema Schema, final_populations:
     Ok(trie)
 }
 
+/// The error for an unsupported element at `index` of a vector value of the tuple attribute `a` of
+/// `e`.  A `:db/tupleAttrs` value defines the composite `e`, so that error names `e` instead.
+fn unsupported_tuple_element<V>(e: &entmod::EntityPlace<V>, a: Entid, index: usize) -> DbErrorKind {
+    if a != entids::DB_TUPLE_ATTRS {
+        return DbErrorKind::UnsupportedTupleElement(a, index);
+    }
+    let composite = match e {
+        &entmod::EntityPlace::Entid(entmod::EntidOrIdent::Entid(entid)) => entid.to_string(),
+        &entmod::EntityPlace::Entid(entmod::EntidOrIdent::Ident(ref ident)) => ident.to_string(),
+        &entmod::EntityPlace::TempId(ref tempid) => tempid.to_string(),
+        &entmod::EntityPlace::PartitionedTempId(ref temp_id) => format!("(tempid {})", temp_id.partition),
+        &entmod::EntityPlace::LookupRef(_) => "(lookup-ref ...)".to_string(),
+        &entmod::EntityPlace::TxFunction(ref tx_function) => format!("({})", tx_function.op),
+    };
+    DbErrorKind::UnsupportedTupleAttrsElement(composite, index)
+}
+
+/// Assert or retract the composite tuple attributes of each entity whose components `aev_trie`
+/// changes, so that an entity has a composite exactly when it has every one of its components.
+fn maintain_composite_tuples<'schema>(store: &rusqlite::Connection, schema: &'schema Schema, fresh_entids: &BTreeSet<Entid>, aev_trie: &mut AEVTrie<'schema>) -> Result<()> {
+    // Map each composite that has a changed component to the entities whose components changed.
+    let mut changed: BTreeMap<Entid, BTreeSet<Entid>> = BTreeMap::default();
+    for (&(a, _), evs) in aev_trie.iter() {
+        for &c in schema.composites_for_component(a) {
+            changed.entry(c).or_insert_with(BTreeSet::default).extend(evs.keys().cloned());
+        }
+    }
+
+    for (c, es) in changed {
+        let composite = schema.require_attribute_for_entid(c)?;
+        let tuple_attrs = match composite.tuple_attrs {
+            Some(ref tuple_attrs) => tuple_attrs,
+            None => continue,
+        };
+
+        // A fresh entity has no datoms in the store, so its components are all in the trie.  For
+        // the others, fetch the stored components and composite in one go.
+        let stored_es: Vec<Entid> = es.iter().cloned().filter(|e| !fresh_entids.contains(e)).collect();
+        let attributes: Vec<Entid> = tuple_attrs.iter().cloned().chain(once(c)).collect();
+        let stored = store.resolve_eas(&stored_es[..], &attributes[..])?;
+
+        for e in es {
+            let mut elements: Vec<TypedValue> = Vec::with_capacity(tuple_attrs.len());
+            for &a in tuple_attrs {
+                let ars = aev_trie.get(&(a, schema.require_attribute_for_entid(a)?)).and_then(|evs| evs.get(&e));
+                let value = match ars.and_then(|ars| ars.add.iter().next()) {
+                    Some(v) => Some(v.clone()),
+                    None => stored.get(&(e, a)).and_then(|v| if ars.map_or(false, |ars| ars.retract.contains(v)) { None } else { Some(v.clone()) }),
+                };
+                match value {
+                    Some(v) => elements.push(v),
+                    None => break,
+                }
+            }
+
+            if elements.len() == tuple_attrs.len() {
+                let ars = aev_trie.entry((c, composite)).or_insert(BTreeMap::default()).entry(e).or_insert(AddAndRetract::default());
+                ars.add.clear();
+                ars.add.insert(elements.into());
+            } else if let Some(current) = stored.get(&(e, c)) {
+                let ars = aev_trie.entry((c, composite)).or_insert(BTreeMap::default()).entry(e).or_insert(AddAndRetract::default());
+                ars.add.clear();
+                ars.retract.insert(current.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Transact [:db/add :db/txInstant tx_instant (transaction-tx)] if the trie doesn't contain it
 /// already.  Return the instant from the input or the instant inserted.
-fn get_or_insert_tx_instant<'schema>(aev_trie: &mut AEVTrie<'schema>, schema: &'schema Schema, tx_id: Entid) -> Result<DateTime<Utc>> {
+///
+/// An instant from the input can backdate the transaction, but it can't be earlier than the instant
+/// of the latest transaction in the store, nor later than now.
+fn get_or_insert_tx_instant<'schema>(aev_trie: &mut AEVTrie<'schema>, schema: &'schema Schema, store: &rusqlite::Connection, tx_id: Entid) -> Result<DateTime<Utc>> {
     let ars = aev_trie
         .entry((entids::DB_TX_INSTANT, schema.require_attribute_for_entid(entids::DB_TX_INSTANT)?))
         .or_insert(BTreeMap::default())
@@ -925,7 +1657,17 @@ fn get_or_insert_tx_instant<'schema>(aev_trie: &mut AEVTrie<'schema>, schema: &'
 
     let first = ars.add.iter().next().cloned();
     match first {
-        Some(TypedValue::Instant(instant)) => Ok(instant),
+        Some(TypedValue::Instant(instant)) => {
+            if instant > now() {
+                bail!(DbErrorKind::FutureTxInstant(instant));
+            }
+            if let Some(latest) = store.resolve_latest_tx_instant()? {
+                if instant < latest {
+                    bail!(DbErrorKind::NonMonotonicTxInstant { instant, latest });
+                }
+            }
+            Ok(instant)
+        },
         Some(_) => unreachable!(), // This is a coding error -- we should have typechecked this already.
         None => {
             let instant = now();
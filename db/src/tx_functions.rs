@@ -0,0 +1,139 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! User-registered transaction functions.
+//!
+//! A transaction function is named by a keyword, like `:counter/increment`.  When the transactor
+//! finds `(counter/increment)` in entity or value position, it calls the function registered under
+//! that name.  The function sees the store as it is before the transaction, and returns the value
+//! to use in place of the call, along with any further entities to transact.
+
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+
+use rusqlite;
+
+use edn::entities::{
+    Entity,
+};
+
+use mentat_core::{
+    Entid,
+    Keyword,
+    Schema,
+    TypedValue,
+};
+
+use db::{
+    MentatStoring,
+};
+
+use errors::{
+    Result,
+};
+
+use registry::{
+    Registry,
+};
+
+use schema::{
+    SchemaBuilding,
+};
+
+/// How many times the entities that transaction functions return can call transaction functions
+/// in turn, before the transaction fails.
+pub const MAX_TX_FUNCTION_DEPTH: usize = 64;
+
+/// What a transaction function can see of the store it's transacting against.
+pub struct TxFunctionContext<'a> {
+    store: &'a rusqlite::Connection,
+    schema: &'a Schema,
+    tx_id: Entid,
+}
+
+impl<'a> TxFunctionContext<'a> {
+    pub(crate) fn new(store: &'a rusqlite::Connection, schema: &'a Schema, tx_id: Entid) -> TxFunctionContext<'a> {
+        TxFunctionContext {
+            store,
+            schema,
+            tx_id,
+        }
+    }
+
+    pub fn schema(&self) -> &Schema {
+        self.schema
+    }
+
+    /// The ID of the transaction that invoked the function.
+    pub fn tx_id(&self) -> Entid {
+        self.tx_id
+    }
+
+    /// The value of the cardinality one `attribute` of `entity`, if it has one.
+    pub fn lookup_value_for_attribute(&self, entity: Entid, attribute: &Keyword) -> Result<Option<TypedValue>> {
+        let a = self.schema.require_entid(attribute)?;
+        self.store.resolve_ea(entity, a.0)
+    }
+
+    /// All the values of `attribute` of `entity`.
+    pub fn lookup_values_for_attribute(&self, entity: Entid, attribute: &Keyword) -> Result<Vec<TypedValue>> {
+        let a = self.schema.require_entid(attribute)?;
+        self.store.resolve_ea_values(entity, a.0)
+    }
+}
+
+/// What a transaction function produces: the value that stands in place of the call, and any
+/// entities to transact alongside the entities that made the call.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TxFunctionOutput {
+    pub value: TypedValue,
+    pub entities: Vec<Entity<TypedValue>>,
+}
+
+impl TxFunctionOutput {
+    pub fn value(value: TypedValue) -> TxFunctionOutput {
+        TxFunctionOutput {
+            value,
+            entities: vec![],
+        }
+    }
+}
+
+pub type TxFunctionFn = Fn(&TxFunctionContext) -> Result<TxFunctionOutput> + Send + Sync;
+
+/// The transaction functions known to a store, by name.
+#[derive(Clone, Debug, Default)]
+pub struct TxFunctions {
+    functions: Registry<TxFunctionFn>,
+}
+
+impl TxFunctions {
+    /// Register `function` under `name`, replacing any function previously registered under it.
+    pub fn register<F>(&mut self, name: Keyword, function: F)
+    where F: Fn(&TxFunctionContext) -> Result<TxFunctionOutput> + 'static + Send + Sync {
+        self.functions.register(name, Box::new(function));
+    }
+}
+
+impl Deref for TxFunctions {
+    type Target = Registry<TxFunctionFn>;
+
+    fn deref(&self) -> &Registry<TxFunctionFn> {
+        &self.functions
+    }
+}
+
+impl DerefMut for TxFunctions {
+    fn deref_mut(&mut self) -> &mut Registry<TxFunctionFn> {
+        &mut self.functions
+    }
+}
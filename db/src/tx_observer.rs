@@ -159,6 +159,7 @@ impl Drop for TxObservationService {
     }
 }
 
+#[derive(Clone)]
 pub struct InProgressObserverTransactWatcher {
     collected_attributes: AttributeSet,
     pub txes: IndexMap<Entid, AttributeSet>,
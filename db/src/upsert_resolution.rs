@@ -22,7 +22,6 @@ use indexmap;
 use petgraph::unionfind;
 
 use errors::{
-    DbErrorKind,
     Result,
 };
 use types::{
@@ -44,6 +43,7 @@ use mentat_core::{
     attribute,
     Attribute,
     Entid,
+    HasSchema,
     Schema,
     TypedValue,
 };
@@ -85,6 +85,10 @@ pub(crate) struct Generation {
     /// Entities that resolved due to other upserts and no longer reference tempids.  These
     /// assertions may or may not be in the store.
     resolved: Vec<TermWithoutTempIds>,
+
+    /// The [TEMPID c] pairs, where c is a :db.unique/identity composite tuple attribute, for which
+    /// a "simple upsert" [:db/add TEMPID c tuple] has been added.
+    composite_upserts: BTreeSet<(TempIdHandle, Entid)>,
 }
 
 #[derive(Clone,Debug,Default,Eq,Hash,Ord,PartialOrd,PartialEq)]
@@ -147,6 +151,63 @@ impl Generation {
         !self.upserts_e.is_empty()
     }
 
+    /// Rewrite the existing :db/add entities using temporary IDs that are known before any upsert
+    /// resolution, like those that an earlier transaction resolved.  Unlike `evolve_one_step`, the
+    /// remaining upserts stay upserts.
+    ///
+    /// Known tempids might not have their `[a v]` pairs in the store yet, so upserts that resolve
+    /// this way are resolved rather than upserted.
+    pub(crate) fn resolve_known_temp_ids(self, temp_id_map: &TempIdMap) -> Generation {
+        let mut next = Generation::default();
+        next.resolved = self.resolved;
+        next.upserted = self.upserted;
+        next.composite_upserts = self.composite_upserts;
+
+        for UpsertE(t, a, v) in self.upserts_e {
+            match temp_id_map.get(&*t) {
+                Some(&n) => next.resolved.push(Term::AddOrRetract(OpType::Add, n, a, v)),
+                None => next.upserts_e.push(UpsertE(t, a, v)),
+            }
+        }
+
+        for UpsertEV(t1, a, t2) in self.upserts_ev {
+            match (temp_id_map.get(&*t1), temp_id_map.get(&*t2)) {
+                (Some(&n1), Some(&n2)) => next.resolved.push(Term::AddOrRetract(OpType::Add, n1, a, TypedValue::Ref(n2.0))),
+                (None, Some(&n2)) => next.upserts_e.push(UpsertE(t1, a, TypedValue::Ref(n2.0))),
+                (Some(&n1), None) => next.allocations.push(Term::AddOrRetract(OpType::Add, Left(n1), a, Right(t2))),
+                (None, None) => next.upserts_ev.push(UpsertEV(t1, a, t2)),
+            }
+        }
+
+        for term in self.allocations {
+            match term {
+                Term::AddOrRetract(op, Right(t1), a, Right(t2)) => {
+                    match (temp_id_map.get(&*t1), temp_id_map.get(&*t2)) {
+                        (Some(&n1), Some(&n2)) => next.resolved.push(Term::AddOrRetract(op, n1, a, TypedValue::Ref(n2.0))),
+                        (None, Some(&n2)) => next.allocations.push(Term::AddOrRetract(op, Right(t1), a, Left(TypedValue::Ref(n2.0)))),
+                        (Some(&n1), None) => next.allocations.push(Term::AddOrRetract(op, Left(n1), a, Right(t2))),
+                        (None, None) => next.allocations.push(Term::AddOrRetract(op, Right(t1), a, Right(t2))),
+                    }
+                },
+                Term::AddOrRetract(op, Right(t), a, Left(v)) => {
+                    match temp_id_map.get(&*t) {
+                        Some(&n) => next.resolved.push(Term::AddOrRetract(op, n, a, v)),
+                        None => next.allocations.push(Term::AddOrRetract(op, Right(t), a, Left(v))),
+                    }
+                },
+                Term::AddOrRetract(op, Left(e), a, Right(t)) => {
+                    match temp_id_map.get(&*t) {
+                        Some(&n) => next.resolved.push(Term::AddOrRetract(op, e, a, TypedValue::Ref(n.0))),
+                        None => next.allocations.push(Term::AddOrRetract(op, Left(e), a, Right(t))),
+                    }
+                },
+                Term::AddOrRetract(_, Left(_), _, Left(_)) => unreachable!(),
+            }
+        }
+
+        next
+    }
+
     /// Evolve this generation one step further by rewriting the existing :db/add entities using the
     /// given temporary IDs.
     ///
@@ -157,6 +218,7 @@ impl Generation {
         // We'll iterate our own allocations to resolve more things, but terms that have already
         // resolved stay resolved.
         next.resolved = self.resolved;
+        next.composite_upserts = self.composite_upserts;
 
         for UpsertE(t, a, v) in self.upserts_e {
             match temp_id_map.get(&*t) {
@@ -212,6 +274,54 @@ impl Generation {
         next
     }
 
+    /// Add a "simple upsert" [:db/add TEMPID c tuple] for each :db.unique/identity composite tuple
+    /// attribute c whose components are all asserted about TEMPID, so that TEMPID can upsert by c.
+    ///
+    /// A component whose value is itself a tempid only has a value once that tempid resolves, so
+    /// this is repeated at each evolutionary step.  Each [TEMPID c] pair is added at most once.
+    pub(crate) fn add_composite_upserts(&mut self, schema: &Schema) {
+        if schema.composite_tuples.is_empty() {
+            return;
+        }
+
+        // The values asserted about each tempid, by attribute.
+        let mut asserted: BTreeMap<TempIdHandle, BTreeMap<Entid, TypedValue>> = BTreeMap::default();
+        for &UpsertE(ref t, a, ref v) in &self.upserts_e {
+            asserted.entry(t.clone()).or_insert_with(BTreeMap::default).insert(a, v.clone());
+        }
+        for term in &self.allocations {
+            if let &Term::AddOrRetract(OpType::Add, Right(ref t), a, Left(ref v)) = term {
+                asserted.entry(t.clone()).or_insert_with(BTreeMap::default).insert(a, v.clone());
+            }
+        }
+
+        let mut upserts_e = vec![];
+        for (t, avs) in asserted {
+            let composites: BTreeSet<Entid> = avs.keys().flat_map(|&a| schema.composites_for_component(a).iter().cloned()).collect();
+            for c in composites {
+                if self.composite_upserts.contains(&(t.clone(), c)) {
+                    continue;
+                }
+                let attribute = match schema.attribute_for_entid(c) {
+                    Some(attribute) => attribute,
+                    None => continue,
+                };
+                if attribute.unique != Some(attribute::Unique::Identity) {
+                    continue;
+                }
+                let elements: Option<Vec<TypedValue>> = match attribute.tuple_attrs {
+                    Some(ref tuple_attrs) => tuple_attrs.iter().map(|a| avs.get(a).cloned()).collect(),
+                    None => None,
+                };
+                if let Some(elements) = elements {
+                    self.composite_upserts.insert((t.clone(), c));
+                    upserts_e.push(UpsertE(t.clone(), c, elements.into()));
+                }
+            }
+        }
+        self.upserts_e.extend(upserts_e);
+    }
+
     // Collect id->[a v] pairs that might upsert at this evolutionary step.
     pub(crate) fn temp_id_avs<'a>(&'a self) -> Vec<(TempIdHandle, AVPair)> {
         let mut temp_id_avs: Vec<(TempIdHandle, AVPair)> = vec![];
@@ -315,6 +425,9 @@ impl Generation {
 
     /// After evolution is complete, use the provided allocated entids to segment `self` into
     /// populations, each with no references to tempids.
+    ///
+    /// A `[:db/retract ...]` term referencing a tempid that did not upsert is dropped: the tempid
+    /// names a new entity, which can't have any datoms to retract.
     pub(crate) fn into_final_populations(self, temp_id_map: &TempIdMap) -> Result<FinalPopulations> {
         assert!(self.upserts_e.is_empty());
         assert!(self.upserts_ev.is_empty());
@@ -331,21 +444,30 @@ impl Generation {
                     match (op, temp_id_map.get(&*t1), temp_id_map.get(&*t2)) {
                         (op, Some(&n1), Some(&n2)) => Term::AddOrRetract(op, n1, a, TypedValue::Ref(n2.0)),
                         (OpType::Add, _, _) => unreachable!(), // This is a coding error -- every tempid in a :db/add entity should resolve or be allocated.
-                        (OpType::Retract, _, _) => bail!(DbErrorKind::NotYetImplemented(format!("[:db/retract ...] entity referenced tempid that did not upsert: one of {}, {}", t1, t2))),
+                        (OpType::Retract, _, _) => {
+                            debug!("[:db/retract ...] entity referenced tempid that did not upsert: one of {}, {}", t1, t2);
+                            continue
+                        },
                     }
                 },
                 Term::AddOrRetract(op, Right(t), a, Left(v)) => {
                     match (op, temp_id_map.get(&*t)) {
                         (op, Some(&n)) => Term::AddOrRetract(op, n, a, v),
                         (OpType::Add, _) => unreachable!(), // This is a coding error.
-                        (OpType::Retract, _) => bail!(DbErrorKind::NotYetImplemented(format!("[:db/retract ...] entity referenced tempid that did not upsert: {}", t))),
+                        (OpType::Retract, _) => {
+                            debug!("[:db/retract ...] entity referenced tempid that did not upsert: {}", t);
+                            continue
+                        },
                     }
                 },
                 Term::AddOrRetract(op, Left(e), a, Right(t)) => {
                     match (op, temp_id_map.get(&*t)) {
                         (op, Some(&n)) => Term::AddOrRetract(op, e, a, TypedValue::Ref(n.0)),
                         (OpType::Add, _) => unreachable!(), // This is a coding error.
-                        (OpType::Retract, _) => bail!(DbErrorKind::NotYetImplemented(format!("[:db/retract ...] entity referenced tempid that did not upsert: {}", t))),
+                        (OpType::Retract, _) => {
+                            debug!("[:db/retract ...] entity referenced tempid that did not upsert: {}", t);
+                            continue
+                        },
                     }
                 },
                 Term::AddOrRetract(_, Left(_), _, Left(_)) => unreachable!(), // This is a coding error -- these should not be in allocations.
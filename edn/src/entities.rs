@@ -109,11 +109,34 @@ pub struct LookupRef<V> {
 /// (transaction-tx) n)` to find the n-th ancestor of the current transaction.  If we do accept
 /// arguments, then the special case of `(lookup-ref a v)` should be handled as part of the
 /// generalization.
+///
+/// Stores can also register their own transaction functions, named by namespaced keywords: the
+/// function registered as `:counter/increment` is called like `(counter/increment)`.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
 pub struct TxFunction {
     pub op: PlainSymbol,
 }
 
+impl TxFunction {
+    /// The keyword naming this function, like `:counter/increment` for `(counter/increment)`.
+    pub fn keyword(&self) -> Keyword {
+        let op = self.op.0.as_str();
+        match op.find('/') {
+            Some(i) => Keyword::namespaced(&op[..i], &op[i + 1..]),
+            None => Keyword::plain(op),
+        }
+    }
+}
+
+/// A tempid to allocate in a particular partition, like `(tempid :my.part)`.  Each anonymous
+/// tempid names a distinct entity; a named tempid, like `(tempid :my.part "name")`, is the same as
+/// the tempid `"name"` used elsewhere in the transaction.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub struct PartitionedTempId {
+    pub partition: Keyword,
+    pub name: Option<String>,
+}
+
 pub type MapNotation<V> = BTreeMap<EntidOrIdent, ValuePlace<V>>;
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
@@ -126,6 +149,7 @@ pub enum ValuePlace<V> {
     TempId(ValueRc<TempId>),
     LookupRef(LookupRef<V>),
     TxFunction(TxFunction),
+    PartitionedTempId(PartitionedTempId),
     Vector(Vec<ValuePlace<V>>),
     Atom(V),
     MapNotation(MapNotation<V>),
@@ -161,6 +185,12 @@ impl<V: TransactableValueMarker> From<TxFunction> for ValuePlace<V> {
     }
 }
 
+impl<V: TransactableValueMarker> From<PartitionedTempId> for ValuePlace<V> {
+    fn from(v: PartitionedTempId) -> Self {
+        ValuePlace::PartitionedTempId(v)
+    }
+}
+
 impl<V: TransactableValueMarker> From<Vec<ValuePlace<V>>> for ValuePlace<V> {
     fn from(v: Vec<ValuePlace<V>>) -> Self {
         ValuePlace::Vector(v)
@@ -185,6 +215,7 @@ pub enum EntityPlace<V> {
     TempId(ValueRc<TempId>),
     LookupRef(LookupRef<V>),
     TxFunction(TxFunction),
+    PartitionedTempId(PartitionedTempId),
 }
 
 impl<V, E: Into<EntidOrIdent>> From<E> for EntityPlace<V> {
@@ -217,6 +248,12 @@ impl<V: TransactableValueMarker> From<TxFunction> for EntityPlace<V> {
     }
 }
 
+impl<V: TransactableValueMarker> From<PartitionedTempId> for EntityPlace<V> {
+    fn from(v: PartitionedTempId) -> Self {
+        EntityPlace::PartitionedTempId(v)
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
 pub enum AttributePlace {
     Entid(EntidOrIdent),
@@ -245,4 +282,24 @@ pub enum Entity<V> {
     },
     // Like {:db/id "tempid" a1 v1 a2 v2}.
     MapNotation(MapNotation<V>),
+    // Like [:db/cas e a old new].  The transaction fails unless `e` has the value `old` for the
+    // cardinality one attribute `a` -- or no value, if `old` is `nil` (`None`) -- and otherwise
+    // asserts [:db/add e a new].
+    Cas {
+        e: EntityPlace<V>,
+        a: AttributePlace,
+        old: Option<ValuePlace<V>>,
+        new: ValuePlace<V>,
+    },
+    // Like [:db/retractEntity e].  Retracts every datom [e a v] and every datom [e' a' e] that
+    // refers to `e`.  Entities referred to by `e` through component attributes are themselves
+    // retracted, recursively.
+    RetractEntity {
+        e: EntityPlace<V>,
+    },
+    // Like [:db/retractAttribute e a].  Retracts every datom [e a v].
+    RetractAttribute {
+        e: EntityPlace<V>,
+        a: AttributePlace,
+    },
 }
@@ -62,7 +62,7 @@ pub use ::{
 pub type SrcVarName = String;          // Do not include the required syntactic '$'.
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub struct Variable(pub Rc<PlainSymbol>);
+pub struct Variable(pub ValueRc<PlainSymbol>);
 
 impl Variable {
     pub fn as_str(&self) -> &str {
@@ -81,7 +81,7 @@ impl Variable {
     pub fn from_valid_name(name: &str) -> Variable {
         let s = PlainSymbol::plain(name);
         assert!(s.is_var_symbol());
-        Variable(Rc::new(s))
+        Variable(ValueRc::new(s))
     }
 }
 
@@ -105,7 +105,7 @@ impl FromValue<Variable> for Variable {
 impl Variable {
     pub fn from_rc(sym: Rc<PlainSymbol>) -> Option<Variable> {
         if sym.is_var_symbol() {
-            Some(Variable(sym.clone()))
+            Some(Variable(ValueRc::from_rc(sym)))
         } else {
             None
         }
@@ -114,7 +114,7 @@ impl Variable {
     /// TODO: intern strings. #398.
     pub fn from_symbol(sym: &PlainSymbol) -> Option<Variable> {
         if sym.is_var_symbol() {
-            Some(Variable(Rc::new(sym.clone())))
+            Some(Variable(ValueRc::new(sym.clone())))
         } else {
             None
         }
@@ -479,14 +479,14 @@ impl PatternValuePlace {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum PullConcreteAttribute {
-    Ident(Rc<Keyword>),
+    Ident(ValueRc<Keyword>),
     Entid(i64),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct NamedPullAttribute {
     pub attribute: PullConcreteAttribute,
-    pub alias: Option<Rc<Keyword>>,
+    pub alias: Option<ValueRc<Keyword>>,
 }
 
 impl From<PullConcreteAttribute> for NamedPullAttribute {
@@ -623,6 +623,14 @@ pub enum Limit {
     Variable(Variable),
 }
 
+/// How many results to skip before the first: `:offset 20` or `:offset ?n`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Offset {
+    None,
+    Fixed(u64),
+    Variable(Variable),
+}
+
 /// A definition of the first part of a find query: the
 /// `[:find ?foo ?bar…]` bit.
 ///
@@ -823,6 +831,10 @@ pub struct Pattern {
     pub attribute: PatternNonValuePlace,
     pub value: PatternValuePlace,
     pub tx: PatternNonValuePlace,
+
+    /// Whether the datom was asserted or retracted. Only a history query can see retractions;
+    /// every datom in any other view of the database is an assertion.
+    pub added: PatternValuePlace,
 }
 
 impl Pattern {
@@ -851,6 +863,7 @@ impl Pattern {
                         attribute: k.to_reversed().into(),
                         value: e_v,
                         tx: tx,
+                        added: PatternValuePlace::Placeholder,
                     });
                 } else {
                     return None;
@@ -863,6 +876,7 @@ impl Pattern {
             attribute: a,
             value: v,
             tx: tx,
+            added: PatternValuePlace::Placeholder,
         })
     }
 }
@@ -958,6 +972,31 @@ impl NotJoin {
     }
 }
 
+/// An invocation of a named rule: `(rule-name ?x :some/keyword ?y)`.
+/// Arguments are variables or constants; the rule definitions themselves are supplied
+/// separately, via `:in %`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RuleExpr {
+    pub source: Option<SrcVar>,
+    pub name: PlainSymbol,
+    pub args: Vec<FnArg>,
+}
+
+/// A single definition of a rule, like
+///
+/// ```edn
+/// [(ancestor ?a ?b) [?a :person/parent ?b]]
+/// ```
+///
+/// A rule can have several definitions, each with the same name and arity: the rule matches if
+/// any of its definitions match. A rule can refer to itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    pub name: PlainSymbol,
+    pub vars: Vec<Variable>,
+    pub clauses: Vec<WhereClause>,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct TypeAnnotation {
     pub value_type: Keyword,
@@ -971,7 +1010,7 @@ pub enum WhereClause {
     OrJoin(OrJoin),
     Pred(Predicate),
     WhereFn(WhereFn),
-    RuleExpr,
+    RuleExpr(RuleExpr),
     Pattern(Pattern),
     TypeAnnotation(TypeAnnotation),
 }
@@ -984,16 +1023,26 @@ pub struct ParsedQuery {
     pub with: Vec<Variable>,
     pub in_vars: Vec<Variable>,
     pub in_sources: BTreeSet<SrcVar>,
+    pub in_rules: bool,
     pub limit: Limit,
+    pub offset: Offset,
     pub where_clauses: Vec<WhereClause>,
     pub order: Option<Vec<Order>>,
 }
 
+/// One of the inputs named in an `:in` clause: a source, the rule set `%`, or a variable.
+pub(crate) enum InVar {
+    Source(SrcVar),
+    Rules,
+    Variable(Variable),
+}
+
 pub(crate) enum QueryPart {
     FindSpec(FindSpec),
     WithVars(Vec<Variable>),
-    InVars(Vec<Variable>),
+    InVars(Vec<InVar>),
     Limit(Limit),
+    Offset(Offset),
     WhereClauses(Vec<WhereClause>),
     Order(Vec<Order>),
 }
@@ -1008,8 +1057,9 @@ impl ParsedQuery {
     pub(crate) fn from_parts(parts: Vec<QueryPart>) -> std::result::Result<ParsedQuery, &'static str> {
         let mut find_spec: Option<FindSpec> = None;
         let mut with: Option<Vec<Variable>> = None;
-        let mut in_vars: Option<Vec<Variable>> = None;
+        let mut in_vars: Option<Vec<InVar>> = None;
         let mut limit: Option<Limit> = None;
+        let mut offset: Option<Offset> = None;
         let mut where_clauses: Option<Vec<WhereClause>> = None;
         let mut order: Option<Vec<Order>> = None;
 
@@ -1039,6 +1089,12 @@ impl ParsedQuery {
                     }
                     limit = Some(x)
                 },
+                QueryPart::Offset(x) => {
+                    if offset.is_some() {
+                        return Err("find query has repeated :offset");
+                    }
+                    offset = Some(x)
+                },
                 QueryPart::WhereClauses(x) => {
                     if where_clauses.is_some() {
                         return Err("find query has repeated :where");
@@ -1054,13 +1110,35 @@ impl ParsedQuery {
             }
         }
 
+        let mut vars: Vec<Variable> = vec![];
+        let mut in_sources: BTreeSet<SrcVar> = BTreeSet::default();
+        let mut in_rules = false;
+        for in_var in in_vars.unwrap_or(vec![]).into_iter() {
+            match in_var {
+                InVar::Source(source) => {
+                    if !in_sources.insert(source) {
+                        return Err("find query has repeated source in :in");
+                    }
+                },
+                InVar::Rules => {
+                    if in_rules {
+                        return Err("find query has repeated % in :in");
+                    }
+                    in_rules = true;
+                },
+                InVar::Variable(var) => vars.push(var),
+            }
+        }
+
         Ok(ParsedQuery {
             find_spec: find_spec.ok_or("expected :find")?,
             default_source: SrcVar::DefaultSrc,
             with: with.unwrap_or(vec![]),
-            in_vars: in_vars.unwrap_or(vec![]),
-            in_sources: BTreeSet::default(),
+            in_vars: vars,
+            in_sources,
+            in_rules,
             limit: limit.unwrap_or(Limit::None),
+            offset: offset.unwrap_or(Offset::None),
             where_clauses: where_clauses.ok_or("expected :where")?,
             order,
         })
@@ -1116,7 +1194,7 @@ impl ContainsVariables for WhereClause {
             &NotJoin(ref n)        => n.accumulate_mentioned_variables(acc),
             &WhereFn(ref f)        => f.accumulate_mentioned_variables(acc),
             &TypeAnnotation(ref a) => a.accumulate_mentioned_variables(acc),
-            &RuleExpr              => (),
+            &RuleExpr(ref r)       => r.accumulate_mentioned_variables(acc),
         }
     }
 }
@@ -1180,6 +1258,16 @@ impl ContainsVariables for Predicate {
     }
 }
 
+impl ContainsVariables for RuleExpr {
+    fn accumulate_mentioned_variables(&self, acc: &mut BTreeSet<Variable>) {
+        for arg in &self.args {
+            if let &FnArg::Variable(ref v) = arg {
+                acc_ref(acc, v)
+            }
+        }
+    }
+}
+
 impl ContainsVariables for TypeAnnotation {
     fn accumulate_mentioned_variables(&self, acc: &mut BTreeSet<Variable>) {
         acc_ref(acc, &self.variable);
@@ -1235,5 +1323,8 @@ impl ContainsVariables for Pattern {
         if let PatternNonValuePlace::Variable(ref v) = self.tx {
             acc_ref(acc, v)
         }
+        if let PatternValuePlace::Variable(ref v) = self.added {
+            acc_ref(acc, v)
+        }
     }
 }
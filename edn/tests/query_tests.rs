@@ -22,6 +22,7 @@ use edn::query::{
     FnArg,
     Limit,
     NonIntegerConstant,
+    Offset,
     Order,
     OrJoin,
     OrWhereClause,
@@ -29,6 +30,9 @@ use edn::query::{
     PatternNonValuePlace,
     PatternValuePlace,
     Predicate,
+    Rule,
+    RuleExpr,
+    SrcVar,
     UnifyVars,
     Variable,
     WhereClause,
@@ -36,6 +40,7 @@ use edn::query::{
 
 use edn::parse::{
     parse_query,
+    parse_rules,
 };
 
 ///! N.B., parsing a query can be done without reference to a DB.
@@ -58,6 +63,7 @@ fn can_parse_predicates() {
                        attribute: PatternNonValuePlace::Placeholder,
                        value: PatternValuePlace::Variable(Variable::from_valid_name("?y")),
                        tx: PatternNonValuePlace::Placeholder,
+                       added: PatternValuePlace::Placeholder,
                    }),
                    WhereClause::Pred(Predicate { operator: PlainSymbol::plain("<"), args: vec![
                        FnArg::Variable(Variable::from_valid_name("?y")), FnArg::EntidOrInteger(10),
@@ -84,6 +90,7 @@ fn can_parse_simple_or() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(10),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                            OrWhereClause::Clause(
                                WhereClause::Pattern(Pattern {
@@ -92,6 +99,7 @@ fn can_parse_simple_or() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(15),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                        ],
                    )),
@@ -117,6 +125,7 @@ fn can_parse_unit_or_join() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(15),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                        ],
                    )),
@@ -142,6 +151,7 @@ fn can_parse_simple_or_join() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(10),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                            OrWhereClause::Clause(
                                WhereClause::Pattern(Pattern {
@@ -150,6 +160,7 @@ fn can_parse_simple_or_join() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(-15),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                        ],
                    )),
@@ -180,6 +191,7 @@ fn can_parse_simple_or_and_join() {
                                    attribute: PatternNonValuePlace::Placeholder,
                                    value: PatternValuePlace::EntidOrInteger(10),
                                    tx: PatternNonValuePlace::Placeholder,
+                                   added: PatternValuePlace::Placeholder,
                                })),
                            OrWhereClause::And(
                                vec![
@@ -192,6 +204,7 @@ fn can_parse_simple_or_and_join() {
                                                attribute: ident("foo", "bar"),
                                                value: PatternValuePlace::Variable(Variable::from_valid_name("?y")),
                                                tx: PatternNonValuePlace::Placeholder,
+                                               added: PatternValuePlace::Placeholder,
                                            })),
                                            OrWhereClause::Clause(WhereClause::Pattern(Pattern {
                                                source: None,
@@ -199,6 +212,7 @@ fn can_parse_simple_or_and_join() {
                                                attribute: ident("foo", "baz"),
                                                value: PatternValuePlace::Variable(Variable::from_valid_name("?y")),
                                                tx: PatternNonValuePlace::Placeholder,
+                                               added: PatternValuePlace::Placeholder,
                                            })),
                                        ],
                                    )),
@@ -266,6 +280,35 @@ fn can_parse_limit() {
                Limit::Variable(Variable::from_valid_name("?limit")));
 }
 
+#[test]
+fn can_parse_offset() {
+    let invalid = "[:find ?x :where [?x :foo/baz ?y] :offset]";
+    assert!(parse_query(invalid).is_err());
+
+    let negative_invalid = "[:find ?x :where [?x :foo/baz ?y] :offset -1]";
+    assert!(parse_query(negative_invalid).is_err());
+
+    let repeated_invalid = "[:find ?x :where [?x :foo/baz ?y] :offset 1 :offset 2]";
+    assert!(parse_query(repeated_invalid).is_err());
+
+    let none = "[:find ?x :where [?x :foo/baz ?y] :limit 10]";
+    assert_eq!(parse_query(none).unwrap().offset,
+               Offset::None);
+
+    let zero = "[:find ?x :where [?x :foo/baz ?y] :offset 0]";
+    assert_eq!(parse_query(zero).unwrap().offset,
+               Offset::Fixed(0));
+
+    let with_limit = "[:find ?x :where [?x :foo/baz ?y] :limit 10 :offset 20]";
+    let parsed = parse_query(with_limit).unwrap();
+    assert_eq!(parsed.limit, Limit::Fixed(10));
+    assert_eq!(parsed.offset, Offset::Fixed(20));
+
+    let variable_with_in = "[:find ?x :in ?offset :where [?x :foo/baz ?y] :offset ?offset]";
+    assert_eq!(parse_query(variable_with_in).unwrap().offset,
+               Offset::Variable(Variable::from_valid_name("?offset")));
+}
+
 #[test]
 fn can_parse_uuid() {
     let expected = edn::Uuid::parse_str("4cb3f828-752d-497a-90c9-b1fd516d5644").expect("valid uuid");
@@ -298,3 +341,110 @@ fn can_parse_exotic_whitespace() {
                                 PatternNonValuePlace::Placeholder)
                        .expect("valid pattern")));
 }
+
+#[test]
+fn can_parse_added_place() {
+    let s = "[:find ?e ?tx :where [?e :foo/bar _ ?tx false]]";
+    let p = parse_query(s).unwrap();
+
+    assert_eq!(p.where_clauses,
+               vec![
+                   WhereClause::Pattern(Pattern {
+                       source: None,
+                       entity: PatternNonValuePlace::Variable(Variable::from_valid_name("?e")),
+                       attribute: ident("foo", "bar"),
+                       value: PatternValuePlace::Placeholder,
+                       tx: PatternNonValuePlace::Variable(Variable::from_valid_name("?tx")),
+                       added: PatternValuePlace::Constant(NonIntegerConstant::Boolean(false)),
+                   }),
+               ]);
+
+    let s = "[:find ?added :where [_ _ _ _ ?added]]";
+    let p = parse_query(s).unwrap();
+    match p.where_clauses[0] {
+        WhereClause::Pattern(ref pattern) => {
+            assert_eq!(pattern.added, PatternValuePlace::Variable(Variable::from_valid_name("?added")));
+        },
+        _ => panic!("expected a pattern"),
+    }
+}
+
+#[test]
+fn can_parse_in_sources_and_rules() {
+    let s = "[:find ?x :in $ % ?y :where [?x :foo/baz ?y]]";
+    let p = parse_query(s).expect("parsed");
+    assert_eq!(p.in_vars, vec![Variable::from_valid_name("?y")]);
+    assert!(p.in_sources.contains(&SrcVar::DefaultSrc));
+    assert!(p.in_rules);
+
+    let none = "[:find ?x :in ?y :where [?x :foo/baz ?y]]";
+    assert!(!parse_query(none).expect("parsed").in_rules);
+
+    let repeated = "[:find ?x :in % % :where [?x :foo/baz ?y]]";
+    assert!(parse_query(repeated).is_err());
+}
+
+#[test]
+fn can_parse_rule_expr() {
+    let s = "[:find ?x :in % :where [?x :foo/baz ?y] (ancestor ?x ?y) (friend-of ?x :foo/bar)]";
+    let p = parse_query(s).expect("parsed");
+    assert_eq!(p.where_clauses[1],
+               WhereClause::RuleExpr(RuleExpr {
+                   source: None,
+                   name: PlainSymbol::plain("ancestor"),
+                   args: vec![
+                       FnArg::Variable(Variable::from_valid_name("?x")),
+                       FnArg::Variable(Variable::from_valid_name("?y")),
+                   ],
+               }));
+    assert_eq!(p.where_clauses[2],
+               WhereClause::RuleExpr(RuleExpr {
+                   source: None,
+                   name: PlainSymbol::plain("friend-of"),
+                   args: vec![
+                       FnArg::Variable(Variable::from_valid_name("?x")),
+                       FnArg::IdentOrKeyword(Keyword::namespaced("foo", "bar")),
+                   ],
+               }));
+
+    // Reserved words aren't rule names.
+    assert!(parse_query("[:find ?x :where (and [?x :foo/baz ?y])]").is_err());
+
+    // Rules need at least one argument.
+    assert!(parse_query("[:find ?x :where [?x :foo/baz ?y] (ancestor)]").is_err());
+}
+
+#[test]
+fn can_parse_rules() {
+    let s = r#"[[(ancestor ?a ?b) [?a :person/parent ?b]]
+                [(ancestor ?a ?b) [?a :person/parent ?x] (ancestor ?x ?b)]]"#;
+    let rules = parse_rules(s).expect("parsed");
+    assert_eq!(rules.len(), 2);
+    assert_eq!(rules[0],
+               Rule {
+                   name: PlainSymbol::plain("ancestor"),
+                   vars: vec![Variable::from_valid_name("?a"), Variable::from_valid_name("?b")],
+                   clauses: vec![
+                       WhereClause::Pattern(Pattern {
+                           source: None,
+                           entity: PatternNonValuePlace::Variable(Variable::from_valid_name("?a")),
+                           attribute: ident("person", "parent"),
+                           value: PatternValuePlace::Variable(Variable::from_valid_name("?b")),
+                           tx: PatternNonValuePlace::Placeholder,
+                           added: PatternValuePlace::Placeholder,
+                       }),
+                   ],
+               });
+    assert_eq!(rules[1].clauses[1],
+               WhereClause::RuleExpr(RuleExpr {
+                   source: None,
+                   name: PlainSymbol::plain("ancestor"),
+                   args: vec![
+                       FnArg::Variable(Variable::from_valid_name("?x")),
+                       FnArg::Variable(Variable::from_valid_name("?b")),
+                   ],
+               }));
+
+    // Head variables must be unique.
+    assert!(parse_rules("[[(same ?a ?a) [?a :person/parent ?b]]]").is_err());
+}
@@ -72,6 +72,8 @@
 extern crate core;
 extern crate libc;
 extern crate mentat;
+#[macro_use]
+extern crate rental;
 
 use core::fmt::Display;
 
@@ -105,6 +107,7 @@ pub use mentat::{
     QueryInputs,
     QueryOutput,
     QueryResults,
+    QueryStream,
     RelResult,
     Store,
     Syncable,
@@ -144,6 +147,32 @@ pub use utils::log;
 pub type BindingIterator = vec::IntoIter<Binding>;
 pub type BindingListIterator = std::slice::Chunks<'static, mentat::Binding>;
 
+rental! {
+    pub mod rentals {
+        use mentat::{
+            QueryRows,
+            QueryStream,
+        };
+
+        /// The results of a query, read from the store a row at a time as they're requested. The
+        /// rows borrow the stream's statement, so the two are kept together.
+        #[rental_mut]
+        pub struct QueryResultStream<'a> {
+            stream: Box<QueryStream<'a>>,
+            rows: QueryRows<'stream>,
+        }
+    }
+}
+
+pub use rentals::QueryResultStream;
+
+impl<'a> QueryResultStream<'a> {
+    fn from_stream(stream: QueryStream<'a>) -> mentat::errors::Result<QueryResultStream<'a>> {
+        QueryResultStream::try_new(Box::new(stream), |stream| stream.rows())
+            .map_err(|e| e.0)
+    }
+}
+
 /// Helper macro for asserting one or more pointers are not null at the same time.
 #[macro_export]
 macro_rules! assert_not_null {
@@ -1522,6 +1551,43 @@ pub unsafe extern "C" fn query_builder_execute(query_builder: *mut QueryBuilder,
     translate_result(results, error)
 }
 
+/// Executes a query and returns an iterator that reads its results from the store a row at a time,
+/// rather than collecting them all first. Only collection and relation queries can be streamed.
+///
+/// # Safety
+///
+/// The store the query builder was created from must outlive the returned iterator.
+/// Callers are responsible for managing the memory for the return value.
+/// A destructor `query_result_stream_destroy` is provided for releasing the memory for this
+/// pointer type.
+#[no_mangle]
+pub unsafe extern "C" fn query_builder_execute_stream<'a>(query_builder: *mut QueryBuilder<'a>, error: *mut ExternError) -> *mut QueryResultStream<'a> {
+    assert_not_null!(query_builder);
+    let query_builder = &mut *query_builder;
+    let results = query_builder.execute_stream().and_then(QueryResultStream::from_stream);
+    translate_result(results, error)
+}
+
+/// Returns the next row of the `stream` as a `Vec<Binding>`.
+/// If there are no more rows, or reading the next one fails, a null pointer is returned.
+///
+/// # Safety
+///
+/// Callers are responsible for managing the memory for the return value.
+/// A destructor `typed_value_list_destroy` is provided for releasing the memory for this
+/// pointer type.
+#[no_mangle]
+pub unsafe extern "C" fn query_result_stream_next(stream: *mut QueryResultStream, error: *mut ExternError) -> *mut Vec<Binding> {
+    assert_not_null!(stream);
+    let stream = &mut *stream;
+    let row = match stream.rent_mut(|rows| rows.next()) {
+        Some(Ok(row)) => Ok(Some(row)),
+        Some(Err(e)) => Err(e),
+        None => Ok(None),
+    };
+    translate_opt_result(row, error)
+}
+
 fn unwrap_conversion<T>(value: Option<T>, expected_type: ValueType) -> T {
     match value {
         Some(v) => v,
@@ -2078,5 +2144,8 @@ define_destructor!(typed_value_result_set_destroy, RelResult<Binding>);
 /// Destructor for releasing the memory of [BindingListIterator](::BindingListIterator).
 define_destructor!(typed_value_result_set_iter_destroy, BindingListIterator);
 
+/// Destructor for releasing the memory of [QueryResultStream](::QueryResultStream).
+define_destructor_with_lifetimes!(query_result_stream_destroy, QueryResultStream<'a>);
+
 /// Destructor for releasing the memory of [InProgress](mentat::InProgress).
 define_destructor!(in_progress_destroy, InProgress);
@@ -20,7 +20,6 @@ use mentat_query::{
     Binding,
     FnArg,
     NonIntegerConstant,
-    SrcVar,
     VariableOrPlaceholder,
     WhereFn,
 };
@@ -44,7 +43,6 @@ use types::{
     FulltextColumn,
     QualifiedAlias,
     QueryValue,
-    SourceAlias,
 };
 
 use Known;
@@ -92,11 +90,11 @@ impl ConjoiningClauses {
 
         let mut args = where_fn.args.into_iter();
 
-        // TODO: process source variables.
-        match args.next().unwrap() {
-            FnArg::SrcVar(SrcVar::DefaultSrc) => {},
+        // Fulltext search always reads the current datoms of its source.
+        let source = match args.next().unwrap() {
+            FnArg::SrcVar(source) => self.data_source(&source)?,
             _ => bail!(AlgebrizerError::InvalidArgument(where_fn.operator.clone(), "source variable", 0)),
-        }
+        };
 
         let schema = known.schema;
 
@@ -141,13 +139,15 @@ impl ConjoiningClauses {
             return Ok(());
         }
 
-        let fulltext_values_alias = self.next_alias_for_table(DatomsTable::FulltextValues);
-        let datoms_table_alias = self.next_alias_for_table(DatomsTable::Datoms);
+        let fulltext_values = self.alias_table_in_database(source.database(), DatomsTable::FulltextValues);
+        let datoms_table = self.alias_table_in_database(source.database(), DatomsTable::Datoms);
+        let fulltext_values_alias = fulltext_values.1.clone();
+        let datoms_table_alias = datoms_table.1.clone();
 
         // We do a fulltext lookup by joining the fulltext values table against datoms -- just
         // like applying a pattern, but two tables contribute instead of one.
-        self.from.push(SourceAlias(DatomsTable::FulltextValues, fulltext_values_alias.clone()));
-        self.from.push(SourceAlias(DatomsTable::Datoms, datoms_table_alias.clone()));
+        self.from.push(fulltext_values);
+        self.from.push(datoms_table);
 
         // TODO: constrain the type in the more general cases (e.g., `a` is a var).
         self.constrain_attribute(datoms_table_alias.clone(), a);
@@ -272,6 +272,7 @@ mod testing {
         FnArg,
         Keyword,
         PlainSymbol,
+        SrcVar,
         Variable,
     };
 
@@ -16,6 +16,8 @@ use mentat_core::{
 };
 
 use mentat_query::{
+    Rule,
+    SrcVar,
     Variable,
 };
 
@@ -24,15 +26,32 @@ use errors::{
     Result,
 };
 
+use super::Rules;
+
+use types::{
+    DataSource,
+    DatabaseView,
+    QueryCursor,
+};
+
+use validate::{
+    validate_rules,
+};
+
 /// Define the inputs to a query. This is in two parts: a set of values known now, and a set of
 /// types known now.
 /// The separate map of types is to allow queries to be algebrized without full knowledge of
 /// the bindings that will be used at execution time.
 /// When built correctly, `types` is guaranteed to contain the types of `values` -- use
 /// `QueryInputs::new` or `QueryInputs::with_values` to construct an instance.
+#[derive(Clone)]
 pub struct QueryInputs {
     pub(crate) types: BTreeMap<Variable, ValueType>,
     pub(crate) values: BTreeMap<Variable, TypedValue>,
+    pub(crate) rules: Rules,
+    pub(crate) view: DatabaseView,
+    pub(crate) sources: BTreeMap<SrcVar, DataSource>,
+    pub(crate) cursor: Option<QueryCursor>,
 }
 
 impl Default for QueryInputs {
@@ -40,6 +59,10 @@ impl Default for QueryInputs {
         QueryInputs {
             types: BTreeMap::default(),
             values: BTreeMap::default(),
+            rules: Rules::default(),
+            view: DatabaseView::default(),
+            sources: BTreeMap::default(),
+            cursor: None,
         }
     }
 }
@@ -54,6 +77,10 @@ impl QueryInputs {
         QueryInputs {
             types: types.into_iter().collect(),
             values: BTreeMap::default(),
+            rules: Rules::default(),
+            view: DatabaseView::default(),
+            sources: BTreeMap::default(),
+            cursor: None,
         }
     }
 
@@ -61,6 +88,10 @@ impl QueryInputs {
         QueryInputs {
             types: values.iter().map(|(var, val)| (var.clone(), val.value_type())).collect(),
             values: values,
+            rules: Rules::default(),
+            view: DatabaseView::default(),
+            sources: BTreeMap::default(),
+            cursor: None,
         }
     }
 
@@ -76,6 +107,63 @@ impl QueryInputs {
                 }
             }
         }
-        Ok(QueryInputs { types: types, values: values })
+        Ok(QueryInputs { types: types, values: values, rules: Rules::default(), view: DatabaseView::default(), sources: BTreeMap::default(), cursor: None })
+    }
+
+    /// The values bound by these inputs.
+    pub fn values(&self) -> &BTreeMap<Variable, TypedValue> {
+        &self.values
+    }
+
+    /// The types of the input variables, including those of the bound values.
+    pub fn types(&self) -> &BTreeMap<Variable, ValueType> {
+        &self.types
+    }
+
+    /// Return true if these inputs supply only values and types: no rules, sources, historical
+    /// view, or cursor, each of which changes how a query is planned.
+    pub fn is_plain(&self) -> bool {
+        self.rules.is_empty() &&
+        self.sources.is_empty() &&
+        self.view == DatabaseView::Current &&
+        self.cursor.is_none()
+    }
+
+    /// Bind `values`, replacing any existing bindings -- and their types -- for the same variables.
+    pub fn rebind_values(mut self, values: BTreeMap<Variable, TypedValue>) -> QueryInputs {
+        for (var, value) in values.into_iter() {
+            self.types.insert(var.clone(), value.value_type());
+            self.values.insert(var, value);
+        }
+        self
+    }
+
+    /// Add rule definitions. Definitions that share a name are alternatives; they must all
+    /// take the same number of arguments.
+    pub fn with_rules(mut self, rules: Vec<Rule>) -> Result<QueryInputs> {
+        for rule in rules.into_iter() {
+            self.rules.entry(rule.name.clone()).or_insert(vec![]).push(rule);
+        }
+        validate_rules(&self.rules)?;
+        Ok(self)
+    }
+
+    /// Query a historical view of the store rather than its current state.
+    pub fn with_view(mut self, view: DatabaseView) -> QueryInputs {
+        self.view = view;
+        self
+    }
+
+    /// Bind a source, like `$a`, that the query names in `:in`. Binding the default source `$`
+    /// replaces the store being queried.
+    pub fn with_source(mut self, source: SrcVar, data: DataSource) -> QueryInputs {
+        self.sources.insert(source, data);
+        self
+    }
+
+    /// Only return the results of an ordered query that sort after `cursor`.
+    pub fn after(mut self, cursor: QueryCursor) -> QueryInputs {
+        self.cursor = Some(cursor);
+        self
     }
 }
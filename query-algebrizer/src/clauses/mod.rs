@@ -25,6 +25,8 @@ use std::fmt::{
     Formatter,
 };
 
+use std::rc::Rc;
+
 use mentat_core::{
     Attribute,
     Cloned,
@@ -43,12 +45,15 @@ use mentat_query::{
     Element,
     FindSpec,
     Keyword,
+    PlainSymbol,
     Pull,
+    SrcVar,
     Variable,
     WhereClause,
 };
 
 use mentat_query::{
+    Pattern,
     PatternNonValuePlace,
 };
 
@@ -62,6 +67,8 @@ use types::{
     ColumnIntersection,
     ComputedTable,
     Column,
+    DataSource,
+    DatabaseView,
     DatomsColumn,
     DatomsTable,
     EmptyBecause,
@@ -83,6 +90,7 @@ mod not;
 mod pattern;
 mod predicate;
 mod resolve;
+mod rules;
 
 mod ground;
 mod fulltext;
@@ -96,6 +104,11 @@ use validate::{
 
 pub use self::inputs::QueryInputs;
 
+pub(crate) use self::rules::{
+    RecursiveRule,
+    Rules,
+};
+
 use Known;
 
 trait Contains<K, T> {
@@ -223,6 +236,23 @@ pub struct ConjoiningClauses {
 
     /// Map of variables to the set of type requirements we have for them.
     required_types: BTreeMap<Variable, ValueTypeSet>,
+
+    /// The rules that clauses can invoke, shared with every nested CC.
+    rules: Rc<Rules>,
+
+    /// The recursive rules whose definitions we're inside. Invoking one of these again is only
+    /// allowed as the self-reference of the innermost, `recursive_rule`.
+    rules_in_progress: Vec<PlainSymbol>,
+
+    /// `Some` if this CC is one definition of a recursive rule, in which case the rule can refer
+    /// to itself.
+    recursive_rule: Option<RecursiveRule>,
+
+    /// Which state of the store patterns match against.
+    view: DatabaseView,
+
+    /// The sources bound by the query's inputs, shared with every nested CC.
+    sources: Rc<BTreeMap<SrcVar, DataSource>>,
 }
 
 impl PartialEq for ConjoiningClauses {
@@ -274,6 +304,11 @@ impl Default for ConjoiningClauses {
             value_bindings: BTreeMap::new(),
             known_types: BTreeMap::new(),
             extracted_types: BTreeMap::new(),
+            rules: Rc::new(Rules::default()),
+            rules_in_progress: vec![],
+            recursive_rule: None,
+            view: DatabaseView::Current,
+            sources: Rc::new(BTreeMap::new()),
         }
     }
 }
@@ -313,7 +348,7 @@ impl ConjoiningClauses {
     where T: Into<Option<QueryInputs>> {
         match inputs.into() {
             None => ConjoiningClauses::with_alias_counter(alias_counter),
-            Some(QueryInputs { mut types, mut values }) => {
+            Some(QueryInputs { mut types, mut values, rules, view, sources }) => {
                 // Discard any bindings not mentioned in our :in clause.
                 types.keep_intersected_keys(&in_variables);
                 values.keep_intersected_keys(&in_variables);
@@ -322,6 +357,9 @@ impl ConjoiningClauses {
                     alias_counter: alias_counter,
                     input_variables: in_variables,
                     value_bindings: values,
+                    rules: Rc::new(rules),
+                    view: view,
+                    sources: Rc::new(sources),
                     ..Default::default()
                 };
 
@@ -361,6 +399,10 @@ impl ConjoiningClauses {
             known_types: self.known_types.clone(),
             extracted_types: self.extracted_types.clone(),
             required_types: self.required_types.clone(),
+            rules: self.rules.clone(),
+            rules_in_progress: self.rules_in_progress.clone(),
+            view: self.view,
+            sources: self.sources.clone(),
             ..Default::default()
         }
     }
@@ -376,6 +418,10 @@ impl ConjoiningClauses {
             known_types: self.known_types.with_intersected_keys(&vars),
             extracted_types: self.extracted_types.with_intersected_keys(&vars),
             required_types: self.required_types.with_intersected_keys(&vars),
+            rules: self.rules.clone(),
+            rules_in_progress: self.rules_in_progress.clone(),
+            view: self.view,
+            sources: self.sources.clone(),
             ..Default::default()
         }
     }
@@ -413,6 +459,11 @@ impl ConjoiningClauses {
         self.value_bindings.contains_key(var)
     }
 
+    /// Return true if `var` is named in `:in` but its value won't be known until the query is run.
+    pub fn is_late_bound(&self, var: &Variable) -> bool {
+        self.input_variables.contains(var) && !self.value_bindings.contains_key(var)
+    }
+
     pub fn value_bindings(&self, variables: &BTreeSet<Variable>) -> VariableBindings {
         self.value_bindings.with_intersected_keys(variables)
     }
@@ -869,14 +920,58 @@ impl ConjoiningClauses {
     /// Note that if this function decides that a pattern cannot match, it will flip
     /// `empty_because`.
     fn alias_table<'s, 'a>(&mut self, schema: &'s Schema, pattern: &'a EvolvedPattern) -> Option<SourceAlias> {
+        let source = self.pattern_source(pattern);
         self.table_for_places(schema, &pattern.attribute, &pattern.value)
             .map_err(|reason| {
                 self.mark_known_empty(reason);
             })
-            .map(|table: DatomsTable| SourceAlias(table, self.next_alias_for_table(table)))
+            .map(|table: DatomsTable| table_for_view(source.view(), table))
+            .map(|table: DatomsTable| self.alias_table_in_database(source.database(), table))
             .ok()
     }
 
+    /// Produce an alias for `table` in the given database: the store being queried if `database`
+    /// is `None`, or an attached database. Tables in attached databases are tracked as computed
+    /// tables.
+    pub(crate) fn alias_table_in_database(&mut self, database: Option<&str>, table: DatomsTable) -> SourceAlias {
+        let alias = self.next_alias_for_table(table);
+        match database {
+            None => SourceAlias(table, alias),
+            Some(database) => {
+                let computed = self.computed_tables.push_computed(ComputedTable::Attached {
+                    database: database.to_string(),
+                    table: table,
+                });
+                SourceAlias(computed, alias)
+            },
+        }
+    }
+
+    /// Find the datoms that `source` refers to. The default source is the store being queried,
+    /// unless the inputs bound it to something else.
+    pub(crate) fn data_source(&self, source: &SrcVar) -> Result<DataSource> {
+        match self.sources.get(source) {
+            Some(data) => Ok(data.clone()),
+            None => match source {
+                &SrcVar::DefaultSrc => Ok(DataSource::Store(self.view)),
+                &SrcVar::NamedSrc(ref name) => bail!(AlgebrizerError::UnknownSource(name.clone())),
+            },
+        }
+    }
+
+    /// Fail if `pattern` names a source that the query doesn't know about.
+    pub(crate) fn check_pattern_source(&self, pattern: &Pattern) -> Result<()> {
+        if let Some(ref source) = pattern.source {
+            self.data_source(source)?;
+        }
+        Ok(())
+    }
+
+    /// The source of a pattern's datoms. We check a pattern's source before applying it.
+    fn pattern_source(&self, pattern: &EvolvedPattern) -> DataSource {
+        self.data_source(&pattern.source).expect("pattern source to be known")
+    }
+
     fn get_attribute_for_value<'s>(&self, schema: &'s Schema, value: &TypedValue) -> Option<&'s Attribute> {
         match value {
             // We know this one is known if the attribute lookup succeeds…
@@ -924,8 +1019,18 @@ impl ConjoiningClauses {
     ///    datoms12.e = datoms13.v
     ///    datoms12.e = datoms14.e
     /// ```
+    ///
+    /// A late-bound input variable is also constrained to equal its input:
+    ///
+    /// ```example
+    ///    datoms12.e = $ifoo
+    /// ```
     pub(crate) fn expand_column_bindings(&mut self) {
-        for cols in self.column_bindings.values() {
+        for (var, cols) in self.column_bindings.iter() {
+            if self.is_late_bound(var) {
+                self.wheres.add_intersection(ColumnConstraint::Equals(cols[0].clone(), QueryValue::Input(var.clone())));
+            }
+
             if cols.len() > 1 {
                 let ref primary = cols[0];
                 let secondaries = cols.iter().skip(1);
@@ -1011,9 +1116,12 @@ impl ConjoiningClauses {
             // Update known types.
             self.narrow_types_for_var(var.clone(), types);
 
-            let qa = self.extracted_types
-                         .get(&var)
-                         .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()))?;
+            let qa = match self.extracted_types.get(&var) {
+                Some(qa) => qa,
+                // We check the type of a late-bound input when it's supplied.
+                None if self.is_late_bound(&var) => continue,
+                None => bail!(AlgebrizerError::UnboundVariable(var.name())),
+            };
             self.wheres.add_intersection(ColumnConstraint::HasTypes {
                 value: qa.0.clone(),
                 value_types: types,
@@ -1106,6 +1214,7 @@ impl ConjoiningClauses {
             }
             match clause {
                 WhereClause::Pattern(p) => {
+                    self.check_pattern_source(&p)?;
                     match self.make_evolved_pattern(known, p) {
                         PlaceOrEmpty::Place(evolved) => patterns.push_back(evolved),
                         PlaceOrEmpty::Empty(because) => {
@@ -1131,6 +1240,7 @@ impl ConjoiningClauses {
     pub(crate) fn apply_clause(&mut self, known: Known, where_clause: WhereClause) -> Result<()> {
         match where_clause {
             WhereClause::Pattern(p) => {
+                self.check_pattern_source(&p)?;
                 match self.make_evolved_pattern(known, p) {
                     PlaceOrEmpty::Place(evolved) => self.apply_pattern(known, evolved),
                     PlaceOrEmpty::Empty(because) => self.mark_known_empty(because),
@@ -1154,7 +1264,9 @@ impl ConjoiningClauses {
             WhereClause::TypeAnnotation(anno) => {
                 self.apply_type_anno(&anno)
             },
-            _ => unimplemented!(),
+            WhereClause::RuleExpr(r) => {
+                self.apply_rule_expr(known, r)
+            },
         }
     }
 }
@@ -1171,6 +1283,18 @@ impl PushComputed for Vec<ComputedTable> {
     }
 }
 
+/// Historical views are derived from the `transactions` log instead of `datoms`. Those tables
+/// resolve fulltext values themselves, so it doesn't matter which of the datoms tables we would
+/// otherwise have picked.
+fn table_for_view(view: DatabaseView, table: DatomsTable) -> DatomsTable {
+    match view {
+        DatabaseView::Current |
+        DatabaseView::Since(_) => table,
+        DatabaseView::AsOf(tx) => DatomsTable::AsOf(tx),
+        DatabaseView::History => DatomsTable::History,
+    }
+}
+
 // These are helpers that tests use to build Schema instances.
 #[cfg(test)]
 fn associate_ident(schema: &mut Schema, i: Keyword, e: Entid) {
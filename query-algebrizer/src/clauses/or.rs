@@ -246,7 +246,8 @@ impl ConjoiningClauses {
                                 _simply_matches_place(&template.entity, &p.entity) &&
                                 _simply_matches_place(&template.attribute, &p.attribute) &&
                                 _simply_matches_value_place(&template.value, &p.value) &&
-                                _simply_matches_place(&template.tx, &p.tx)
+                                _simply_matches_place(&template.tx, &p.tx) &&
+                                _simply_matches_value_place(&template.added, &p.added)
                             } else {
                                 // No previous pattern.
                                 true
@@ -322,6 +323,7 @@ impl ConjoiningClauses {
             },
             DeconstructedOrJoin::UnitPattern(pattern) => {
                 // Same, but simpler.
+                self.check_pattern_source(&pattern)?;
                 match self.make_evolved_pattern(known, pattern) {
                     PlaceOrEmpty::Empty(e) => {
                         self.mark_known_empty(e);
@@ -336,6 +338,9 @@ impl ConjoiningClauses {
                 // Hooray! Fully unified and plain ol' patterns that all use the same table.
                 // Go right ahead and produce a set of constraint alternations that we can collect,
                 // using a single table alias.
+                for pattern in patterns.iter() {
+                    self.check_pattern_source(pattern)?;
+                }
                 self.apply_simple_or_join(known, patterns, mentioned_vars)
             },
             DeconstructedOrJoin::Complex(or_join) => {
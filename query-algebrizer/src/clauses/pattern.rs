@@ -12,6 +12,7 @@ use mentat_core::{
     Cloned,
     Entid,
     HasSchema,
+    Schema,
     TypedValue,
     ValueType,
     ValueTypeSet,
@@ -32,13 +33,20 @@ use clauses::{
 
 use types::{
     ColumnConstraint,
+    DataSource,
+    DatabaseView,
     DatomsColumn,
     EmptyBecause,
     EvolvedNonValuePlace,
     EvolvedPattern,
     EvolvedValuePlace,
+    Inequality,
     PlaceOrEmpty,
+    QualifiedAlias,
+    QueryValue,
     SourceAlias,
+    TableAlias,
+    TransactionsColumn,
 };
 
 use Known;
@@ -157,6 +165,13 @@ impl ConjoiningClauses {
                     if self.is_known_empty() {
                         return;
                     }
+                } else if self.is_late_bound(v) {
+                    // Just as for a constant value, we must constrain the type tag if we can't
+                    // determine the range of values from the attribute. The input will be checked
+                    // against this type when it's bound.
+                    if let Some(input_type) = self.known_type(v) {
+                        self.wheres.add_intersection(ColumnConstraint::has_unit_type(col.clone(), input_type));
+                    }
                 }
 
                 self.bind_column_to_var(schema, col.clone(), DatomsColumn::Value, v.clone());
@@ -264,6 +279,66 @@ impl ConjoiningClauses {
                 self.constrain_column_to_entity(col.clone(), DatomsColumn::Tx, entid);
             },
         }
+
+        let view = self.pattern_source(pattern).view();
+        if let DatabaseView::Since(since) = view {
+            let tx = QualifiedAlias::new(col.clone(), DatomsColumn::Tx);
+            self.wheres.add_intersection(ColumnConstraint::Inequality {
+                operator: Inequality::TxAfter,
+                left: QueryValue::Column(tx),
+                right: QueryValue::Entid(since),
+            });
+        }
+
+        self.apply_added_place(schema, view, col, &pattern.added);
+    }
+
+    /// Only the history view has an `added` column. Every datom in any other view is an
+    /// assertion, so there `added` is always `true`.
+    fn apply_added_place(&mut self, schema: &Schema, view: DatabaseView, col: &TableAlias, added: &EvolvedValuePlace) {
+        match added {
+            &EvolvedValuePlace::Placeholder => (),
+            &EvolvedValuePlace::Variable(ref v) => {
+                self.constrain_var_to_type(v.clone(), ValueType::Boolean);
+                if self.is_known_empty() {
+                    return;
+                }
+                if view == DatabaseView::History {
+                    self.bind_column_to_var(schema, col.clone(), TransactionsColumn::Added, v.clone());
+                } else {
+                    match self.bound_value(v) {
+                        Some(TypedValue::Boolean(true)) => (),
+                        Some(existing) => {
+                            self.mark_known_empty(EmptyBecause::ConflictingBindings {
+                                var: v.clone(),
+                                existing: existing,
+                                desired: TypedValue::Boolean(true),
+                            });
+                        },
+                        None => self.bind_value(v, TypedValue::Boolean(true)),
+                    }
+                }
+            },
+            &EvolvedValuePlace::Value(TypedValue::Boolean(b)) => {
+                if view == DatabaseView::History {
+                    self.constrain_column_to_constant(col.clone(), TransactionsColumn::Added, TypedValue::Boolean(b));
+                } else if !b {
+                    self.mark_known_empty(EmptyBecause::RetractionOutsideHistory);
+                }
+            },
+            &EvolvedValuePlace::Value(ref v) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(ValueType::Boolean, v.clone()));
+            },
+            &EvolvedValuePlace::Entid(e) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(ValueType::Boolean, TypedValue::Ref(e)));
+            },
+            &EvolvedValuePlace::EntidOrInteger(i) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(ValueType::Boolean, TypedValue::Long(i)));
+            },
+            &EvolvedValuePlace::IdentOrKeyword(ref kw) => {
+                self.mark_known_empty(EmptyBecause::ValueTypeMismatch(ValueType::Boolean, TypedValue::Keyword(kw.clone())));
+            },
+        }
     }
 
     fn reverse_lookup(&mut self, known: Known, var: &Variable, attr: Entid, val: &TypedValue) -> bool {
@@ -315,15 +390,18 @@ impl ConjoiningClauses {
     // TODO: use constant values -- extract transformation code from apply_pattern_clause_for_alias.
     // TODO: loop over all patterns until no more cache values apply?
     fn attempt_cache_lookup(&mut self, known: Known, pattern: &EvolvedPattern) -> bool {
-        // Precondition: default source. If it's not default, don't call this.
-        assert!(pattern.source == SrcVar::DefaultSrc);
-
         let schema = known.schema;
 
         if pattern.tx != EvolvedNonValuePlace::Placeholder {
             return false;
         }
 
+        // The cache only knows about the current state of the store.
+        if self.pattern_source(pattern) != DataSource::Store(DatabaseView::Current) ||
+           pattern.added != EvolvedValuePlace::Placeholder {
+            return false;
+        }
+
         // See if we can use the cache.
         match pattern.attribute {
             EvolvedNonValuePlace::Entid(attr) => {
@@ -536,7 +614,7 @@ impl ConjoiningClauses {
     }
 
     pub(crate) fn make_evolved_pattern(&self, known: Known, pattern: Pattern) -> PlaceOrEmpty<EvolvedPattern> {
-        let (e, a, v, tx, source) = (pattern.entity, pattern.attribute, pattern.value, pattern.tx, pattern.source);
+        let (e, a, v, tx, added, source) = (pattern.entity, pattern.attribute, pattern.value, pattern.tx, pattern.added, pattern.source);
         use self::PlaceOrEmpty::*;
         match self.make_evolved_entity(&known, e) {
             Empty(because) => Empty(because),
@@ -550,13 +628,19 @@ impl ConjoiningClauses {
                                 match self.make_evolved_tx(&known, tx) {
                                     Empty(because) => Empty(because),
                                     Place(tx) => {
-                                        PlaceOrEmpty::Place(EvolvedPattern {
-                                            source: source.unwrap_or(SrcVar::DefaultSrc),
-                                            entity: e,
-                                            attribute: a,
-                                            value: v,
-                                            tx: tx,
-                                        })
+                                        match self.make_evolved_value(&known, Some(ValueType::Boolean), added) {
+                                            Empty(because) => Empty(because),
+                                            Place(added) => {
+                                                PlaceOrEmpty::Place(EvolvedPattern {
+                                                    source: source.unwrap_or(SrcVar::DefaultSrc),
+                                                    entity: e,
+                                                    attribute: a,
+                                                    value: v,
+                                                    tx: tx,
+                                                    added: added,
+                                                })
+                                            },
+                                        }
                                     },
                                 }
                             },
@@ -627,11 +711,6 @@ impl ConjoiningClauses {
     }
 
     pub(crate) fn apply_pattern(&mut self, known: Known, pattern: EvolvedPattern) {
-        // For now we only support the default source.
-        if pattern.source != SrcVar::DefaultSrc {
-            unimplemented!();
-        }
-
         if self.attempt_cache_lookup(known, &pattern) {
             return;
         }
@@ -675,17 +754,27 @@ mod testing {
         ident,
     };
 
+    use errors::{
+        AlgebrizerError,
+    };
+
     use types::{
         Column,
         ColumnConstraint,
+        ComputedTable,
+        DataSource,
+        DatabaseView,
         DatomsTable,
+        Inequality,
         QualifiedAlias,
         QueryValue,
         SourceAlias,
+        TransactionsColumn,
     };
 
     use {
         algebrize,
+        algebrize_with_inputs,
         parse_find_string,
     };
 
@@ -695,6 +784,13 @@ mod testing {
         algebrize(known, parsed).expect("algebrize failed").cc
     }
 
+    fn alg_in_view(schema: &Schema, view: DatabaseView, input: &str) -> ConjoiningClauses {
+        let parsed = parse_find_string(input).expect("parse failed");
+        let known = Known::for_schema(schema);
+        let inputs = QueryInputs::default().with_view(view);
+        algebrize_with_inputs(known, parsed, 0, inputs).expect("algebrize failed").cc
+    }
+
     #[test]
     fn test_unknown_ident() {
         let mut cc = ConjoiningClauses::default();
@@ -707,6 +803,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         assert!(cc.is_known_empty());
@@ -726,6 +823,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         assert!(cc.is_known_empty());
@@ -750,6 +848,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -791,6 +890,7 @@ mod testing {
             attribute: PatternNonValuePlace::Placeholder,
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -841,6 +941,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(a.clone()),
             value: PatternValuePlace::Variable(v.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -885,6 +986,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(a.clone()),
             value: PatternValuePlace::Variable(v.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         assert!(cc.is_known_empty());
@@ -908,6 +1010,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(a.clone()),
             value: PatternValuePlace::Variable(v.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -939,6 +1042,7 @@ mod testing {
             attribute: PatternNonValuePlace::Placeholder,
             value: PatternValuePlace::Constant("hello".into()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // println!("{:#?}", cc);
@@ -990,6 +1094,7 @@ mod testing {
             attribute: ident("foo", "roz"),
             value: PatternValuePlace::Constant("idgoeshere".into()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         cc.apply_parsed_pattern(known, Pattern {
             source: None,
@@ -997,6 +1102,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // Finally, expand column bindings to get the overlaps for ?x.
@@ -1065,6 +1171,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         let d0_e = QualifiedAlias::new("datoms00".to_string(), DatomsColumn::Entity);
@@ -1113,6 +1220,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // The type of the provided binding doesn't match the type of the attribute.
@@ -1149,6 +1257,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // The type of the provided binding doesn't match the type of the attribute.
@@ -1184,6 +1293,7 @@ mod testing {
             attribute: ident("foo", "roz"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         cc.apply_parsed_pattern(known, Pattern {
             source: None,
@@ -1191,6 +1301,7 @@ mod testing {
             attribute: ident("foo", "bar"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // Finally, expand column bindings to get the overlaps for ?x.
@@ -1227,6 +1338,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(y.clone()),
             value: PatternValuePlace::Constant(NonIntegerConstant::Boolean(true)),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         cc.apply_parsed_pattern(known, Pattern {
             source: None,
@@ -1234,6 +1346,7 @@ mod testing {
             attribute: PatternNonValuePlace::Variable(y.clone()),
             value: PatternValuePlace::Variable(x.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // Finally, expand column bindings to get the overlaps for ?x.
@@ -1265,4 +1378,114 @@ mod testing {
         assert!(!cc.extracted_types.contains_key(&e));
         assert!(!cc.extracted_types.contains_key(&v));
     }
+
+    fn prepopulated_view_schema() -> Schema {
+        let mut schema = Schema::default();
+        associate_ident(&mut schema, Keyword::namespaced("foo", "bar"), 99);
+        add_attribute(&mut schema, 99, Attribute {
+            value_type: ValueType::String,
+            ..Default::default()
+        });
+        schema
+    }
+
+    #[test]
+    fn test_as_of_view() {
+        let schema = prepopulated_view_schema();
+        let cc = alg_in_view(&schema, DatabaseView::AsOf(1000),
+                             r#"[:find ?e ?v :where [?e :foo/bar ?v]]"#);
+        assert!(!cc.is_known_empty());
+        assert_eq!(cc.from, vec![SourceAlias(DatomsTable::AsOf(1000), "as_of00".to_string())]);
+
+        // Nothing but assertions are visible as of a transaction.
+        let cc = alg_in_view(&schema, DatabaseView::AsOf(1000),
+                             r#"[:find ?e ?v :where [?e :foo/bar ?v _ false]]"#);
+        assert!(cc.is_known_empty());
+        assert_eq!(cc.empty_because, Some(EmptyBecause::RetractionOutsideHistory));
+
+        let added = Variable::from_valid_name("?added");
+        let cc = alg_in_view(&schema, DatabaseView::AsOf(1000),
+                             r#"[:find ?e ?added :where [?e :foo/bar _ _ ?added]]"#);
+        assert!(!cc.is_known_empty());
+        assert_eq!(cc.bound_value(&added), Some(TypedValue::Boolean(true)));
+    }
+
+    #[test]
+    fn test_since_view() {
+        let schema = prepopulated_view_schema();
+        let cc = alg_in_view(&schema, DatabaseView::Since(1000),
+                             r#"[:find ?e ?v :where [?e :foo/bar ?v]]"#);
+        assert!(!cc.is_known_empty());
+        assert_eq!(cc.from, vec![SourceAlias(DatomsTable::Datoms, "datoms00".to_string())]);
+
+        let tx = QualifiedAlias::new("datoms00".to_string(), DatomsColumn::Tx);
+        let expected = ColumnConstraint::Inequality {
+            operator: Inequality::TxAfter,
+            left: QueryValue::Column(tx),
+            right: QueryValue::Entid(1000),
+        };
+        assert!(cc.wheres.0.contains(&expected.into()));
+    }
+
+    #[test]
+    fn test_history_view() {
+        let schema = prepopulated_view_schema();
+        let added = Variable::from_valid_name("?added");
+        let cc = alg_in_view(&schema, DatabaseView::History,
+                             r#"[:find ?e ?v ?tx ?added :where [?e :foo/bar ?v ?tx ?added]]"#);
+        assert!(!cc.is_known_empty());
+        assert_eq!(cc.from, vec![SourceAlias(DatomsTable::History, "history00".to_string())]);
+        assert_eq!(cc.column_bindings.get(&added),
+                   Some(&vec![QualifiedAlias::new("history00".to_string(), TransactionsColumn::Added)]));
+        assert_eq!(cc.known_type(&added), Some(ValueType::Boolean));
+
+        // Retractions can be matched by value.
+        let cc = alg_in_view(&schema, DatabaseView::History,
+                             r#"[:find ?e ?v :where [?e :foo/bar ?v _ false]]"#);
+        assert!(!cc.is_known_empty());
+        let d0_added = QualifiedAlias::new("history00".to_string(), TransactionsColumn::Added);
+        assert!(cc.wheres.0.contains(&ColumnConstraint::Equals(d0_added, QueryValue::TypedValue(TypedValue::Boolean(false))).into()));
+    }
+
+    #[test]
+    fn test_named_sources() {
+        let schema = prepopulated_view_schema();
+        let known = Known::for_schema(&schema);
+        let a = SrcVar::NamedSrc("a".to_string());
+
+        // A named source must be bound.
+        let parsed = parse_find_string(r#"[:find ?e :in $ $a :where [$a ?e :foo/bar _]]"#).expect("parse failed");
+        match algebrize_with_inputs(known, parsed, 0, QueryInputs::default()) {
+            Err(AlgebrizerError::UnboundSource(name)) => assert_eq!(name, "a"),
+            x => panic!("expected an unbound source, got {:?}", x.err()),
+        }
+
+        // And named in `:in`.
+        let inputs = QueryInputs::default().with_source(a.clone(), DataSource::Store(DatabaseView::History));
+        let parsed = parse_find_string(r#"[:find ?e :where [$a ?e :foo/bar _]]"#).expect("parse failed");
+        match algebrize_with_inputs(known, parsed, 0, inputs) {
+            Err(AlgebrizerError::UnknownSource(name)) => assert_eq!(name, "a"),
+            x => panic!("expected an unknown source, got {:?}", x.err()),
+        }
+
+        // Each pattern reads from its own source.
+        let inputs = QueryInputs::default().with_source(a.clone(), DataSource::Store(DatabaseView::History));
+        let parsed = parse_find_string(r#"[:find ?e ?added :in $ $a :where [?e :foo/bar ?v] [$a ?e :foo/bar ?v _ ?added]]"#).expect("parse failed");
+        let cc = algebrize_with_inputs(known, parsed, 0, inputs).expect("algebrize failed").cc;
+        assert_eq!(cc.from, vec![SourceAlias(DatomsTable::Datoms, "datoms00".to_string()),
+                                 SourceAlias(DatomsTable::History, "history01".to_string())]);
+        let added = Variable::from_valid_name("?added");
+        assert_eq!(cc.column_bindings.get(&added),
+                   Some(&vec![QualifiedAlias::new("history01".to_string(), TransactionsColumn::Added)]));
+
+        // Tables in attached databases are computed tables.
+        let inputs = QueryInputs::default().with_source(a.clone(), DataSource::Attached("other".to_string(), DatabaseView::Current));
+        let parsed = parse_find_string(r#"[:find ?e :in $a :where [$a ?e :foo/bar _]]"#).expect("parse failed");
+        let cc = algebrize_with_inputs(known, parsed, 0, inputs).expect("algebrize failed").cc;
+        assert_eq!(cc.from, vec![SourceAlias(DatomsTable::Computed(0), "datoms00".to_string())]);
+        assert_eq!(cc.computed_tables, vec![ComputedTable::Attached {
+            database: "other".to_string(),
+            table: DatomsTable::Datoms,
+        }]);
+    }
 }
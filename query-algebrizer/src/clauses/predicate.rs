@@ -239,6 +239,7 @@ mod testing {
             attribute: PatternNonValuePlace::Placeholder,
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         assert!(!cc.is_known_empty());
 
@@ -300,6 +301,7 @@ mod testing {
             attribute: PatternNonValuePlace::Placeholder,
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
         assert!(!cc.is_known_empty());
 
@@ -318,6 +320,7 @@ mod testing {
             attribute: ident("foo", "roz"),
             value: PatternValuePlace::Variable(y.clone()),
             tx: PatternNonValuePlace::Placeholder,
+            added: PatternValuePlace::Placeholder,
         });
 
         // Finally, expand column bindings to get the overlaps for ?x.
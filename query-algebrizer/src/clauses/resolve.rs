@@ -19,6 +19,7 @@ use mentat_query::{
     FnArg,
     NonIntegerConstant,
     PlainSymbol,
+    Variable,
 };
 
 use clauses::ConjoiningClauses;
@@ -35,6 +36,16 @@ use types::{
 
 /// Argument resolution.
 impl ConjoiningClauses {
+    /// The first column to which `var` is bound, or -- if `var` is an `:in` variable that will only
+    /// be bound when the query is run -- the input itself.
+    fn column_or_input(&self, var: &Variable) -> Result<QueryValue> {
+        match self.column_bindings.get(var).and_then(|cols| cols.first()) {
+            Some(col) => Ok(QueryValue::Column(col.clone())),
+            None if self.is_late_bound(var) => Ok(QueryValue::Input(var.clone())),
+            None => bail!(AlgebrizerError::UnboundVariable(var.name())),
+        }
+    }
+
     /// Take a function argument and turn it into a `QueryValue` suitable for use in a concrete
     /// constraint.
     /// Additionally, do two things:
@@ -53,10 +64,7 @@ impl ConjoiningClauses {
                     }
                 } else {
                     self.constrain_var_to_numeric(var.clone());
-                    self.column_bindings
-                        .get(&var)
-                        .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
-                        .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()).into())
+                    self.column_or_input(&var)
                 }
             },
             // Can't be an entid.
@@ -86,10 +94,7 @@ impl ConjoiningClauses {
                     Some(v) => bail!(AlgebrizerError::InputTypeDisagreement(var.name().clone(), ValueType::Instant, v.value_type())),
                     None => {
                         self.constrain_var_to_type(var.clone(), ValueType::Instant);
-                        self.column_bindings
-                            .get(&var)
-                            .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
-                            .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()).into())
+                        self.column_or_input(&var)
                     },
                 }
             },
@@ -124,10 +129,7 @@ impl ConjoiningClauses {
                     // Incorrect types will be handled by the constraint, above.
                     Ok(QueryValue::Entid(e))
                 } else {
-                    self.column_bindings
-                        .get(&var)
-                        .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
-                        .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()).into())
+                    self.column_or_input(&var)
                 }
             },
             EntidOrInteger(i) => Ok(QueryValue::TypedValue(TypedValue::Ref(i))),
@@ -169,10 +171,7 @@ impl ConjoiningClauses {
                 match self.bound_value(&var) {
                     Some(v) => Ok(QueryValue::TypedValue(v)),
                     None => {
-                        self.column_bindings
-                            .get(&var)
-                            .and_then(|cols| cols.first().map(|col| QueryValue::Column(col.clone())))
-                            .ok_or_else(|| AlgebrizerError::UnboundVariable(var.name()).into())
+                        self.column_or_input(&var)
                     },
                 }
             },
@@ -0,0 +1,641 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use mentat_core::{
+    Schema,
+    SQLValueType,
+    TypedValue,
+};
+
+use mentat_core::counter::RcCounter;
+
+use mentat_query::{
+    Binding,
+    FnArg,
+    NotJoin,
+    OrJoin,
+    OrWhereClause,
+    Pattern,
+    PatternNonValuePlace,
+    PatternValuePlace,
+    PlainSymbol,
+    Predicate,
+    Rule,
+    RuleExpr,
+    SrcVar,
+    TypeAnnotation,
+    UnifyVars,
+    Variable,
+    VariableOrPlaceholder,
+    WhereClause,
+    WhereFn,
+};
+
+use clauses::{
+    ConjoiningClauses,
+    PushComputed,
+};
+
+use errors::{
+    AlgebrizerError,
+    Result,
+};
+
+use types::{
+    ColumnConstraint,
+    ComputedTable,
+    DatomsTable,
+    EmptyBecause,
+    QualifiedAlias,
+    QueryValue,
+    SourceAlias,
+    TableAlias,
+    VariableColumn,
+};
+
+use Known;
+
+/// The rules available to a query, by name. A rule can have several definitions.
+pub(crate) type Rules = BTreeMap<PlainSymbol, Vec<Rule>>;
+
+/// Tracks the recursive rule whose definition a CC is compiling. `columns` names the columns of
+/// the rule's table, one per rule variable.
+#[derive(Clone, Debug)]
+pub(crate) struct RecursiveRule {
+    name: PlainSymbol,
+    columns: Vec<Variable>,
+    referenced: bool,
+}
+
+/// Rewrites the variables in one definition of a rule so that it can be spliced into a query.
+/// The rule's own variables become the arguments at the call site; every other variable becomes
+/// a fresh variable, so that it can't collide with anything in the enclosing query.
+/// If the rule was invoked against a source, like `($a ancestor ?x ?y)`, the definition's uses of
+/// the default source refer to that source instead.
+struct VariableRenamer<'a> {
+    rule: &'a PlainSymbol,
+    counter: RcCounter,
+    renames: BTreeMap<Variable, Variable>,
+    source: Option<SrcVar>,
+}
+
+fn fresh_variable(counter: &RcCounter, rule: &PlainSymbol, var: &Variable) -> Variable {
+    Variable::from_valid_name(format!("{}_{}_{}", var.as_str(), rule.0, counter.next()).as_str())
+}
+
+impl<'a> VariableRenamer<'a> {
+    fn new(rule: &'a PlainSymbol, counter: RcCounter, head: &[Variable], args: &[Variable], source: Option<SrcVar>) -> VariableRenamer<'a> {
+        VariableRenamer {
+            rule: rule,
+            counter: counter,
+            renames: head.iter().cloned().zip(args.iter().cloned()).collect(),
+            source: source,
+        }
+    }
+
+    fn source(&self, source: Option<SrcVar>) -> Option<SrcVar> {
+        match source {
+            None |
+            Some(SrcVar::DefaultSrc) => self.source.clone().or(source),
+            named => named,
+        }
+    }
+
+    fn var(&mut self, var: Variable) -> Variable {
+        if let Some(renamed) = self.renames.get(&var) {
+            return renamed.clone();
+        }
+        let renamed = fresh_variable(&self.counter, self.rule, &var);
+        self.renames.insert(var, renamed.clone());
+        renamed
+    }
+
+    fn non_value_place(&mut self, place: PatternNonValuePlace) -> PatternNonValuePlace {
+        match place {
+            PatternNonValuePlace::Variable(var) => PatternNonValuePlace::Variable(self.var(var)),
+            place => place,
+        }
+    }
+
+    fn value_place(&mut self, place: PatternValuePlace) -> PatternValuePlace {
+        match place {
+            PatternValuePlace::Variable(var) => PatternValuePlace::Variable(self.var(var)),
+            place => place,
+        }
+    }
+
+    fn fn_arg(&mut self, arg: FnArg) -> FnArg {
+        match arg {
+            FnArg::Variable(var) => FnArg::Variable(self.var(var)),
+            FnArg::Vector(args) => FnArg::Vector(self.fn_args(args)),
+            FnArg::SrcVar(source) => FnArg::SrcVar(self.source(Some(source)).unwrap_or(SrcVar::DefaultSrc)),
+            arg => arg,
+        }
+    }
+
+    fn fn_args(&mut self, args: Vec<FnArg>) -> Vec<FnArg> {
+        args.into_iter().map(|arg| self.fn_arg(arg)).collect()
+    }
+
+    fn variable_or_placeholder(&mut self, place: VariableOrPlaceholder) -> VariableOrPlaceholder {
+        match place {
+            VariableOrPlaceholder::Variable(var) => VariableOrPlaceholder::Variable(self.var(var)),
+            VariableOrPlaceholder::Placeholder => VariableOrPlaceholder::Placeholder,
+        }
+    }
+
+    fn binding(&mut self, binding: Binding) -> Binding {
+        match binding {
+            Binding::BindScalar(var) => Binding::BindScalar(self.var(var)),
+            Binding::BindColl(var) => Binding::BindColl(self.var(var)),
+            Binding::BindRel(places) => Binding::BindRel(places.into_iter().map(|p| self.variable_or_placeholder(p)).collect()),
+            Binding::BindTuple(places) => Binding::BindTuple(places.into_iter().map(|p| self.variable_or_placeholder(p)).collect()),
+        }
+    }
+
+    fn unify_vars(&mut self, unify_vars: UnifyVars) -> UnifyVars {
+        match unify_vars {
+            UnifyVars::Implicit => UnifyVars::Implicit,
+            UnifyVars::Explicit(vars) => UnifyVars::Explicit(vars.into_iter().map(|v| self.var(v)).collect()),
+        }
+    }
+
+    fn or_where_clause(&mut self, clause: OrWhereClause) -> OrWhereClause {
+        match clause {
+            OrWhereClause::Clause(clause) => OrWhereClause::Clause(self.clause(clause)),
+            OrWhereClause::And(clauses) => OrWhereClause::And(self.clauses(clauses)),
+        }
+    }
+
+    fn clauses(&mut self, clauses: Vec<WhereClause>) -> Vec<WhereClause> {
+        clauses.into_iter().map(|clause| self.clause(clause)).collect()
+    }
+
+    fn clause(&mut self, clause: WhereClause) -> WhereClause {
+        match clause {
+            WhereClause::Pattern(Pattern { source, entity, attribute, value, tx, added }) => {
+                WhereClause::Pattern(Pattern {
+                    source: self.source(source),
+                    entity: self.non_value_place(entity),
+                    attribute: self.non_value_place(attribute),
+                    value: self.value_place(value),
+                    tx: self.non_value_place(tx),
+                    added: self.value_place(added),
+                })
+            },
+            WhereClause::Pred(Predicate { operator, args }) => {
+                WhereClause::Pred(Predicate {
+                    operator: operator,
+                    args: self.fn_args(args),
+                })
+            },
+            WhereClause::WhereFn(WhereFn { operator, args, binding }) => {
+                WhereClause::WhereFn(WhereFn {
+                    operator: operator,
+                    args: self.fn_args(args),
+                    binding: self.binding(binding),
+                })
+            },
+            WhereClause::OrJoin(or_join) => {
+                let (clauses, unify_vars, _) = or_join.dismember();
+                let unify_vars = self.unify_vars(unify_vars);
+                let clauses = clauses.into_iter().map(|c| self.or_where_clause(c)).collect();
+                WhereClause::OrJoin(OrJoin::new(unify_vars, clauses))
+            },
+            WhereClause::NotJoin(NotJoin { unify_vars, clauses }) => {
+                let unify_vars = self.unify_vars(unify_vars);
+                WhereClause::NotJoin(NotJoin::new(unify_vars, self.clauses(clauses)))
+            },
+            WhereClause::TypeAnnotation(TypeAnnotation { value_type, variable }) => {
+                WhereClause::TypeAnnotation(TypeAnnotation {
+                    value_type: value_type,
+                    variable: self.var(variable),
+                })
+            },
+            WhereClause::RuleExpr(RuleExpr { source, name, args }) => {
+                WhereClause::RuleExpr(RuleExpr {
+                    source: self.source(source),
+                    name: name,
+                    args: self.fn_args(args),
+                })
+            },
+        }
+    }
+}
+
+/// Add the names of every rule invoked by `clause`, including those nested inside `or` and `not`.
+fn accumulate_invoked_rules(clause: &WhereClause, acc: &mut BTreeSet<PlainSymbol>) {
+    match clause {
+        &WhereClause::RuleExpr(ref r) => {
+            acc.insert(r.name.clone());
+        },
+        &WhereClause::OrJoin(ref o) => {
+            for c in o.clauses.iter() {
+                match c {
+                    &OrWhereClause::Clause(ref clause) => accumulate_invoked_rules(clause, acc),
+                    &OrWhereClause::And(ref clauses) => {
+                        for clause in clauses.iter() {
+                            accumulate_invoked_rules(clause, acc);
+                        }
+                    },
+                }
+            }
+        },
+        &WhereClause::NotJoin(ref n) => {
+            for clause in n.clauses.iter() {
+                accumulate_invoked_rules(clause, acc);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Return true if the named rule can invoke itself, directly or via other rules.
+fn is_recursive(rules: &Rules, name: &PlainSymbol) -> bool {
+    let mut seen: BTreeSet<PlainSymbol> = BTreeSet::new();
+    let mut pending: Vec<PlainSymbol> = vec![name.clone()];
+    while let Some(next) = pending.pop() {
+        let mut invoked = BTreeSet::new();
+        for rule in rules.get(&next).into_iter().flat_map(|definitions| definitions.iter()) {
+            for clause in rule.clauses.iter() {
+                accumulate_invoked_rules(clause, &mut invoked);
+            }
+        }
+        if invoked.contains(name) {
+            return true;
+        }
+        for invoked_name in invoked.into_iter() {
+            if seen.insert(invoked_name.clone()) {
+                pending.push(invoked_name);
+            }
+        }
+    }
+    false
+}
+
+/// Application of rule invocations.
+///
+/// A non-recursive rule is expanded in place: a rule with one definition is simply conjoined with
+/// the enclosing clauses, and a rule with several definitions becomes an `or-join` on the
+/// arguments. Variables inside the definitions are renamed so that they don't collide with the
+/// enclosing query.
+///
+/// A recursive rule can't be expanded like that. Instead each definition is algebrized into its
+/// own CC, and the CCs become the arms of a recursive common table expression:
+///
+/// ```sql
+/// WITH RECURSIVE rule(a, a_value_type_tag, b, b_value_type_tag) AS
+///   (SELECT … base case …
+///    UNION
+///    SELECT … FROM rule AS rule01, datoms AS datoms02 WHERE …)
+/// SELECT * FROM rule
+/// ```
+///
+/// The self-reference in each recursive definition turns into a reference to the `rule` table.
+/// SQLite only allows the recursive table to appear once in each recursive arm, and not within a
+/// subquery, so a recursive definition can only refer to its own rule once, and not inside `or`
+/// or `not`. Mutually recursive rules aren't supported.
+impl ConjoiningClauses {
+    pub(crate) fn apply_rule_expr(&mut self, known: Known, rule_expr: RuleExpr) -> Result<()> {
+        let RuleExpr { source, name, args } = rule_expr;
+        if let Some(ref source) = source {
+            self.data_source(source)?;
+        }
+
+        let definitions = match self.rules.get(&name) {
+            Some(definitions) => definitions.clone(),
+            None => bail!(AlgebrizerError::UnknownRule(name)),
+        };
+
+        let arity = definitions[0].vars.len();
+        if args.len() != arity {
+            bail!(AlgebrizerError::InvalidNumberOfArguments(name, args.len(), arity));
+        }
+
+        // Each argument is either a variable or a constant. We ground constants into fresh
+        // variables, which keeps the rest of this simple.
+        let schema = known.schema;
+        let mut vars: Vec<Variable> = Vec::with_capacity(arity);
+        for (i, arg) in args.into_iter().enumerate() {
+            match arg {
+                FnArg::Variable(var) => vars.push(var),
+                FnArg::SrcVar(_) |
+                FnArg::Vector(_) => {
+                    bail!(AlgebrizerError::InvalidArgument(name, "variable or constant", i));
+                },
+                constant => {
+                    let var = fresh_variable(&self.alias_counter, &name, &definitions[0].vars[i]);
+                    self.apply_ground_var(schema, var.clone(), constant)?;
+                    vars.push(var);
+                },
+            }
+        }
+
+        if self.recursive_rule.as_ref().map_or(false, |r| r.name == name) {
+            return self.apply_recursive_reference(schema, vars);
+        }
+
+        if self.rules_in_progress.contains(&name) {
+            bail!(AlgebrizerError::UnsupportedRuleRecursion(name));
+        }
+
+        if is_recursive(&self.rules, &name) {
+            self.apply_recursive_rule(known, source, name, definitions, vars)
+        } else {
+            self.apply_non_recursive_rule(known, source, definitions, vars)
+        }
+    }
+
+    fn expand_rule(&self, source: &Option<SrcVar>, rule: Rule, args: &[Variable]) -> Vec<WhereClause> {
+        let Rule { name, vars, clauses } = rule;
+        let mut renamer = VariableRenamer::new(&name, self.alias_counter.clone(), &vars, args, source.clone());
+        renamer.clauses(clauses)
+    }
+
+    fn apply_non_recursive_rule(&mut self, known: Known, source: Option<SrcVar>, definitions: Vec<Rule>, vars: Vec<Variable>) -> Result<()> {
+        let mut arms: Vec<Vec<WhereClause>> = definitions.into_iter()
+                                                         .map(|rule| self.expand_rule(&source, rule, &vars))
+                                                         .collect();
+
+        if arms.len() == 1 {
+            // Every variable that isn't an argument is fresh, so a single definition can be
+            // applied directly.
+            let clauses = arms.pop().expect("one definition");
+            return self.apply_clauses(known, clauses);
+        }
+
+        let unify_vars = UnifyVars::Explicit(vars.into_iter().collect());
+        let or_join = OrJoin::new(unify_vars, arms.into_iter().map(OrWhereClause::And).collect());
+        self.apply_or_join(known, or_join)
+    }
+
+    fn apply_recursive_rule(&mut self, known: Known, source: Option<SrcVar>, name: PlainSymbol, definitions: Vec<Rule>, vars: Vec<Variable>) -> Result<()> {
+        // The columns of the rule's table. Every definition is rewritten to bind these.
+        let columns: Vec<Variable> = definitions[0].vars
+                                                   .iter()
+                                                   .map(|v| fresh_variable(&self.alias_counter, &name, v))
+                                                   .collect();
+
+        let mut rules_in_progress = self.rules_in_progress.clone();
+        rules_in_progress.push(name.clone());
+
+        let mut base = Vec::with_capacity(definitions.len());
+        let mut recursive = Vec::with_capacity(definitions.len());
+        let mut empty_because: Option<EmptyBecause> = None;
+
+        for rule in definitions.into_iter() {
+            let clauses = self.expand_rule(&source, rule, &columns);
+
+            // Each arm is computed without reference to the enclosing query: its results don't
+            // depend on any bindings at the call site.
+            let mut arm = ConjoiningClauses {
+                alias_counter: self.alias_counter.clone(),
+                rules: self.rules.clone(),
+                rules_in_progress: rules_in_progress.clone(),
+                recursive_rule: Some(RecursiveRule {
+                    name: name.clone(),
+                    columns: columns.clone(),
+                    referenced: false,
+                }),
+                view: self.view,
+                sources: self.sources.clone(),
+                ..Default::default()
+            };
+            arm.apply_clauses(known, clauses)?;
+
+            let is_recursive_arm = arm.recursive_rule.as_ref().map_or(false, |r| r.referenced);
+            if !arm.is_known_empty() {
+                arm.expand_column_bindings();
+                arm.prune_extracted_types();
+                arm.process_required_types()?;
+            }
+
+            if arm.is_known_empty() {
+                if !is_recursive_arm {
+                    empty_because = arm.empty_because.clone();
+                }
+            } else if is_recursive_arm {
+                recursive.push(arm);
+            } else {
+                base.push(arm);
+            }
+        }
+
+        // SQLite only accepts more than one recursive SELECT in a common table expression from
+        // 3.34 onwards, which is newer than the SQLite we bundle.
+        if recursive.len() > 1 {
+            bail!(AlgebrizerError::UnsupportedRuleRecursion(name));
+        }
+
+        if base.is_empty() {
+            // With no base case there's nothing to recurse from.
+            self.mark_known_empty(empty_because.unwrap_or(EmptyBecause::RuleHasNoBaseCase(name)));
+            return Ok(());
+        }
+
+        let table = self.computed_tables.push_computed(ComputedTable::RecursiveRule {
+            projection: columns.clone(),
+            base: base,
+            recursive: recursive,
+        });
+        let alias = self.next_alias_for_table(table);
+        self.bind_rule_columns(known.schema, alias.clone(), &columns, vars);
+        self.from.push(SourceAlias(table, alias));
+        Ok(())
+    }
+
+    /// A recursive definition refers to the rule it's defining: join against the rule's table.
+    fn apply_recursive_reference(&mut self, schema: &Schema, vars: Vec<Variable>) -> Result<()> {
+        let columns = {
+            let recursive_rule = self.recursive_rule.as_mut().expect("a recursive rule");
+            if recursive_rule.referenced {
+                bail!(AlgebrizerError::UnsupportedRuleRecursion(recursive_rule.name.clone()));
+            }
+            recursive_rule.referenced = true;
+            recursive_rule.columns.clone()
+        };
+
+        let alias = self.next_alias_for_table(DatomsTable::Rule);
+        self.bind_rule_columns(schema, alias.clone(), &columns, vars);
+        self.from.push(SourceAlias(DatomsTable::Rule, alias));
+        Ok(())
+    }
+
+    /// Bind each of `vars` to the corresponding column of a rule table. The table can hold values
+    /// of any type, so we either constrain its type tag column to the type we already know, or we
+    /// extract the type from it.
+    fn bind_rule_columns(&mut self, schema: &Schema, alias: TableAlias, columns: &[Variable], vars: Vec<Variable>) {
+        for (column, var) in columns.iter().zip(vars.into_iter()) {
+            let type_tag = QualifiedAlias::new(alias.clone(), VariableColumn::VariableTypeTag(column.clone()));
+            if let Some(value_type) = self.known_type(&var) {
+                let tag = TypedValue::Long(value_type.value_type_tag() as i64);
+                self.wheres.add_intersection(ColumnConstraint::Equals(type_tag, QueryValue::TypedValue(tag)));
+            } else if !self.input_variables.contains(&var) &&
+                      !self.extracted_types.contains_key(&var) {
+                self.extracted_types.insert(var.clone(), type_tag);
+            }
+            self.bind_column_to_var(schema, alias.clone(), VariableColumn::Variable(column.clone()), var);
+        }
+    }
+}
+
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    use mentat_core::{
+        Attribute,
+        ValueType,
+    };
+
+    use mentat_query::{
+        Keyword,
+    };
+
+    use clauses::{
+        QueryInputs,
+        add_attribute,
+        associate_ident,
+    };
+
+    use {
+        algebrize_with_inputs,
+        parse_find_string,
+        parse_rules_string,
+    };
+
+    fn prepopulated_schema() -> Schema {
+        let mut schema = Schema::default();
+        associate_ident(&mut schema, Keyword::namespaced("person", "parent"), 65);
+        associate_ident(&mut schema, Keyword::namespaced("person", "name"), 66);
+        add_attribute(&mut schema, 65, Attribute {
+            value_type: ValueType::Ref,
+            multival: true,
+            ..Default::default()
+        });
+        add_attribute(&mut schema, 66, Attribute {
+            value_type: ValueType::String,
+            multival: false,
+            ..Default::default()
+        });
+        schema
+    }
+
+    fn alg_with_rules(schema: &Schema, query: &str, rules: &str) -> Result<ConjoiningClauses> {
+        let known = Known::for_schema(schema);
+        let parsed = parse_find_string(query).expect("parse failed");
+        let rules = parse_rules_string(rules).expect("rules parse failed");
+        let inputs = QueryInputs::default().with_rules(rules)?;
+        algebrize_with_inputs(known, parsed, 0, inputs).map(|q| q.cc)
+    }
+
+    #[test]
+    fn test_single_definition_is_inlined() {
+        let schema = prepopulated_schema();
+        let cc = alg_with_rules(&schema,
+                                r#"[:find ?x :in % :where (named ?x "John")]"#,
+                                r#"[[(named ?p ?n) [?p :person/name ?n]]]"#).expect("algebrized");
+        assert!(!cc.is_known_empty());
+        assert!(cc.computed_tables.is_empty());
+        assert_eq!(cc.from.len(), 1);
+        assert_eq!(cc.from[0].0, DatomsTable::Datoms);
+    }
+
+    #[test]
+    fn test_multiple_definitions_become_or_join() {
+        let schema = prepopulated_schema();
+        let cc = alg_with_rules(&schema,
+                                r#"[:find ?x :in % :where [?x :person/name _] (related ?x ?y)]"#,
+                                r#"[[(related ?a ?b) [?a :person/parent ?b]]
+                                    [(related ?a ?b) [?b :person/parent ?a]]]"#).expect("algebrized");
+        assert!(!cc.is_known_empty());
+        assert_eq!(cc.computed_tables.len(), 1);
+        match cc.computed_tables[0] {
+            ComputedTable::Union { ref arms, .. } => assert_eq!(arms.len(), 2),
+            _ => panic!("expected a union"),
+        }
+    }
+
+    #[test]
+    fn test_recursive_rule_is_cte() {
+        let schema = prepopulated_schema();
+        let cc = alg_with_rules(&schema,
+                                r#"[:find ?a :in % :where [?x :person/name "John"] (ancestor ?x ?a)]"#,
+                                r#"[[(ancestor ?a ?b) [?a :person/parent ?b]]
+                                    [(ancestor ?a ?b) [?a :person/parent ?x] (ancestor ?x ?b)]]"#).expect("algebrized");
+        assert!(!cc.is_known_empty());
+        assert_eq!(cc.computed_tables.len(), 1);
+        match cc.computed_tables[0] {
+            ComputedTable::RecursiveRule { ref projection, ref base, ref recursive } => {
+                assert_eq!(projection.len(), 2);
+                assert_eq!(base.len(), 1);
+                assert_eq!(recursive.len(), 1);
+                assert!(recursive[0].from.iter().any(|s| s.0 == DatomsTable::Rule));
+            },
+            _ => panic!("expected a recursive rule"),
+        }
+
+        // ?x is known to be a ref, so we constrain the type tag rather than extracting it.
+        // ?a isn't known, so we extract its type from the rule's table.
+        assert!(cc.extracted_types.contains_key(&Variable::from_valid_name("?a")));
+    }
+
+    #[test]
+    fn test_rule_errors() {
+        let schema = prepopulated_schema();
+        let ancestor = r#"[[(ancestor ?a ?b) [?a :person/parent ?b]]
+                           [(ancestor ?a ?b) [?a :person/parent ?x] (ancestor ?x ?b)]]"#;
+
+        // Rules must be asked for.
+        assert_eq!(alg_with_rules(&schema, "[:find ?a :where (ancestor ?x ?a)]", ancestor).err(),
+                   Some(AlgebrizerError::UnknownRule(PlainSymbol::plain("ancestor"))));
+
+        assert_eq!(alg_with_rules(&schema, "[:find ?a :in % :where (ancestor ?a)]", ancestor).err(),
+                   Some(AlgebrizerError::InvalidNumberOfArguments(PlainSymbol::plain("ancestor"), 1, 2)));
+
+        // Non-linear recursion.
+        let doubled = r#"[[(ancestor ?a ?b) [?a :person/parent ?b]]
+                          [(ancestor ?a ?b) (ancestor ?a ?x) (ancestor ?x ?b)]]"#;
+        assert_eq!(alg_with_rules(&schema, "[:find ?a :in % :where (ancestor ?x ?a)]", doubled).err(),
+                   Some(AlgebrizerError::UnsupportedRuleRecursion(PlainSymbol::plain("ancestor"))));
+
+        // More than one recursive definition.
+        let branching = r#"[[(ancestor ?a ?b) [?a :person/parent ?b]]
+                            [(ancestor ?a ?b) [?a :person/parent ?x] (ancestor ?x ?b)]
+                            [(ancestor ?a ?b) [?x :person/parent ?a] (ancestor ?x ?b)]]"#;
+        assert_eq!(alg_with_rules(&schema, "[:find ?a :in % :where (ancestor ?x ?a)]", branching).err(),
+                   Some(AlgebrizerError::UnsupportedRuleRecursion(PlainSymbol::plain("ancestor"))));
+
+        // Mutual recursion.
+        let mutual = r#"[[(up ?a ?b) [?a :person/parent ?b]]
+                         [(up ?a ?b) [?a :person/parent ?x] (down ?x ?b)]
+                         [(down ?a ?b) [?b :person/parent ?a]]
+                         [(down ?a ?b) [?x :person/parent ?a] (up ?x ?b)]]"#;
+        assert!(alg_with_rules(&schema, "[:find ?a :in % :where (up ?x ?a)]", mutual).is_err());
+
+        // Unused rule variables.
+        let unused = r#"[[(named ?p ?n) [?p :person/name _]]]"#;
+        assert_eq!(alg_with_rules(&schema, "[:find ?a :in % :where (named ?a ?b)]", unused).err(),
+                   Some(AlgebrizerError::UnusedRuleVariable(PlainSymbol::plain("named"), PlainSymbol::plain("?n"))));
+
+        // Rule variables that are only constrained, and never bound.
+        let predicate = r#"[[(after ?p ?n) [?p :person/parent ?x] [(> ?x ?n)]]]"#;
+        assert_eq!(alg_with_rules(&schema, "[:find ?p :in % :where (after ?p ?n)]", predicate).err(),
+                   Some(AlgebrizerError::UnboundRuleVariable(PlainSymbol::plain("after"), PlainSymbol::plain("?n"))));
+        let negated = r#"[[(orphan ?p ?q) [?p :person/name _] (not [?p :person/parent ?q])]]"#;
+        assert_eq!(alg_with_rules(&schema, "[:find ?p :in % :where (orphan ?p ?q)]", negated).err(),
+                   Some(AlgebrizerError::UnboundRuleVariable(PlainSymbol::plain("orphan"), PlainSymbol::plain("?q"))));
+    }
+}
@@ -15,7 +15,6 @@ use mentat_core::{
 use mentat_query::{
     Binding,
     FnArg,
-    SrcVar,
     VariableOrPlaceholder,
     WhereFn,
 };
@@ -37,7 +36,6 @@ use types::{
     Inequality,
     QualifiedAlias,
     QueryValue,
-    SourceAlias,
     TransactionsColumn,
 };
 
@@ -98,18 +96,18 @@ impl ConjoiningClauses {
 
         let mut args = where_fn.args.into_iter();
 
-        // TODO: process source variables.
-        match args.next().unwrap() {
-            FnArg::SrcVar(SrcVar::DefaultSrc) => {},
+        let source = match args.next().unwrap() {
+            FnArg::SrcVar(source) => self.data_source(&source)?,
             _ => bail!(AlgebrizerError::InvalidArgument(where_fn.operator.clone(), "source variable", 0)),
-        }
+        };
 
         let tx1 = self.resolve_tx_argument(&known.schema, &where_fn.operator, 1, args.next().unwrap())?;
         let tx2 = self.resolve_tx_argument(&known.schema, &where_fn.operator, 2, args.next().unwrap())?;
 
-        let transactions = self.next_alias_for_table(DatomsTable::Transactions);
+        let transactions_table = self.alias_table_in_database(source.database(), DatomsTable::Transactions);
+        let transactions = transactions_table.1.clone();
 
-        self.from.push(SourceAlias(DatomsTable::Transactions, transactions.clone()));
+        self.from.push(transactions_table);
 
         // Bound variable must be a ref.
         self.constrain_var_to_type(tx_var.clone(), ValueType::Ref);
@@ -177,17 +175,17 @@ impl ConjoiningClauses {
 
         let mut args = where_fn.args.into_iter();
 
-        // TODO: process source variables.
-        match args.next().unwrap() {
-            FnArg::SrcVar(SrcVar::DefaultSrc) => {},
+        let source = match args.next().unwrap() {
+            FnArg::SrcVar(source) => self.data_source(&source)?,
             _ => bail!(AlgebrizerError::InvalidArgument(where_fn.operator.clone(), "source variable", 0)),
-        }
+        };
 
         let tx = self.resolve_tx_argument(&known.schema, &where_fn.operator, 1, args.next().unwrap())?;
 
-        let transactions = self.next_alias_for_table(DatomsTable::Transactions);
+        let transactions_table = self.alias_table_in_database(source.database(), DatomsTable::Transactions);
+        let transactions = transactions_table.1.clone();
 
-        self.from.push(SourceAlias(DatomsTable::Transactions, transactions.clone()));
+        self.from.push(transactions_table);
 
         let tx_constraint = ColumnConstraint::Equals(
             QualifiedAlias(transactions.clone(), Column::Transactions(TransactionsColumn::Tx)),
@@ -258,6 +256,7 @@ mod testing {
         Binding,
         FnArg,
         PlainSymbol,
+        SrcVar,
         Variable,
     };
 
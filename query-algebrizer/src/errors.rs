@@ -78,6 +78,9 @@ pub enum AlgebrizerError {
     #[fail(display = "invalid limit {} of type {}: expected natural number.", _0, _1)]
     InvalidLimit(String, ValueType),
 
+    #[fail(display = "invalid offset {} of type {}: expected non-negative integer.", _0, _1)]
+    InvalidOffset(String, ValueType),
+
     #[fail(display = "mismatched bindings in ground")]
     GroundBindingsMismatch,
 
@@ -90,6 +93,15 @@ pub enum AlgebrizerError {
     #[fail(display = ":limit var {} not present in :in", _0)]
     UnknownLimitVar(PlainSymbol),
 
+    #[fail(display = ":offset var {} not present in :in", _0)]
+    UnknownOffsetVar(PlainSymbol),
+
+    #[fail(display = "cursor has {} values, but the query orders by {} variables", _0, _1)]
+    CursorLengthMismatch(usize, usize),
+
+    #[fail(display = "cursor value of type {} can't resume ordering by {}", _0, _1)]
+    InvalidCursorValue(ValueType, PlainSymbol),
+
     #[fail(display = "unbound variable {} in order clause or function call", _0)]
     UnboundVariable(PlainSymbol),
 
@@ -100,6 +112,30 @@ pub enum AlgebrizerError {
     #[fail(display = "non-matching variables in 'not' clause")]
     NonMatchingVariablesInNotClause,
 
+    #[fail(display = "no rule named {}", _0)]
+    UnknownRule(PlainSymbol),
+
+    #[fail(display = "definitions of rule {} have different numbers of arguments", _0)]
+    InconsistentRuleArity(PlainSymbol),
+
+    #[fail(display = "rule {} doesn't use its variable {}", _0, _1)]
+    UnusedRuleVariable(PlainSymbol, PlainSymbol),
+
+    #[fail(display = "rule {} doesn't bind its variable {}: it's only used in `not` or a predicate", _0, _1)]
+    UnboundRuleVariable(PlainSymbol, PlainSymbol),
+
+    #[fail(display = "unsupported recursion in rule {}: a rule can have only one recursive definition, which refers to the rule once, outside of `or` and `not`", _0)]
+    UnsupportedRuleRecursion(PlainSymbol),
+
+    #[fail(display = "source ${} is named in :in but wasn't bound", _0)]
+    UnboundSource(String),
+
+    #[fail(display = "unknown source ${}; sources must be named in :in", _0)]
+    UnknownSource(String),
+
+    #[fail(display = "pull expressions can only be used to query the current state of the store")]
+    PullInHistoricalView,
+
     #[fail(display = "binding error in {}: {:?}", _0, _1)]
     InvalidBinding(PlainSymbol, BindingError),
 
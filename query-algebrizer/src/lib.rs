@@ -17,7 +17,7 @@ extern crate mentat_query;
 
 use std::collections::BTreeSet;
 use std::ops::Sub;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[macro_use]
 mod errors;
@@ -32,16 +32,20 @@ use mentat_core::{
     TypedValue,
     ValueType,
     parse_query,
+    parse_rules,
 };
 
 use mentat_core::counter::RcCounter;
 
 use mentat_query::{
+    Direction,
     Element,
     FindSpec,
     Limit,
+    Offset,
     Order,
     ParsedQuery,
+    Rule,
     SrcVar,
     Variable,
     WhereClause,
@@ -63,6 +67,10 @@ pub use types::{
     FindQuery,
 };
 
+use types::{
+    Inequality,
+};
+
 /// A convenience wrapper around things known in memory: the schema and caches.
 /// We use a trait object here to avoid making dozens of functions generic over the type
 /// of the cache. If performance becomes a concern, we should hard-code specific kinds of
@@ -128,7 +136,7 @@ impl<'s, 'c> Known<'s, 'c> {
 #[derive(Debug)]
 pub struct AlgebraicQuery {
     default_source: SrcVar,
-    pub find_spec: Rc<FindSpec>,
+    pub find_spec: Arc<FindSpec>,
     has_aggregates: bool,
 
     /// The set of variables that the caller wishes to be used for grouping when aggregating.
@@ -145,6 +153,7 @@ pub struct AlgebraicQuery {
     pub named_projection: BTreeSet<Variable>,
     pub order: Option<Vec<OrderBy>>,
     pub limit: Limit,
+    pub offset: Offset,
     pub cc: clauses::ConjoiningClauses,
 }
 
@@ -270,20 +279,132 @@ fn simplify_limit(mut query: AlgebraicQuery) -> Result<AlgebraicQuery> {
     Ok(query)
 }
 
+fn simplify_offset(mut query: AlgebraicQuery) -> Result<AlgebraicQuery> {
+    // Just as for limits, unpack any offset variable in place.
+    let refined_offset =
+        match query.offset {
+            Offset::Variable(ref v) => {
+                match query.cc.bound_value(v) {
+                    Some(TypedValue::Long(n)) => {
+                        if n < 0 {
+                            bail!(AlgebrizerError::InvalidOffset(n.to_string(), ValueType::Long))
+                        } else {
+                            Some(Offset::Fixed(n as u64))
+                        }
+                    },
+                    Some(val) => {
+                        bail!(AlgebrizerError::InvalidOffset(format!("{:?}", val), val.value_type()))
+                    },
+                    None => None,
+                }
+            },
+            Offset::None => None,
+            Offset::Fixed(_) => None,
+        };
+
+    if let Some(offset) = refined_offset {
+        query.offset = offset;
+    }
+    Ok(query)
+}
+
+/// Constrain the query to the results that sort after `cursor`. For `:order ?a (desc ?b)` and a
+/// cursor `[a b]`, that's `?a > a OR (?a = a AND ?b < b)`.
+fn apply_cursor(cc: &mut ConjoiningClauses, order: Option<&Vec<Order>>, cursor: QueryCursor) -> Result<()> {
+    let no_order = vec![];
+    let order = order.unwrap_or(&no_order);
+    if order.len() != cursor.0.len() {
+        bail!(AlgebrizerError::CursorLengthMismatch(cursor.0.len(), order.len()));
+    }
+
+    // Each alternate matches the preceding keys exactly and follows on this one.
+    let mut alternation = ColumnAlternation::default();
+    let mut preceding: Vec<(QualifiedAlias, TypedValue)> = vec![];
+    for (&Order(ref direction, ref var), value) in order.iter().zip(cursor.0.into_iter()) {
+        // We can only compare values of the same type, so the variable's type must be fixed.
+        if cc.known_type(var) != Some(value.value_type()) {
+            bail!(AlgebrizerError::InvalidCursorValue(value.value_type(), var.name()));
+        }
+
+        // Every result shares the value of a bound variable.
+        if cc.bound_value(var).is_some() {
+            continue;
+        }
+
+        let column = match cc.column_bindings.get(var).and_then(|columns| columns.first()) {
+            Some(column) => column.clone(),
+            None => bail!(AlgebrizerError::UnboundVariable(var.name())),
+        };
+        let operator = match direction {
+            &Direction::Ascending => Inequality::GreaterThan,
+            &Direction::Descending => Inequality::LessThan,
+        };
+
+        let mut alternate = ColumnIntersection::default();
+        for &(ref column, ref value) in preceding.iter() {
+            alternate.add_intersection(ColumnConstraint::Equals(column.clone(), QueryValue::TypedValue(value.clone())));
+        }
+        alternate.add_intersection(ColumnConstraint::Inequality {
+            operator: operator,
+            left: QueryValue::Column(column.clone()),
+            right: QueryValue::TypedValue(value.clone()),
+        });
+        alternation.add_alternate(alternate);
+        preceding.push((column, value));
+    }
+
+    if !alternation.0.is_empty() {
+        cc.wheres.add(ColumnConstraintOrAlternation::Alternation(alternation));
+    }
+    Ok(())
+}
+
 pub fn algebrize_with_inputs(known: Known,
                              parsed: FindQuery,
                              counter: usize,
-                             inputs: QueryInputs) -> Result<AlgebraicQuery> {
+                             mut inputs: QueryInputs) -> Result<AlgebraicQuery> {
+    // Rules are only visible to queries that ask for them with `:in %`.
+    if !parsed.in_rules {
+        inputs.rules.clear();
+    }
+    let cursor = inputs.cursor.take();
+
+    // Every source named in `:in` must be bound, and the query can only see those sources.
+    for source in parsed.in_sources.iter() {
+        if let &SrcVar::NamedSrc(ref name) = source {
+            if !inputs.sources.contains_key(source) {
+                bail!(AlgebrizerError::UnboundSource(name.clone()));
+            }
+        }
+    }
+    inputs.sources = inputs.sources
+                           .into_iter()
+                           .filter(|&(ref source, _)| *source == SrcVar::DefaultSrc || parsed.in_sources.contains(source))
+                           .collect();
+
+    // Pull expressions always read the current state of the store.
+    let default_source = inputs.sources
+                               .get(&SrcVar::DefaultSrc)
+                               .cloned()
+                               .unwrap_or(DataSource::Store(inputs.view));
+    if default_source != DataSource::Store(DatabaseView::Current) &&
+       parsed.find_spec.columns().any(|e| if let &Element::Pull(_) = e { true } else { false }) {
+        bail!(AlgebrizerError::PullInHistoricalView);
+    }
+
     let alias_counter = RcCounter::with_initial(counter);
     let mut cc = ConjoiningClauses::with_inputs_and_alias_counter(parsed.in_vars, inputs, alias_counter);
 
     // This is so the rest of the query knows that `?x` is a ref if `(pull ?x …)` appears in `:find`.
     cc.derive_types_from_find_spec(&parsed.find_spec);
 
-    // Do we have a variable limit? If so, tell the CC that the var must be numeric.
+    // Do we have a variable limit or offset? If so, tell the CC that the var must be numeric.
     if let &Limit::Variable(ref var) = &parsed.limit {
         cc.constrain_var_to_long(var.clone());
     }
+    if let &Offset::Variable(ref var) = &parsed.offset {
+        cc.constrain_var_to_long(var.clone());
+    }
 
     // TODO: integrate default source into pattern processing.
     // TODO: flesh out the rest of find-into-context.
@@ -293,23 +414,28 @@ pub fn algebrize_with_inputs(known: Known,
     cc.prune_extracted_types();
     cc.process_required_types()?;
 
+    if let Some(cursor) = cursor {
+        apply_cursor(&mut cc, parsed.order.as_ref(), cursor)?;
+    }
+
     let (order, extra_vars) = validate_and_simplify_order(&cc, parsed.order)?;
 
     // This might leave us with an unused `:in` variable.
     let limit = if parsed.find_spec.is_unit_limited() { Limit::Fixed(1) } else { parsed.limit };
     let q = AlgebraicQuery {
         default_source: parsed.default_source,
-        find_spec: Rc::new(parsed.find_spec),
+        find_spec: Arc::new(parsed.find_spec),
         has_aggregates: false,           // TODO: we don't parse them yet.
         with: parsed.with,
         named_projection: extra_vars,
         order: order,
         limit: limit,
+        offset: parsed.offset,
         cc: cc,
     };
 
     // Substitute in any fixed values and fail if they're out of range.
-    simplify_limit(q)
+    simplify_limit(q).and_then(simplify_offset)
 }
 
 pub use clauses::{
@@ -324,11 +450,14 @@ pub use types::{
     ColumnIntersection,
     ColumnName,
     ComputedTable,
+    DataSource,
+    DatabaseView,
     DatomsColumn,
     DatomsTable,
     FulltextColumn,
     OrderBy,
     QualifiedAlias,
+    QueryCursor,
     QueryValue,
     SourceAlias,
     TableAlias,
@@ -344,7 +473,9 @@ impl FindQuery {
             with: BTreeSet::default(),
             in_vars: BTreeSet::default(),
             in_sources: BTreeSet::default(),
+            in_rules: false,
             limit: Limit::None,
+            offset: Offset::None,
             where_clauses: where_clauses,
             order: None,
         }
@@ -382,13 +513,22 @@ impl FindQuery {
             }
         }
 
+        // Likewise for `:offset ?x`.
+        if let Offset::Variable(ref v) = parsed.offset {
+            if !in_vars.contains(v) {
+                bail!(AlgebrizerError::UnknownOffsetVar(v.name()));
+            }
+        }
+
         Ok(FindQuery {
             find_spec: parsed.find_spec,
             default_source: parsed.default_source,
             with,
             in_vars,
             in_sources: parsed.in_sources,
+            in_rules: parsed.in_rules,
             limit: parsed.limit,
+            offset: parsed.offset,
             where_clauses: parsed.where_clauses,
             order: parsed.order,
         })
@@ -400,3 +540,15 @@ pub fn parse_find_string(string: &str) -> Result<FindQuery> {
         .map_err(|e| e.into())
         .and_then(|parsed| FindQuery::from_parsed_query(parsed))
 }
+
+/// Parse a set of rule definitions, like
+///
+/// ```edn
+/// [[(ancestor ?a ?b) [?a :person/parent ?b]]
+///  [(ancestor ?a ?b) [?a :person/parent ?x] (ancestor ?x ?b)]]
+/// ```
+///
+/// for use with `QueryInputs::with_rules`.
+pub fn parse_rules_string(string: &str) -> Result<Vec<Rule>> {
+    parse_rules(string).map_err(|e| e.into())
+}
@@ -27,7 +27,9 @@ use mentat_query::{
     FindSpec,
     Keyword,
     Limit,
+    Offset,
     Order,
+    PlainSymbol,
     SrcVar,
     Variable,
     WhereClause,
@@ -43,8 +45,69 @@ pub enum DatomsTable {
     AllDatoms,          // Fulltext and non-fulltext datoms.
     Computed(usize),    // A computed table, tracked elsewhere in the query.
     Transactions,       // The transactions table, which makes the tx-data log API efficient.
+    Rule,               // The recursive rule being defined by an enclosing `WITH RECURSIVE`.
+    AsOf(Entid),        // The datoms as of the given transaction, derived from `transactions`.
+    History,            // Every assertion and retraction, derived from `transactions`.
 }
 
+/// Which state of the store a query sees.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DatabaseView {
+    /// The datoms as they are now.
+    Current,
+
+    /// The datoms as they were immediately after the given transaction.
+    AsOf(Entid),
+
+    /// The current datoms that were asserted after the given transaction.
+    Since(Entid),
+
+    /// Every datom that was ever asserted or retracted. Patterns can use a fifth place to match
+    /// whether a datom was added, like `[?e :foo/bar ?v ?tx ?added]`.
+    History,
+}
+
+impl Default for DatabaseView {
+    fn default() -> DatabaseView {
+        DatabaseView::Current
+    }
+}
+
+/// The datoms that a source, like `$a` in `[:find ?x :in $ $a :where [$a ?x :foo/bar _]]`,
+/// ranges over.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum DataSource {
+    /// A view of the store being queried.
+    Store(DatabaseView),
+
+    /// A view of another Mentat store, attached to the same SQLite connection under the given
+    /// name with `ATTACH DATABASE`. Patterns are resolved against the queried store's schema, so
+    /// the two stores must share their schema.
+    Attached(String, DatabaseView),
+}
+
+impl DataSource {
+    pub fn view(&self) -> DatabaseView {
+        match self {
+            &DataSource::Store(view) => view,
+            &DataSource::Attached(_, view) => view,
+        }
+    }
+
+    /// The name of the SQLite database that holds the datoms, or `None` for the queried store.
+    pub fn database(&self) -> Option<&str> {
+        match self {
+            &DataSource::Store(_) => None,
+            &DataSource::Attached(ref name, _) => Some(name.as_str()),
+        }
+    }
+}
+
+/// Where to resume an ordered query: the values of its `:order` variables in the last result
+/// already seen. Given a cursor, a query only returns the results that sort after it.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct QueryCursor(pub Vec<TypedValue>);
+
 /// A source of rows that isn't a named table -- typically a subquery or union.
 #[derive(PartialEq, Eq, Debug)]
 pub enum ComputedTable {
@@ -58,6 +121,19 @@ pub enum ComputedTable {
         names: Vec<Variable>,
         values: Vec<TypedValue>,
     },
+    /// A recursive rule. Each arm projects every variable in `projection` together with its type
+    /// tag. There is at most one `recursive` arm, which refers back to the table itself via
+    /// `DatomsTable::Rule`.
+    RecursiveRule {
+        projection: Vec<Variable>,
+        base: Vec<::clauses::ConjoiningClauses>,
+        recursive: Vec<::clauses::ConjoiningClauses>,
+    },
+    /// One of our tables, but in an attached database rather than the store being queried.
+    Attached {
+        database: String,
+        table: DatomsTable,
+    },
 }
 
 impl DatomsTable {
@@ -69,6 +145,9 @@ impl DatomsTable {
             DatomsTable::AllDatoms => "all_datoms",
             DatomsTable::Computed(_) => "c",
             DatomsTable::Transactions => "transactions",
+            DatomsTable::Rule => "rule",
+            DatomsTable::AsOf(_) => "as_of",
+            DatomsTable::History => "history",
         }
     }
 }
@@ -307,6 +386,10 @@ pub enum QueryValue {
     // cannot be a boolean, so `datoms00.value_type_tag` must be in the set `#{0, 4, 5}`.
     // Note that `5 = 5.0` in SQLite, and we preserve that here.
     PrimitiveLong(i64),
+
+    // An `:in` variable that wasn't bound when the query was algebrized. Its value is supplied as
+    // a SQL parameter when the query is run.
+    Input(Variable),
 }
 
 impl Debug for QueryValue {
@@ -325,6 +408,9 @@ impl Debug for QueryValue {
             &PrimitiveLong(value) => {
                 write!(f, "primitive({:?})", value)
             },
+            &Input(ref var) => {
+                write!(f, "input({:?})", var)
+            },
 
         }
     }
@@ -624,6 +710,8 @@ pub enum EmptyBecause {
     InvalidAttributeEntid(Entid),
     InvalidBinding(Column, TypedValue),
     ValueTypeMismatch(ValueType, TypedValue),
+    RuleHasNoBaseCase(PlainSymbol),
+    RetractionOutsideHistory,
     AttributeLookupFailed,         // Catch-all, because the table lookup code is lazy. TODO
 }
 
@@ -686,6 +774,12 @@ impl Debug for EmptyBecause {
                 write!(f, "Type mismatch: {:?} doesn't match attribute type {:?}",
                        typed_value, value_type)
             },
+            &RuleHasNoBaseCase(ref name) => {
+                write!(f, "Rule {} has no non-recursive definition that can match", name)
+            },
+            &RetractionOutsideHistory => {
+                write!(f, "Only a history query can match retracted datoms")
+            },
             &AttributeLookupFailed => {
                 write!(f, "Attribute lookup failed")
             },
@@ -706,7 +800,9 @@ pub struct FindQuery {
     pub with: BTreeSet<Variable>,
     pub in_vars: BTreeSet<Variable>,
     pub in_sources: BTreeSet<SrcVar>,
+    pub in_rules: bool,
     pub limit: Limit,
+    pub offset: Offset,
     pub where_clauses: Vec<WhereClause>,
     pub order: Option<Vec<Order>>,
 }
@@ -751,4 +847,5 @@ pub struct EvolvedPattern {
     pub attribute: EvolvedNonValuePlace,
     pub value: EvolvedValuePlace,
     pub tx: EvolvedNonValuePlace,
+    pub added: EvolvedValuePlace,
 }
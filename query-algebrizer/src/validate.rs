@@ -13,9 +13,15 @@ use std::collections::BTreeSet;
 use mentat_query::{
     ContainsVariables,
     OrJoin,
+    OrWhereClause,
     NotJoin,
     Variable,
     UnifyVars,
+    WhereClause,
+};
+
+use clauses::{
+    Rules,
 };
 
 use errors::{
@@ -92,6 +98,67 @@ pub(crate) fn validate_not_join(not_join: &NotJoin) -> Result<()> {
     }
 }
 
+/// Accumulate the variables that `clause` binds: those that a pattern, a rule invocation, or the
+/// binding of a function mentions.  Predicates, type annotations and `not` only constrain
+/// variables that are bound elsewhere, and an `or` binds only what each of its arms binds.
+fn accumulate_bound_variables(clause: &WhereClause, acc: &mut BTreeSet<Variable>) {
+    match clause {
+        &WhereClause::Pattern(ref p) => p.accumulate_mentioned_variables(acc),
+        &WhereClause::RuleExpr(ref r) => r.accumulate_mentioned_variables(acc),
+        &WhereClause::WhereFn(ref f) => f.binding.accumulate_mentioned_variables(acc),
+        &WhereClause::OrJoin(ref o) => {
+            let mut arms = o.clauses.iter().map(|arm| {
+                let mut bound = BTreeSet::new();
+                match arm {
+                    &OrWhereClause::Clause(ref clause) => accumulate_bound_variables(clause, &mut bound),
+                    &OrWhereClause::And(ref clauses) => for clause in clauses { accumulate_bound_variables(clause, &mut bound) },
+                }
+                bound
+            });
+            let mut bound = arms.next().unwrap_or_default();
+            for arm in arms {
+                bound = bound.intersection(&arm).cloned().collect();
+            }
+            if let UnifyVars::Explicit(ref vars) = o.unify_vars {
+                bound = bound.into_iter().filter(|v| vars.contains(v)).collect();
+            }
+            acc.extend(bound);
+        },
+        &WhereClause::NotJoin(_) |
+        &WhereClause::Pred(_) |
+        &WhereClause::TypeAnnotation(_) => {},
+    }
+}
+
+/// Every definition of a rule must take the same number of arguments, and must use each of them:
+/// otherwise there's no way to bind the unused variable when the rule is invoked.  Each argument
+/// must also be bound by the body, because a rule's results are the values of its arguments.
+pub(crate) fn validate_rules(rules: &Rules) -> Result<()> {
+    for (name, definitions) in rules.iter() {
+        let arity = definitions.first().map(|rule| rule.vars.len());
+        for rule in definitions.iter() {
+            if Some(rule.vars.len()) != arity {
+                bail!(AlgebrizerError::InconsistentRuleArity(name.clone()));
+            }
+            let mut mentioned: BTreeSet<Variable> = BTreeSet::new();
+            let mut bound: BTreeSet<Variable> = BTreeSet::new();
+            for clause in rule.clauses.iter() {
+                clause.accumulate_mentioned_variables(&mut mentioned);
+                accumulate_bound_variables(clause, &mut bound);
+            }
+            for var in rule.vars.iter() {
+                if !mentioned.contains(var) {
+                    bail!(AlgebrizerError::UnusedRuleVariable(name.clone(), var.name()));
+                }
+                if !bound.contains(var) {
+                    bail!(AlgebrizerError::UnboundRuleVariable(name.clone(), var.name()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     extern crate mentat_core;
@@ -161,6 +228,7 @@ mod tests {
                         attribute: ident("artist", "type"),
                         value: value_ident("artist.type", "group"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     })));
                 assert_eq!(
                     right,
@@ -172,6 +240,7 @@ mod tests {
                                 attribute: ident("artist", "type"),
                                 value: value_ident("artist.type", "person"),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             }),
                             WhereClause::Pattern(Pattern {
                                 source: None,
@@ -179,6 +248,7 @@ mod tests {
                                 attribute: ident("artist", "gender"),
                                 value: value_ident("artist.gender", "female"),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             }),
                         ]));
             },
@@ -223,6 +293,7 @@ mod tests {
                         attribute: ident("artist", "type"),
                         value: value_ident("artist.type", "group"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     })));
                 assert_eq!(
                     right,
@@ -234,6 +305,7 @@ mod tests {
                                 attribute: ident("artist", "type"),
                                 value: PatternValuePlace::Variable(Variable::from_valid_name("?type")),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             }),
                             WhereClause::Pattern(Pattern {
                                 source: None,
@@ -241,6 +313,7 @@ mod tests {
                                 attribute: ident("artist", "role"),
                                 value: value_ident("artist.role", "parody"),
                                 tx: PatternNonValuePlace::Placeholder,
+                                added: PatternValuePlace::Placeholder,
                             }),
                         ]));
             },
@@ -296,6 +369,7 @@ mod tests {
                         attribute: artist_country.clone(),
                         value: value_ident("country", "CA"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }));
                 assert_eq!(
                     clause2,
@@ -305,6 +379,7 @@ mod tests {
                         attribute: artist_country,
                         value: value_ident("country", "GB"),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }));
             },
             _ => panic!(),
@@ -335,6 +410,7 @@ mod tests {
                         attribute: ident("release", "artists"),
                         value: artist,
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }));
                 assert_eq!(
                     clause2,
@@ -344,6 +420,7 @@ mod tests {
                         attribute: ident("release", "year"),
                         value: PatternValuePlace::EntidOrInteger(1970),
                         tx: PatternNonValuePlace::Placeholder,
+                        added: PatternValuePlace::Placeholder,
                     }));
             },
             _ => panic!(),
@@ -29,7 +29,7 @@ use std::collections::{
 
 use std::iter;
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use rusqlite::{
     Row,
@@ -56,6 +56,7 @@ use mentat_query::{
     Element,
     FindSpec,
     Limit,
+    Offset,
     Variable,
 };
 
@@ -98,6 +99,7 @@ pub use project::{
 pub use projectors::{
     ConstantProjector,
     Projector,
+    RowProjector,
 };
 
 use projectors::{
@@ -123,7 +125,7 @@ pub use errors::{
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct QueryOutput {
-    pub spec: Rc<FindSpec>,
+    pub spec: Arc<FindSpec>,
     pub results: QueryResults,
 }
 
@@ -142,7 +144,7 @@ impl From<QueryOutput> for QueryResults {
 }
 
 impl QueryOutput {
-    pub fn empty_factory(spec: &FindSpec) -> Box<Fn() -> QueryResults> {
+    pub fn empty_factory(spec: &FindSpec) -> Box<Fn() -> QueryResults + Send + Sync> {
         use self::FindSpec::*;
         match spec {
             &FindScalar(_)   => Box::new(|| QueryResults::Scalar(None)),
@@ -163,7 +165,7 @@ impl QueryOutput {
         self.results.is_empty()
     }
 
-    pub fn empty(spec: &Rc<FindSpec>) -> QueryOutput {
+    pub fn empty(spec: &Arc<FindSpec>) -> QueryOutput {
         use self::FindSpec::*;
         let results =
             match &**spec {
@@ -178,7 +180,7 @@ impl QueryOutput {
         }
     }
 
-    pub fn from_constants(spec: &Rc<FindSpec>, bindings: VariableBindings) -> QueryResults {
+    pub fn from_constants(spec: &Arc<FindSpec>, bindings: VariableBindings) -> QueryResults {
         use self::FindSpec::*;
         match &**spec {
             &FindScalar(Element::Variable(ref var)) |
@@ -346,6 +348,7 @@ impl QueryResults {
 }
 
 type Index = i32;            // See rusqlite::RowIndex.
+#[derive(Clone)]
 enum TypedIndex {
     Known(Index, ValueTypeTag),
     Unknown(Index, Index),
@@ -418,8 +421,9 @@ pub struct CombinedProjection {
 }
 
 impl CombinedProjection {
-    fn flip_distinct_for_limit(mut self, limit: &Limit) -> Self {
-        if *limit == Limit::Fixed(1) {
+    fn flip_distinct_for_limit(mut self, limit: &Limit, offset: &Offset) -> Self {
+        // Skipping rows that might be duplicates would skip the wrong number of results.
+        if *limit == Limit::Fixed(1) && *offset == Offset::None {
             self.distinct = false;
         }
         self
@@ -490,7 +494,7 @@ pub fn query_projection(schema: &Schema, query: &AlgebraicQuery) -> Result<Eithe
                     CollTwoStagePullProjector::combine(spec, elements)
                 } else {
                     CollProjector::combine(spec, elements)
-                }.map(|p| p.flip_distinct_for_limit(&query.limit))
+                }.map(|p| p.flip_distinct_for_limit(&query.limit, &query.offset))
             },
 
             FindScalar(ref element) => {
@@ -510,7 +514,7 @@ pub fn query_projection(schema: &Schema, query: &AlgebraicQuery) -> Result<Eithe
                     RelTwoStagePullProjector::combine(spec, column_count, elements)
                 } else {
                     RelProjector::combine(spec, column_count, elements)
-                }.map(|p| p.flip_distinct_for_limit(&query.limit))
+                }.map(|p| p.flip_distinct_for_limit(&query.limit, &query.offset))
             },
 
             FindTuple(ref elements) => {
@@ -530,7 +534,7 @@ pub fn query_projection(schema: &Schema, query: &AlgebraicQuery) -> Result<Eithe
 #[test]
 fn test_into_tuple() {
     let query_output = QueryOutput {
-        spec: Rc::new(FindSpec::FindTuple(vec![Element::Variable(Variable::from_valid_name("?x")),
+        spec: Arc::new(FindSpec::FindTuple(vec![Element::Variable(Variable::from_valid_name("?x")),
                                                Element::Variable(Variable::from_valid_name("?y"))])),
         results: QueryResults::Tuple(Some(vec![Binding::Scalar(TypedValue::Long(0)),
                                                Binding::Scalar(TypedValue::Long(2))])),
@@ -549,7 +553,7 @@ fn test_into_tuple() {
     }
 
     let query_output = QueryOutput {
-        spec: Rc::new(FindSpec::FindTuple(vec![Element::Variable(Variable::from_valid_name("?x")),
+        spec: Arc::new(FindSpec::FindTuple(vec![Element::Variable(Variable::from_valid_name("?x")),
                                                Element::Variable(Variable::from_valid_name("?y"))])),
         results: QueryResults::Tuple(None),
     };
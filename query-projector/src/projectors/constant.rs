@@ -8,7 +8,7 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use ::{
     Element,
@@ -31,12 +31,12 @@ use super::{
 /// A projector that produces a `QueryResult` containing fixed data.
 /// Takes a boxed function that should return an empty result set of the desired type.
 pub struct ConstantProjector {
-    spec: Rc<FindSpec>,
-    results_factory: Box<Fn() -> QueryResults>,
+    spec: Arc<FindSpec>,
+    results_factory: Box<Fn() -> QueryResults + Send + Sync>,
 }
 
 impl ConstantProjector {
-    pub fn new(spec: Rc<FindSpec>, results_factory: Box<Fn() -> QueryResults>) -> ConstantProjector {
+    pub fn new(spec: Arc<FindSpec>, results_factory: Box<Fn() -> QueryResults + Send + Sync>) -> ConstantProjector {
         ConstantProjector {
             spec: spec,
             results_factory: results_factory,
@@ -9,10 +9,13 @@
 // specific language governing permissions and limitations under the License.
 
 use super::{
+    Binding,
     Element,
     Schema,
     QueryOutput,
+    Row,
     Rows,
+    TypedIndex,
     rusqlite,
 };
 
@@ -20,9 +23,38 @@ use super::errors::{
     Result,
 };
 
-pub trait Projector {
+/// Projectors are `Send` and `Sync` so that a planned query can be shared between threads.
+pub trait Projector: Send + Sync {
     fn project<'stmt, 's>(&self, schema: &Schema, sqlite: &'s rusqlite::Connection, rows: Rows<'stmt>) -> Result<QueryOutput>;
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's>;
+
+    /// Collections and relations without pull expressions turn each row into a result on its own,
+    /// so their results can be read a row at a time. Such projectors return a `RowProjector` that
+    /// does that work.
+    fn row_projector(&self) -> Option<RowProjector> {
+        None
+    }
+}
+
+/// Turns a single row into the bindings for one result of a collection or relation.
+#[derive(Clone)]
+pub struct RowProjector {
+    templates: Vec<TypedIndex>,
+}
+
+impl RowProjector {
+    pub(crate) fn with_templates(templates: Vec<TypedIndex>) -> RowProjector {
+        RowProjector {
+            templates: templates,
+        }
+    }
+
+    pub fn project_row<'a, 'stmt>(&self, row: &Row<'a, 'stmt>) -> Result<Vec<Binding>> {
+        self.templates
+            .iter()
+            .map(|ti| ti.lookup(row))
+            .collect()
+    }
 }
 
 mod constant;
@@ -8,7 +8,7 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use std::iter::{
     once,
@@ -53,7 +53,7 @@ use super::{
 };
 
 pub(crate) struct ScalarTwoStagePullProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     puller: Puller,
 }
 
@@ -61,14 +61,14 @@ pub(crate) struct ScalarTwoStagePullProjector {
 // The only output is the pull expression, and so we can directly supply the projected entity
 // to the pull SQL.
 impl ScalarTwoStagePullProjector {
-    fn with_template(schema: &Schema, spec: Rc<FindSpec>, pull: PullOperation) -> Result<ScalarTwoStagePullProjector> {
+    fn with_template(schema: &Schema, spec: Arc<FindSpec>, pull: PullOperation) -> Result<ScalarTwoStagePullProjector> {
         Ok(ScalarTwoStagePullProjector {
             spec: spec,
             puller: Puller::prepare(schema, pull.0.clone())?,
         })
     }
 
-    pub(crate) fn combine(schema: &Schema, spec: Rc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
+    pub(crate) fn combine(schema: &Schema, spec: Arc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let pull = elements.pulls.pop().expect("Expected a single pull");
         let projector = Box::new(ScalarTwoStagePullProjector::with_template(schema, spec, pull.op)?);
         let distinct = false;
@@ -103,14 +103,14 @@ impl Projector for ScalarTwoStagePullProjector {
 
 /// A tuple projector produces a single vector. It's the single-result version of rel.
 pub(crate) struct TupleTwoStagePullProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     len: usize,
     templates: Vec<TypedIndex>,
     pulls: Vec<PullTemplate>,
 }
 
 impl TupleTwoStagePullProjector {
-    fn with_templates(spec: Rc<FindSpec>, len: usize, templates: Vec<TypedIndex>, pulls: Vec<PullTemplate>) -> TupleTwoStagePullProjector {
+    fn with_templates(spec: Arc<FindSpec>, len: usize, templates: Vec<TypedIndex>, pulls: Vec<PullTemplate>) -> TupleTwoStagePullProjector {
         TupleTwoStagePullProjector {
             spec: spec,
             len: len,
@@ -131,7 +131,7 @@ impl TupleTwoStagePullProjector {
             .collect::<Result<Vec<Binding>>>()
     }
 
-    pub(crate) fn combine(spec: Rc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
+    pub(crate) fn combine(spec: Arc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let projector = Box::new(TupleTwoStagePullProjector::with_templates(spec, column_count, elements.take_templates(), elements.take_pulls()));
         let distinct = false;
         elements.combine(projector, distinct)
@@ -188,14 +188,14 @@ impl Projector for TupleTwoStagePullProjector {
 /// Each column in each stride is the result of taking one or two columns from
 /// the `Row`: one for the value and optionally one for the type tag.
 pub(crate) struct RelTwoStagePullProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     len: usize,
     templates: Vec<TypedIndex>,
     pulls: Vec<PullTemplate>,
 }
 
 impl RelTwoStagePullProjector {
-    fn with_templates(spec: Rc<FindSpec>, len: usize, templates: Vec<TypedIndex>, pulls: Vec<PullTemplate>) -> RelTwoStagePullProjector {
+    fn with_templates(spec: Arc<FindSpec>, len: usize, templates: Vec<TypedIndex>, pulls: Vec<PullTemplate>) -> RelTwoStagePullProjector {
         RelTwoStagePullProjector {
             spec: spec,
             len: len,
@@ -220,7 +220,7 @@ impl RelTwoStagePullProjector {
         Ok(())
     }
 
-    pub(crate) fn combine(spec: Rc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
+    pub(crate) fn combine(spec: Arc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let projector = Box::new(RelTwoStagePullProjector::with_templates(spec, column_count, elements.take_templates(), elements.take_pulls()));
 
         // If every column yields only one value, or if this is an aggregate query
@@ -283,19 +283,19 @@ impl Projector for RelTwoStagePullProjector {
 /// A coll projector produces a vector of values.
 /// Each value is sourced from the same column.
 pub(crate) struct CollTwoStagePullProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     pull: PullOperation,
 }
 
 impl CollTwoStagePullProjector {
-    fn with_pull(spec: Rc<FindSpec>, pull: PullOperation) -> CollTwoStagePullProjector {
+    fn with_pull(spec: Arc<FindSpec>, pull: PullOperation) -> CollTwoStagePullProjector {
         CollTwoStagePullProjector {
             spec: spec,
             pull: pull,
         }
     }
 
-    pub(crate) fn combine(spec: Rc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
+    pub(crate) fn combine(spec: Arc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let pull = elements.pulls.pop().expect("Expected a single pull");
         let projector = Box::new(CollTwoStagePullProjector::with_pull(spec, pull.op));
 
@@ -8,7 +8,7 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use ::{
     Binding,
@@ -32,22 +32,23 @@ use ::errors::{
 
 use super::{
     Projector,
+    RowProjector,
 };
 
 pub(crate) struct ScalarProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     template: TypedIndex,
 }
 
 impl ScalarProjector {
-    fn with_template(spec: Rc<FindSpec>, template: TypedIndex) -> ScalarProjector {
+    fn with_template(spec: Arc<FindSpec>, template: TypedIndex) -> ScalarProjector {
         ScalarProjector {
             spec: spec,
             template: template,
         }
     }
 
-    pub(crate) fn combine(spec: Rc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
+    pub(crate) fn combine(spec: Arc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let template = elements.templates.pop().expect("Expected a single template");
         let projector = Box::new(ScalarProjector::with_template(spec, template));
         let distinct = false;
@@ -78,13 +79,13 @@ impl Projector for ScalarProjector {
 
 /// A tuple projector produces a single vector. It's the single-result version of rel.
 pub(crate) struct TupleProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     len: usize,
     templates: Vec<TypedIndex>,
 }
 
 impl TupleProjector {
-    fn with_templates(spec: Rc<FindSpec>, len: usize, templates: Vec<TypedIndex>) -> TupleProjector {
+    fn with_templates(spec: Arc<FindSpec>, len: usize, templates: Vec<TypedIndex>) -> TupleProjector {
         TupleProjector {
             spec: spec,
             len: len,
@@ -104,7 +105,7 @@ impl TupleProjector {
             .collect::<Result<Vec<Binding>>>()
     }
 
-    pub(crate) fn combine(spec: Rc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
+    pub(crate) fn combine(spec: Arc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let projector = Box::new(TupleProjector::with_templates(spec, column_count, elements.take_templates()));
         let distinct = false;
         elements.combine(projector, distinct)
@@ -137,13 +138,13 @@ impl Projector for TupleProjector {
 /// Each column in each stride is the result of taking one or two columns from
 /// the `Row`: one for the value and optionally one for the type tag.
 pub(crate) struct RelProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     len: usize,
     templates: Vec<TypedIndex>,
 }
 
 impl RelProjector {
-    fn with_templates(spec: Rc<FindSpec>, len: usize, templates: Vec<TypedIndex>) -> RelProjector {
+    fn with_templates(spec: Arc<FindSpec>, len: usize, templates: Vec<TypedIndex>) -> RelProjector {
         RelProjector {
             spec: spec,
             len: len,
@@ -167,7 +168,7 @@ impl RelProjector {
         Ok(())
     }
 
-    pub(crate) fn combine(spec: Rc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
+    pub(crate) fn combine(spec: Arc<FindSpec>, column_count: usize, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let projector = Box::new(RelProjector::with_templates(spec, column_count, elements.take_templates()));
 
         // If every column yields only one value, or if this is an aggregate query
@@ -202,24 +203,28 @@ impl Projector for RelProjector {
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
+
+    fn row_projector(&self) -> Option<RowProjector> {
+        Some(RowProjector::with_templates(self.templates.clone()))
+    }
 }
 
 /// A coll projector produces a vector of values.
 /// Each value is sourced from the same column.
 pub(crate) struct CollProjector {
-    spec: Rc<FindSpec>,
+    spec: Arc<FindSpec>,
     template: TypedIndex,
 }
 
 impl CollProjector {
-    fn with_template(spec: Rc<FindSpec>, template: TypedIndex) -> CollProjector {
+    fn with_template(spec: Arc<FindSpec>, template: TypedIndex) -> CollProjector {
         CollProjector {
             spec: spec,
             template: template,
         }
     }
 
-    pub(crate) fn combine(spec: Rc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
+    pub(crate) fn combine(spec: Arc<FindSpec>, mut elements: ProjectedElements) -> Result<CombinedProjection> {
         let template = elements.templates.pop().expect("Expected a single template");
         let projector = Box::new(CollProjector::with_template(spec, template));
 
@@ -250,4 +255,8 @@ impl Projector for CollProjector {
     fn columns<'s>(&'s self) -> Box<Iterator<Item=&Element> + 's> {
         self.spec.columns()
     }
+
+    fn row_projector(&self) -> Option<RowProjector> {
+        Some(RowProjector::with_templates(vec![self.template.clone()]))
+    }
 }
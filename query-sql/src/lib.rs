@@ -17,6 +17,7 @@ use std::boxed::Box;
 use mentat_core::{
     Entid,
     SQLTypeAffinity,
+    SQLValueType,
     TypedValue,
     ValueType,
 };
@@ -24,11 +25,13 @@ use mentat_core::{
 use mentat_query::{
     Direction,
     Limit,
+    Offset,
     Variable,
 };
 
 use mentat_query_algebrizer::{
     Column,
+    DatomsTable,
     OrderBy,
     QualifiedAlias,
     QueryValue,
@@ -62,6 +65,7 @@ pub enum ColumnOrExpression {
     Integer(i32),       // We use these for type codes etc.
     Long(i64),
     Value(TypedValue),
+    Input(Variable),    // A late-bound input, supplied as a SQL parameter.
     // Some aggregates (`min`, `max`, `avg`) can be over 0 rows, and therefore can be `NULL`; that
     // needs special treatment.
     NullableAggregate(Box<Expression>, ValueType),      // Track the return type.
@@ -80,6 +84,7 @@ impl From<QueryValue> for ColumnOrExpression {
             QueryValue::Entid(e) => ColumnOrExpression::Entid(e),
             QueryValue::PrimitiveLong(v) => ColumnOrExpression::Long(v),
             QueryValue::TypedValue(v) => ColumnOrExpression::Value(v),
+            QueryValue::Input(v) => ColumnOrExpression::Input(v),
         }
     }
 }
@@ -198,7 +203,18 @@ pub struct Join {
 #[allow(dead_code)]
 pub enum TableOrSubquery {
     Table(SourceAlias),
+    /// One of our tables in an attached database, like "`other`.datoms AS `datoms00`".
+    Attached(String, SourceAlias),
     Union(Vec<SelectQuery>, TableAlias),
+    /// A recursive common table expression, like
+    /// "(WITH RECURSIVE rule(a, b) AS (base UNION recursive) SELECT * FROM rule) AS alias".
+    /// The recursive arm, if any, refers to the `rule` table.  SQLite before 3.34 accepts only one.
+    RecursiveUnion {
+        columns: Vec<VariableColumn>,
+        base: Vec<SelectQuery>,
+        recursive: Vec<SelectQuery>,
+        alias: TableAlias,
+    },
     Subquery(Box<SelectQuery>),
     Values(Values, TableAlias),
 }
@@ -228,6 +244,7 @@ pub struct SelectQuery {
     pub group_by: Vec<GroupBy>,
     pub order: Vec<OrderBy>,
     pub limit: Limit,
+    pub offset: Offset,
 }
 
 fn push_variable_column(qb: &mut QueryBuilder, vc: &VariableColumn) -> BuildQueryResult {
@@ -287,6 +304,9 @@ impl QueryFragment for ColumnOrExpression {
             &Value(ref v) => {
                 out.push_typed_value(v)
             },
+            &Input(ref var) => {
+                push_variable_param(var, out)
+            },
             &NullableAggregate(ref e, _) |
             &Expression(ref e, _) => {
                 e.push_sql(out)
@@ -437,10 +457,60 @@ fn qualified_alias_push_sql(out: &mut QueryBuilder, qa: &QualifiedAlias) -> Buil
     push_column(out, &qa.1)
 }
 
+/// Name one of our tables, qualified by the database that holds it if it isn't the main database.
+fn push_table_name(out: &mut QueryBuilder, database: Option<&str>, table: &str) -> BuildQueryResult {
+    if let Some(database) = database {
+        out.push_identifier(database)?;
+        out.push_sql(".");
+    }
+    out.push_sql(table);
+    Ok(())
+}
+
+/// The `transactions` log stores fulltext values as rowids into `fulltext_values`, just like
+/// `datoms` does. Unlike `datoms` it has no `index_fulltext` flag, but a fulltext value is the only
+/// kind of string we store as an integer.
+fn push_historical_value(out: &mut QueryBuilder, database: Option<&str>) -> BuildQueryResult {
+    out.push_sql(&format!("CASE WHEN value_type_tag = {} AND typeof(v) = 'integer' \
+                            THEN (SELECT text FROM ",
+                          ValueType::String.value_type_tag()));
+    push_table_name(out, database, "fulltext_values")?;
+    out.push_sql(" WHERE fulltext_values.rowid = transactions.v) ELSE v END AS v");
+    Ok(())
+}
+
+/// Historical views of the datoms, derived from the `transactions` log. Each has the columns of
+/// `datoms` that queries use, along with `added`.
+fn push_historical_table(out: &mut QueryBuilder, database: Option<&str>, table: &DatomsTable) -> BuildQueryResult {
+    out.push_sql("(SELECT e, a, ");
+    push_historical_value(out, database)?;
+    out.push_sql(", tx, value_type_tag, added FROM ");
+    push_table_name(out, database, "transactions")?;
+    if let &DatomsTable::AsOf(tx) = table {
+        // Every assertion made at or before `tx` that hasn't since been retracted.
+        out.push_sql(&format!(" WHERE tx <= {} AND added = 1 AND NOT EXISTS (SELECT 1 FROM ", tx));
+        push_table_name(out, database, "transactions")?;
+        out.push_sql(&format!(" AS retractions \
+                                 WHERE retractions.e = transactions.e AND retractions.a = transactions.a \
+                                 AND retractions.value_type_tag = transactions.value_type_tag \
+                                 AND retractions.v = transactions.v \
+                                 AND retractions.added = 0 \
+                                 AND retractions.tx > transactions.tx AND retractions.tx <= {})",
+                              tx));
+    }
+    out.push_sql(")");
+    Ok(())
+}
+
 // We don't own SourceAlias or QueryFragment, so we can't implement the trait.
-fn source_alias_push_sql(out: &mut QueryBuilder, sa: &SourceAlias) -> BuildQueryResult {
+fn source_alias_push_sql(out: &mut QueryBuilder, database: Option<&str>, sa: &SourceAlias) -> BuildQueryResult {
     let &SourceAlias(ref table, ref alias) = sa;
-    out.push_identifier(table.name())?;
+    match (database, table) {
+        (_, &DatomsTable::AsOf(_)) |
+        (_, &DatomsTable::History) => push_historical_table(out, database, table)?,
+        (None, _) => out.push_identifier(table.name())?,
+        (Some(_), _) => push_table_name(out, database, table.name())?,
+    }
     out.push_sql(" AS ");
     out.push_identifier(alias.as_str())
 }
@@ -470,7 +540,8 @@ impl QueryFragment for TableOrSubquery {
     fn push_sql(&self, out: &mut QueryBuilder) -> BuildQueryResult {
         use self::TableOrSubquery::*;
         match self {
-            &Table(ref sa) => source_alias_push_sql(out, sa),
+            &Table(ref sa) => source_alias_push_sql(out, None, sa),
+            &Attached(ref database, ref sa) => source_alias_push_sql(out, Some(database.as_str()), sa),
             &Union(ref subqueries, ref table_alias) => {
                 out.push_sql("(");
                 interpose!(subquery, subqueries,
@@ -479,6 +550,23 @@ impl QueryFragment for TableOrSubquery {
                 out.push_sql(") AS ");
                 out.push_identifier(table_alias.as_str())
             },
+            &RecursiveUnion { ref columns, ref base, ref recursive, ref alias } => {
+                // SQLite requires the non-recursive arms to come first.
+                out.push_sql("(WITH RECURSIVE ");
+                out.push_identifier(DatomsTable::Rule.name())?;
+                out.push_sql("(");
+                interpose!(column, columns,
+                           { push_variable_column(out, column)? },
+                           { out.push_sql(", ") });
+                out.push_sql(") AS (");
+                interpose_iter!(subquery, base.iter().chain(recursive.iter()),
+                                { subquery.push_sql(out)? },
+                                { out.push_sql(" UNION ") });
+                out.push_sql(") SELECT * FROM ");
+                out.push_identifier(DatomsTable::Rule.name())?;
+                out.push_sql(") AS ");
+                out.push_identifier(alias.as_str())
+            },
             &Subquery(ref subquery) => {
                 out.push_sql("(");
                 subquery.push_sql(out)?;
@@ -561,7 +649,8 @@ impl QueryFragment for FromClause {
 /// `var` is something like `?foo99-people`.
 /// Trim the `?` and escape the rest. Prepend `i` to distinguish from
 /// the inline value space `v`.
-fn format_select_var(var: &str) -> String {
+/// This is the name of the SQL parameter that holds the value of a late-bound input.
+pub fn format_select_var(var: &str) -> String {
     use std::iter::once;
     let without_question = var.split_at(1).1;
     let replaced_iter = without_question.chars().map(|c|
@@ -570,11 +659,9 @@ fn format_select_var(var: &str) -> String {
     once('i').chain(replaced_iter).collect()
 }
 
-impl SelectQuery {
-    fn push_variable_param(&self, var: &Variable, out: &mut QueryBuilder) -> BuildQueryResult {
-        let bind_param = format_select_var(var.as_str());
-        out.push_bind_param(bind_param.as_str())
-    }
+fn push_variable_param(var: &Variable, out: &mut QueryBuilder) -> BuildQueryResult {
+    let bind_param = format_select_var(var.as_str());
+    out.push_bind_param(bind_param.as_str())
 }
 
 impl QueryFragment for SelectQuery {
@@ -626,7 +713,23 @@ impl QueryFragment for SelectQuery {
             &Limit::Variable(ref var) => {
                 // Guess this wasn't bound yet. Produce an argument.
                 out.push_sql(" LIMIT ");
-                self.push_variable_param(var, out)?;
+                push_variable_param(var, out)?;
+            },
+        }
+
+        // SQLite only accepts OFFSET after a LIMIT; a negative limit means there's no limit.
+        if self.offset != Offset::None && self.limit == Limit::None {
+            out.push_sql(" LIMIT -1");
+        }
+        match &self.offset {
+            &Offset::None => (),
+            &Offset::Fixed(offset) => {
+                out.push_sql(" OFFSET ");
+                out.push_sql(offset.to_string().as_str());
+            },
+            &Offset::Variable(ref var) => {
+                out.push_sql(" OFFSET ");
+                push_variable_param(var, out)?;
             },
         }
 
@@ -807,6 +910,7 @@ mod tests {
             group_by: vec![],
             order: vec![],
             limit: Limit::None,
+            offset: Offset::None,
         };
 
         let SQLQuery { sql, args } = query.to_sql_query().unwrap();
@@ -823,6 +927,85 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_recursive_union() {
+        // The transitive closure of :person/parent (65537), starting from the people with names.
+        let a = Variable::from_valid_name("?a");
+        let a_column = VariableColumn::Variable(a.clone());
+        let base = SelectQuery {
+            distinct: false,
+            projection: Projection::Columns(vec![
+                ProjectedColumn(ColumnOrExpression::Column(QualifiedAlias::new("datoms00".to_string(), DatomsColumn::Entity)),
+                                "?a".to_string()),
+            ]),
+            from: FromClause::TableList(TableList(vec![
+                TableOrSubquery::Table(SourceAlias(DatomsTable::Datoms, "datoms00".to_string())),
+            ])),
+            constraints: vec![],
+            group_by: vec![],
+            order: vec![],
+            limit: Limit::None,
+            offset: Offset::None,
+        };
+        let recursive = SelectQuery {
+            distinct: false,
+            projection: Projection::Columns(vec![
+                ProjectedColumn(ColumnOrExpression::Column(QualifiedAlias::new("datoms02".to_string(), DatomsColumn::Value)),
+                                "?a".to_string()),
+            ]),
+            from: FromClause::TableList(TableList(vec![
+                TableOrSubquery::Table(SourceAlias(DatomsTable::Rule, "rule01".to_string())),
+                TableOrSubquery::Table(SourceAlias(DatomsTable::Datoms, "datoms02".to_string())),
+            ])),
+            constraints: vec![
+                Constraint::Infix {
+                    op: Op("="),
+                    left: ColumnOrExpression::Column(QualifiedAlias::new("datoms02".to_string(), DatomsColumn::Entity)),
+                    right: ColumnOrExpression::Column(QualifiedAlias::new("rule01".to_string(), a_column.clone())),
+                },
+                Constraint::Infix {
+                    op: Op("="),
+                    left: ColumnOrExpression::Column(QualifiedAlias::new("datoms02".to_string(), DatomsColumn::Attribute)),
+                    right: ColumnOrExpression::Entid(65537),
+                },
+            ],
+            group_by: vec![],
+            order: vec![],
+            limit: Limit::None,
+            offset: Offset::None,
+        };
+
+        let table = TableOrSubquery::RecursiveUnion {
+            columns: vec![a_column],
+            base: vec![base],
+            recursive: vec![recursive],
+            alias: "c00".to_string(),
+        };
+        assert_eq!(build(&table),
+                   "(WITH RECURSIVE `rule`(`?a`) AS (\
+                    SELECT `datoms00`.e AS `?a` FROM `datoms` AS `datoms00` \
+                    UNION \
+                    SELECT `datoms02`.v AS `?a` FROM `rule` AS `rule01`, `datoms` AS `datoms02` \
+                    WHERE `datoms02`.e = `rule01`.`?a` AND `datoms02`.a = 65537) \
+                    SELECT * FROM `rule`) AS `c00`");
+    }
+
+    #[test]
+    fn test_historical_tables() {
+        let history = TableOrSubquery::Table(SourceAlias(DatomsTable::History, "history00".to_string()));
+        assert_eq!(build(&history),
+                   "(SELECT e, a, CASE WHEN value_type_tag = 10 AND typeof(v) = 'integer' \
+                    THEN (SELECT text FROM fulltext_values WHERE fulltext_values.rowid = transactions.v) \
+                    ELSE v END AS v, tx, value_type_tag, added FROM transactions) AS `history00`");
+
+        let as_of = TableOrSubquery::Table(SourceAlias(DatomsTable::AsOf(268435457), "as_of00".to_string()));
+        let sql = build(&as_of);
+        assert!(sql.starts_with("(SELECT e, a, CASE WHEN"));
+        assert!(sql.contains("FROM transactions WHERE tx <= 268435457 AND added = 1 AND NOT EXISTS"));
+        assert!(sql.contains("AND retractions.tx <= 268435457)"));
+        assert!(sql.ends_with(") AS `as_of00`"));
+    }
+
     #[test]
     fn test_format_select_var() {
         assert_eq!(format_select_var("?foo99-people"), "ifoo99_people");
@@ -21,6 +21,7 @@ mod translate;
 
 pub use mentat_query_sql::{
     Projection,
+    format_select_var,
 };
 
 pub use translate::{
@@ -25,6 +25,8 @@ use mentat_core::util::{
 
 use mentat_query::{
     Limit,
+    Offset,
+    Variable,
 };
 
 use mentat_query_algebrizer::{
@@ -73,7 +75,7 @@ use std::collections::HashMap;
 use super::Result;
 
 trait ToConstraint {
-    fn to_constraint(self) -> Constraint;
+    fn to_constraint(self) -> Result<Constraint>;
 }
 
 trait ToColumn {
@@ -87,23 +89,23 @@ impl ToColumn for QualifiedAlias {
 }
 
 impl ToConstraint for ColumnIntersection {
-    fn to_constraint(self) -> Constraint {
-        Constraint::And {
-            constraints: self.into_iter().map(|x| x.to_constraint()).collect()
-        }
+    fn to_constraint(self) -> Result<Constraint> {
+        Ok(Constraint::And {
+            constraints: self.into_iter().map(|x| x.to_constraint()).collect::<Result<Vec<_>>>()?
+        })
     }
 }
 
 impl ToConstraint for ColumnAlternation {
-    fn to_constraint(self) -> Constraint {
-        Constraint::Or {
-            constraints: self.into_iter().map(|x| x.to_constraint()).collect()
-        }
+    fn to_constraint(self) -> Result<Constraint> {
+        Ok(Constraint::Or {
+            constraints: self.into_iter().map(|x| x.to_constraint()).collect::<Result<Vec<_>>>()?
+        })
     }
 }
 
 impl ToConstraint for ColumnConstraintOrAlternation {
-    fn to_constraint(self) -> Constraint {
+    fn to_constraint(self) -> Result<Constraint> {
         use self::ColumnConstraintOrAlternation::*;
         match self {
             Alternation(alt) => alt.to_constraint(),
@@ -158,9 +160,9 @@ fn possible_affinities(value_types: ValueTypeSet) -> HashMap<ValueTypeTag, Vec<S
 }
 
 impl ToConstraint for ColumnConstraint {
-    fn to_constraint(self) -> Constraint {
+    fn to_constraint(self) -> Result<Constraint> {
         use self::ColumnConstraint::*;
-        match self {
+        Ok(match self {
             Equals(qa, QueryValue::Entid(entid)) =>
                 Constraint::equal(qa.to_column(), ColumnOrExpression::Entid(entid)),
 
@@ -170,6 +172,9 @@ impl ToConstraint for ColumnConstraint {
             Equals(left, QueryValue::Column(right)) =>
                 Constraint::equal(left.to_column(), right.to_column()),
 
+            Equals(qa, QueryValue::Input(var)) =>
+                Constraint::equal(qa.to_column(), ColumnOrExpression::Input(var)),
+
             Equals(qa, QueryValue::PrimitiveLong(value)) => {
                 let tag_column = qa.for_associated_type_tag().expect("an associated type tag alias").to_column();
                 let value_column = qa.to_column();
@@ -238,12 +243,12 @@ impl ToConstraint for ColumnConstraint {
             },
 
             NotExists(computed_table) => {
-                let subquery = table_for_computed(computed_table, TableAlias::new());
+                let subquery = table_for_computed(computed_table, TableAlias::new())?;
                 Constraint::NotExists {
                     subquery: subquery,
                 }
             },
-        }
+        })
     }
 }
 
@@ -272,63 +277,87 @@ impl<T> ConsumableVec<T> {
     }
 }
 
-fn table_for_computed(computed: ComputedTable, alias: TableAlias) -> TableOrSubquery {
-    match computed {
+/// Project `vars` from `cc` as a subquery arm of a computed table. Every arm of a computed table
+/// must have the same shape and the same names, so we project each variable, followed by its type
+/// tag if `extract_type` says the enclosing query needs it. The values we project might be fixed
+/// or they might be columns.
+fn project_arm<'a, I, F>(cc: ConjoiningClauses, vars: I, extract_type: F) -> Result<SelectQuery>
+where I: Iterator<Item=&'a Variable>,
+      F: Fn(&Variable) -> bool {
+    // We're going to end up with the variables being projected and also some type tag columns.
+    let mut columns: Vec<ProjectedColumn> = Vec::new();
+
+    // For each variable, find out which column it maps to within this arm, and
+    // project it as the variable name.
+    // E.g., SELECT datoms03.v AS `?x`.
+    for var in vars {
+        let (projected_column, type_set) = projected_column_for_var(var, &cc)?;
+        columns.push(projected_column);
+
+        // Similarly, project type tags if they're not known conclusively in the
+        // outer query.
+        // Assumption: we'll never need to project a tag without projecting the value of a variable.
+        if extract_type(var) {
+            let expression =
+                if let Some(tag) = type_set.unique_type_tag() {
+                    // If we know the type for sure, just project the constant.
+                    // SELECT datoms03.v AS `?x`, 10 AS `?x_value_type_tag`
+                    ColumnOrExpression::Integer(tag)
+                } else {
+                    // Otherwise, we'll have an established type binding! This'll be
+                    // either a datoms table or, recursively, a subquery. Project
+                    // this:
+                    // SELECT datoms03.v AS `?x`,
+                    //        datoms03.value_type_tag AS `?x_value_type_tag`
+                    let extract = cc.extracted_types
+                                    .get(var)
+                                    .expect("Expected variable to have a known type or an extracted type");
+                    ColumnOrExpression::Column(extract.clone())
+                };
+            let type_column = VariableColumn::VariableTypeTag(var.clone());
+            let proj = ProjectedColumn(expression, type_column.column_name());
+            columns.push(proj);
+        }
+    }
+
+    // Each arm simply turns into a subquery.
+    // The SQL translation will stuff "UNION" between each arm.
+    let projection = Projection::Columns(columns);
+    cc_to_select_query(projection, cc, false, vec![], None, Limit::None, Offset::None)
+}
+
+fn table_for_computed(computed: ComputedTable, alias: TableAlias) -> Result<TableOrSubquery> {
+    Ok(match computed {
         ComputedTable::Union {
             projection, type_extraction, arms,
         } => {
-            // The projection list for each CC must have the same shape and the same names.
-            // The values we project might be fixed or they might be columns.
             TableOrSubquery::Union(
                 arms.into_iter()
-                    .map(|cc| {
-                        // We're going to end up with the variables being projected and also some
-                        // type tag columns.
-                        let mut columns: Vec<ProjectedColumn> = Vec::with_capacity(projection.len() + type_extraction.len());
-
-                        // For each variable, find out which column it maps to within this arm, and
-                        // project it as the variable name.
-                        // E.g., SELECT datoms03.v AS `?x`.
-                        for var in projection.iter() {
-                            // TODO: chain results out.
-                            let (projected_column, type_set) = projected_column_for_var(var, &cc).expect("every var to be bound");
-                            columns.push(projected_column);
-
-                            // Similarly, project type tags if they're not known conclusively in the
-                            // outer query.
-                            // Assumption: we'll never need to project a tag without projecting the value of a variable.
-                            if type_extraction.contains(var) {
-                                let expression =
-                                    if let Some(tag) = type_set.unique_type_tag() {
-                                        // If we know the type for sure, just project the constant.
-                                        // SELECT datoms03.v AS `?x`, 10 AS `?x_value_type_tag`
-                                        ColumnOrExpression::Integer(tag)
-                                    } else {
-                                        // Otherwise, we'll have an established type binding! This'll be
-                                        // either a datoms table or, recursively, a subquery. Project
-                                        // this:
-                                        // SELECT datoms03.v AS `?x`,
-                                        //        datoms03.value_type_tag AS `?x_value_type_tag`
-                                        let extract = cc.extracted_types
-                                                        .get(var)
-                                                        .expect("Expected variable to have a known type or an extracted type");
-                                        ColumnOrExpression::Column(extract.clone())
-                                    };
-                                let type_column = VariableColumn::VariableTypeTag(var.clone());
-                                let proj = ProjectedColumn(expression, type_column.column_name());
-                                columns.push(proj);
-                            }
-                        }
-
-                        // Each arm simply turns into a subquery.
-                        // The SQL translation will stuff "UNION" between each arm.
-                        let projection = Projection::Columns(columns);
-                        cc_to_select_query(projection, cc, false, vec![], None, Limit::None)
-                  }).collect(),
+                    .map(|cc| project_arm(cc, projection.iter(), |var| type_extraction.contains(var)))
+                    .collect::<Result<_>>()?,
                 alias)
         },
+        ComputedTable::RecursiveRule {
+            projection, base, recursive,
+        } => {
+            // The rule table can hold values of any type, so we always carry type tags.
+            let columns = projection.iter()
+                                    .flat_map(|var| vec![VariableColumn::Variable(var.clone()),
+                                                         VariableColumn::VariableTypeTag(var.clone())])
+                                    .collect();
+            TableOrSubquery::RecursiveUnion {
+                columns: columns,
+                base: base.into_iter()
+                          .map(|cc| project_arm(cc, projection.iter(), |_| true))
+                          .collect::<Result<_>>()?,
+                recursive: recursive.into_iter()
+                                    .map(|cc| project_arm(cc, projection.iter(), |_| true))
+                                    .collect::<Result<_>>()?,
+                alias: alias,
+            }
+        },
         ComputedTable::Subquery(subquery) => {
-            TableOrSubquery::Subquery(Box::new(cc_to_exists(subquery)))
+            TableOrSubquery::Subquery(Box::new(cc_to_exists(subquery)?))
         },
         ComputedTable::NamedValues {
             names, values,
@@ -336,7 +365,12 @@ fn table_for_computed(computed: ComputedTable, alias: TableAlias) -> TableOrSubq
             // We assume column homogeneity, so we won't have any type tag columns.
             TableOrSubquery::Values(Values::Named(names, values), alias)
         },
-    }
+        ComputedTable::Attached {
+            database, table,
+        } => {
+            TableOrSubquery::Attached(database, SourceAlias(table, alias))
+        },
+    })
 }
 
 fn empty_query() -> SelectQuery {
@@ -348,6 +382,7 @@ fn empty_query() -> SelectQuery {
         constraints: vec![],
         order: vec![],
         limit: Limit::None,
+        offset: Offset::None,
     }
 }
 
@@ -359,7 +394,8 @@ fn cc_to_select_query(projection: Projection,
                       distinct: bool,
                       group_by: Vec<GroupBy>,
                       order: Option<Vec<OrderBy>>,
-                      limit: Limit) -> SelectQuery {
+                      limit: Limit,
+                      offset: Offset) -> Result<SelectQuery> {
     let from = if cc.from.is_empty() {
         FromClause::Nothing
     } else {
@@ -379,17 +415,17 @@ fn cc_to_select_query(projection: Projection,
                         table_for_computed(comp, alias)
                     },
                     _ => {
-                        TableOrSubquery::Table(source_alias)
+                        Ok(TableOrSubquery::Table(source_alias))
                     }
                 }
             });
 
-        FromClause::TableList(TableList(tables.collect()))
+        FromClause::TableList(TableList(tables.collect::<Result<_>>()?))
     };
 
     let order = order.map_or(vec![], |vec| { vec.into_iter().map(|o| o.into()).collect() });
     let limit = if cc.empty_because.is_some() { Limit::Fixed(0) } else { limit };
-    SelectQuery {
+    Ok(SelectQuery {
         distinct: distinct,
         projection: projection,
         from: from,
@@ -397,26 +433,27 @@ fn cc_to_select_query(projection: Projection,
         constraints: cc.wheres
                        .into_iter()
                        .map(|c| c.to_constraint())
-                       .collect(),
+                       .collect::<Result<_>>()?,
         order: order,
         limit: limit,
-    }
+        offset: offset,
+    })
 }
 
 /// Return a query that projects `1` if the `cc` matches the store, and returns no results
 /// if it doesn't.
-pub fn cc_to_exists(cc: ConjoiningClauses) -> SelectQuery {
+pub fn cc_to_exists(cc: ConjoiningClauses) -> Result<SelectQuery> {
     if cc.is_known_empty() {
         // In this case we can produce a very simple query that returns no results.
-        empty_query()
+        Ok(empty_query())
     } else {
-        cc_to_select_query(Projection::One, cc, false, vec![], None, Limit::None)
+        cc_to_select_query(Projection::One, cc, false, vec![], None, Limit::None, Offset::None)
     }
 }
 
 /// Take a query and wrap it as a subquery of a new query with the provided projection list.
-/// All limits, ordering, and grouping move to the outer query. The inner query is marked as
-/// distinct.
+/// All limits, offsets, ordering, and grouping move to the outer query. The inner query is marked
+/// as distinct.
 fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
     let outer_distinct = inner.distinct;
     inner.distinct = true;
@@ -426,6 +463,8 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
     inner.order = vec![];
     let limit = inner.limit;
     inner.limit = Limit::None;
+    let offset = inner.offset;
+    inner.offset = Offset::None;
 
     use self::Projection::*;
 
@@ -455,25 +494,27 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
             group_by: group_by,
             order: order_by,
             limit: limit,
+            offset: offset,
         };
     }
 
     // Our pattern is `SELECT * FROM (SELECT ...) WHERE (nullable aggregate) IS NOT NULL`.  If
     // there's an `ORDER BY` in the subselect, SQL does not guarantee that the outer select will
-    // respect that order.  But `ORDER BY` is relevant to the subselect when we have a `LIMIT`.
-    // Thus we lift the `ORDER BY` if there’s no `LIMIT` in the subselect, and repeat the `ORDER BY`
-    // if there is.
+    // respect that order.  But `ORDER BY` is relevant to the subselect when we have a `LIMIT` or
+    // an `OFFSET`. Thus we lift the `ORDER BY` if there’s neither in the subselect, and repeat the
+    // `ORDER BY` if there is.
     let subselect = SelectQuery {
         distinct: outer_distinct,
         projection: projection,
         from: FromClause::TableList(TableList(vec![TableOrSubquery::Subquery(Box::new(inner))])),
         constraints: vec![],
         group_by: group_by,
-        order: match &limit {
-            &Limit::None => vec![],
-            &Limit::Fixed(_) | &Limit::Variable(_) => order_by.clone(),
+        order: match (&limit, &offset) {
+            (&Limit::None, &Offset::None) => vec![],
+            _ => order_by.clone(),
         },
         limit,
+        offset,
     };
 
     SelectQuery {
@@ -484,6 +525,7 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
         group_by: vec![],
         order: order_by,
         limit: Limit::None, // Any limiting comes from the internal query.
+        offset: Offset::None,
     }
 }
 
@@ -492,7 +534,8 @@ fn re_project(mut inner: SelectQuery, projection: Projection) -> SelectQuery {
 pub fn query_to_select(schema: &Schema, query: AlgebraicQuery) -> Result<ProjectedSelect> {
     // TODO: we can't pass `query.limit` here if we aggregate during projection.
     // SQL-based aggregation -- `SELECT SUM(datoms00.e)` -- is fine.
-    query_projection(schema, &query).map(|e| match e {
+    let projection = query_projection(schema, &query)?;
+    Ok(match projection {
         Either::Left(constant) => ProjectedSelect::Constant(constant),
         Either::Right(CombinedProjection {
             sql_projection,
@@ -510,12 +553,13 @@ pub fn query_to_select(schema: &Schema, query: AlgebraicQuery) -> Result<Project
                                                        distinct,
                                                        group_by_cols,
                                                        query.order,
-                                                       query.limit);
+                                                       query.limit,
+                                                       query.offset)?;
                         let outer = re_project(inner, sql_projection);
                         outer
                     },
                     None => {
-                        cc_to_select_query(sql_projection, query.cc, distinct, group_by_cols, query.order, query.limit)
+                        cc_to_select_query(sql_projection, query.cc, distinct, group_by_cols, query.order, query.limit, query.offset)?
                     },
                 },
                 projector: datalog_projector,
@@ -22,6 +22,7 @@ use std::rc::Rc;
 use mentat_query::{
     FindSpec,
     Keyword,
+    SrcVar,
     Variable,
 };
 
@@ -34,6 +35,8 @@ use mentat_core::{
 };
 
 use mentat_query_algebrizer::{
+    DataSource,
+    DatabaseView,
     Known,
     QueryInputs,
     algebrize,
@@ -222,6 +225,85 @@ fn test_bound_variable_limit() {
     assert_eq!(args, vec![make_arg("$v0", "yyy")]);
 }
 
+#[test]
+fn test_offset() {
+    let schema = prepopulated_schema();
+
+    // SQLite only accepts OFFSET after LIMIT, so we add an unbounded limit if there isn't one.
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"] :offset 10]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT -1 OFFSET 10");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"] :limit 5 :offset 10]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT 5 OFFSET 10");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+}
+
+#[test]
+fn test_variable_offset() {
+    let schema = prepopulated_schema();
+
+    // An unbound offset becomes an escaped SQL variable, just like an unbound limit.
+    let query = r#"[:find ?x :in ?skip :where [?x :foo/bar "yyy"] :limit 5 :offset ?skip]"#;
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, QueryInputs::default());
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 \
+                     LIMIT 5 OFFSET $iskip");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+
+    // A bound offset is substituted directly.
+    let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?skip"), TypedValue::Long(3))]);
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT 5 OFFSET 3");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+}
+
+#[test]
+fn test_offset_keeps_distinct() {
+    let schema = prepopulated_schema();
+
+    // Skipping rows means a limit of one no longer makes `DISTINCT` redundant.
+    let query = r#"[:find ?x :where [?x :foo/bar "yyy"] :limit 1 :offset 2]"#;
+    let SQLQuery { sql, args } = translate(&schema, query);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00` WHERE `datoms00`.a = 99 AND `datoms00`.v = $v0 LIMIT 1 OFFSET 2");
+    assert_eq!(args, vec![make_arg("$v0", "yyy")]);
+}
+
+#[test]
+fn test_unbound_variable_inputs() {
+    let schema = prepopulated_schema();
+
+    // An unbound input becomes an escaped SQL variable, just like an unbound limit.
+    let query = r#"[:find ?x :in ?v :where [?x :foo/bar ?v]]"#;
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, QueryInputs::default());
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 AND `datoms00`.v = $iv");
+    assert_eq!(args, vec![]);
+
+    // If we don't know the attribute, the input's type tag is constrained instead.
+    let query = r#"[:find ?x :in ?v :where [?x _ ?v]]"#;
+    let inputs = QueryInputs::with_type_sequence(vec![(Variable::from_valid_name("?v"), ValueType::Long)]);
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `datoms` AS `datoms00` \
+                     WHERE (`datoms00`.value_type_tag = 5) AND `datoms00`.v = $iv");
+    assert_eq!(args, vec![]);
+
+    // Inputs can be used as function arguments without being bound to a column.
+    let schema = prepopulated_typed_schema(ValueType::Long);
+    let query = r#"[:find ?x :in ?max :where [?x :foo/bar ?y] [(< ?y ?max)]]"#;
+    let inputs = QueryInputs::with_type_sequence(vec![(Variable::from_valid_name("?max"), ValueType::Long)]);
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs);
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `datoms` AS `datoms00` \
+                     WHERE `datoms00`.a = 99 AND `datoms00`.v < $imax");
+    assert_eq!(args, vec![]);
+}
+
 #[test]
 fn test_bound_variable_limit_affects_distinct() {
     let schema = prepopulated_schema();
@@ -1326,3 +1408,54 @@ fn test_tx_data() {
                      AND `transactions01`.tx = `transactions00`.tx");
     assert_eq!(args, vec![]);
 }
+
+#[test]
+fn test_sources() {
+    let schema = prepopulated_typed_schema(ValueType::Long);
+    let a = SrcVar::NamedSrc("a".to_string());
+    let b = SrcVar::NamedSrc("b".to_string());
+    let inputs = || {
+        QueryInputs::default()
+            .with_source(a.clone(), DataSource::Attached("other".to_string(), DatabaseView::Current))
+            .with_source(b.clone(), DataSource::Store(DatabaseView::AsOf(1000)))
+    };
+
+    // Join the store with an attached store.
+    let query = r#"[:find ?x :in $ $a :where [?x :foo/bar 5] [$a ?x :foo/bar 5]]"#;
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs());
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `datoms` AS `datoms00`, `other`.datoms AS `datoms01` \
+                     WHERE `datoms00`.a = 99 AND `datoms00`.v = 5 \
+                     AND `datoms01`.a = 99 AND `datoms01`.v = 5 \
+                     AND `datoms00`.e = `datoms01`.e");
+    assert_eq!(args, vec![]);
+
+    // Join the store with an earlier view of itself.
+    let query = r#"[:find ?x :in $ $b :where [?x :foo/bar 5] [$b ?x :foo/bar 5]]"#;
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs());
+    assert!(sql.starts_with("SELECT DISTINCT `datoms00`.e AS `?x` FROM `datoms` AS `datoms00`, (SELECT e, a, "));
+    assert!(sql.contains("FROM transactions WHERE tx <= 1000 AND added = 1"));
+    assert!(sql.ends_with(") AS `as_of01` \
+                           WHERE `datoms00`.a = 99 AND `datoms00`.v = 5 \
+                           AND `as_of01`.a = 99 AND `as_of01`.v = 5 \
+                           AND `datoms00`.e = `as_of01`.e"));
+    assert_eq!(args, vec![]);
+
+    // The transaction log functions take a source, too.
+    let query = r#"[:find ?tx :in $a :where [(tx-ids $a 1000 2000) [[?tx]]]]"#;
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs());
+    assert_eq!(sql, "SELECT DISTINCT `transactions00`.tx AS `?tx` \
+                     FROM `other`.transactions AS `transactions00` \
+                     WHERE 1000 <= `transactions00`.tx \
+                     AND `transactions00`.tx < 2000");
+    assert_eq!(args, vec![]);
+
+    // Rules invoked against a source read from it.
+    let query = r#"[:find ?x :in $ $a % :where ($a fives ?x)]"#;
+    let rules = mentat_query_algebrizer::parse_rules_string(r#"[[(fives ?e) [?e :foo/bar 5]]]"#).expect("rules");
+    let SQLQuery { sql, args } = translate_with_inputs(&schema, query, inputs().with_rules(rules).expect("valid rules"));
+    assert_eq!(sql, "SELECT DISTINCT `datoms00`.e AS `?x` \
+                     FROM `other`.datoms AS `datoms00` \
+                     WHERE `datoms00`.a = 99 AND `datoms00`.v = 5");
+    assert_eq!(args, vec![]);
+}
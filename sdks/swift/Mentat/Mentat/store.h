@@ -32,6 +32,7 @@ struct QueryResultRow; // Note: a `Vec<mentat::Binding>`
 struct QueryResultRows; // Note: a `mentat::RelResult<Binding>`
 struct QueryRowsIterator; // Note: a `mentat::BindingListIterator`
 struct QueryRowIterator; // Note: a `mentat::BindingIterator`
+struct QueryResultStream;
 struct Store;
 struct TxReport;
 struct TypedValue; // Note: a `mentat::Binding`
@@ -115,6 +116,7 @@ void typed_value_list_destroy(struct QueryResultRow* _Nullable obj);
 void typed_value_list_iter_destroy(struct QueryRowIterator* _Nullable obj);
 void typed_value_result_set_destroy(struct QueryResultRows* _Nullable obj);
 void typed_value_result_set_iter_destroy(struct QueryRowsIterator* _Nullable obj);
+void query_result_stream_destroy(struct QueryResultStream* _Nullable obj);
 void in_progress_destroy(struct InProgress* _Nullable obj);
 void in_progress_builder_destroy(struct InProgressBuilder* _Nullable obj);
 void entity_builder_destroy(struct EntityBuilder* _Nullable obj);
@@ -212,6 +214,7 @@ struct QueryResultRows* _Nullable query_builder_execute(struct Query*_Nonnull qu
 struct TypedValue* _Nullable query_builder_execute_scalar(struct Query*_Nonnull query, struct RustError* _Nonnull error);
 struct QueryResultRow* _Nullable query_builder_execute_coll(struct Query*_Nonnull query, struct RustError* _Nonnull error);
 struct QueryResultRow* _Nullable query_builder_execute_tuple(struct Query*_Nonnull query, struct RustError* _Nonnull error);
+struct QueryResultStream* _Nullable query_builder_execute_stream(struct Query*_Nonnull query, struct RustError* _Nonnull error);
 
 // Query Result Processing
 int64_t typed_value_into_long(struct TypedValue*_Nonnull  value);
@@ -227,6 +230,7 @@ enum ValueType typed_value_value_type(struct TypedValue*_Nonnull value);
 struct QueryResultRow* _Nullable row_at_index(struct QueryResultRows* _Nonnull rows, const int32_t index);
 struct QueryRowsIterator* _Nonnull typed_value_result_set_into_iter(struct QueryResultRows* _Nonnull rows);
 struct QueryResultRow* _Nullable typed_value_result_set_iter_next(struct QueryRowsIterator* _Nonnull iter);
+struct QueryResultRow* _Nullable query_result_stream_next(struct QueryResultStream* _Nonnull stream, struct RustError* _Nonnull error);
 struct QueryRowIterator* _Nonnull typed_value_list_into_iter(struct QueryResultRow* _Nonnull row);
 struct TypedValue* _Nullable typed_value_list_iter_next(struct QueryRowIterator* _Nonnull iter);
 
@@ -25,6 +25,7 @@ use mentat_core::{
     ToMicros,
     TypedValue,
     ValueRc,
+    tuple_to_bytes,
 };
 
 pub use rusqlite::types::Value;
@@ -190,6 +191,16 @@ impl QueryBuilder for SQLiteQueryBuilder {
                 let v = Rc::new(rusqlite::types::Value::Text(s.as_ref().to_string()));
                 self.push_static_arg(v);
             },
+            &Tuple(ref t) => {
+                let bytes = tuple_to_bytes(t.as_slice());
+                if let Some(arg) = self.byte_args.get(&bytes).cloned() {
+                    self.push_named_arg(arg.as_str());
+                } else {
+                    let arg = self.next_argument_name();
+                    self.push_named_arg(arg.as_str());
+                    self.byte_args.insert(bytes, arg);
+                }
+            },
         }
         Ok(())
     }
@@ -16,6 +16,7 @@ use std::borrow::{
 
 use std::collections::{
     BTreeMap,
+    BTreeSet,
 };
 
 use std::fs::{
@@ -23,9 +24,18 @@ use std::fs::{
 };
 
 use std::io::{
+    BufRead,
+    BufReader,
     Read,
 };
 
+use std::mem;
+
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+
 use std::path::{
     Path,
 };
@@ -35,18 +45,28 @@ use std::sync::{
     Mutex,
 };
 
+use std::time::{
+    Duration,
+};
+
 use rusqlite;
 use rusqlite::{
     TransactionBehavior,
 };
+use rusqlite::backup::{
+    Backup,
+};
 
 use edn;
 use edn::{
     InternSet,
+    ValueAndSpan,
 };
 
 use mentat_core::{
     Attribute,
+    CachedAttributes,
+    DateTime,
     Entid,
     HasSchema,
     KnownEntid,
@@ -55,6 +75,7 @@ use mentat_core::{
     StructuredMap,
     TxReport,
     TypedValue,
+    Utc,
     ValueRc,
     ValueType,
 };
@@ -67,14 +88,24 @@ use mentat_db::cache::{
 
 use mentat_db::db;
 use mentat_db::{
-    transact,
+    excise,
+    explain_transact,
+    migrate_value_type,
+    revert_tx,
     transact_terms,
+    transact_with_options,
+    Excision,
     InProgressObserverTransactWatcher,
     PartitionMap,
     TransactableValue,
     TransactWatcher,
+    TxExplanation,
+    TxFunctionContext,
+    TxFunctionOutput,
+    TxFunctions,
     TxObservationService,
     TxObserver,
+    TxOptions,
 };
 
 use mentat_db::internal_types::TermWithTempIds;
@@ -85,6 +116,7 @@ use mentat_query_pull::{
 };
 
 use edn::entities::{
+    Entity,
     TempId,
     OpType,
 };
@@ -99,20 +131,43 @@ use errors::{
     MentatError,
 };
 
+use import::{
+    BulkImportReport,
+    EntityStream,
+};
+
 use query::{
+    DatabaseView,
     Known,
     PreparedResult,
+    QueryCursor,
     QueryExplanation,
     QueryInputs,
     QueryOutput,
+    QueryPage,
+    QueryPlanCache,
+    QueryPlanCacheStats,
+    StreamResult,
+    Variable,
+    cursor_after,
+    cursor_columns,
     lookup_value_for_attribute,
     lookup_values_for_attribute,
     q_explain,
     q_once,
+    q_planned,
     q_prepare,
+    q_stream,
     q_uncached,
 };
 
+use validation::{
+    ConstraintContext,
+    Constraints,
+    ValidationError,
+    ValidationErrors,
+};
+
 /// Connection metadata required to query from, or apply transactions to, a Mentat store.
 ///
 /// Owned data for the volatile parts (generation and partition map), and `Arc` for the infrequently
@@ -159,9 +214,18 @@ pub struct Conn {
 
     // TODO: maintain set of change listeners or handles to transaction report queues. #298.
 
-    // TODO: maintain cache of query plans that could be shared across threads and invalidated when
-    // the schema changes. #315.
+    /// Plans for queries run with `q_once`, shared across threads. They're made for the schema in
+    /// `metadata`, so take this lock only while holding that one.
+    query_plans: Mutex<QueryPlanCache>,
+
     pub(crate) tx_observer_service: Mutex<TxObservationService>,
+
+    /// Transaction functions registered by the user.  Each `InProgress` takes a copy when it begins.
+    pub(crate) tx_functions: Mutex<TxFunctions>,
+
+    /// Constraints registered by the user.  Each `InProgress` takes a copy when it begins, and
+    /// checks them when it commits.
+    pub(crate) constraints: Mutex<Constraints>,
 }
 
 pub trait Queryable {
@@ -171,10 +235,113 @@ pub trait Queryable {
         where T: Into<Option<QueryInputs>>;
     fn q_prepare<T>(&self, query: &str, inputs: T) -> PreparedResult
         where T: Into<Option<QueryInputs>>;
+    fn q_stream<T>(&self, query: &str, inputs: T) -> StreamResult
+        where T: Into<Option<QueryInputs>>;
     fn lookup_values_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Vec<TypedValue>>
         where E: Into<Entid>;
     fn lookup_value_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Option<TypedValue>>
         where E: Into<Entid>;
+
+    /// Run an ordered query a page at a time. Each page holds the results that sort after `after`,
+    /// up to the query's `:limit`, and a cursor to pass as `after` to fetch the next page.
+    ///
+    /// The query must find a collection or relation that includes each of its `:order` variables.
+    /// Results that tie with the cursor are skipped, so the ordering should distinguish every
+    /// result: order by the entity last, for example.
+    fn q_page<T>(&self, query: &str, inputs: T, after: Option<QueryCursor>) -> Result<QueryPage>
+        where T: Into<Option<QueryInputs>> {
+        let columns = cursor_columns(query)?;
+        let inputs = inputs.into().unwrap_or_default();
+        let inputs = match after {
+            Some(cursor) => inputs.after(cursor),
+            None => inputs,
+        };
+        let output = self.q_once(query, inputs)?;
+        let cursor = cursor_after(&output.results, &columns);
+        Ok(QueryPage { output, cursor })
+    }
+
+    /// Query the store as it was immediately after the transaction `tx`.
+    fn as_of(&self, tx: Entid) -> HistoricalRead<Self> where Self: Sized {
+        HistoricalRead::new(self, DatabaseView::AsOf(tx))
+    }
+
+    /// Query only the current datoms that were asserted after the transaction `tx`.
+    fn since(&self, tx: Entid) -> HistoricalRead<Self> where Self: Sized {
+        HistoricalRead::new(self, DatabaseView::Since(tx))
+    }
+
+    /// Query every assertion and retraction the store has seen. Patterns can use a fifth place
+    /// to match whether a datom was added: `[?e :foo/bar ?v ?tx ?added]`.
+    fn history(&self) -> HistoricalRead<Self> where Self: Sized {
+        HistoricalRead::new(self, DatabaseView::History)
+    }
+}
+
+/// Queries against some historical view of a `Queryable`. Obtain one with `Queryable::as_of`,
+/// `Queryable::since`, or `Queryable::history`.
+///
+/// The attribute cache only describes the current state of the store, so it isn't used; nor
+/// are pull expressions supported.
+pub struct HistoricalRead<'q, Q: 'q> {
+    queryable: &'q Q,
+    view: DatabaseView,
+}
+
+impl<'q, Q> HistoricalRead<'q, Q> where Q: Queryable {
+    fn new(queryable: &'q Q, view: DatabaseView) -> HistoricalRead<'q, Q> {
+        HistoricalRead {
+            queryable: queryable,
+            view: view,
+        }
+    }
+
+    fn inputs<T>(&self, inputs: T) -> QueryInputs where T: Into<Option<QueryInputs>> {
+        inputs.into()
+              .unwrap_or_default()
+              .with_view(self.view)
+    }
+
+    fn entity_inputs(&self, entity: Entid) -> QueryInputs {
+        QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?e"), TypedValue::Ref(entity))])
+            .with_view(self.view)
+    }
+}
+
+impl<'q, Q> Queryable for HistoricalRead<'q, Q> where Q: Queryable {
+    fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        self.queryable.q_once(query, self.inputs(inputs))
+    }
+
+    fn q_prepare<T>(&self, query: &str, inputs: T) -> PreparedResult
+        where T: Into<Option<QueryInputs>> {
+        self.queryable.q_prepare(query, self.inputs(inputs))
+    }
+
+    fn q_stream<T>(&self, query: &str, inputs: T) -> StreamResult
+        where T: Into<Option<QueryInputs>> {
+        self.queryable.q_stream(query, self.inputs(inputs))
+    }
+
+    fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
+        where T: Into<Option<QueryInputs>> {
+        self.queryable.q_explain(query, self.inputs(inputs))
+    }
+
+    fn lookup_values_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Vec<TypedValue>>
+        where E: Into<Entid> {
+        let query = format!("[:find [?v ...] :in ?e :where [?e {} ?v]]", attribute);
+        let values = self.queryable.q_once(query.as_str(), self.entity_inputs(entity.into()))?.into_coll()?;
+        Ok(values.into_iter().filter_map(|v| v.into_scalar()).collect())
+    }
+
+    fn lookup_value_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Option<TypedValue>>
+        where E: Into<Entid> {
+        let query = format!("[:find ?v . :in ?e :where [?e {} ?v]]", attribute);
+        let value = self.queryable.q_once(query.as_str(), self.entity_inputs(entity.into()))?.into_scalar()?;
+        Ok(value.and_then(|v| v.into_scalar()))
+    }
 }
 
 pub trait Pullable {
@@ -203,6 +370,30 @@ pub struct InProgress<'a, 'c> {
     use_caching: bool,
     tx_observer: &'a Mutex<TxObservationService>,
     tx_observer_watcher: InProgressObserverTransactWatcher,
+    tx_functions: TxFunctions,
+    constraints: Constraints,
+    /// The entities that had datoms asserted or retracted, for checking `constraints` on commit.
+    /// Only tracked if there are constraints to check.
+    touched_entities: BTreeSet<Entid>,
+    /// `[a v]` pairs to assert about the next transaction entity.
+    tx_metadata: Vec<(Entid, TypedValue)>,
+}
+
+/// A savepoint within an `InProgress`, made with `InProgress::savepoint`.  Transact and query
+/// through it just like the `InProgress` itself, including making nested savepoints.
+///
+/// Call `release` to keep the changes made since the savepoint, or `rollback` to discard them,
+/// restoring the in-memory partition map, schema, and attribute cache to their state at the
+/// savepoint.  Dropping the savepoint rolls back.
+pub struct InProgressSavepoint<'s, 'a: 's, 'c: 's> {
+    in_progress: &'s mut InProgress<'a, 'c>,
+    partition_map: PartitionMap,
+    schema: Schema,
+    cache: InProgressSQLiteAttributeCache,
+    tx_observer_watcher: InProgressObserverTransactWatcher,
+    touched_entities: BTreeSet<Entid>,
+    tx_metadata: Vec<(Entid, TypedValue)>,
+    finished: bool,
 }
 
 /// Represents an in-progress set of reads to the store. Just like `InProgress`,
@@ -220,6 +411,11 @@ impl<'a, 'c> Queryable for InProgressRead<'a, 'c> {
         self.0.q_prepare(query, inputs)
     }
 
+    fn q_stream<T>(&self, query: &str, inputs: T) -> StreamResult
+        where T: Into<Option<QueryInputs>> {
+        self.0.q_stream(query, inputs)
+    }
+
     fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
         where T: Into<Option<QueryInputs>> {
         self.0.q_explain(query, inputs)
@@ -249,6 +445,80 @@ impl<'a, 'c> Pullable for InProgressRead<'a, 'c> {
     }
 }
 
+/// The store as it would be after a speculative transaction, made with `Conn::with` or
+/// `Store::with`. Queries and pulls see the transaction's changes, but nothing is committed to the
+/// store: the transaction is made against a private in-memory copy of it, which is discarded when
+/// this is dropped, and observers are never notified of it.
+///
+/// The copy is independent of the store, so other connections can keep reading and writing the
+/// store while this is alive, and this doesn't see their changes.  The attribute cache describes
+/// the store rather than the copy, so queries against this don't use it.
+pub struct SpeculativeDb {
+    sqlite: rusqlite::Connection,
+    partition_map: PartitionMap,
+    schema: Schema,
+    report: TxReport,
+}
+
+impl SpeculativeDb {
+    /// The report of the speculative transaction, including the entids its tempids would have
+    /// been allocated.
+    pub fn report(&self) -> &TxReport {
+        &self.report
+    }
+
+    pub fn last_tx_id(&self) -> Entid {
+        self.partition_map[":db.part/tx"].next_entid() - 1
+    }
+}
+
+impl Queryable for SpeculativeDb {
+    fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        q_uncached(&self.sqlite, &self.schema, query, inputs)
+    }
+
+    fn q_prepare<T>(&self, query: &str, inputs: T) -> PreparedResult
+        where T: Into<Option<QueryInputs>> {
+        q_prepare(&self.sqlite, Known::for_schema(&self.schema), query, inputs)
+    }
+
+    fn q_stream<T>(&self, query: &str, inputs: T) -> StreamResult
+        where T: Into<Option<QueryInputs>> {
+        q_stream(&self.sqlite, Known::for_schema(&self.schema), query, inputs)
+    }
+
+    fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
+        where T: Into<Option<QueryInputs>> {
+        q_explain(&self.sqlite, Known::for_schema(&self.schema), query, inputs)
+    }
+
+    fn lookup_values_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Vec<TypedValue>>
+        where E: Into<Entid> {
+        lookup_values_for_attribute(&self.sqlite, Known::for_schema(&self.schema), entity, attribute)
+    }
+
+    fn lookup_value_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Option<TypedValue>>
+        where E: Into<Entid> {
+        lookup_value_for_attribute(&self.sqlite, Known::for_schema(&self.schema), entity, attribute)
+    }
+}
+
+impl Pullable for SpeculativeDb {
+    fn pull_attributes_for_entities<E, A>(&self, entities: E, attributes: A) -> Result<BTreeMap<Entid, ValueRc<StructuredMap>>>
+    where E: IntoIterator<Item=Entid>,
+          A: IntoIterator<Item=Entid> {
+        pull_attributes_for_entities(&self.schema, &self.sqlite, entities, attributes)
+            .map_err(|e| e.into())
+    }
+
+    fn pull_attributes_for_entity<A>(&self, entity: Entid, attributes: A) -> Result<StructuredMap>
+    where A: IntoIterator<Item=Entid> {
+        pull_attributes_for_entity(&self.schema, &self.sqlite, entity, attributes)
+            .map_err(|e| e.into())
+    }
+}
+
 impl<'a, 'c> Queryable for InProgress<'a, 'c> {
     fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
         where T: Into<Option<QueryInputs>> {
@@ -277,6 +547,16 @@ impl<'a, 'c> Queryable for InProgress<'a, 'c> {
                   inputs)
     }
 
+    fn q_stream<T>(&self, query: &str, inputs: T) -> StreamResult
+        where T: Into<Option<QueryInputs>> {
+
+        let known = Known::new(&self.schema, Some(&self.cache));
+        q_stream(&*(self.transaction),
+                 known,
+                 query,
+                 inputs)
+    }
+
     fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
         where T: Into<Option<QueryInputs>> {
 
@@ -387,6 +667,56 @@ impl<'a, 'c> HasSchema for InProgress<'a, 'c> {
     }
 }
 
+impl<'s, 'a: 's, 'c: 's> InProgressSavepoint<'s, 'a, 'c> {
+    /// Keep the changes made since the savepoint.  They are committed or rolled back along with
+    /// the enclosing `InProgress` or savepoint.
+    pub fn release(mut self) -> Result<()> {
+        self.finished = true;
+        self.in_progress.transaction.execute_batch("RELEASE mentat_in_progress")?;
+        Ok(())
+    }
+
+    /// Discard the changes made since the savepoint.
+    pub fn rollback(mut self) -> Result<()> {
+        self.rollback_to_savepoint()
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        self.finished = true;
+        self.in_progress.transaction.execute_batch("ROLLBACK TO mentat_in_progress; RELEASE mentat_in_progress")?;
+        mem::swap(&mut self.in_progress.partition_map, &mut self.partition_map);
+        mem::swap(&mut self.in_progress.schema, &mut self.schema);
+        mem::swap(&mut self.in_progress.cache, &mut self.cache);
+        mem::swap(&mut self.in_progress.tx_observer_watcher, &mut self.tx_observer_watcher);
+        mem::swap(&mut self.in_progress.touched_entities, &mut self.touched_entities);
+        mem::swap(&mut self.in_progress.tx_metadata, &mut self.tx_metadata);
+        Ok(())
+    }
+}
+
+impl<'s, 'a: 's, 'c: 's> Deref for InProgressSavepoint<'s, 'a, 'c> {
+    type Target = InProgress<'a, 'c>;
+
+    fn deref(&self) -> &InProgress<'a, 'c> {
+        &*self.in_progress
+    }
+}
+
+impl<'s, 'a: 's, 'c: 's> DerefMut for InProgressSavepoint<'s, 'a, 'c> {
+    fn deref_mut(&mut self) -> &mut InProgress<'a, 'c> {
+        &mut *self.in_progress
+    }
+}
+
+impl<'s, 'a: 's, 'c: 's> Drop for InProgressSavepoint<'s, 'a, 'c> {
+    fn drop(&mut self) {
+        if !self.finished {
+            // We can't report failure here; call `rollback` to find out.
+            let _ = self.rollback_to_savepoint();
+        }
+    }
+}
+
 impl<'a, 'c> InProgressRead<'a, 'c> {
     pub fn last_tx_id(&self) -> Entid {
         self.0.last_tx_id()
@@ -412,26 +742,30 @@ impl<'a, 'c> InProgress<'a, 'c> {
                })
     }
 
-    pub fn transact_terms<I>(&mut self, terms: I, tempid_set: InternSet<TempId>) -> Result<TxReport> where I: IntoIterator<Item=TermWithTempIds> {
-        let w = InProgressTransactWatcher::new(
-                &mut self.tx_observer_watcher,
-                self.cache.transact_watcher());
-        let (report, next_partition_map, next_schema, _watcher) =
-            transact_terms(&self.transaction,
-                           self.partition_map.clone(),
-                           &self.schema,
-                           &self.schema,
-                           w,
-                           terms,
-                           tempid_set)?;
-        self.partition_map = next_partition_map;
-        if let Some(schema) = next_schema {
-            self.schema = schema;
-        }
-        Ok(report)
+    /// Assert `[(transaction-tx) attribute value]` in the next transaction made with this
+    /// `InProgress`: for example, to record who made a change, and why.
+    pub fn annotate_tx<V>(&mut self, attribute: &Keyword, value: V) -> Result<()> where V: Into<TypedValue> {
+        let a: Entid = self.schema
+                           .attribute_for_ident(attribute)
+                           .ok_or_else(|| MentatError::UnknownAttribute(attribute.to_string()))?.1.into();
+        self.tx_metadata.push((a, value.into()));
+        Ok(())
     }
 
-    pub fn transact_entities<I, V: TransactableValue>(&mut self, entities: I) -> Result<TxReport> where I: IntoIterator<Item=edn::entities::Entity<V>> {
+    /// Use `instant` as the `:db/txInstant` of the next transaction made with this `InProgress`,
+    /// rather than the current time.  This is for importing historical data: the instant can't be
+    /// earlier than that of the latest transaction in the store, nor later than now.
+    pub fn set_tx_instant(&mut self, instant: DateTime<Utc>) -> Result<()> {
+        self.annotate_tx(&Keyword::namespaced("db", "txInstant"), instant)
+    }
+
+    /// Make a transaction with `f`, which is given the SQLite transaction, the partition map and
+    /// schema to transact against, this `InProgress`'s transaction functions, the watcher that keeps
+    /// its cache, observers, and constraints up to date, and the annotations made with
+    /// `annotate_tx`, which are used up.  The partition map and schema that `f` returns, if any,
+    /// replace this `InProgress`'s own.
+    fn transact_with_watcher<F>(&mut self, f: F) -> Result<TxReport>
+    where F: FnOnce(&rusqlite::Transaction, PartitionMap, &Schema, &TxFunctions, InProgressTransactWatcher, &[(Entid, TypedValue)]) -> ::mentat_db::errors::Result<(TxReport, PartitionMap, Option<Schema>)> {
         // We clone the partition map here, rather than trying to use a Cell or using a mutable
         // reference, for two reasons:
         // 1. `transact` allocates new IDs in partitions before and while doing work that might
@@ -440,16 +774,14 @@ impl<'a, 'c> InProgress<'a, 'c> {
         //    `Metadata` on return. If we used `Cell` or other mechanisms, we'd be using
         //    `Default::default` in those situations to extract the partition map, and so there
         //    would still be some cost.
-        let w = InProgressTransactWatcher::new(
-                &mut self.tx_observer_watcher,
-                self.cache.transact_watcher());
-        let (report, next_partition_map, next_schema, _watcher) =
-            transact(&self.transaction,
-                     self.partition_map.clone(),
-                     &self.schema,
-                     &self.schema,
-                     w,
-                     entities)?;
+        let tx_metadata = ::std::mem::replace(&mut self.tx_metadata, vec![]);
+        let (report, next_partition_map, next_schema) = {
+            let w = InProgressTransactWatcher::new(
+                    &mut self.tx_observer_watcher,
+                    self.cache.transact_watcher(),
+                    if self.constraints.is_empty() { None } else { Some(&mut self.touched_entities) });
+            f(&self.transaction, self.partition_map.clone(), &self.schema, &self.tx_functions, w, &tx_metadata)?
+        };
         self.partition_map = next_partition_map;
         if let Some(schema) = next_schema {
             self.schema = schema;
@@ -457,11 +789,72 @@ impl<'a, 'c> InProgress<'a, 'c> {
         Ok(report)
     }
 
+    pub fn transact_terms<I>(&mut self, terms: I, tempid_set: InternSet<TempId>) -> Result<TxReport> where I: IntoIterator<Item=TermWithTempIds> {
+        self.transact_with_watcher(|conn, partition_map, schema, _tx_functions, w, tx_metadata| {
+            transact_terms(conn, partition_map, schema, schema, w, tx_metadata, terms, tempid_set)
+                .map(|(report, partition_map, schema, _watcher)| (report, partition_map, schema))
+        })
+    }
+
+    pub fn transact_entities<I, V: TransactableValue>(&mut self, entities: I) -> Result<TxReport> where I: IntoIterator<Item=edn::entities::Entity<V>> {
+        self.transact_with_watcher(|conn, partition_map, schema, tx_functions, w, tx_metadata| {
+            let options = TxOptions::new().tx_functions(tx_functions).tx_metadata(tx_metadata);
+            transact_with_options(conn, partition_map, schema, schema, w, options, entities)
+                .map(|(report, partition_map, schema, _watcher)| (report, partition_map, schema))
+        })
+    }
+
     pub fn transact<B>(&mut self, transaction: B) -> Result<TxReport> where B: Borrow<str> {
         let entities = edn::parse::entities(transaction.borrow())?;
         self.transact_entities(entities)
     }
 
+    /// Explain what `transact` would do with `transaction` -- how its tempids and lookup refs
+    /// resolve, which datoms it would assert and retract, and how it would alter the schema --
+    /// without changing anything.  Annotations made with `annotate_tx` are included.
+    pub fn explain_transact<B>(&self, transaction: B) -> Result<TxExplanation> where B: Borrow<str> {
+        let entities = edn::parse::entities(transaction.borrow())?;
+        let explanation = explain_transact(&self.transaction,
+                                           self.partition_map.clone(),
+                                           &self.schema,
+                                           &self.schema,
+                                           TxOptions::new().tx_functions(&self.tx_functions).tx_metadata(&self.tx_metadata),
+                                           entities)?;
+        Ok(explanation)
+    }
+
+    /// Permanently purge the datoms matching `excisions` from the store, including from the
+    /// transaction log.  The excision is itself recorded as a transaction, which carries any
+    /// annotations made with `annotate_tx`.
+    pub fn excise(&mut self, excisions: &[Excision]) -> Result<TxReport> {
+        self.transact_with_watcher(|conn, partition_map, schema, _tx_functions, w, tx_metadata| {
+            excise(conn, partition_map, schema, w, tx_metadata, excisions)
+                .map(|(report, partition_map, _watcher)| (report, partition_map, None))
+        })
+    }
+
+    /// Change the value type of `attribute` to `value_type`, rewriting each of its values with
+    /// `convert`.  If any value can't be converted, nothing changes.
+    pub fn migrate_value_type<F>(&mut self, attribute: &Keyword, value_type: ValueType, convert: F) -> Result<TxReport>
+    where F: Fn(&TypedValue) -> Option<TypedValue> {
+        let a: Entid = self.schema
+                           .attribute_for_ident(attribute)
+                           .ok_or_else(|| MentatError::UnknownAttribute(attribute.to_string()))?.1.into();
+        self.transact_with_watcher(|conn, partition_map, schema, _tx_functions, w, tx_metadata| {
+            migrate_value_type(conn, partition_map, schema, w, tx_metadata, a, value_type, convert)
+                .map(|(report, partition_map, schema, _watcher)| (report, partition_map, Some(schema)))
+        })
+    }
+
+    /// Transact the inverse of the transaction `tx`: retract what it asserted and reassert what it
+    /// retracted.  Fails, changing nothing, if a later transaction changed any of the same datoms.
+    pub fn revert_tx(&mut self, tx: Entid) -> Result<TxReport> {
+        self.transact_with_watcher(|conn, partition_map, schema, _tx_functions, w, tx_metadata| {
+            revert_tx(conn, partition_map, schema, schema, w, tx_metadata, tx)
+                .map(|(report, partition_map, schema, _watcher)| (report, partition_map, schema))
+        })
+    }
+
     pub fn import<P>(&mut self, path: P) -> Result<TxReport>
     where P: AsRef<Path> {
         let mut file = File::open(path)?;
@@ -470,11 +863,90 @@ impl<'a, 'c> InProgress<'a, 'c> {
         self.transact(text.as_str())
     }
 
+    /// Import the EDN transactions in the file at `path` with `import_stream`.
+    pub fn import_bulk<P>(&mut self, path: P, batch_size: usize) -> Result<BulkImportReport>
+    where P: AsRef<Path> {
+        let file = File::open(path)?;
+        self.import_stream(BufReader::new(file), batch_size)
+    }
+
+    /// Import the entities of the EDN transactions read from `reader` without reading them all into
+    /// memory first.  The entities are transacted in batches of up to `batch_size` entities, each
+    /// batch in a transaction of its own; a tempid in one batch refers to the same entity as in
+    /// earlier batches.
+    ///
+    /// A batch that only asserts datoms about new entities, as is usual for an import, is written
+    /// straight to the store without searching for existing datoms.  Any annotations made with
+    /// `annotate_tx` are asserted about the first batch's transaction.
+    pub fn import_stream<R>(&mut self, reader: R, batch_size: usize) -> Result<BulkImportReport>
+    where R: BufRead {
+        if batch_size == 0 {
+            bail!(MentatError::InvalidImport("batch size must be positive".into()));
+        }
+
+        let mut report = BulkImportReport::default();
+        let mut entities = EntityStream::new(reader);
+        loop {
+            let batch: Vec<Entity<ValueAndSpan>> = entities.by_ref().take(batch_size).collect::<Result<_>>()?;
+            if batch.is_empty() {
+                break;
+            }
+            let count = batch.len();
+
+            let tx_report = {
+                let known_temp_ids = &report.tempids;
+                self.transact_with_watcher(|conn, partition_map, schema, tx_functions, w, tx_metadata| {
+                    let options = TxOptions::new()
+                        .tx_functions(tx_functions)
+                        .tx_metadata(tx_metadata)
+                        .known_temp_ids(known_temp_ids);
+                    transact_with_options(conn, partition_map, schema, schema, w, options, batch)
+                        .map(|(report, partition_map, schema, _watcher)| (report, partition_map, schema))
+                })?
+            };
+
+            report.entities += count;
+            report.transactions += 1;
+            report.last_tx_id = Some(tx_report.tx_id);
+            report.tempids.extend(tx_report.tempids);
+        }
+        Ok(report)
+    }
+
+    /// Mark a savepoint in this `InProgress`.  Changes made through the returned
+    /// `InProgressSavepoint` can be rolled back without abandoning the rest of the `InProgress`.
+    pub fn savepoint<'s>(&'s mut self) -> Result<InProgressSavepoint<'s, 'a, 'c>> {
+        self.transaction.execute_batch("SAVEPOINT mentat_in_progress")?;
+        Ok(InProgressSavepoint {
+            partition_map: self.partition_map.clone(),
+            schema: self.schema.clone(),
+            cache: self.cache.clone(),
+            tx_observer_watcher: self.tx_observer_watcher.clone(),
+            touched_entities: self.touched_entities.clone(),
+            tx_metadata: self.tx_metadata.clone(),
+            in_progress: self,
+            finished: false,
+        })
+    }
+
     pub fn rollback(self) -> Result<()> {
         self.transaction.rollback().map_err(|e| e.into())
     }
 
+    /// Check the registered constraints against the entities touched by this `InProgress`.
+    /// `commit` does this too, failing with `MentatError::ValidationFailed` if any are broken.
+    pub fn validate(&self) -> Result<Vec<ValidationError>> {
+        self.constraints.check(self, &self.touched_entities)
+    }
+
     pub fn commit(self) -> Result<()> {
+        // Constraints may run queries, so check them before taking the mutex.  Failing here
+        // drops, and so rolls back, the transaction.
+        let errors = self.validate()?;
+        if !errors.is_empty() {
+            bail!(MentatError::ValidationFailed(ValidationErrors(errors)));
+        }
+
         // The mutex is taken during this entire method.
         let mut metadata = self.mutex.lock().unwrap();
 
@@ -540,14 +1012,18 @@ impl<'a, 'c> InProgress<'a, 'c> {
 struct InProgressTransactWatcher<'a, 'o> {
     cache_watcher: InProgressCacheTransactWatcher<'a>,
     observer_watcher: &'o mut InProgressObserverTransactWatcher,
+    touched_entities: Option<&'o mut BTreeSet<Entid>>,
     tx_id: Option<Entid>,
 }
 
 impl<'a, 'o> InProgressTransactWatcher<'a, 'o> {
-    fn new(observer_watcher: &'o mut InProgressObserverTransactWatcher, cache_watcher: InProgressCacheTransactWatcher<'a>) -> Self {
+    fn new(observer_watcher: &'o mut InProgressObserverTransactWatcher,
+           cache_watcher: InProgressCacheTransactWatcher<'a>,
+           touched_entities: Option<&'o mut BTreeSet<Entid>>) -> Self {
         InProgressTransactWatcher {
             cache_watcher: cache_watcher,
             observer_watcher: observer_watcher,
+            touched_entities: touched_entities,
             tx_id: None,
         }
     }
@@ -557,11 +1033,18 @@ impl<'a, 'o> TransactWatcher for InProgressTransactWatcher<'a, 'o> {
     fn datom(&mut self, op: OpType, e: Entid, a: Entid, v: &TypedValue) {
         self.cache_watcher.datom(op.clone(), e.clone(), a.clone(), v);
         self.observer_watcher.datom(op.clone(), e.clone(), a.clone(), v);
+        if let Some(ref mut touched_entities) = self.touched_entities {
+            touched_entities.insert(e);
+        }
     }
 
     fn done(&mut self, t: &Entid, schema: &Schema) -> ::mentat_db::errors::Result<()> {
         self.cache_watcher.done(t, schema)?;
         self.observer_watcher.done(t, schema)?;
+        // The transaction entity isn't something constraints are about.
+        if let Some(ref mut touched_entities) = self.touched_entities {
+            touched_entities.remove(t);
+        }
         self.tx_id = Some(t.clone());
         Ok(())
     }
@@ -585,7 +1068,10 @@ impl Conn {
     fn new(partition_map: PartitionMap, schema: Schema) -> Conn {
         Conn {
             metadata: Mutex::new(Metadata::new(0, partition_map, Arc::new(schema), Default::default())),
+            query_plans: Mutex::new(Default::default()),
             tx_observer_service: Mutex::new(TxObservationService::new()),
+            tx_functions: Mutex::new(TxFunctions::default()),
+            constraints: Mutex::new(Constraints::default()),
         }
     }
 
@@ -642,6 +1128,14 @@ impl Conn {
 
         // Doesn't clone, unlike `current_schema`.
         let metadata = self.metadata.lock().unwrap();
+        let inputs = inputs.into().unwrap_or(QueryInputs::default());
+
+        // Plans don't know about cached attributes, nor about inputs that change how a query is
+        // planned, so only plain queries against uncached attributes use them.
+        if !metadata.attribute_cache.has_cached_attributes() && inputs.is_plain() {
+            return q_planned(&self.query_plans, sqlite, &metadata.schema, query, inputs);
+        }
+
         let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache));
         q_once(sqlite,
                known,
@@ -649,6 +1143,11 @@ impl Conn {
                inputs)
     }
 
+    /// How often `q_once` has reused a cached query plan.
+    pub fn query_plan_cache_stats(&self) -> QueryPlanCacheStats {
+        self.query_plans.lock().unwrap().stats()
+    }
+
     /// Query the Mentat store, using the given connection and the current metadata,
     /// but without using the cache.
     pub fn q_uncached<T>(&self,
@@ -678,6 +1177,20 @@ impl Conn {
                   inputs)
     }
 
+    pub fn q_stream<'sqlite, 'query, T>(&self,
+                        sqlite: &'sqlite rusqlite::Connection,
+                        query: &'query str,
+                        inputs: T) -> StreamResult<'sqlite>
+        where T: Into<Option<QueryInputs>> {
+
+        let metadata = self.metadata.lock().unwrap();
+        let known = Known::new(&*metadata.schema, Some(&metadata.attribute_cache));
+        q_stream(sqlite,
+                 known,
+                 query,
+                 inputs)
+    }
+
     pub fn q_explain<T>(&self,
                         sqlite: &rusqlite::Connection,
                         query: &str,
@@ -758,6 +1271,10 @@ impl Conn {
             use_caching: true,
             tx_observer: &self.tx_observer_service,
             tx_observer_watcher: InProgressObserverTransactWatcher::new(),
+            tx_functions: self.tx_functions.lock().unwrap().clone(),
+            constraints: self.constraints.lock().unwrap().clone(),
+            touched_entities: BTreeSet::new(),
+            tx_metadata: vec![],
         })
     }
 
@@ -784,6 +1301,47 @@ impl Conn {
         self.begin_transaction_with_behavior(sqlite, TransactionBehavior::Immediate)
     }
 
+    /// Transact entities speculatively, returning a view of the store as if the transaction had
+    /// been committed.  Neither the store nor this `Conn`'s metadata is changed.
+    ///
+    /// The transaction is made against an in-memory copy of the store, using this `Conn`'s
+    /// transaction functions and constraints, so no lock on the store is held once this returns.
+    /// Copying takes time and memory in proportion to the size of the store.
+    pub fn with<B>(&mut self,
+                   sqlite: &rusqlite::Connection,
+                   transaction: B) -> Result<SpeculativeDb> where B: Borrow<str> {
+        let mut copy = db::new_connection("")?;
+        {
+            // Pages can only be copied into an in-memory database of the same page size.
+            let page_size: i64 = sqlite.query_row("PRAGMA page_size", &[], |row| row.get(0))?;
+            copy.execute_batch(&format!("PRAGMA page_size={}", page_size))?;
+            let backup = Backup::new(sqlite, &mut copy)?;
+            backup.run_to_completion(1024, Duration::from_millis(0), None)?;
+        }
+
+        let (report, partition_map, schema) = {
+            let mut in_progress = self.begin_transaction_with_behavior(&mut copy, TransactionBehavior::Immediate)?;
+            let report = in_progress.transact(transaction)?;
+
+            // Like `InProgress::commit`, but without touching this `Conn`'s metadata or notifying
+            // observers.
+            let errors = in_progress.validate()?;
+            if !errors.is_empty() {
+                bail!(MentatError::ValidationFailed(ValidationErrors(errors)));
+            }
+            let InProgress { transaction, partition_map, schema, .. } = in_progress;
+            transaction.commit()?;
+            (report, partition_map, schema)
+        };
+
+        Ok(SpeculativeDb {
+            sqlite: copy,
+            partition_map: partition_map,
+            schema: schema,
+            report: report,
+        })
+    }
+
     /// Transact entities against the Mentat store, using the given connection and the current
     /// metadata.
     pub fn transact<B>(&mut self,
@@ -846,6 +1404,28 @@ impl Conn {
     pub fn unregister_observer(&mut self, key: &String) {
         self.tx_observer_service.lock().unwrap().deregister(key);
     }
+
+    /// Register `function` as the transaction function named `name`.  Transactions begun after
+    /// this call can call it like `(counter/increment)` in entity or value position.
+    pub fn register_tx_function<F>(&mut self, name: Keyword, function: F)
+    where F: Fn(&TxFunctionContext) -> ::mentat_db::Result<TxFunctionOutput> + 'static + Send + Sync {
+        self.tx_functions.lock().unwrap().register(name, function);
+    }
+
+    pub fn unregister_tx_function(&mut self, name: &Keyword) {
+        self.tx_functions.lock().unwrap().deregister(name);
+    }
+
+    /// Register `constraint` under `name`.  Transactions begun after this call check it against
+    /// the entities they touch when they commit, and fail to commit if it reports any errors.
+    pub fn register_constraint<F>(&mut self, name: Keyword, constraint: F)
+    where F: Fn(&ConstraintContext) -> Result<Vec<ValidationError>> + 'static + Send + Sync {
+        self.constraints.lock().unwrap().register(name, constraint);
+    }
+
+    pub fn unregister_constraint(&mut self, name: &Keyword) {
+        self.constraints.lock().unwrap().deregister(name);
+    }
 }
 
 #[cfg(test)]
@@ -854,6 +1434,7 @@ mod tests {
 
     extern crate time;
 
+    use std::str::FromStr;
     use std::time::{
         Instant,
     };
@@ -874,7 +1455,10 @@ mod tests {
         QueryResults,
     };
 
-    use mentat_db::USER0;
+    use mentat_db::{
+        TempIdResolution,
+        USER0,
+    };
 
     #[test]
     fn test_transact_does_not_collide_existing_entids() {
@@ -1109,6 +1693,241 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_annotate_tx() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+
+        // Import a schema and some data as of 2017.
+        let schema_instant = DateTime::<Utc>::from_str("2017-01-01T00:00:00.000Z").expect("parsed");
+        in_progress.set_tx_instant(schema_instant).expect("set");
+        let report = in_progress.transact(r#"[
+            {:db/ident :audit/user :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).expect("transacted schema");
+        assert_eq!(report.tx_instant, schema_instant);
+
+        let data_instant = DateTime::<Utc>::from_str("2017-06-16T00:56:41.257Z").expect("parsed");
+        in_progress.set_tx_instant(data_instant).expect("set");
+        in_progress.annotate_tx(&kw!(:audit/user), "ivan").expect("annotated");
+        let report = in_progress.transact(r#"[[:db/add "x" :foo/name "Ivan"]]"#).expect("transacted data");
+        assert_eq!(report.tx_instant, data_instant);
+
+        let user = in_progress.q_once(format!("[:find ?user . :where [{} :audit/user ?user]]", report.tx_id).as_str(), None)
+                              .expect("query succeeded");
+        assert_eq!(user.results, QueryResults::Scalar(Some(TypedValue::typed_string("ivan").into())));
+
+        // Annotations only apply to the next transaction.
+        let report = in_progress.transact(r#"[[:db/add "y" :foo/name "Petr"]]"#).expect("transacted data");
+        assert!(report.tx_instant > data_instant);
+        let user = in_progress.q_once(format!("[:find ?user . :where [{} :audit/user ?user]]", report.tx_id).as_str(), None)
+                              .expect("query succeeded");
+        assert_eq!(user.results, QueryResults::Scalar(None));
+
+        // Now that the store has caught up with the present, we can't go back.
+        in_progress.set_tx_instant(data_instant).expect("set");
+        match in_progress.transact(r#"[[:db/add "z" :foo/name "Vlad"]]"#).expect_err("expected transact error") {
+            MentatError::DbError(e) => {
+                match e.kind() {
+                    ::mentat_db::DbErrorKind::NonMonotonicTxInstant { .. } => {},
+                    x => panic!("expected NonMonotonicTxInstant, got {:?}", x),
+                }
+            },
+            x => panic!("expected db error, got {:?}", x),
+        }
+
+        match in_progress.annotate_tx(&kw!(:audit/nope), "ivan").expect_err("expected annotate error") {
+            MentatError::UnknownAttribute(msg) => assert_eq!(msg, ":audit/nope"),
+            x => panic!("expected UnknownAttribute error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_excise() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        conn.transact(&mut sqlite, r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).unwrap();
+        let report = conn.transact(&mut sqlite, r#"[{:db/id "ivan" :foo/name "Ivan"}]"#).unwrap();
+        let ivan = report.tempids["ivan"];
+        let foo_name = conn.current_schema().get_entid(&kw!(:foo/name)).expect("foo/name").0;
+
+        {
+            let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            in_progress.cache(&kw!(:foo/name), CacheDirection::Forward, CacheAction::Register).expect("registered");
+            assert_eq!(in_progress.cache.get_value_for_entid(&in_progress.schema, foo_name, ivan),
+                       Some(&TypedValue::typed_string("Ivan")));
+
+            in_progress.excise(&[Excision::entity(ivan)]).expect("excised");
+            assert_eq!(in_progress.cache.get_value_for_entid(&in_progress.schema, foo_name, ivan), None);
+
+            let name = in_progress.q_once("[:find ?name . :where [_ :foo/name ?name]]", None).expect("query succeeded");
+            assert_eq!(name.results, QueryResults::Scalar(None));
+            in_progress.commit().expect("committed");
+        }
+
+        let schema = conn.current_schema();
+        assert_eq!(conn.current_cache().get_value_for_entid(&schema, foo_name, ivan), None);
+    }
+
+    #[test]
+    fn test_migrate_value_type() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        conn.transact(&mut sqlite, r#"[
+            {:db/ident :foo/rating :db/valueType :db.type/long :db/cardinality :db.cardinality/one}]"#).unwrap();
+        let report = conn.transact(&mut sqlite, r#"[{:db/id "ivan" :foo/rating 4}]"#).unwrap();
+        let ivan = report.tempids["ivan"];
+        let foo_rating = conn.current_schema().get_entid(&kw!(:foo/rating)).expect("foo/rating").0;
+
+        {
+            let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            in_progress.cache(&kw!(:foo/rating), CacheDirection::Forward, CacheAction::Register).expect("registered");
+
+            in_progress.migrate_value_type(&kw!(:foo/rating), ValueType::Double, |v| match v {
+                &TypedValue::Long(x) => Some((x as f64).into()),
+                _ => None,
+            }).expect("migrated");
+            assert_eq!(in_progress.cache.get_value_for_entid(&in_progress.schema, foo_rating, ivan),
+                       Some(&TypedValue::from(4.0)));
+
+            let rating = in_progress.q_once("[:find ?rating . :where [_ :foo/rating ?rating]]", None).expect("query succeeded");
+            assert_eq!(rating.results, QueryResults::Scalar(Some(TypedValue::from(4.0).into())));
+            in_progress.commit().expect("committed");
+        }
+
+        assert_eq!(conn.current_schema().attribute_for_entid(foo_rating).expect("attribute").value_type, ValueType::Double);
+        match conn.begin_transaction(&mut sqlite).expect("begun successfully")
+                  .migrate_value_type(&kw!(:foo/nope), ValueType::Long, |v| Some(v.clone())).expect_err("expected migrate error") {
+            MentatError::UnknownAttribute(msg) => assert_eq!(msg, ":foo/nope"),
+            x => panic!("expected UnknownAttribute error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_revert_tx() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        conn.transact(&mut sqlite, r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).unwrap();
+        let report = conn.transact(&mut sqlite, r#"[{:db/id "ivan" :foo/name "Ivan"}]"#).unwrap();
+        let ivan = report.tempids["ivan"];
+        let renamed = conn.transact(&mut sqlite, format!(r#"[[:db/add {} :foo/name "Petr"]]"#, ivan).as_str()).unwrap().tx_id;
+        let foo_name = conn.current_schema().get_entid(&kw!(:foo/name)).expect("foo/name").0;
+
+        {
+            let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            in_progress.cache(&kw!(:foo/name), CacheDirection::Forward, CacheAction::Register).expect("registered");
+
+            match in_progress.revert_tx(report.tx_id).expect_err("expected revert error") {
+                MentatError::DbError(e) => assert_eq!(e.to_string(),
+                                                      format!("cannot revert transaction {}: transaction {} later changed [{} {} String(\"Ivan\")]",
+                                                              report.tx_id, renamed, ivan, foo_name)),
+                x => panic!("expected DbError, got {:?}", x),
+            }
+
+            in_progress.revert_tx(renamed).expect("reverted");
+            assert_eq!(in_progress.cache.get_value_for_entid(&in_progress.schema, foo_name, ivan),
+                       Some(&TypedValue::typed_string("Ivan")));
+            in_progress.commit().expect("committed");
+        }
+
+        assert_eq!(conn.lookup_value_for_attribute(&sqlite, ivan, &kw!(:foo/name)).expect("looked up"),
+                   Some(TypedValue::typed_string("Ivan")));
+    }
+
+    #[test]
+    fn test_explain_transact() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        conn.transact(&mut sqlite, r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).unwrap();
+        let ivan = conn.transact(&mut sqlite, r#"[{:db/id "ivan" :foo/name "Ivan"}]"#).unwrap().tempids["ivan"];
+        let foo_name = conn.current_schema().get_entid(&kw!(:foo/name)).expect("foo/name").0;
+
+        let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+        let last_tx_id = in_progress.last_tx_id();
+        let transaction = format!(r#"[[:db/add {} :foo/name "Petr"] {{:db/id "x" :foo/name "X"}}]"#, ivan);
+
+        let explanation = in_progress.explain_transact(transaction.as_str()).expect("explained");
+        let x = match explanation.tempids["x"] {
+            TempIdResolution::Allocated(x) => x,
+            r => panic!("expected x to be allocated, got {:?}", r),
+        };
+        assert_eq!(explanation.tx_id, last_tx_id + 1);
+        assert!(explanation.asserted.contains(&(ivan, foo_name, TypedValue::typed_string("Petr"))));
+        assert!(explanation.asserted.contains(&(x, foo_name, TypedValue::typed_string("X"))));
+        assert_eq!(explanation.retracted, vec![(ivan, foo_name, TypedValue::typed_string("Ivan"))]);
+        assert!(explanation.unchanged.is_empty());
+        assert_eq!(explanation.schema_alterations, None);
+
+        // Explaining changed nothing, so transacting does just what was explained.
+        assert_eq!(in_progress.last_tx_id(), last_tx_id);
+        assert_eq!(in_progress.lookup_value_for_attribute(ivan, &kw!(:foo/name)).expect("looked up"),
+                   Some(TypedValue::typed_string("Ivan")));
+        let report = in_progress.transact(transaction.as_str()).expect("transacted");
+        assert_eq!(report.tx_id, explanation.tx_id);
+        assert_eq!(report.tempids["x"], x);
+    }
+
+    #[test]
+    fn test_savepoint() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        conn.transact(&mut sqlite, r#"[
+            {:db/ident :foo/count :db/valueType :db.type/long :db/cardinality :db.cardinality/one}]"#).unwrap();
+        let foo_count = conn.current_schema().get_entid(&kw!(:foo/count)).expect("foo/count").0;
+        let count_query = "[:find (count ?e) . :where [?e :foo/count _]]";
+
+        {
+            let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            in_progress.transact(r#"[{:foo/count 1}]"#).expect("transacted");
+            let tx_id = in_progress.last_tx_id();
+
+            {
+                let mut savepoint = in_progress.savepoint().expect("savepoint");
+                savepoint.transact(r#"[{:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+                                       {:foo/count 2}]"#).expect("transacted");
+                savepoint.cache(&kw!(:foo/count), CacheDirection::Forward, CacheAction::Register).expect("registered");
+
+                // Nested savepoints can be released into their enclosing savepoint.
+                {
+                    let mut nested = savepoint.savepoint().expect("nested savepoint");
+                    nested.transact(r#"[{:foo/name "x" :foo/count 3}]"#).expect("transacted");
+                    nested.release().expect("released");
+                }
+                assert_eq!(savepoint.q_once(count_query, None).expect("query").results,
+                           QueryResults::Scalar(Some(TypedValue::Long(3).into())));
+                assert_eq!(savepoint.last_tx_id(), tx_id + 2);
+
+                savepoint.rollback().expect("rolled back");
+            }
+
+            // Everything since the savepoint is undone, in SQLite and in memory.
+            assert_eq!(in_progress.last_tx_id(), tx_id);
+            assert_eq!(in_progress.q_once(count_query, None).expect("query").results,
+                       QueryResults::Scalar(Some(TypedValue::Long(1).into())));
+            assert!(in_progress.schema.get_entid(&kw!(:foo/name)).is_none());
+            assert!(!in_progress.cache.is_attribute_cached_forward(foo_count));
+
+            // Dropping a savepoint rolls it back, too.
+            {
+                let mut savepoint = in_progress.savepoint().expect("savepoint");
+                savepoint.transact(r#"[{:foo/count 4}]"#).expect("transacted");
+            }
+            assert_eq!(in_progress.last_tx_id(), tx_id);
+
+            // The enclosing transaction carries on regardless.
+            let report = in_progress.transact(r#"[{:foo/count 5}]"#).expect("transacted");
+            assert_eq!(report.tx_id, tx_id + 1);
+            in_progress.commit().expect("committed");
+        }
+
+        assert_eq!(conn.q_once(&sqlite, count_query, None).expect("query").results,
+                   QueryResults::Scalar(Some(TypedValue::Long(2).into())));
+    }
+
     #[test]
     fn test_add_to_cache_failure_no_attribute() {
         let mut sqlite = db::new_connection("").unwrap();
@@ -105,6 +105,11 @@ pub trait BuildTerms where Self: Sized {
     where E: Into<EntityPlace<TypedValue>>,
           A: Into<AttributePlace>,
           V: Into<ValuePlace<TypedValue>>;
+    fn retract_entity<E>(&mut self, e: E) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>>;
+    fn retract_attribute<E, A>(&mut self, e: E, a: A) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>>,
+          A: Into<AttributePlace>;
 }
 
 impl BuildTerms for TermBuilder {
@@ -139,6 +144,19 @@ impl BuildTerms for TermBuilder {
         self.terms.push(Entity::AddOrRetract { op: OpType::Retract, e: e.into(), a: a.into(), v: v.into() });
         Ok(())
     }
+
+    fn retract_entity<E>(&mut self, e: E) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>> {
+        self.terms.push(Entity::RetractEntity { e: e.into() });
+        Ok(())
+    }
+
+    fn retract_attribute<E, A>(&mut self, e: E, a: A) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>>,
+          A: Into<AttributePlace> {
+        self.terms.push(Entity::RetractAttribute { e: e.into(), a: a.into() });
+        Ok(())
+    }
 }
 
 impl TermBuilder {
@@ -189,6 +207,15 @@ impl<T> EntityBuilder<T> where T: BuildTerms {
           V: Into<ValuePlace<TypedValue>> {
         self.builder.retract(self.entity.clone(), a, v)
     }
+
+    pub fn retract_entity(&mut self) -> Result<()> {
+        self.builder.retract_entity(self.entity.clone())
+    }
+
+    pub fn retract_attribute<A>(&mut self, a: A) -> Result<()>
+    where A: Into<AttributePlace> {
+        self.builder.retract_attribute(self.entity.clone(), a)
+    }
 }
 
 pub struct InProgressBuilder<'a, 'c> {
@@ -259,6 +286,17 @@ impl<'a, 'c> BuildTerms for InProgressBuilder<'a, 'c> {
           V: Into<ValuePlace<TypedValue>> {
         self.builder.retract(e, a, v)
     }
+
+    fn retract_entity<E>(&mut self, e: E) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>> {
+        self.builder.retract_entity(e)
+    }
+
+    fn retract_attribute<E, A>(&mut self, e: E, a: A) -> Result<()>
+    where E: Into<EntityPlace<TypedValue>>,
+          A: Into<AttributePlace> {
+        self.builder.retract_attribute(e, a)
+    }
 }
 
 impl<'a, 'c> EntityBuilder<InProgressBuilder<'a, 'c>> {
@@ -428,4 +466,52 @@ mod testing {
         assert_eq!(conn.lookup_value_for_attribute(&mut sqlite, *y, &foo_ref).expect("lookup succeeded"),
                    Some(TypedValue::Ref(*x)));
     }
+
+    #[test]
+    fn test_entity_builder_retractions() {
+        let mut sqlite = mentat_db::db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        let foo_one = kw!(:foo/one);
+        let foo_ref = kw!(:foo/ref);
+
+        conn.transact(&mut sqlite, r#"[
+            [:db/add "o" :db/ident :foo/one]
+            [:db/add "o" :db/valueType :db.type/long]
+            [:db/add "o" :db/cardinality :db.cardinality/one]
+            [:db/add "r" :db/ident :foo/ref]
+            [:db/add "r" :db/valueType :db.type/ref]
+            [:db/add "r" :db/cardinality :db.cardinality/one]
+        ]"#).unwrap();
+
+        let report = conn.transact(&mut sqlite, r#"[
+            [:db/add "x" :foo/one 1]
+            [:db/add "y" :foo/one 2]
+            [:db/add "y" :foo/ref "x"]
+        ]"#).unwrap();
+        let x = *report.tempids.get("x").expect("our tempid has an ID");
+        let y = *report.tempids.get("y").expect("our tempid has an ID");
+
+        {
+            let in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            let mut builder = in_progress.builder().describe(y);
+            builder.retract_attribute(foo_one.clone()).expect("retract_attribute succeeded");
+            builder.commit().expect("commit succeeded");
+        }
+
+        assert_eq!(conn.lookup_value_for_attribute(&mut sqlite, y, &foo_one).expect("lookup succeeded"), None);
+        assert_eq!(conn.lookup_value_for_attribute(&mut sqlite, y, &foo_ref).expect("lookup succeeded"),
+                   Some(TypedValue::Ref(x)));
+
+        {
+            let in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            let mut builder = in_progress.builder();
+            builder.retract_entity(x).expect("retract_entity succeeded");
+            builder.commit().expect("commit succeeded");
+        }
+
+        // Retracting `x` also retracted the reference to it.
+        assert_eq!(conn.lookup_value_for_attribute(&mut sqlite, x, &foo_one).expect("lookup succeeded"), None);
+        assert_eq!(conn.lookup_value_for_attribute(&mut sqlite, y, &foo_ref).expect("lookup succeeded"), None);
+    }
 }
@@ -80,9 +80,27 @@ pub enum MentatError {
     #[fail(display = "schema changed since query was prepared")]
     PreparedQuerySchemaMismatch,
 
+    #[fail(display = "can't page query: {}", _0)]
+    UnpageableQuery(String),
+
+    #[fail(display = "can't stream query: {}", _0)]
+    UnstreamableQuery(String),
+
     #[fail(display = "provided value of type {} doesn't match attribute value type {}", _0, _1)]
     ValueTypeMismatch(ValueType, ValueType),
 
+    #[fail(display = "invalid database name: '{}'", _0)]
+    InvalidDatabaseName(String),
+
+    #[fail(display = "attached store {} doesn't share this store's schema", _0)]
+    IncompatibleAttachedStore(String),
+
+    #[fail(display = "validation failed: {}", _0)]
+    ValidationFailed(::validation::ValidationErrors),
+
+    #[fail(display = "invalid import: {}", _0)]
+    InvalidImport(String),
+
     #[fail(display = "{}", _0)]
     IoError(#[cause] std::io::Error),
 
@@ -0,0 +1,253 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Streaming bulk import.
+//!
+//! `InProgress::import` reads a whole EDN file into memory and transacts it at once, which doesn't
+//! scale to large data sets.  An `EntityStream` instead reads the EDN transactions in its input one
+//! entity at a time, so that `InProgress::import_stream` can transact them in batches.  Tempids are
+//! shared between batches, so an entity can refer to a tempid that was allocated in an earlier
+//! batch.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use std::io::{
+    BufRead,
+};
+
+use edn;
+use edn::{
+    ValueAndSpan,
+};
+use edn::entities::{
+    Entity,
+};
+
+use mentat_core::{
+    Entid,
+};
+
+use errors::{
+    MentatError,
+    Result,
+};
+
+/// The outcome of a bulk import.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BulkImportReport {
+    /// The number of entities imported.
+    pub entities: usize,
+
+    /// The number of transactions the entities were imported in.
+    pub transactions: usize,
+
+    /// The ID of the last of those transactions, if there were any.
+    pub last_tx_id: Option<Entid>,
+
+    /// A map from string literal tempid to resolved or allocated entid, across every transaction.
+    pub tempids: BTreeMap<String, Entid>,
+}
+
+/// The entities of a sequence of EDN transactions, like `[{:db/id "a" ...} [:db/add ...]]`, read
+/// and parsed one at a time.
+///
+/// The input is scanned only for the brackets, strings, and comments that delimit each entity;
+/// the entity itself is parsed with `edn::parse::entity`.
+pub struct EntityStream<R> {
+    reader: R,
+
+    /// Whether the current byte is inside the vector of a transaction.
+    in_transaction: bool,
+
+    /// Set once the input is exhausted or malformed.
+    done: bool,
+}
+
+impl<R> EntityStream<R> where R: BufRead {
+    pub fn new(reader: R) -> EntityStream<R> {
+        EntityStream {
+            reader: reader,
+            in_transaction: false,
+            done: false,
+        }
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        let byte = {
+            let buf = self.reader.fill_buf()?;
+            match buf.first() {
+                Some(&byte) => byte,
+                None => return Ok(None),
+            }
+        };
+        self.reader.consume(1);
+        Ok(Some(byte))
+    }
+
+    /// Skip to the end of the current line, for a `;` comment.
+    fn skip_comment(&mut self) -> Result<()> {
+        while let Some(byte) = self.next_byte()? {
+            if byte == b'\n' {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The next byte that isn't whitespace, a comma, or in a comment.
+    fn next_significant_byte(&mut self) -> Result<Option<u8>> {
+        while let Some(byte) = self.next_byte()? {
+            match byte {
+                b' ' | b'\t' | b'\r' | b'\n' | b',' => {},
+                b';' => self.skip_comment()?,
+                _ => return Ok(Some(byte)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read the rest of the form opened by `open`, returning its text.
+    fn read_form(&mut self, open: u8) -> Result<String> {
+        let mut text = vec![open];
+        let mut depth = 1;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        while depth > 0 {
+            let byte = match self.next_byte()? {
+                Some(byte) => byte,
+                None => bail!(MentatError::InvalidImport("unexpected end of input inside an entity".into())),
+            };
+
+            if in_string {
+                text.push(byte);
+                if escaped {
+                    escaped = false;
+                } else if byte == b'\\' {
+                    escaped = true;
+                } else if byte == b'"' {
+                    in_string = false;
+                }
+                continue;
+            }
+
+            match byte {
+                b';' => {
+                    // The parser doesn't need the comment, but it does need the line to end.
+                    self.skip_comment()?;
+                    text.push(b'\n');
+                    continue;
+                },
+                b'"' => in_string = true,
+                b'[' | b'(' | b'{' => depth += 1,
+                b']' | b')' | b'}' => depth -= 1,
+                _ => {},
+            }
+            text.push(byte);
+        }
+
+        String::from_utf8(text).map_err(|_| MentatError::InvalidImport("entity is not valid UTF-8".into()))
+    }
+
+    fn read_entity(&mut self) -> Result<Option<Entity<ValueAndSpan>>> {
+        loop {
+            let byte = match self.next_significant_byte()? {
+                Some(byte) => byte,
+                None if self.in_transaction => bail!(MentatError::InvalidImport("unexpected end of input inside a transaction".into())),
+                None => return Ok(None),
+            };
+
+            match (self.in_transaction, byte) {
+                (false, b'[') => self.in_transaction = true,
+                (false, _) => bail!(MentatError::InvalidImport(format!("expected a transaction, found '{}'", byte as char))),
+                (true, b']') => self.in_transaction = false,
+                (true, b'[') | (true, b'{') => {
+                    let text = self.read_form(byte)?;
+                    return Ok(Some(edn::parse::entity(text.as_str())?));
+                },
+                (true, _) => bail!(MentatError::InvalidImport(format!("expected an entity, found '{}'", byte as char))),
+            }
+        }
+    }
+}
+
+impl<R> Iterator for EntityStream<R> where R: BufRead {
+    type Item = Result<Entity<ValueAndSpan>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_entity() {
+            Ok(Some(entity)) => Some(Ok(entity)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entities(input: &str) -> Result<Vec<Entity<ValueAndSpan>>> {
+        EntityStream::new(input.as_bytes()).collect()
+    }
+
+    #[test]
+    fn test_entity_stream() {
+        let input = r#"
+            ; Two transactions.
+            [{:db/id "a" :foo/name "[not a vector" :foo/note "\"quoted\" ; not a comment"}
+             [:db/add "a" :foo/ref "b"] ; A comment.
+             ]
+            [[:db/retract 1 :foo/name "x"],]
+        "#;
+        let streamed = entities(input).expect("streamed");
+        let expected = vec![
+            edn::parse::entity(r#"{:db/id "a" :foo/name "[not a vector" :foo/note "\"quoted\" ; not a comment"}"#).expect("parsed"),
+            edn::parse::entity(r#"[:db/add "a" :foo/ref "b"]"#).expect("parsed"),
+            edn::parse::entity(r#"[:db/retract 1 :foo/name "x"]"#).expect("parsed"),
+        ];
+        assert_eq!(streamed, expected);
+
+        assert_eq!(entities("").expect("empty"), vec![]);
+        assert_eq!(entities("[]").expect("empty"), vec![]);
+    }
+
+    #[test]
+    fn test_entity_stream_malformed() {
+        match entities(r#"{:db/id "a"}"#) {
+            Err(MentatError::InvalidImport(_)) => {},
+            x => panic!("expected InvalidImport, got {:?}", x),
+        }
+        match entities(r#"[{:db/id "a" :foo/name "unterminated}]"#) {
+            Err(MentatError::InvalidImport(_)) => {},
+            x => panic!("expected InvalidImport, got {:?}", x),
+        }
+        match entities(r#"[{:db/id "a"} :foo/name]"#) {
+            Err(MentatError::InvalidImport(_)) => {},
+            x => panic!("expected InvalidImport, got {:?}", x),
+        }
+        match entities(r#"[[:db/add "a"]]"#) {
+            Err(MentatError::EdnParseError(_)) => {},
+            x => panic!("expected EdnParseError, got {:?}", x),
+        }
+    }
+}
@@ -60,6 +60,11 @@ pub use mentat_db::{
     CORE_SCHEMA_VERSION,
     DB_SCHEMA_CORE,
     AttributeSet,
+    Excision,
+    TempIdResolution,
+    TxExplanation,
+    TxFunctionContext,
+    TxFunctionOutput,
     TxObserver,
     new_connection,
 };
@@ -145,21 +150,38 @@ pub use mentat_sql::SQLError;
 
 pub mod conn;
 pub mod entity_builder;
+pub mod import;
 pub mod query;
 pub mod query_builder;
 pub mod store;
+pub mod validation;
 pub mod vocabulary;
 
+pub use import::{
+    BulkImportReport,
+    EntityStream,
+};
+
 pub use query::{
+    DataSource,
+    DatabaseView,
     IntoResult,
     PlainSymbol,
+    PreparedQuery,
+    QueryCursor,
     QueryExecutionResult,
     QueryExplanation,
     QueryInputs,
     QueryOutput,
+    QueryPage,
+    QueryPlanCacheStats,
     QueryPlanStep,
     QueryResults,
+    QueryRows,
+    QueryStream,
     RelResult,
+    SrcVar,
+    StreamResult,
     Variable,
     q_once,
 };
@@ -172,10 +194,13 @@ pub use conn::{
     CacheAction,
     CacheDirection,
     Conn,
+    HistoricalRead,
     InProgress,
+    InProgressSavepoint,
     Metadata,
     Pullable,
     Queryable,
+    SpeculativeDb,
     Syncable,
 };
 
@@ -183,6 +208,13 @@ pub use store::{
     Store,
 };
 
+pub use validation::{
+    ConstraintContext,
+    Constraints,
+    ValidationError,
+    ValidationErrors,
+};
+
 #[cfg(test)]
 mod tests {
     use edn::symbols::Keyword;
@@ -11,7 +11,15 @@
 use rusqlite;
 use rusqlite::types::ToSql;
 
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
 use std::rc::Rc;
+use std::sync::{
+    Arc,
+    Mutex,
+};
 
 use mentat_core::{
     Binding,
@@ -19,11 +27,15 @@ use mentat_core::{
     HasSchema,
     KnownEntid,
     Schema,
+    ToMicros,
     TypedValue,
+    ValueType,
+    tuple_to_bytes,
 };
 
 use mentat_query_algebrizer::{
     AlgebraicQuery,
+    AlgebrizerError,
     EmptyBecause,
     FindQuery,
     algebrize_with_inputs,
@@ -31,18 +43,25 @@ use mentat_query_algebrizer::{
 };
 
 pub use mentat_query_algebrizer::{
+    DataSource,
+    DatabaseView,
+    QueryCursor,
     QueryInputs,
 };
 
 pub use mentat_query::{
     Keyword,
     PlainSymbol,
+    SrcVar,
     Variable,
 };
 
 use mentat_query::{
     Element,
     FindSpec,
+    Limit,
+    Offset,
+    Order,
     Pattern,
     PatternNonValuePlace,
     PatternValuePlace,
@@ -52,6 +71,7 @@ use mentat_query::{
 use mentat_query_projector::{
     ConstantProjector,
     Projector,
+    RowProjector,
 };
 
 use mentat_sql::{
@@ -60,6 +80,7 @@ use mentat_sql::{
 
 use mentat_query_translator::{
     ProjectedSelect,
+    format_select_var,
     query_to_select,
 };
 
@@ -80,10 +101,11 @@ use errors::{
 
 pub type QueryExecutionResult = Result<QueryOutput>;
 pub type PreparedResult<'sqlite> = Result<PreparedQuery<'sqlite>>;
+pub type StreamResult<'sqlite> = Result<QueryStream<'sqlite>>;
 
 pub enum PreparedQuery<'sqlite> {
     Empty {
-        find_spec: Rc<FindSpec>,
+        find_spec: Arc<FindSpec>,
     },
     Constant {
         select: ConstantProjector,
@@ -94,11 +116,135 @@ pub enum PreparedQuery<'sqlite> {
         connection: &'sqlite rusqlite::Connection,
         args: Vec<(String, Rc<rusqlite::types::Value>)>,
         projector: Box<Projector>,
+        /// The `:in` variables that weren't bound when the query was prepared. Their values are
+        /// supplied to `run`.
+        parameters: Vec<PreparedInput>,
+        /// The query and the inputs it was prepared with, in case new inputs change its plan.
+        query: String,
+        inputs: QueryInputs,
+    },
+    /// A query with inputs whose types weren't known when it was prepared. Its plan depends on
+    /// those types, so it's algebrized again each time it's run.
+    Deferred {
+        schema: Schema,
+        connection: &'sqlite rusqlite::Connection,
+        query: String,
+        inputs: QueryInputs,
     },
 }
 
+/// An `:in` variable that wasn't bound when a query was prepared.
+pub struct PreparedInput {
+    var: Variable,
+    value_type: ValueType,
+    /// The SQL parameter that takes the value, if the statement uses the variable.
+    parameter: Option<String>,
+    /// Whether the variable is the query's `:limit`.
+    is_limit: bool,
+    /// Whether the variable is the query's `:offset`.
+    is_offset: bool,
+}
+
+impl PreparedInput {
+    /// Check `value` against the type the algebrizer inferred for this input, and produce the
+    /// binding for the statement.
+    fn bind(&self, schema: &Schema, value: &TypedValue) -> Result<Option<(String, Rc<rusqlite::types::Value>)>> {
+        let value = match (self.value_type, value) {
+            (ValueType::Ref, &TypedValue::Keyword(ref kw)) => {
+                let entid = schema.get_entid(kw)
+                                  .ok_or_else(|| AlgebrizerError::UnrecognizedIdent(kw.to_string()))?;
+                TypedValue::Ref(entid.into())
+            },
+            (expected, value) => {
+                if value.value_type() != expected {
+                    bail!(AlgebrizerError::InputTypeDisagreement(self.var.name(), expected, value.value_type()));
+                }
+                value.clone()
+            },
+        };
+
+        if let TypedValue::Long(n) = value {
+            if self.is_limit && n <= 0 {
+                // User-specified limits should always be natural numbers (> 0).
+                bail!(AlgebrizerError::InvalidLimit(n.to_string(), ValueType::Long));
+            }
+            if self.is_offset && n < 0 {
+                bail!(AlgebrizerError::InvalidOffset(n.to_string(), ValueType::Long));
+            }
+        }
+
+        Ok(self.parameter.as_ref().map(|name| (name.clone(), Rc::new(to_sql_value(value)))))
+    }
+}
+
+/// Describe the `:in` variables that `algebrized` leaves unbound. Returns `None` if the type of one
+/// isn't known, in which case the query can't be planned until it's bound.
+fn unbound_inputs(algebrized: &AlgebraicQuery) -> Option<Vec<PreparedInput>> {
+    let unbound = algebrized.unbound_variables();
+    let mut parameters = Vec::with_capacity(unbound.len());
+    for var in unbound.into_iter() {
+        let value_type = algebrized.cc.known_type(&var)?;
+        let is_limit = algebrized.limit == Limit::Variable(var.clone());
+        let is_offset = algebrized.offset == Offset::Variable(var.clone());
+        parameters.push(PreparedInput { var, value_type, parameter: None, is_limit, is_offset });
+    }
+    Some(parameters)
+}
+
+/// Record which of `parameters` the statement actually takes.
+fn name_parameters(statement: &rusqlite::Statement, parameters: &mut [PreparedInput]) -> Result<()> {
+    for input in parameters.iter_mut() {
+        let name = format!("${}", format_select_var(input.var.as_str()));
+        if statement.parameter_index(name.as_str())?.is_some() {
+            input.parameter = Some(name);
+        }
+    }
+    Ok(())
+}
+
+/// Bind the values in `inputs` to `parameters`, adding them to `args`. Every parameter must have a
+/// value.
+fn bind_inputs(schema: &Schema,
+               parameters: &[PreparedInput],
+               inputs: &QueryInputs,
+               args: &mut Vec<(String, Rc<rusqlite::types::Value>)>) -> Result<()> {
+    let mut unbound = BTreeSet::new();
+    for input in parameters.iter() {
+        match inputs.values().get(&input.var) {
+            Some(value) => args.extend(input.bind(schema, value)?),
+            None => { unbound.insert(input.var.to_string()); },
+        }
+    }
+    if !unbound.is_empty() {
+        bail!(MentatError::UnboundVariables(unbound));
+    }
+    Ok(())
+}
+
+/// The storage representation of an input's value. This matches the way `SQLiteQueryBuilder`
+/// writes values into a query.
+fn to_sql_value(value: TypedValue) -> rusqlite::types::Value {
+    use rusqlite::types::Value;
+    match value {
+        TypedValue::Ref(x) => Value::Integer(x),
+        TypedValue::Boolean(x) => Value::Integer(if x { 1 } else { 0 }),
+        TypedValue::Instant(x) => Value::Integer(x.to_micros()),
+        TypedValue::Long(x) => Value::Integer(x),
+        TypedValue::Double(x) => Value::Real(x.into_inner()),
+        TypedValue::String(x) => Value::Text(x.as_ref().clone()),
+        TypedValue::Uuid(x) => Value::Blob(x.as_bytes().to_vec()),
+        TypedValue::Keyword(x) => Value::Text(x.as_ref().to_string()),
+        TypedValue::Tuple(x) => Value::Blob(tuple_to_bytes(x.as_slice())),
+    }
+}
+
 impl<'sqlite> PreparedQuery<'sqlite> {
-    pub fn run<T>(&mut self, _inputs: T) -> QueryExecutionResult where T: Into<Option<QueryInputs>> {
+    /// Run the query. `inputs` supplies values for the `:in` variables that weren't bound when
+    /// the query was prepared; other parts of `inputs`, like its view or sources, are ignored.
+    /// Supplying a new value for a variable that was bound when the query was prepared causes the
+    /// query to be algebrized again.
+    pub fn run<T>(&mut self, inputs: T) -> QueryExecutionResult where T: Into<Option<QueryInputs>> {
+        let inputs = inputs.into().unwrap_or(QueryInputs::default());
         match self {
             &mut PreparedQuery::Empty { ref find_spec } => {
                 Ok(QueryOutput::empty(find_spec))
@@ -106,15 +252,38 @@ impl<'sqlite> PreparedQuery<'sqlite> {
             &mut PreparedQuery::Constant { ref select } => {
                 select.project_without_rows().map_err(|e| e.into())
             },
-            &mut PreparedQuery::Bound { ref mut statement, ref schema, ref connection, ref args, ref projector } => {
-                let rows = run_statement(statement, args)?;
+            &mut PreparedQuery::Bound { ref mut statement, ref schema, ref connection, ref args, ref projector, ref parameters, ref query, inputs: ref prepared } => {
+                let rebound = inputs.values().iter().any(|(var, value)| {
+                    prepared.values().get(var).map_or(false, |existing| existing != value)
+                });
+                if rebound {
+                    return q_again(connection, schema, query, prepared, inputs);
+                }
+
+                let mut args = args.clone();
+                bind_inputs(schema, parameters, &inputs, &mut args)?;
+
+                let rows = run_statement(statement, &args)?;
                 projector.project(schema, connection, rows)
                          .map_err(|e| e.into())
-            }
+            },
+            &mut PreparedQuery::Deferred { ref schema, ref connection, ref query, inputs: ref prepared } => {
+                q_again(connection, schema, query, prepared, inputs)
+            },
         }
     }
 }
 
+/// Algebrize and run a prepared query with new inputs, layered over those it was prepared with.
+fn q_again(sqlite: &rusqlite::Connection,
+           schema: &Schema,
+           query: &str,
+           prepared: &QueryInputs,
+           inputs: QueryInputs) -> QueryExecutionResult {
+    let inputs = prepared.clone().rebind_values(inputs.values().clone());
+    q_uncached(sqlite, schema, query, inputs)
+}
+
 pub trait IntoResult {
     fn into_scalar_result(self) -> Result<Option<Binding>>;
     fn into_coll_result(self) -> Result<Vec<Binding>>;
@@ -140,6 +309,68 @@ impl IntoResult for QueryExecutionResult {
     }
 }
 
+/// A page of the results of an ordered query. See `Queryable::q_page`.
+pub struct QueryPage {
+    pub output: QueryOutput,
+    /// Where to resume to fetch the next page: the `:order` values of the last result on this one.
+    /// `None` if this page is empty.
+    pub cursor: Option<QueryCursor>,
+}
+
+/// Find where each of the query's `:order` variables appears in its results. Only queries that
+/// find a collection or relation, and that find each of their `:order` variables, can be paged.
+pub(crate) fn cursor_columns(query: &str) -> Result<Vec<usize>> {
+    let parsed = parse_find_string(query)?;
+    let elements: Vec<&Element> = match parsed.find_spec {
+        FindSpec::FindColl(ref element) => vec![element],
+        FindSpec::FindRel(ref elements) => elements.iter().collect(),
+        FindSpec::FindScalar(_) | FindSpec::FindTuple(_) => {
+            bail!(MentatError::UnpageableQuery("only collections and relations can be paged".to_string()));
+        },
+    };
+
+    let order = parsed.order.unwrap_or(vec![]);
+    if order.is_empty() {
+        bail!(MentatError::UnpageableQuery("paged queries must specify an :order".to_string()));
+    }
+
+    let mut columns = Vec::with_capacity(order.len());
+    for Order(_, var) in order.into_iter() {
+        let column = elements.iter().position(|e| match e {
+            &&Element::Variable(ref v) => *v == var,
+            _ => false,
+        });
+        match column {
+            Some(column) => columns.push(column),
+            None => bail!(MentatError::UnpageableQuery(format!("{} is in :order but not in :find", var))),
+        }
+    }
+    Ok(columns)
+}
+
+/// Produce the cursor that follows the last of `results`, taking its values from `columns`.
+pub(crate) fn cursor_after(results: &QueryResults, columns: &[usize]) -> Option<QueryCursor> {
+    let last: &[Binding] = match results {
+        &QueryResults::Coll(ref values) => {
+            if values.is_empty() {
+                return None;
+            }
+            &values[values.len() - 1..]
+        },
+        &QueryResults::Rel(ref rel) => {
+            if rel.is_empty() {
+                return None;
+            }
+            rel.row(rel.row_count() - 1)?
+        },
+        &QueryResults::Scalar(_) | &QueryResults::Tuple(_) => return None,
+    };
+    columns.iter()
+           .map(|&i| last.get(i).and_then(|binding| binding.as_scalar()).cloned())
+           .collect::<Option<Vec<TypedValue>>>()
+           .map(QueryCursor)
+}
+
 /// A struct describing information about how Mentat would execute a query.
 pub enum QueryExplanation {
     /// A query known in advance to be empty, and why we believe that.
@@ -385,6 +616,8 @@ pub fn q_uncached<'sqlite, 'schema, 'query, T>
     run_algebrized_query(known, sqlite, algebrized)
 }
 
+/// Prepare a query to be run later, perhaps repeatedly. Any `:in` variables that aren't bound by
+/// `inputs` must be bound each time the query is run.
 pub fn q_prepare<'sqlite, 'schema, 'cache, 'query, T>
 (sqlite: &'sqlite rusqlite::Connection,
  known: Known<'schema, 'cache>,
@@ -392,14 +625,9 @@ pub fn q_prepare<'sqlite, 'schema, 'cache, 'query, T>
  inputs: T) -> PreparedResult<'sqlite>
         where T: Into<Option<QueryInputs>>
 {
-    let algebrized = algebrize_query_str(known, query, inputs)?;
-
-    let unbound = algebrized.unbound_variables();
-    if !unbound.is_empty() {
-        // TODO: Allow binding variables at execution time, not just
-        // preparation time.
-        bail!(MentatError::UnboundVariables(unbound.into_iter().map(|v| v.to_string()).collect()));
-    }
+    let inputs = inputs.into().unwrap_or(QueryInputs::default());
+    let parsed = parse_find_string(query)?;
+    let algebrized = algebrize_with_inputs(known, parsed, 0, inputs.clone())?;
 
     if algebrized.is_known_empty() {
         // We don't need to do any SQL work at all.
@@ -408,6 +636,20 @@ pub fn q_prepare<'sqlite, 'schema, 'cache, 'query, T>
         });
     }
 
+    // Each unbound input becomes a SQL parameter. If we don't know the type of one, we can't
+    // plan the query until it's bound: the type determines which tables and type tags we use.
+    let mut parameters = match unbound_inputs(&algebrized) {
+        Some(parameters) => parameters,
+        None => {
+            return Ok(PreparedQuery::Deferred {
+                schema: known.schema.clone(),
+                connection: sqlite,
+                query: query.to_string(),
+                inputs,
+            });
+        },
+    };
+
     let select = query_to_select(known.schema, algebrized)?;
     match select {
         ProjectedSelect::Constant(constant) => {
@@ -415,21 +657,329 @@ pub fn q_prepare<'sqlite, 'schema, 'cache, 'query, T>
                 select: constant,
             })
         },
-        ProjectedSelect::Query { query, projector } => {
-            let SQLQuery { sql, args } = query.to_sql_query()?;
+        ProjectedSelect::Query { query: select, projector } => {
+            let SQLQuery { sql, args } = select.to_sql_query()?;
             let statement = sqlite.prepare(sql.as_str())?;
+            name_parameters(&statement, &mut parameters)?;
 
             Ok(PreparedQuery::Bound {
                 statement,
                 schema: known.schema.clone(),
                 connection: sqlite,
                 args,
-                projector: projector
+                projector: projector,
+                parameters,
+                query: query.to_string(),
+                inputs,
             })
         },
     }
 }
 
+/// The results of a collection or relation query, read from SQLite a row at a time rather than
+/// collected up front. Call `rows` to run the query and iterate over its results.
+pub struct QueryStream<'sqlite> {
+    spec: Arc<FindSpec>,
+    source: StreamSource<'sqlite>,
+}
+
+enum StreamSource<'sqlite> {
+    /// Results we have without stepping through rows: the query is known to be empty or constant,
+    /// or it pulls attributes, which needs every row before it can produce any result.
+    Projected(Vec<Vec<Binding>>),
+    Statement {
+        statement: rusqlite::Statement<'sqlite>,
+        args: Vec<(String, Rc<rusqlite::types::Value>)>,
+        projector: RowProjector,
+    },
+}
+
+impl<'sqlite> QueryStream<'sqlite> {
+    fn projected(output: QueryOutput) -> Result<QueryStream<'sqlite>> {
+        let rows: Vec<Vec<Binding>> = match output.results {
+            QueryResults::Coll(values) => values.into_iter().map(|v| vec![v]).collect(),
+            QueryResults::Rel(rel) => rel.into_iter().collect(),
+            QueryResults::Scalar(_) | QueryResults::Tuple(_) => {
+                bail!(MentatError::UnstreamableQuery("only collections and relations can be streamed".to_string()));
+            },
+        };
+        Ok(QueryStream {
+            spec: output.spec,
+            source: StreamSource::Projected(rows),
+        })
+    }
+
+    pub fn spec(&self) -> &Arc<FindSpec> {
+        &self.spec
+    }
+
+    /// Run the query. Each result is a vector of bindings, one per column of the find spec; the
+    /// results of a collection have a single binding each.
+    pub fn rows<'s>(&'s mut self) -> Result<QueryRows<'s>> {
+        match self.source {
+            StreamSource::Projected(ref rows) => {
+                Ok(QueryRows(RowSource::Projected(rows.iter())))
+            },
+            StreamSource::Statement { ref mut statement, ref args, ref projector } => {
+                let rows = run_statement(statement, args)?;
+                Ok(QueryRows(RowSource::Rows(rows, projector)))
+            },
+        }
+    }
+}
+
+/// An iterator over the results of a `QueryStream`.
+pub struct QueryRows<'s>(RowSource<'s>);
+
+enum RowSource<'s> {
+    Projected(::std::slice::Iter<'s, Vec<Binding>>),
+    Rows(rusqlite::Rows<'s>, &'s RowProjector),
+}
+
+impl<'s> Iterator for QueryRows<'s> {
+    type Item = Result<Vec<Binding>>;
+
+    fn next(&mut self) -> Option<Result<Vec<Binding>>> {
+        match self.0 {
+            RowSource::Projected(ref mut rows) => rows.next().cloned().map(Ok),
+            RowSource::Rows(ref mut rows, projector) => {
+                rows.next().map(|row| {
+                    let row = row?;
+                    projector.project_row(&row).map_err(|e| e.into())
+                })
+            },
+        }
+    }
+}
+
+/// Run a query that finds a collection or relation, producing its results a row at a time. Unlike
+/// `q_once`, this doesn't hold every result in memory at once, which matters for large exports.
+pub fn q_stream<'sqlite, 'query, T>
+(sqlite: &'sqlite rusqlite::Connection,
+ known: Known,
+ query: &'query str,
+ inputs: T) -> StreamResult<'sqlite>
+        where T: Into<Option<QueryInputs>>
+{
+    let algebrized = algebrize_query_str(known, query, inputs)?;
+    let spec = algebrized.find_spec.clone();
+    match *spec {
+        FindSpec::FindColl(_) | FindSpec::FindRel(_) => {},
+        FindSpec::FindScalar(_) | FindSpec::FindTuple(_) => {
+            bail!(MentatError::UnstreamableQuery("only collections and relations can be streamed".to_string()));
+        },
+    }
+
+    if algebrized.is_known_empty() {
+        // We don't need to do any SQL work at all.
+        return QueryStream::projected(QueryOutput::empty(&spec));
+    }
+
+    let select = query_to_select(known.schema, algebrized)?;
+    match select {
+        ProjectedSelect::Constant(constant) => {
+            QueryStream::projected(constant.project_without_rows()?)
+        },
+        ProjectedSelect::Query { query, projector } => {
+            let SQLQuery { sql, args } = query.to_sql_query()?;
+            let mut statement = sqlite.prepare(sql.as_str())?;
+
+            match projector.row_projector() {
+                Some(row_projector) => {
+                    Ok(QueryStream {
+                        spec,
+                        source: StreamSource::Statement {
+                            statement,
+                            args,
+                            projector: row_projector,
+                        },
+                    })
+                },
+                None => {
+                    let output = {
+                        let rows = run_statement(&mut statement, &args)?;
+                        projector.project(known.schema, sqlite, rows)?
+                    };
+                    QueryStream::projected(output)
+                },
+            }
+        },
+    }
+}
+
+/// A translated query that doesn't depend on the values of its inputs, only their types. Unlike a
+/// `PreparedQuery` it doesn't borrow a connection, so it can outlive the query that made it.
+struct QueryPlan {
+    sql: String,
+    args: Vec<(String, rusqlite::types::Value)>,
+    projector: Box<Projector>,
+    parameters: Vec<PreparedInput>,
+}
+
+impl QueryPlan {
+    /// Plan `query` for inputs of the given types. Returns `None` if the query can't be planned
+    /// without the values of its inputs -- for example, because it uses them in `ground` -- or if
+    /// planning fails; running the query the usual way reports any error.
+    fn new(sqlite: &rusqlite::Connection,
+           schema: &Schema,
+           query: &str,
+           types: &BTreeMap<Variable, ValueType>) -> Option<QueryPlan> {
+        let parsed = parse_find_string(query).ok()?;
+        let inputs = QueryInputs::with_type_sequence(types.iter().map(|(var, t)| (var.clone(), *t)).collect());
+        let algebrized = algebrize_with_inputs(Known::for_schema(schema), parsed, 0, inputs).ok()?;
+
+        // An empty result might be down to the values we left out: keyword inputs, for example,
+        // can name attributes.
+        if algebrized.is_known_empty() {
+            return None;
+        }
+
+        let mut parameters = unbound_inputs(&algebrized)?;
+        match query_to_select(schema, algebrized).ok()? {
+            ProjectedSelect::Constant(_) => None,
+            ProjectedSelect::Query { query: select, projector } => {
+                let SQLQuery { sql, args } = select.to_sql_query().ok()?;
+                {
+                    let statement = sqlite.prepare_cached(sql.as_str()).ok()?;
+                    name_parameters(&statement, &mut parameters).ok()?;
+                }
+                Some(QueryPlan {
+                    sql,
+                    args: args.into_iter().map(|(name, value)| (name, (*value).clone())).collect(),
+                    projector,
+                    parameters,
+                })
+            },
+        }
+    }
+
+    fn run(&self, sqlite: &rusqlite::Connection, schema: &Schema, inputs: &QueryInputs) -> QueryExecutionResult {
+        let mut args: Vec<(String, Rc<rusqlite::types::Value>)> =
+            self.args.iter()
+                     .map(|&(ref name, ref value)| (name.clone(), Rc::new(value.clone())))
+                     .collect();
+        bind_inputs(schema, &self.parameters, inputs, &mut args)?;
+
+        let mut statement = sqlite.prepare_cached(self.sql.as_str())?;
+        let rows = run_statement(&mut statement, &args)?;
+        self.projector.project(schema, sqlite, rows).map_err(|e| e.into())
+    }
+}
+
+/// How many plans a `QueryPlanCache` holds before it evicts the least recently used.
+const QUERY_PLAN_CACHE_CAPACITY: usize = 1024;
+
+/// How often queries found their plan in a `QueryPlanCache`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct QueryPlanCacheStats {
+    /// Queries that reused a plan.
+    pub hits: u64,
+    /// Queries that had to be planned, including those that can't be cached.
+    pub misses: u64,
+}
+
+type QueryPlanKey = (String, Vec<(Variable, ValueType)>);
+
+/// Query plans keyed by query text and input types, so that running the same query with different
+/// values skips parsing, algebrizing, and translating it. Plans are made for a particular schema:
+/// they're dropped when the schema changes.
+#[derive(Default)]
+pub(crate) struct QueryPlanCache {
+    schema: Option<Arc<Schema>>,
+    /// `None` records that a query can't be planned ahead of its input values.  Each plan is
+    /// stamped with when it was last used.
+    plans: BTreeMap<QueryPlanKey, (u64, Option<Arc<QueryPlan>>)>,
+    /// The keys of `plans` by when they were last used, least recent first.
+    recency: BTreeMap<u64, QueryPlanKey>,
+    clock: u64,
+    stats: QueryPlanCacheStats,
+}
+
+impl QueryPlanCache {
+    pub(crate) fn stats(&self) -> QueryPlanCacheStats {
+        self.stats
+    }
+
+    fn use_schema(&mut self, schema: &Arc<Schema>) {
+        let current = self.schema.as_ref().map_or(false, |s| Arc::ptr_eq(s, schema));
+        if !current {
+            self.plans.clear();
+            self.recency.clear();
+            self.schema = Some(schema.clone());
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// The plan for `key`, if it's been planned: `Some(None)` if it can't be planned ahead.
+    fn get(&mut self, schema: &Arc<Schema>, key: &QueryPlanKey) -> Option<Option<Arc<QueryPlan>>> {
+        self.use_schema(schema);
+        let now = self.tick();
+        let found = match self.plans.get_mut(key) {
+            Some(&mut (ref mut last_used, ref plan)) => {
+                self.recency.remove(&*last_used);
+                *last_used = now;
+                Some(plan.clone())
+            },
+            None => None,
+        };
+        match found {
+            Some(Some(_)) => self.stats.hits += 1,
+            _ => self.stats.misses += 1,
+        }
+        if found.is_some() {
+            self.recency.insert(now, key.clone());
+        }
+        found
+    }
+
+    fn insert(&mut self, schema: &Arc<Schema>, key: QueryPlanKey, plan: Option<Arc<QueryPlan>>) {
+        self.use_schema(schema);
+        if !self.plans.contains_key(&key) && self.plans.len() >= QUERY_PLAN_CACHE_CAPACITY {
+            let oldest = self.recency.keys().next().cloned();
+            if let Some(oldest) = oldest {
+                if let Some(evicted) = self.recency.remove(&oldest) {
+                    self.plans.remove(&evicted);
+                }
+            }
+        }
+        let now = self.tick();
+        if let Some((last_used, _)) = self.plans.insert(key.clone(), (now, plan)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(now, key);
+    }
+}
+
+/// Like `q_uncached`, but reusing a plan for `query` from `plans` if there is one. Plans don't take
+/// account of cached attributes, so callers with any should use `q_once` instead.
+///
+/// `plans` is only locked to find or to store a plan, not while the query runs.
+pub(crate) fn q_planned(plans: &Mutex<QueryPlanCache>,
+                        sqlite: &rusqlite::Connection,
+                        schema: &Arc<Schema>,
+                        query: &str,
+                        inputs: QueryInputs) -> QueryExecutionResult {
+    let key = (query.to_string(),
+               inputs.types().iter().map(|(var, t)| (var.clone(), *t)).collect::<Vec<_>>());
+    let cached = plans.lock().unwrap().get(schema, &key);
+    let plan = match cached {
+        Some(plan) => plan,
+        None => {
+            let plan = QueryPlan::new(sqlite, schema, query, inputs.types()).map(Arc::new);
+            plans.lock().unwrap().insert(schema, key, plan.clone());
+            plan
+        },
+    };
+    match plan {
+        Some(plan) => plan.run(sqlite, schema, &inputs),
+        None => q_uncached(sqlite, schema, query, inputs),
+    }
+}
+
 pub fn q_explain<'sqlite, 'query, T>
 (sqlite: &'sqlite rusqlite::Connection,
  known: Known,
@@ -461,3 +1011,33 @@ pub fn q_explain<'sqlite, 'query, T>
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_plan_cache_evicts_least_recently_used() {
+        let schema = Arc::new(Schema::default());
+        let key = |i: usize| (format!("[:find ?x :where [?x :foo/bar {}]]", i), vec![]);
+
+        let mut plans = QueryPlanCache::default();
+        for i in 0..QUERY_PLAN_CACHE_CAPACITY {
+            plans.insert(&schema, key(i), None);
+        }
+
+        // Using the oldest plan makes the next oldest the one to go.
+        assert!(plans.get(&schema, &key(0)).is_some());
+        plans.insert(&schema, key(QUERY_PLAN_CACHE_CAPACITY), None);
+        assert_eq!(plans.plans.len(), QUERY_PLAN_CACHE_CAPACITY);
+        assert!(plans.get(&schema, &key(0)).is_some());
+        assert!(plans.get(&schema, &key(1)).is_none());
+        assert!(plans.get(&schema, &key(2)).is_some());
+        assert!(plans.get(&schema, &key(QUERY_PLAN_CACHE_CAPACITY)).is_some());
+
+        // A new schema starts again.
+        let schema = Arc::new(Schema::default());
+        assert!(plans.get(&schema, &key(0)).is_none());
+        assert!(plans.plans.is_empty());
+    }
+}
@@ -23,11 +23,20 @@ use mentat_core::{
     ValueType,
 };
 
+use mentat_query::{
+    Rule,
+};
+
+use mentat_query_algebrizer::{
+    parse_rules_string,
+};
+
 use ::{
     HasSchema,
     Queryable,
     QueryInputs,
     QueryOutput,
+    QueryStream,
     RelResult,
     Store,
     Variable,
@@ -42,12 +51,13 @@ pub struct QueryBuilder<'a> {
     query: String,
     values: BTreeMap<Variable, TypedValue>,
     types: BTreeMap<Variable, ValueType>,
+    rules: Vec<Rule>,
     store: &'a mut Store,
 }
 
 impl<'a> QueryBuilder<'a> {
     pub fn new<T>(store: &'a mut Store, query: T) -> QueryBuilder where T: Into<String> {
-        QueryBuilder { query: query.into(), values: BTreeMap::new(), types: BTreeMap::new(), store }
+        QueryBuilder { query: query.into(), values: BTreeMap::new(), types: BTreeMap::new(), rules: vec![], store }
     }
 
     pub fn bind_value<T>(&mut self, var: &str, value: T) -> &mut Self where T: Into<TypedValue> {
@@ -87,14 +97,33 @@ impl<'a> QueryBuilder<'a> {
         self
     }
 
-    pub fn execute(&mut self) -> Result<QueryOutput> {
+    /// Supply rules for a query that takes `%` in its `:in` clause, like
+    /// `[[(ancestor ?a ?b) [?a :person/parent ?b]]]`.
+    pub fn bind_rules(&mut self, rules: &str) -> Result<&mut Self> {
+        self.rules.extend(parse_rules_string(rules)?);
+        Ok(self)
+    }
+
+    fn take_inputs(&mut self) -> Result<QueryInputs> {
         let values = ::std::mem::replace(&mut self.values, Default::default());
         let types = ::std::mem::replace(&mut self.types, Default::default());
-        let query_inputs = QueryInputs::new(types, values)?;
+        let rules = ::std::mem::replace(&mut self.rules, Default::default());
+        QueryInputs::new(types, values)?.with_rules(rules).map_err(|e| e.into())
+    }
+
+    pub fn execute(&mut self) -> Result<QueryOutput> {
+        let query_inputs = self.take_inputs()?;
         let read = self.store.begin_read()?;
         read.q_once(&self.query, query_inputs)
     }
 
+    /// Run a collection or relation query, reading its results from the store a row at a time
+    /// rather than collecting them all first.
+    pub fn execute_stream(&mut self) -> Result<QueryStream> {
+        let query_inputs = self.take_inputs()?;
+        self.store.q_stream(&self.query, query_inputs)
+    }
+
     pub fn execute_scalar(&mut self) -> Result<Option<Binding>> {
         let results = self.execute()?;
         results.into_scalar().map_err(|e| e.into())
@@ -35,6 +35,8 @@ use mentat_core::{
     ValueRc,
 };
 use mentat_db::{
+    TxFunctionContext,
+    TxFunctionOutput,
     TxObserver,
 };
 
@@ -49,6 +51,7 @@ use conn::{
     InProgressRead,
     Pullable,
     Queryable,
+    SpeculativeDb,
 };
 
 #[cfg(feature = "syncable")]
@@ -63,6 +66,12 @@ use query::{
     QueryExplanation,
     QueryInputs,
     QueryOutput,
+    StreamResult,
+};
+
+use validation::{
+    ConstraintContext,
+    ValidationError,
 };
 
 /// A convenience wrapper around a single SQLite connection and a Conn. This is suitable
@@ -178,6 +187,12 @@ impl Store {
         self.conn.begin_transaction(&mut self.sqlite)
     }
 
+    /// Transact speculatively: query the returned `SpeculativeDb` to see the store as if the
+    /// transaction had been committed, then drop it to discard the transaction.  See `Conn::with`.
+    pub fn with(&mut self, transaction: &str) -> Result<SpeculativeDb> {
+        self.conn.with(&self.sqlite, transaction)
+    }
+
     pub fn cache(&mut self, attr: &Keyword, direction: CacheDirection) -> Result<()> {
         let schema = &self.conn.current_schema();
         self.conn.cache(&mut self.sqlite,
@@ -195,9 +210,76 @@ impl Store {
         self.conn.unregister_observer(key);
     }
 
+    pub fn register_tx_function<F>(&mut self, name: Keyword, function: F)
+    where F: Fn(&TxFunctionContext) -> ::mentat_db::Result<TxFunctionOutput> + 'static + Send + Sync {
+        self.conn.register_tx_function(name, function);
+    }
+
+    pub fn unregister_tx_function(&mut self, name: &Keyword) {
+        self.conn.unregister_tx_function(name);
+    }
+
+    pub fn register_constraint<F>(&mut self, name: Keyword, constraint: F)
+    where F: Fn(&ConstraintContext) -> Result<Vec<ValidationError>> + 'static + Send + Sync {
+        self.conn.register_constraint(name, constraint);
+    }
+
+    pub fn unregister_constraint(&mut self, name: &Keyword) {
+        self.conn.unregister_constraint(name);
+    }
+
     pub fn last_tx_id(&self) -> Entid {
         self.conn.last_tx_id()
     }
+
+    /// Attach the Mentat store at `path` to this store's SQLite connection under `name`. Queries
+    /// can then read it through a source bound to `DataSource::Attached`, like
+    /// `[:find ?x :in $ $other :where [$other ?x :foo/bar _]]`.
+    ///
+    /// Queries are resolved against this store's schema, so the attached store must have exactly
+    /// the same idents and attributes. Encrypted stores can't be attached.
+    pub fn attach(&mut self, name: &str, path: &str) -> Result<()> {
+        let valid = !name.is_empty() &&
+                    !name.starts_with(|c: char| c.is_digit(10)) &&
+                    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') &&
+                    !name.eq_ignore_ascii_case("main") &&
+                    !name.eq_ignore_ascii_case("temp");
+        if !valid {
+            bail!(MentatError::InvalidDatabaseName(name.to_string()));
+        }
+
+        self.sqlite.execute(format!("ATTACH DATABASE ? AS {}", name).as_str(), &[&path])?;
+
+        // Compare the materialized views that describe each store's schema.
+        let differ_sql = format!("SELECT EXISTS (SELECT e, a, v, value_type_tag FROM main.idents \
+                                                 EXCEPT SELECT e, a, v, value_type_tag FROM {name}.idents) \
+                                  OR EXISTS (SELECT e, a, v, value_type_tag FROM {name}.idents \
+                                             EXCEPT SELECT e, a, v, value_type_tag FROM main.idents) \
+                                  OR EXISTS (SELECT e, a, v, value_type_tag FROM main.schema \
+                                             EXCEPT SELECT e, a, v, value_type_tag FROM {name}.schema) \
+                                  OR EXISTS (SELECT e, a, v, value_type_tag FROM {name}.schema \
+                                             EXCEPT SELECT e, a, v, value_type_tag FROM main.schema)",
+                                 name = name);
+        let differ: Result<bool> = self.sqlite.query_row(differ_sql.as_str(), &[], |row| row.get(0))
+                                              .map_err(|e| e.into());
+        match differ {
+            Ok(false) => Ok(()),
+            Ok(true) => {
+                self.detach(name)?;
+                bail!(MentatError::IncompatibleAttachedStore(name.to_string()));
+            },
+            Err(e) => {
+                self.detach(name)?;
+                Err(e)
+            },
+        }
+    }
+
+    /// Detach a store that was attached with `attach`.
+    pub fn detach(&mut self, name: &str) -> Result<()> {
+        self.sqlite.execute(format!("DETACH DATABASE {}", name).as_str(), &[])?;
+        Ok(())
+    }
 }
 
 impl Queryable for Store {
@@ -211,6 +293,11 @@ impl Queryable for Store {
         self.conn.q_prepare(&self.sqlite, query, inputs)
     }
 
+    fn q_stream<T>(&self, query: &str, inputs: T) -> StreamResult
+        where T: Into<Option<QueryInputs>> {
+        self.conn.q_stream(&self.sqlite, query, inputs)
+    }
+
     fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
         where T: Into<Option<QueryInputs>> {
         self.conn.q_explain(&self.sqlite, query, inputs)
@@ -284,6 +371,10 @@ mod tests {
         ValueType,
     };
 
+    use ::validation::{
+        ValidationErrors,
+    };
+
     use ::entity_builder::{
         BuildTerms,
     };
@@ -294,6 +385,7 @@ mod tests {
 
     use ::{
         QueryInputs,
+        QueryResults,
     };
 
     use ::vocabulary::{
@@ -522,6 +614,110 @@ mod tests {
         assert!(!conn.is_registered_as_observer(&key));
     }
 
+    #[test]
+    fn test_register_tx_function() {
+        let mut store = Store::open("").unwrap();
+        store.transact(r#"[
+            {:db/ident :test/count :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+
+        store.register_tx_function(kw!(:test/ten), |_context| Ok(TxFunctionOutput::value(TypedValue::Long(10))));
+
+        let report = store.transact(r#"[[:db/add "x" :test/count (test/ten)]]"#).expect("transacted");
+        let x = *report.tempids.get("x").expect("x was allocated");
+        assert_eq!(store.lookup_value_for_attribute(x, &kw!(:test/count)).expect("looked up"),
+                   Some(TypedValue::Long(10)));
+
+        store.unregister_tx_function(&kw!(:test/ten));
+        store.transact(r#"[[:db/add "x" :test/count (test/ten)]]"#).expect_err("function is no longer registered");
+    }
+
+    #[test]
+    fn test_register_constraint() {
+        let mut store = Store::open("").unwrap();
+        store.transact(r#"[
+            {:db/ident :order/number :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+            {:db/ident :order/customer :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("transacted schema");
+
+        store.register_constraint(kw!(:order/has-customer), |context| {
+            let mut errors = vec![];
+            for &e in context.touched_entities() {
+                if context.lookup_value_for_attribute(e, &kw!(:order/number))?.is_some() &&
+                   context.lookup_value_for_attribute(e, &kw!(:order/customer))?.is_none() {
+                    errors.push(context.error(e, "an order must have a customer"));
+                }
+            }
+            Ok(errors)
+        });
+
+        let report = store.transact(r#"[{:db/id "o" :order/number 1 :order/customer "Alice"}]"#).expect("valid order");
+        let o = *report.tempids.get("o").expect("o was allocated");
+
+        let error = store.transact(r#"[{:db/id "p" :order/number 2}]"#).expect_err("order without a customer");
+        let message = error.to_string();
+        match error {
+            MentatError::ValidationFailed(ValidationErrors(errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(message, format!("validation failed: :order/has-customer failed for entity {}: an order must have a customer",
+                                            errors[0].entity));
+                assert_eq!(errors[0].constraint, kw!(:order/has-customer));
+                assert_eq!(errors[0].message, "an order must have a customer");
+            },
+            e => panic!("expected ValidationFailed, got {:?}", e),
+        }
+        assert_eq!(store.q_once("[:find ?e . :where [?e :order/number 2]]", None).expect("queried").results,
+                   QueryResults::Scalar(None));
+
+        // The constraint only sees entities touched by the transaction, and only at commit, so an
+        // order can be fixed up before then.
+        {
+            let mut in_progress = store.begin_transaction().expect("began");
+            in_progress.transact(format!(r#"[[:db/retract {} :order/customer "Alice"]]"#, o)).expect("transacted");
+            assert_eq!(in_progress.validate().expect("validated").len(), 1);
+            in_progress.transact(format!(r#"[[:db/add {} :order/customer "Bob"]]"#, o)).expect("transacted");
+            assert!(in_progress.validate().expect("validated").is_empty());
+            in_progress.commit().expect("committed");
+        }
+
+        store.unregister_constraint(&kw!(:order/has-customer));
+        store.transact(r#"[{:db/id "p" :order/number 2}]"#).expect("constraint is no longer registered");
+    }
+
+    #[test]
+    fn test_import_bulk() {
+        let query = r#"[:find ?hood ?district
+                        :where
+                        [?neighborhood :neighborhood/name ?hood]
+                        [?neighborhood :neighborhood/district ?d]
+                        [?d :district/name ?district]
+                        :order ?hood ?district]"#;
+
+        let mut store = Store::open("").expect("opened");
+        let (tempids, expected) = {
+            let mut in_progress = store.begin_transaction().expect("began");
+            in_progress.import(fixture_path("cities.schema")).expect("transacted schema");
+            let report = in_progress.import(fixture_path("all_seattle.edn")).expect("transacted data");
+            (report.tempids, in_progress.q_once(query, None).expect("queried").results)
+        };
+
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.import(fixture_path("cities.schema")).expect("transacted schema");
+        let report = in_progress.import_bulk(fixture_path("all_seattle.edn"), 100).expect("imported data");
+
+        // Tempids are shared across batches, so each names a single entity.
+        assert!(report.entities > 1000);
+        assert_eq!(report.transactions, (report.entities + 99) / 100);
+        assert_eq!(report.tempids.len(), tempids.len());
+        assert_eq!(report.last_tx_id, Some(in_progress.last_tx_id()));
+        assert_eq!(in_progress.q_once(query, None).expect("queried").results, expected);
+
+        match in_progress.import_bulk(fixture_path("all_seattle.edn"), 0) {
+            Err(MentatError::InvalidImport(_)) => {},
+            x => panic!("expected InvalidImport, got {:?}", x),
+        }
+    }
+
     fn add_schema(conn: &mut Store) {
         // transact some schema
         let mut in_progress = conn.begin_transaction().expect("expected in progress");
@@ -707,4 +903,83 @@ mod tests {
         assert_eq!(o.txids, tx_ids);
         assert_eq!(o.changes, changesets);
     }
+
+    #[test]
+    fn test_with() {
+        let mut store = Store::open("").expect("opened");
+        store.transact(r#"[{:db/ident :foo/name
+                             :db/valueType :db.type/string
+                             :db/cardinality :db.cardinality/one
+                             :db/unique :db.unique/identity
+                             :db/index true}]"#).expect("transacted schema");
+        store.transact(r#"[{:foo/name "Alice"}]"#).expect("transacted data");
+        let last_tx_id = store.last_tx_id();
+        let name = store.conn().current_schema().get_entid(&kw!(:foo/name)).expect("entid");
+        let count_query = "[:find (count ?e) . :where [?e :foo/name _]]";
+
+        {
+            let db = store.with(r#"[{:db/id "b" :foo/name "Bob"}]"#).expect("transacted speculatively");
+            let bob = *db.report().tempids.get("b").expect("tempid");
+            assert_eq!(db.last_tx_id(), db.report().tx_id);
+            assert_eq!(db.q_once(count_query, None).expect("query").into_scalar().expect("scalar"),
+                       Some(TypedValue::Long(2).into()));
+            assert_eq!(db.lookup_value_for_attribute(bob, &kw!(:foo/name)).expect("lookup"),
+                       Some(TypedValue::typed_string("Bob")));
+
+            let pulled = db.pull_attributes_for_entity(bob, vec![name.0]).expect("pulled");
+            assert_eq!(pulled.get(&kw!(:foo/name)), Some(&TypedValue::typed_string("Bob").into()));
+        }
+
+        // Nothing was committed.
+        assert_eq!(store.last_tx_id(), last_tx_id);
+        assert_eq!(store.q_once(count_query, None).expect("query").into_scalar().expect("scalar"),
+                   Some(TypedValue::Long(1).into()));
+
+        // A speculative transaction fails just like a real one.
+        assert!(store.with(r#"[{:foo/unknown "Carol"}]"#).is_err());
+        assert_eq!(store.last_tx_id(), last_tx_id);
+    }
+
+    #[test]
+    fn test_with_other_connections() {
+        let path = ::std::env::temp_dir().join(format!("mentat-test-with-{}.db", time::precise_time_ns()));
+        let path = path.to_str().expect("path").to_string();
+        let count_query = "[:find (count ?e) . :where [?e :foo/name _]]";
+        let names = "[:find [?name ...] :where [_ :foo/name ?name] :order ?name]";
+
+        let mut store = Store::open(path.as_str()).expect("opened");
+        store.transact(r#"[{:db/ident :foo/name
+                             :db/valueType :db.type/string
+                             :db/cardinality :db.cardinality/one}]"#).expect("transacted schema");
+        store.transact(r#"[{:foo/name "Alice"}]"#).expect("transacted data");
+        let mut other = Store::open(path.as_str()).expect("opened again");
+
+        {
+            let db = store.with(r#"[{:foo/name "Bob"}]"#).expect("transacted speculatively");
+            assert_eq!(db.q_once(count_query, None).expect("query").into_scalar().expect("scalar"),
+                       Some(TypedValue::Long(2).into()));
+
+            // Other connections don't see the speculative transaction, and can write while the
+            // view is alive...
+            assert_eq!(other.q_once(count_query, None).expect("query").into_scalar().expect("scalar"),
+                       Some(TypedValue::Long(1).into()));
+            other.transact(r#"[{:foo/name "Carol"}]"#).expect("transacted while the view is alive");
+            assert_eq!(other.q_once(count_query, None).expect("query").into_scalar().expect("scalar"),
+                       Some(TypedValue::Long(2).into()));
+
+            // ... without changing what the view sees.
+            assert_eq!(db.q_once(names, None).expect("query").into_coll().expect("coll"),
+                       vec![TypedValue::typed_string("Alice").into(), TypedValue::typed_string("Bob").into()]);
+        }
+
+        // Nothing speculative was committed.
+        assert_eq!(store.q_once(count_query, None).expect("query").into_scalar().expect("scalar"),
+                   Some(TypedValue::Long(2).into()));
+        assert_eq!(store.q_once(names, None).expect("query").into_coll().expect("coll"),
+                   vec![TypedValue::typed_string("Alice").into(), TypedValue::typed_string("Carol").into()]);
+
+        for suffix in &["", "-wal", "-shm"] {
+            let _ = ::std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
 }
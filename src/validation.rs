@@ -0,0 +1,225 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! User-registered constraints, checked when an `InProgress` commits.
+//!
+//! A constraint is named by a keyword, like `:order/has-customer`.  When an `InProgress` commits,
+//! each registered constraint is handed the entities that the `InProgress` asserted or retracted
+//! datoms about, along with a read-only view of the store as it would be after the commit.  The
+//! constraint returns a `ValidationError` for each entity that breaks its invariant.  If any
+//! constraint reports an error, the commit fails with `MentatError::ValidationFailed` and the
+//! `InProgress` is rolled back.
+//!
+//! Constraints aren't checked by a `TransactWatcher`, although watchers see every datom
+//! transacted.  `TransactWatcher::done` runs at the end of each transaction, but an `InProgress`
+//! can make several transactions before committing, and an invariant need only hold once they
+//! have all been made: an order and its customer might well be transacted separately.  `done` also
+//! sees only the schema, not the store, so it couldn't run the queries that constraints need.
+//! Instead, the `InProgress` collects the entities its watcher sees, and checks the constraints
+//! against them as it commits.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use std::fmt;
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+
+use edn;
+
+use mentat_core::{
+    Entid,
+    Keyword,
+    Schema,
+    StructuredMap,
+    TypedValue,
+    ValueRc,
+};
+
+use mentat_db::{
+    Registry,
+};
+
+use conn::{
+    InProgress,
+    Pullable,
+    Queryable,
+};
+
+use errors::{
+    Result,
+};
+
+use query::{
+    PreparedResult,
+    QueryExplanation,
+    QueryInputs,
+    QueryOutput,
+    StreamResult,
+};
+
+/// A broken invariant: `entity` doesn't satisfy the constraint named `constraint`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidationError {
+    pub constraint: Keyword,
+    pub entity: Entid,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed for entity {}: {}", self.constraint, self.entity, self.message)
+    }
+}
+
+/// The errors reported by the constraints that a commit broke.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a constraint can see: the entities touched by the committing `InProgress`, and the store
+/// as it would be after the commit.  Queries and pulls go through the `InProgress` itself.
+pub struct ConstraintContext<'v, 'a: 'v, 'c: 'v> {
+    in_progress: &'v InProgress<'a, 'c>,
+    touched_entities: &'v BTreeSet<Entid>,
+    constraint: &'v Keyword,
+}
+
+impl<'v, 'a, 'c> ConstraintContext<'v, 'a, 'c> {
+    pub fn schema(&self) -> &Schema {
+        &self.in_progress.schema
+    }
+
+    /// The entities that had datoms asserted or retracted, in any transaction made by the
+    /// committing `InProgress`.  Transaction entities themselves aren't included.
+    pub fn touched_entities(&self) -> &BTreeSet<Entid> {
+        self.touched_entities
+    }
+
+    /// An error reporting that `entity` breaks the constraint being checked.
+    pub fn error<M>(&self, entity: Entid, message: M) -> ValidationError where M: Into<String> {
+        ValidationError {
+            constraint: self.constraint.clone(),
+            entity: entity,
+            message: message.into(),
+        }
+    }
+}
+
+impl<'v, 'a, 'c> Queryable for ConstraintContext<'v, 'a, 'c> {
+    fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        self.in_progress.q_once(query, inputs)
+    }
+
+    fn q_prepare<T>(&self, query: &str, inputs: T) -> PreparedResult
+        where T: Into<Option<QueryInputs>> {
+        self.in_progress.q_prepare(query, inputs)
+    }
+
+    fn q_stream<T>(&self, query: &str, inputs: T) -> StreamResult
+        where T: Into<Option<QueryInputs>> {
+        self.in_progress.q_stream(query, inputs)
+    }
+
+    fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
+        where T: Into<Option<QueryInputs>> {
+        self.in_progress.q_explain(query, inputs)
+    }
+
+    fn lookup_values_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Vec<TypedValue>>
+        where E: Into<Entid> {
+        self.in_progress.lookup_values_for_attribute(entity, attribute)
+    }
+
+    fn lookup_value_for_attribute<E>(&self, entity: E, attribute: &edn::Keyword) -> Result<Option<TypedValue>>
+        where E: Into<Entid> {
+        self.in_progress.lookup_value_for_attribute(entity, attribute)
+    }
+}
+
+impl<'v, 'a, 'c> Pullable for ConstraintContext<'v, 'a, 'c> {
+    fn pull_attributes_for_entities<E, A>(&self, entities: E, attributes: A) -> Result<BTreeMap<Entid, ValueRc<StructuredMap>>>
+    where E: IntoIterator<Item=Entid>,
+          A: IntoIterator<Item=Entid> {
+        self.in_progress.pull_attributes_for_entities(entities, attributes)
+    }
+
+    fn pull_attributes_for_entity<A>(&self, entity: Entid, attributes: A) -> Result<StructuredMap>
+    where A: IntoIterator<Item=Entid> {
+        self.in_progress.pull_attributes_for_entity(entity, attributes)
+    }
+}
+
+pub type ConstraintFn = Fn(&ConstraintContext) -> Result<Vec<ValidationError>> + Send + Sync;
+
+/// The constraints known to a store, by name.
+#[derive(Clone, Debug, Default)]
+pub struct Constraints {
+    constraints: Registry<ConstraintFn>,
+}
+
+impl Constraints {
+    /// Register `constraint` under `name`, replacing any constraint previously registered under it.
+    pub fn register<F>(&mut self, name: Keyword, constraint: F)
+    where F: Fn(&ConstraintContext) -> Result<Vec<ValidationError>> + 'static + Send + Sync {
+        self.constraints.register(name, Box::new(constraint));
+    }
+
+    /// Check every constraint against the entities touched by `in_progress`, collecting the
+    /// errors they report.
+    pub(crate) fn check(&self,
+                        in_progress: &InProgress,
+                        touched_entities: &BTreeSet<Entid>) -> Result<Vec<ValidationError>> {
+        let mut errors = vec![];
+        if touched_entities.is_empty() {
+            return Ok(errors);
+        }
+
+        for (name, constraint) in self.constraints.iter() {
+            let context = ConstraintContext {
+                in_progress: in_progress,
+                touched_entities: touched_entities,
+                constraint: name,
+            };
+            errors.extend((**constraint)(&context)?);
+        }
+        Ok(errors)
+    }
+}
+
+impl Deref for Constraints {
+    type Target = Registry<ConstraintFn>;
+
+    fn deref(&self) -> &Registry<ConstraintFn> {
+        &self.constraints
+    }
+}
+
+impl DerefMut for Constraints {
+    fn deref_mut(&mut self) -> &mut Registry<ConstraintFn> {
+        &mut self.constraints
+    }
+}
@@ -946,7 +946,7 @@ mod tests {
                                 .read_vocabularies().expect("OK");
         assert_eq!(vocabularies.len(), 1);
         let core = vocabularies.get(&kw!(:db.schema/core)).expect("exists");
-        assert_eq!(core.version, 1);
+        assert_eq!(core.version, 4);
     }
 
     #[test]
@@ -955,6 +955,6 @@ mod tests {
         let in_progress = store.begin_transaction().expect("in progress");
         let vocab = in_progress.read_vocabularies().expect("vocabulary");
         assert_eq!(1, vocab.len());
-        assert_eq!(1, vocab.get(&kw!(:db.schema/core)).expect("core vocab").version);
+        assert_eq!(4, vocab.get(&kw!(:db.schema/core)).expect("core vocab").version);
     }
 }
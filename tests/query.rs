@@ -41,13 +41,22 @@ use mentat_query_projector::{
 };
 
 use mentat::{
+    CacheDirection,
+    DataSource,
+    DatabaseView,
     IntoResult,
     Keyword,
     PlainSymbol,
+    PreparedQuery,
+    QueryBuilder,
+    QueryCursor,
     QueryInputs,
+    QueryPage,
+    QueryPlanCacheStats,
     Queryable,
     QueryResults,
     RelResult,
+    SrcVar,
     Store,
     Binding,
     TxReport,
@@ -78,7 +87,7 @@ fn test_rel() {
     let end = time::PreciseTime::now();
 
     // This will need to change each time we add a default ident.
-    assert_eq!(40, results.len());
+    assert_eq!(43, results.len());
 
     // Every row is a pair of a Ref and a Keyword.
     if let QueryResults::Rel(rel) = results {
@@ -189,7 +198,7 @@ fn test_coll() {
         .results;
     let end = time::PreciseTime::now();
 
-    assert_eq!(40, results.len());
+    assert_eq!(43, results.len());
 
     if let QueryResults::Coll(ref coll) = results {
         assert!(coll.iter().all(|item| item.matches_type(ValueType::Ref)));
@@ -1398,6 +1407,333 @@ fn test_aggregation_implicit_grouping() {
     }
 }
 
+#[test]
+fn test_prepared_inputs() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name
+         :db/valueType :db.type/string
+         :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age
+         :db/valueType :db.type/long
+         :db/cardinality :db.cardinality/one}
+    ]"#).expect("schema");
+    let report = store.transact(r#"[
+        {:db/id "a" :foo/name "Alice" :foo/age 30}
+        {:db/id "b" :foo/name "Bob" :foo/age 40}
+    ]"#).expect("data");
+    let alice = TypedValue::Ref(report.tempids.get("a").cloned().expect("alice"));
+    let bob = TypedValue::Ref(report.tempids.get("b").cloned().expect("bob"));
+
+    let name = Variable::from_valid_name("?name");
+    let with_name = |n: &str| QueryInputs::with_value_sequence(vec![(name.clone(), TypedValue::typed_string(n))]);
+
+    let mut prepared = store.q_prepare(r#"[:find ?e . :in ?name :where [?e :foo/name ?name]]"#, None)
+                            .expect("prepared");
+    match &prepared {
+        &PreparedQuery::Bound { .. } => {},
+        _ => panic!("expected a bound query"),
+    }
+    assert_eq!(prepared.run(with_name("Alice")).into_scalar_result().expect("alice"), Some(alice.clone().into()));
+    assert_eq!(prepared.run(with_name("Bob")).into_scalar_result().expect("bob"), Some(bob.clone().into()));
+    assert_eq!(prepared.run(with_name("Carol")).into_scalar_result().expect("nobody"), None);
+
+    // The input must be bound, and must have the type the algebrizer inferred.
+    match prepared.run(None).expect_err("expected unbound variables") {
+        MentatError::UnboundVariables(vars) => {
+            assert_eq!(vars, vec!["?name".to_string()].into_iter().collect());
+        },
+        _ => panic!("expected UnboundVariables"),
+    }
+    let long = QueryInputs::with_value_sequence(vec![(name.clone(), TypedValue::Long(30))]);
+    match prepared.run(long).expect_err("expected a type disagreement") {
+        MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::InputTypeDisagreement(var, expected, actual)) => {
+            assert_eq!(var, PlainSymbol::plain("?name"));
+            assert_eq!(expected, ValueType::String);
+            assert_eq!(actual, ValueType::Long);
+        },
+        _ => panic!("expected InputTypeDisagreement"),
+    }
+
+    // Inputs can be compared, and can limit results.
+    let mut prepared = store.q_prepare(r#"[:find [?n ...]
+                                           :in ?min ?limit
+                                           :where [?e :foo/age ?age] [(>= ?age ?min)] [?e :foo/name ?n]
+                                           :order ?n
+                                           :limit ?limit]"#,
+                                       QueryInputs::with_type_sequence(vec![(var!(?min), ValueType::Long)]))
+                            .expect("prepared");
+    let inputs = |min: i64, limit: i64| QueryInputs::with_value_sequence(vec![(var!(?min), TypedValue::Long(min)),
+                                                                             (var!(?limit), TypedValue::Long(limit))]);
+    assert_eq!(prepared.run(inputs(0, 5)).into_coll_result().expect("everyone"),
+               vec![TypedValue::typed_string("Alice").into(), TypedValue::typed_string("Bob").into()]);
+    assert_eq!(prepared.run(inputs(0, 1)).into_coll_result().expect("first"),
+               vec![TypedValue::typed_string("Alice").into()]);
+    assert_eq!(prepared.run(inputs(35, 5)).into_coll_result().expect("older"),
+               vec![TypedValue::typed_string("Bob").into()]);
+    match prepared.run(inputs(0, 0)).expect_err("expected an invalid limit") {
+        MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::InvalidLimit(_, _)) => {},
+        _ => panic!("expected InvalidLimit"),
+    }
+
+    // We can't plan a query without knowing the type of `?v`, so it's algebrized when it's run.
+    let mut prepared = store.q_prepare(r#"[:find ?e . :in ?v :where [?e _ ?v]]"#, None)
+                            .expect("prepared");
+    match &prepared {
+        &PreparedQuery::Deferred { .. } => {},
+        _ => panic!("expected a deferred query"),
+    }
+    let v = |value: TypedValue| QueryInputs::with_value_sequence(vec![(var!(?v), value)]);
+    assert_eq!(prepared.run(v(TypedValue::typed_string("Bob"))).into_scalar_result().expect("bob"), Some(bob.clone().into()));
+    assert_eq!(prepared.run(v(TypedValue::Long(30))).into_scalar_result().expect("alice"), Some(alice.clone().into()));
+
+    // Rebinding a value that was bound when the query was prepared also works.
+    let mut prepared = store.q_prepare(r#"[:find ?e . :in ?name :where [?e :foo/name ?name]]"#, with_name("Alice"))
+                            .expect("prepared");
+    assert_eq!(prepared.run(None).into_scalar_result().expect("alice"), Some(alice.into()));
+    assert_eq!(prepared.run(with_name("Bob")).into_scalar_result().expect("bob"), Some(bob.into()));
+}
+
+#[test]
+fn test_query_plan_cache() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name
+         :db/valueType :db.type/string
+         :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age
+         :db/valueType :db.type/long
+         :db/cardinality :db.cardinality/one}
+    ]"#).expect("schema");
+    let report = store.transact(r#"[
+        {:db/id "a" :foo/name "Alice" :foo/age 30}
+        {:db/id "b" :foo/name "Bob" :foo/age 40}
+    ]"#).expect("data");
+    let alice = TypedValue::Ref(report.tempids.get("a").cloned().expect("alice"));
+    let bob = TypedValue::Ref(report.tempids.get("b").cloned().expect("bob"));
+
+    let query = r#"[:find ?e . :in ?name :where [?e :foo/name ?name]]"#;
+    let with_name = |n: TypedValue| QueryInputs::with_value_sequence(vec![(var!(?name), n)]);
+    let stats = |hits, misses| QueryPlanCacheStats { hits, misses };
+
+    assert_eq!(store.conn().query_plan_cache_stats(), stats(0, 0));
+
+    // The first run plans the query; later runs with inputs of the same types reuse the plan.
+    assert_eq!(store.q_once(query, with_name(TypedValue::typed_string("Alice"))).into_scalar_result().expect("alice"),
+               Some(alice.clone().into()));
+    assert_eq!(store.conn().query_plan_cache_stats(), stats(0, 1));
+    assert_eq!(store.q_once(query, with_name(TypedValue::typed_string("Bob"))).into_scalar_result().expect("bob"),
+               Some(bob.clone().into()));
+    assert_eq!(store.q_once(query, with_name(TypedValue::typed_string("Carol"))).into_scalar_result().expect("nobody"),
+               None);
+    assert_eq!(store.conn().query_plan_cache_stats(), stats(2, 1));
+
+    // Inputs of a different type need a different plan. This one can't match, so it isn't
+    // planned ahead at all, and is a miss each time.
+    assert_eq!(store.q_once(query, with_name(TypedValue::Long(30))).into_scalar_result().expect("no match"),
+               None);
+    assert_eq!(store.q_once(query, with_name(TypedValue::Long(30))).into_scalar_result().expect("no match"),
+               None);
+    assert_eq!(store.conn().query_plan_cache_stats(), stats(2, 3));
+
+    // Cached plans still require their inputs to be bound.
+    match store.q_once(query, None).expect_err("expected unbound variables") {
+        MentatError::UnboundVariables(vars) => {
+            assert_eq!(vars, vec!["?name".to_string()].into_iter().collect());
+        },
+        _ => panic!("expected UnboundVariables"),
+    }
+    assert_eq!(store.conn().query_plan_cache_stats(), stats(2, 4));
+
+    // Changing the schema drops every plan.
+    store.transact(r#"[
+        {:db/ident :foo/nick
+         :db/valueType :db.type/string
+         :db/cardinality :db.cardinality/one}
+    ]"#).expect("schema");
+    assert_eq!(store.q_once(query, with_name(TypedValue::typed_string("Alice"))).into_scalar_result().expect("alice"),
+               Some(alice.clone().into()));
+    assert_eq!(store.q_once(query, with_name(TypedValue::typed_string("Bob"))).into_scalar_result().expect("bob"),
+               Some(bob.clone().into()));
+    assert_eq!(store.conn().query_plan_cache_stats(), stats(3, 5));
+
+    // Transacting data doesn't.
+    store.transact(r#"[{:db/id "c" :foo/name "Carol"}]"#).expect("data");
+    assert!(store.q_once(query, with_name(TypedValue::typed_string("Carol"))).into_scalar_result().expect("carol").is_some());
+    assert_eq!(store.conn().query_plan_cache_stats(), stats(4, 5));
+
+    // Queries that might use cached attributes don't use plans.
+    store.cache(&kw!(:foo/age), CacheDirection::Forward).expect("cached");
+    assert_eq!(store.q_once(query, with_name(TypedValue::typed_string("Alice"))).into_scalar_result().expect("alice"),
+               Some(alice.into()));
+    assert_eq!(store.conn().query_plan_cache_stats(), stats(4, 5));
+}
+
+#[test]
+fn test_offset_and_paging() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name
+         :db/valueType :db.type/string
+         :db/cardinality :db.cardinality/one}
+    ]"#).expect("schema");
+    store.transact(r#"[
+        {:foo/name "Alice"}
+        {:foo/name "Bob"}
+        {:foo/name "Carol"}
+        {:foo/name "Dave"}
+        {:foo/name "Eve"}
+    ]"#).expect("data");
+
+    let names = |ns: &[&str]| -> Vec<Binding> { ns.iter().map(|n| TypedValue::typed_string(*n).into()).collect() };
+
+    // Fixed offsets skip results, with or without a limit.
+    assert_eq!(store.q_once(r#"[:find [?n ...] :where [_ :foo/name ?n] :order ?n :offset 3]"#, None)
+                    .into_coll_result().expect("skipped"),
+               names(&["Dave", "Eve"]));
+    assert_eq!(store.q_once(r#"[:find [?n ...] :where [_ :foo/name ?n] :order ?n :limit 2 :offset 1]"#, None)
+                    .into_coll_result().expect("skipped"),
+               names(&["Bob", "Carol"]));
+    assert_eq!(store.q_once(r#"[:find [?n ...] :where [_ :foo/name ?n] :order ?n :offset 10]"#, None)
+                    .into_coll_result().expect("skipped everything"),
+               names(&[]));
+
+    // Variable offsets can be bound when the query is run, and must not be negative.
+    let mut prepared = store.q_prepare(r#"[:find [?n ...] :in ?skip :where [_ :foo/name ?n] :order ?n :limit 2 :offset ?skip]"#, None)
+                            .expect("prepared");
+    let skip = |n: i64| QueryInputs::with_value_sequence(vec![(var!(?skip), TypedValue::Long(n))]);
+    assert_eq!(prepared.run(skip(0)).into_coll_result().expect("first"), names(&["Alice", "Bob"]));
+    assert_eq!(prepared.run(skip(4)).into_coll_result().expect("last"), names(&["Eve"]));
+    match prepared.run(skip(-1)).expect_err("expected an invalid offset") {
+        MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::InvalidOffset(_, _)) => {},
+        _ => panic!("expected InvalidOffset"),
+    }
+    match store.q_once(r#"[:find [?n ...] :in ?skip :where [_ :foo/name ?n] :offset ?skip]"#, skip(-1))
+               .expect_err("expected an invalid offset") {
+        MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::InvalidOffset(_, _)) => {},
+        _ => panic!("expected InvalidOffset"),
+    }
+
+    // Paging with a cursor walks through every result exactly once, in order.
+    let page_names = |query: &str| -> Vec<Vec<Binding>> {
+        let mut pages = vec![];
+        let mut after: Option<QueryCursor> = None;
+        loop {
+            let QueryPage { output, cursor } = store.q_page(query, None, after.take()).expect("page");
+            let rows = output.results.into_rel().expect("rel");
+            if rows.is_empty() {
+                assert!(cursor.is_none());
+                return pages;
+            }
+            pages.push(rows.into_iter().map(|row| row[0].clone()).collect());
+            after = cursor;
+            assert!(after.is_some());
+        }
+    };
+    assert_eq!(page_names(r#"[:find ?n ?e :where [?e :foo/name ?n] :order ?n ?e :limit 2]"#),
+               vec![names(&["Alice", "Bob"]), names(&["Carol", "Dave"]), names(&["Eve"])]);
+    assert_eq!(page_names(r#"[:find ?n ?e :where [?e :foo/name ?n] :order (desc ?n) ?e :limit 3]"#),
+               vec![names(&["Eve", "Dave", "Carol"]), names(&["Bob", "Alice"])]);
+
+    // Collections can be paged too.
+    let query = r#"[:find [?n ...] :where [_ :foo/name ?n] :order ?n :limit 4]"#;
+    let first = store.q_page(query, None, None).expect("first page");
+    assert_eq!(first.output.results.into_coll().expect("coll"), names(&["Alice", "Bob", "Carol", "Dave"]));
+    let cursor = first.cursor.expect("cursor");
+    assert_eq!(cursor, QueryCursor(vec![TypedValue::typed_string("Dave")]));
+    let second = store.q_page(query, None, Some(cursor)).expect("second page");
+    assert_eq!(second.output.results.into_coll().expect("coll"), names(&["Eve"]));
+
+    // The cursor must match the query's ordering.
+    match store.q_page(query, None, Some(QueryCursor(vec![]))).expect_err("expected a mismatch") {
+        MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::CursorLengthMismatch(1, 0)) => {},
+        _ => panic!("expected CursorLengthMismatch"),
+    }
+
+    // Only ordered collections and relations that find their ordering variables can be paged.
+    for query in &[r#"[:find ?n . :where [_ :foo/name ?n] :order ?n]"#,
+                   r#"[:find [?n ...] :where [_ :foo/name ?n]]"#,
+                   r#"[:find [?n ...] :where [?e :foo/name ?n] :order ?e]"#] {
+        match store.q_page(query, None, None).expect_err("expected an unpageable query") {
+            MentatError::UnpageableQuery(_) => {},
+            _ => panic!("expected UnpageableQuery"),
+        }
+    }
+}
+
+#[test]
+fn test_stream() {
+    let mut store = Store::open("").expect("opened");
+    store.transact(r#"[
+        {:db/ident :foo/name
+         :db/valueType :db.type/string
+         :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/age
+         :db/valueType :db.type/long
+         :db/cardinality :db.cardinality/one}
+    ]"#).expect("schema");
+    store.transact(r#"[
+        {:foo/name "Alice" :foo/age 30}
+        {:foo/name "Bob" :foo/age 40}
+        {:foo/name "Carol" :foo/age 50}
+    ]"#).expect("data");
+
+    let name = |n: &str| -> Binding { TypedValue::typed_string(n).into() };
+    let age = |a: i64| -> Binding { TypedValue::Long(a).into() };
+
+    // Relations produce a row of bindings for each result, in the same order as `q_once`.
+    {
+        let query = r#"[:find ?n ?a :where [?e :foo/name ?n] [?e :foo/age ?a] :order ?n]"#;
+        let mut stream = store.q_stream(query, None).expect("stream");
+        let rows: Vec<Vec<Binding>> = stream.rows().expect("rows").collect::<Result<_, _>>().expect("results");
+        assert_eq!(rows, vec![vec![name("Alice"), age(30)],
+                              vec![name("Bob"), age(40)],
+                              vec![name("Carol"), age(50)]]);
+        let expected: Vec<Vec<Binding>> = store.q_once(query, None).into_rel_result().expect("rel").into_iter().collect();
+        assert_eq!(rows, expected);
+
+        // A stream can be run again, and reads rows only as they're asked for.
+        {
+            let mut partial = stream.rows().expect("rows");
+            assert_eq!(partial.next().expect("a row").expect("alice"), vec![name("Alice"), age(30)]);
+        }
+        assert_eq!(stream.rows().expect("rows").count(), 3);
+    }
+
+    // Collections produce single-binding rows. Streams take inputs, and work within a read.
+    {
+        let read = store.begin_read().expect("read");
+        let inputs = QueryInputs::with_value_sequence(vec![(var!(?min), TypedValue::Long(35))]);
+        let mut stream = read.q_stream(r#"[:find [?n ...] :in ?min :where [?e :foo/age ?a] [(>= ?a ?min)] [?e :foo/name ?n] :order ?n]"#,
+                                       inputs)
+                             .expect("stream");
+        let rows: Vec<Vec<Binding>> = stream.rows().expect("rows").collect::<Result<_, _>>().expect("results");
+        assert_eq!(rows, vec![vec![name("Bob")], vec![name("Carol")]]);
+    }
+
+    // Queries that are known to be empty produce no rows.
+    let mut stream = store.q_stream(r#"[:find ?e ?n :where [?e :foo/name ?n] [?e :foo/age "old"]]"#, None)
+                          .expect("stream");
+    assert_eq!(stream.rows().expect("rows").count(), 0);
+
+    // Pull expressions still work, though their results are collected before they're streamed.
+    let mut stream = store.q_stream(r#"[:find [(pull ?e [:foo/name]) ...] :where [?e :foo/age 40]]"#, None)
+                          .expect("stream");
+    assert_eq!(stream.rows().expect("rows").count(), 1);
+
+    // Scalars and tuples can't be streamed.
+    match store.q_stream(r#"[:find ?n . :where [_ :foo/name ?n]]"#, None).err().expect("expected an error") {
+        MentatError::UnstreamableQuery(_) => {},
+        _ => panic!("expected UnstreamableQuery"),
+    }
+
+    // Inputs still have to be bound.
+    match store.q_stream(r#"[:find [?n ...] :in ?e :where [?e :foo/name ?n]]"#, None).err().expect("expected an error") {
+        MentatError::UnboundVariables(_) => {},
+        _ => panic!("expected UnboundVariables"),
+    }
+}
+
 #[test]
 fn test_tx_ids() {
     let mut store = Store::open("").expect("opened");
@@ -1421,15 +1757,8 @@ fn test_tx_ids() {
         [:db/add "v" :foo/term "3"]
     ]"#).expect("tx3 to apply").tx_id;
 
-    fn assert_tx_id_range(store: &Store, after: Entid, before: Entid, expected: Vec<TypedValue>) {
-        // TODO: after https://github.com/mozilla/mentat/issues/641, use q_prepare with inputs bound
-        // at execution time.
-        let r = store.q_once(r#"[:find [?tx ...]
-                                 :in ?after ?before
-                                 :where
-                                 [(tx-ids $ ?after ?before) [?tx ...]]
-                                ]"#,
-                             QueryInputs::with_value_sequence(vec![
+    fn assert_tx_id_range(prepared: &mut PreparedQuery, after: Entid, before: Entid, expected: Vec<TypedValue>) {
+        let r = prepared.run(QueryInputs::with_value_sequence(vec![
                                  (Variable::from_valid_name("?after"),  TypedValue::Ref(after)),
                                  (Variable::from_valid_name("?before"), TypedValue::Ref(before)),
                              ]))
@@ -1444,10 +1773,279 @@ fn test_tx_ids() {
         }
     }
 
-    assert_tx_id_range(&store, tx1, tx2, vec![TypedValue::Ref(tx1)]);
-    assert_tx_id_range(&store, tx1, tx3, vec![TypedValue::Ref(tx1), TypedValue::Ref(tx2)]);
-    assert_tx_id_range(&store, tx2, tx3, vec![TypedValue::Ref(tx2)]);
-    assert_tx_id_range(&store, tx2, tx3 + 1, vec![TypedValue::Ref(tx2), TypedValue::Ref(tx3)]);
+    let mut prepared = store.q_prepare(r#"[:find [?tx ...]
+                                           :in ?after ?before
+                                           :where
+                                           [(tx-ids $ ?after ?before) [?tx ...]]
+                                          ]"#, None)
+                            .expect("prepared");
+
+    assert_tx_id_range(&mut prepared, tx1, tx2, vec![TypedValue::Ref(tx1)]);
+    assert_tx_id_range(&mut prepared, tx1, tx3, vec![TypedValue::Ref(tx1), TypedValue::Ref(tx2)]);
+    assert_tx_id_range(&mut prepared, tx2, tx3, vec![TypedValue::Ref(tx2)]);
+    assert_tx_id_range(&mut prepared, tx2, tx3 + 1, vec![TypedValue::Ref(tx2), TypedValue::Ref(tx3)]);
+}
+
+#[test]
+fn test_historical_views() {
+    let mut store = Store::open("").expect("opened");
+
+    store.transact(r#"[
+        {:db/ident :foo/term
+         :db/valueType :db.type/string
+         :db/cardinality :db.cardinality/one}
+        {:db/ident :foo/text
+         :db/valueType :db.type/string
+         :db/fulltext true
+         :db/cardinality :db.cardinality/one}
+    ]"#).expect("schema");
+
+    let report = store.transact(r#"[
+        {:db/id "v" :foo/term "first" :foo/text "some words"}
+    ]"#).expect("tx1 to apply");
+    let tx1 = report.tx_id;
+    let v = *report.tempids.get("v").expect("v");
+
+    let tx2 = store.transact(&format!("[[:db/add {} :foo/term \"second\"]]", v))
+                   .expect("tx2 to apply").tx_id;
+    let tx3 = store.transact(&format!("[[:db/retract {} :foo/term \"second\"]]", v))
+                   .expect("tx3 to apply").tx_id;
+
+    let term = Keyword::namespaced("foo", "term");
+    let query = r#"[:find ?t . :where [_ :foo/term ?t]]"#;
+
+    // The current view has nothing: we retracted the only value.
+    assert_eq!(store.q_once(query, None).expect("query").into_scalar().expect("scalar"), None);
+    assert_eq!(store.lookup_value_for_attribute(v, &term).expect("lookup"), None);
+
+    // As of each transaction we see the value at that time.
+    assert_eq!(store.as_of(tx1).q_once(query, None).expect("query").into_scalar().expect("scalar"),
+               Some(TypedValue::typed_string("first").into()));
+    assert_eq!(store.as_of(tx2).lookup_value_for_attribute(v, &term).expect("lookup"),
+               Some(TypedValue::typed_string("second")));
+    assert_eq!(store.as_of(tx3).lookup_value_for_attribute(v, &term).expect("lookup"), None);
+
+    // Fulltext values are resolved to their text.
+    assert_eq!(store.as_of(tx1)
+                    .lookup_value_for_attribute(v, &Keyword::namespaced("foo", "text"))
+                    .expect("lookup"),
+               Some(TypedValue::typed_string("some words")));
+
+    // Since tx1, only the fulltext value remains, and it was asserted in tx1.
+    assert_eq!(store.since(tx1 - 1)
+                    .q_once(r#"[:find ?t . :where [_ :foo/text ?t]]"#, None)
+                    .expect("query").into_scalar().expect("scalar"),
+               Some(TypedValue::typed_string("some words").into()));
+    assert_eq!(store.since(tx1)
+                    .q_once(r#"[:find ?t . :where [_ :foo/text ?t]]"#, None)
+                    .expect("query").into_scalar().expect("scalar"),
+               None);
+
+    // The history view sees everything, including retractions.
+    let history = store.history()
+                       .q_once(r#"[:find ?t ?tx ?added
+                                   :where [_ :foo/term ?t ?tx ?added]
+                                   :order ?tx ?added]"#, None)
+                       .expect("query")
+                       .into_rel()
+                       .expect("rel")
+                       .into_iter()
+                       .collect::<Vec<Vec<Binding>>>();
+    assert_eq!(history, vec![
+        vec![TypedValue::typed_string("first").into(), TypedValue::Ref(tx1).into(), TypedValue::Boolean(true).into()],
+        vec![TypedValue::typed_string("first").into(), TypedValue::Ref(tx2).into(), TypedValue::Boolean(false).into()],
+        vec![TypedValue::typed_string("second").into(), TypedValue::Ref(tx2).into(), TypedValue::Boolean(true).into()],
+        vec![TypedValue::typed_string("second").into(), TypedValue::Ref(tx3).into(), TypedValue::Boolean(false).into()],
+    ]);
+
+    let retractions = store.history()
+                           .q_once(r#"[:find [?tx ...] :where [_ :foo/term _ ?tx false] :order ?tx]"#, None)
+                           .expect("query")
+                           .into_coll()
+                           .expect("coll");
+    assert_eq!(retractions, vec![TypedValue::Ref(tx2).into(), TypedValue::Ref(tx3).into()]);
+
+    // Outside the history view, nothing is retracted.
+    assert_eq!(store.as_of(tx3)
+                    .q_once(r#"[:find [?tx ...] :where [_ :foo/term _ ?tx false]]"#, None)
+                    .expect("query").into_coll().expect("coll"),
+               vec![]);
+
+    // Pull only reads the current state.
+    match store.as_of(tx1).q_once(r#"[:find (pull ?e [:foo/term]) . :where [?e :foo/term _]]"#, None) {
+        Err(MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::PullInHistoricalView)) => {},
+        _ => panic!("expected a pull error"),
+    }
+}
+
+#[test]
+fn test_attached_sources() {
+    let path = std::env::temp_dir().join(format!("mentat-test-attached-{}.db", time::precise_time_ns()));
+    let path = path.to_str().expect("path").to_string();
+
+    let schema = r#"[
+        {:db/ident :foo/name
+         :db/valueType :db.type/string
+         :db/cardinality :db.cardinality/one}
+    ]"#;
+
+    {
+        let mut other = Store::open(path.as_str()).expect("opened");
+        other.transact(schema).expect("schema");
+        other.transact(r#"[{:foo/name "Alice"} {:foo/name "Bob"}]"#).expect("names");
+    }
+
+    let mut store = Store::open("").expect("opened");
+    store.transact(schema).expect("schema");
+    let tx = store.transact(r#"[{:foo/name "Bob"} {:foo/name "Carol"}]"#).expect("names").tx_id;
+    store.transact(r#"[{:foo/name "Dave"}]"#).expect("more names");
+
+    store.attach("other", path.as_str()).expect("attached");
+
+    let query = r#"[:find [?n ...] :in $ $other :where [_ :foo/name ?n] [$other _ :foo/name ?n]]"#;
+    let inputs = QueryInputs::default()
+        .with_source(SrcVar::NamedSrc("other".to_string()),
+                     DataSource::Attached("other".to_string(), DatabaseView::Current));
+    let names = store.q_once(query, inputs).into_coll_result().expect("coll");
+    assert_eq!(names, vec![TypedValue::typed_string("Bob").into()]);
+
+    // A source can also be an earlier view of the store itself.
+    let query = r#"[:find [?n ...] :in $ $then :where [_ :foo/name ?n] (not [$then _ :foo/name ?n]) :order ?n]"#;
+    let inputs = QueryInputs::default()
+        .with_source(SrcVar::NamedSrc("then".to_string()), DataSource::Store(DatabaseView::AsOf(tx)));
+    let names = store.q_once(query, inputs).into_coll_result().expect("coll");
+    assert_eq!(names, vec![TypedValue::typed_string("Dave").into()]);
+
+    // Sources named in `:in` must be bound.
+    match store.q_once(r#"[:find ?n . :in $ $other :where [$other _ :foo/name ?n]]"#, None) {
+        Err(MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::UnboundSource(name))) => {
+            assert_eq!(name, "other");
+        },
+        _ => panic!("expected an unbound source"),
+    }
+
+    store.detach("other").expect("detached");
+
+    // A store with a different schema can't be attached.
+    let mut different = Store::open("").expect("opened");
+    match different.attach("other", path.as_str()) {
+        Err(MentatError::IncompatibleAttachedStore(name)) => assert_eq!(name, "other"),
+        _ => panic!("expected an incompatible store"),
+    }
+    match different.attach("main", path.as_str()) {
+        Err(MentatError::InvalidDatabaseName(name)) => assert_eq!(name, "main"),
+        _ => panic!("expected an invalid name"),
+    }
+
+    std::fs::remove_file(path).expect("removed");
+}
+
+#[test]
+fn test_rules() {
+    let mut store = Store::open("").expect("opened");
+
+    store.transact(r#"[
+        {:db/ident :person/name
+         :db/valueType :db.type/string
+         :db/cardinality :db.cardinality/one
+         :db/unique :db.unique/identity}
+        {:db/ident :person/parent
+         :db/valueType :db.type/ref
+         :db/cardinality :db.cardinality/many}
+    ]"#).expect("schema");
+
+    store.transact(r#"[
+        {:db/id "a" :person/name "Alice" :person/parent "b"}
+        {:db/id "b" :person/name "Beth" :person/parent "c"}
+        {:db/id "c" :person/name "Carol" :person/parent "d"}
+        {:db/id "d" :person/name "Dana"}
+        {:db/id "e" :person/name "Erin" :person/parent "b"}
+    ]"#).expect("data");
+
+    let names = |names: Vec<&str>| -> Vec<Binding> {
+        names.into_iter().map(|n| TypedValue::typed_string(n).into()).collect()
+    };
+
+    // A recursive rule: the transitive closure of :person/parent.
+    let ancestor = r#"[[(ancestor ?a ?b) [?a :person/parent ?b]]
+                       [(ancestor ?a ?b) [?a :person/parent ?x] (ancestor ?x ?b)]]"#;
+    let ancestors = QueryBuilder::new(&mut store, r#"[:find [?name ...]
+                                                      :in %
+                                                      :where
+                                                      [?p :person/name "Alice"]
+                                                      (ancestor ?p ?a)
+                                                      [?a :person/name ?name]
+                                                      :order ?name]"#)
+        .bind_rules(ancestor).expect("rules")
+        .execute_coll().expect("results");
+    assert_eq!(ancestors, names(vec!["Beth", "Carol", "Dana"]));
+
+    // The rule can be joined on either of its arguments.
+    let descendants = QueryBuilder::new(&mut store, r#"[:find [?name ...]
+                                                        :in %
+                                                        :where
+                                                        [?d :person/name "Dana"]
+                                                        (ancestor ?p ?d)
+                                                        [?p :person/name ?name]
+                                                        :order ?name]"#)
+        .bind_rules(ancestor).expect("rules")
+        .execute_coll().expect("results");
+    assert_eq!(descendants, names(vec!["Alice", "Beth", "Carol", "Erin"]));
+
+    // A non-recursive rule with two definitions.
+    let relative = r#"[[(relative ?a ?b) [?a :person/parent ?b]]
+                       [(relative ?a ?b) [?b :person/parent ?a]]]"#;
+    let relatives = QueryBuilder::new(&mut store, r#"[:find [?name ...]
+                                                      :in %
+                                                      :where
+                                                      [?p :person/name "Beth"]
+                                                      (relative ?p ?r)
+                                                      [?r :person/name ?name]
+                                                      :order ?name]"#)
+        .bind_rules(relative).expect("rules")
+        .execute_coll().expect("results");
+    assert_eq!(relatives, names(vec!["Alice", "Carol", "Erin"]));
+
+    // The bundled SQLite can't run a recursive rule with more than one recursive definition.
+    let kin = r#"[[(kin ?a ?b) [?a :person/parent ?b]]
+                  [(kin ?a ?b) [?a :person/parent ?x] (kin ?x ?b)]
+                  [(kin ?a ?b) [?x :person/parent ?a] (kin ?x ?b)]]"#;
+    let err = QueryBuilder::new(&mut store, r#"[:find ?k :in % :where [?p :person/name "Beth"] (kin ?p ?k)]"#)
+        .bind_rules(kin).expect("rules")
+        .execute()
+        .expect_err("expected the rule to be rejected");
+    match err {
+        MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::UnsupportedRuleRecursion(name)) => {
+            assert_eq!(name, PlainSymbol::plain("kin"));
+        },
+        x => panic!("expected UnsupportedRuleRecursion, got {:?}", x),
+    }
+
+    // Rules are only available to queries that ask for them.
+    let err = QueryBuilder::new(&mut store, r#"[:find ?p :where (relative ?p ?r)]"#)
+        .bind_rules(relative).expect("rules")
+        .execute()
+        .expect_err("expected the rule to be unknown");
+    match err {
+        MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::UnknownRule(name)) => {
+            assert_eq!(name, PlainSymbol::plain("relative"));
+        },
+        x => panic!("expected UnknownRule, got {:?}", x),
+    }
+
+    // A rule must bind each of its arguments, not merely constrain them.
+    let unrelated = r#"[[(unrelated ?a ?b) [?a :person/name _] (not [?a :person/parent ?b])]]"#;
+    let err = QueryBuilder::new(&mut store, r#"[:find ?p :in % :where [?r :person/name "Dana"] (unrelated ?p ?r)]"#)
+        .bind_rules(unrelated).expect("rules")
+        .execute()
+        .expect_err("expected the rule variable to be unbound");
+    match err {
+        MentatError::AlgebrizerError(mentat_query_algebrizer::AlgebrizerError::UnboundRuleVariable(name, var)) => {
+            assert_eq!(name, PlainSymbol::plain("unrelated"));
+            assert_eq!(var, PlainSymbol::plain("?b"));
+        },
+        x => panic!("expected UnboundRuleVariable, got {:?}", x),
+    }
 }
 
 fn run_tx_data_test(mut store: Store) {
@@ -1513,6 +2111,94 @@ fn test_tx_data() {
     run_tx_data_test(Store::open("").expect("opened"));
 }
 
+#[test]
+fn test_tx_data_no_history() {
+    let mut store = Store::open("").expect("opened");
+
+    store.transact(r#"[
+        {:db/ident :page/lastVisited
+         :db/valueType :db.type/long
+         :db/cardinality :db.cardinality/one
+         :db/noHistory true}
+    ]"#).unwrap();
+
+    let tx1 = store.transact(r#"[
+        [:db/add "p" :page/lastVisited 1]
+    ]"#).expect("tx1 to apply");
+    let page = tx1.tempids.get("p").cloned().expect("tempid");
+
+    let tx2 = store.transact(format!("[[:db/add {} :page/lastVisited 2]]", page).as_str()).expect("tx2 to apply");
+
+    fn tx_data(store: &Store, tx: Entid) -> QueryResults {
+        store.q_once(r#"[:find ?e ?a-name ?v ?tx ?added
+                         :in ?tx-in
+                         :where
+                         [(tx-data $ ?tx-in) [[?e ?a ?v ?tx ?added]]]
+                         [?a :db/ident ?a-name]
+                         :order ?e
+                        ]"#,
+                     QueryInputs::with_value_sequence(vec![
+                         (Variable::from_valid_name("?tx-in"), TypedValue::Ref(tx)),
+                     ]))
+             .expect("results")
+             .into()
+    }
+
+    // The first transaction no longer remembers the replaced value, nor does the second remember
+    // retracting it.
+    match tx_data(&store, tx1.tx_id) {
+        QueryResults::Rel(vals) => {
+            assert_eq!(vals,
+                       vec![
+                           vec![TypedValue::Ref(tx1.tx_id),
+                                TypedValue::typed_ns_keyword("db", "txInstant"),
+                                TypedValue::Instant(tx1.tx_instant),
+                                TypedValue::Ref(tx1.tx_id),
+                                TypedValue::Boolean(true)],
+                       ].into());
+        },
+        x => panic!("Got unexpected results {:?}", x),
+    }
+    match tx_data(&store, tx2.tx_id) {
+        QueryResults::Rel(vals) => {
+            assert_eq!(vals,
+                       vec![
+                           vec![TypedValue::Ref(page),
+                                TypedValue::typed_ns_keyword("page", "lastVisited"),
+                                TypedValue::Long(2),
+                                TypedValue::Ref(tx2.tx_id),
+                                TypedValue::Boolean(true)],
+                           vec![TypedValue::Ref(tx2.tx_id),
+                                TypedValue::typed_ns_keyword("db", "txInstant"),
+                                TypedValue::Instant(tx2.tx_instant),
+                                TypedValue::Ref(tx2.tx_id),
+                                TypedValue::Boolean(true)],
+                       ].into());
+        },
+        x => panic!("Got unexpected results {:?}", x),
+    }
+
+    // Both transactions are still in the log.
+    let r = store.q_once(r#"[:find [?tx ...]
+                             :in ?after ?before
+                             :where
+                             [(tx-ids $ ?after ?before) [?tx ...]]
+                            ]"#,
+                         QueryInputs::with_value_sequence(vec![
+                             (Variable::from_valid_name("?after"),  TypedValue::Ref(tx1.tx_id)),
+                             (Variable::from_valid_name("?before"), TypedValue::Ref(tx2.tx_id + 1)),
+                         ]))
+        .expect("results")
+        .into();
+    match r {
+        QueryResults::Coll(txs) => {
+            let expected: Vec<Binding> = vec![TypedValue::Ref(tx1.tx_id).into(), TypedValue::Ref(tx2.tx_id).into()];
+            assert_eq!(txs, expected);
+        },
+        x => panic!("Got unexpected results {:?}", x),
+    }
+}
+
 #[cfg(feature = "sqlite")]
 #[test]
 fn test_encrypted() {
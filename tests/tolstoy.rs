@@ -160,4 +160,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_reader_no_history() {
+        let mut c = new_connection("").expect("Couldn't open conn.");
+        let mut conn = Conn::connect(&mut c).expect("Couldn't open DB.");
+
+        conn.transact(&mut c, r#"[
+            {:db/ident :page/lastVisited
+             :db/valueType :db.type/long
+             :db/cardinality :db.cardinality/one
+             :db/noHistory true}
+        ]"#).expect("successful transaction");
+
+        let ids = conn.transact(&mut c, r#"[
+            [:db/add "p" :page/lastVisited 1]
+        ]"#).expect("successful transaction").tempids;
+        let page = *ids.get("p").unwrap();
+
+        conn.transact(&mut c, format!("[[:db/add {} :page/lastVisited 2]]", page).as_str()).expect("successful transaction");
+
+        {
+            let db_tx = c.transaction().expect("db tx");
+            let mut receiver = TestingReceiver::new();
+            Processor::process(&db_tx, None, &mut receiver).expect("processor");
+
+            // Every transaction is uploaded, but only the current value of a :db/noHistory
+            // attribute is: the first value, and its retraction, are gone from the log.
+            assert_eq!(4, receiver.txes.keys().count());
+            assert_tx_datoms_count(&receiver, 2, 1);
+            assert_tx_datoms_count(&receiver, 3, 2);
+
+            let parts: Vec<&TxPart> = receiver.txes.values()
+                                                   .flat_map(|parts| parts.iter())
+                                                   .filter(|part| part.e == page)
+                                                   .collect();
+            assert_eq!(1, parts.len());
+            assert_eq!(TypedValue::Long(2), parts[0].v);
+            assert_eq!(true, parts[0].added);
+        }
+    }
 }
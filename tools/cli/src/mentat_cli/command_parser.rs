@@ -66,6 +66,7 @@ pub static COMMAND_QUERY_SHORT: &'static str = &"q";
 pub static COMMAND_QUERY_EXPLAIN_LONG: &'static str = &"explain_query";
 pub static COMMAND_QUERY_EXPLAIN_SHORT: &'static str = &"eq";
 pub static COMMAND_QUERY_PREPARED_LONG: &'static str = &"query_prepared";
+pub static COMMAND_REVERT: &'static str = &"revert";
 pub static COMMAND_SCHEMA: &'static str = &"schema";
 pub static COMMAND_SYNC: &'static str = &"sync";
 pub static COMMAND_TIMER_LONG: &'static str = &"timer";
@@ -86,6 +87,7 @@ pub enum Command {
     Query(String),
     QueryExplain(String),
     QueryPrepared(String),
+    Revert(i64),
     Schema,
     Sync(Vec<String>),
     Timer(bool),
@@ -115,6 +117,7 @@ impl Command {
             &Command::OpenEmpty(_) |
             &Command::OpenEncrypted(_, _) |
             &Command::OpenEmptyEncrypted(_, _) |
+            &Command::Revert(_) |
             &Command::Timer(_) |
             &Command::Schema |
             &Command::Sync(_)
@@ -127,6 +130,7 @@ impl Command {
             &Command::Import(_) |
             &Command::Query(_) |
             &Command::QueryPrepared(_) |
+            &Command::Revert(_) |
             &Command::Transact(_)
             => true,
 
@@ -184,6 +188,9 @@ impl Command {
             &Command::QueryPrepared(ref args) => {
                 format!(".{} {}", COMMAND_QUERY_PREPARED_LONG, args)
             },
+            &Command::Revert(tx) => {
+                format!(".{} {}", COMMAND_REVERT, tx)
+            },
             &Command::Schema => {
                 format!(".{}", COMMAND_SCHEMA)
             },
@@ -310,6 +317,18 @@ pub fn command(s: &str) -> Result<Command, Error> {
                             Ok(Command::QueryPrepared(x))
                         });
 
+    let revert_parser = string(COMMAND_REVERT)
+                    .with(spaces())
+                    .with(argument())
+                    .skip(spaces())
+                    .skip(eof())
+                    .map(|arg| {
+                        match arg.parse::<i64>() {
+                            Ok(tx) => Ok(Command::Revert(tx)),
+                            Err(_) => bail!(CliError::CommandParse(format!("Invalid transaction ID {:?}", arg))),
+                        }
+                    });
+
     let schema_parser = string(COMMAND_SCHEMA)
                     .with(no_arg_parser())
                     .map(|args| {
@@ -347,7 +366,7 @@ pub fn command(s: &str) -> Result<Command, Error> {
 
     spaces()
     .skip(token('.'))
-    .with(choice::<[&mut Parser<Input = _, Output = Result<Command, Error>>; 16], _>
+    .with(choice::<[&mut Parser<Input = _, Output = Result<Command, Error>>; 17], _>
           ([&mut try(help_parser),
             &mut try(import_parser),
             &mut try(timer_parser),
@@ -361,6 +380,7 @@ pub fn command(s: &str) -> Result<Command, Error> {
             &mut try(exit_parser),
             &mut try(query_prepared_parser),
             &mut try(query_parser),
+            &mut try(revert_parser),
             &mut try(schema_parser),
             &mut try(sync_parser),
             &mut try(transact_parser)]))
@@ -596,6 +616,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_revert_parser() {
+        let input = ".revert 268435457";
+        let cmd = command(&input).expect("Expected revert command");
+        match cmd {
+            Command::Revert(tx) => assert_eq!(tx, 268435457),
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn test_revert_parser_invalid_tx() {
+        let input = ".revert foo";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Invalid transaction ID \"foo\"");
+    }
+
+    #[test]
+    fn test_revert_parser_no_args() {
+        let input = ".revert";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), format!("Invalid command {:?}", input));
+    }
+
     #[test]
     fn test_schema_parser_with_args() {
         let input = ".schema arg1";
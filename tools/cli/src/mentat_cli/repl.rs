@@ -69,6 +69,7 @@ use command_parser::{
     COMMAND_QUERY_EXPLAIN_LONG,
     COMMAND_QUERY_EXPLAIN_SHORT,
     COMMAND_QUERY_PREPARED_LONG,
+    COMMAND_REVERT,
     COMMAND_SCHEMA,
     COMMAND_TIMER_LONG,
     COMMAND_TRANSACT_LONG,
@@ -126,6 +127,8 @@ lazy_static! {
             (COMMAND_TRANSACT_LONG, "Execute a transact against the current open database."),
             (COMMAND_TRANSACT_SHORT, "Shortcut for `.transact`. Execute a transact against the current open database."),
 
+            (COMMAND_REVERT, "Transact the inverse of the transaction with the given ID. Usage: `.revert 268435457`"),
+
             (COMMAND_QUERY_EXPLAIN_LONG, "Show the SQL and query plan that would be executed for a given query."),
             (COMMAND_QUERY_EXPLAIN_SHORT, "Shortcut for `.explain_query`. Show the SQL and query plan that would be executed for a given query."),
 
@@ -362,6 +365,9 @@ impl Repl {
                     })
                     .ok();
             },
+            Command::Revert(tx) => {
+                self.execute_revert(tx);
+            },
             Command::Schema => {
                 let edn = self.store.conn().current_schema().to_edn_value();
                 match edn.to_pretty(120) {
@@ -612,6 +618,20 @@ impl Repl {
         Ok(report)
     }
 
+    fn execute_revert(&mut self, tx: i64) {
+        match self.revert(tx) {
+            Result::Ok(report) => println!("{:?}", report),
+            Result::Err(err) => eprintln!("Error: {:?}.", err),
+        }
+    }
+
+    fn revert(&mut self, tx: i64) -> ::mentat::errors::Result<TxReport> {
+        let mut in_progress = self.store.begin_transaction()?;
+        let report = in_progress.revert_tx(tx)?;
+        in_progress.commit()?;
+        Ok(report)
+    }
+
     fn binding_as_string(&self, value: &Binding) -> String {
         use self::Binding::*;
         match value {
@@ -659,6 +679,7 @@ impl Repl {
             &Ref(r) => format!("{}", r),
             &String(ref s) => format!("{:?}", s.to_string()),
             &Uuid(ref u) => format!("{}", u),
+            &Tuple(ref t) => format!("[{}]", t.iter().map(|v| self.value_as_string(v)).collect::<Vec<_>>().join(" ")),
         }
     }
 }